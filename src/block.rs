@@ -1,11 +1,15 @@
+use std::collections::{HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 
 use petgraph::{
+    algo::tarjan_scc,
     stable_graph::{NodeIndex, StableDiGraph},
-    visit::Bfs,
-    Outgoing,
+    visit::{Bfs, EdgeRef, IntoNeighborsDirected, Reversed, Walker},
+    Incoming, Outgoing,
 };
 
+use thiserror::Error;
+
 use crate::semilattice::SemiLattice;
 
 pub trait BlockLattice<SemiLatticeType: SemiLattice>: Block {
@@ -21,16 +25,51 @@ pub trait Block<Ix = u32> {
     fn set_node_index(&mut self, index: NodeIndex<Ix>);
 }
 
+/// How control flows along an edge of a [`DataFlowGraph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Taken by a branch whose condition is truthy
+    True,
+    /// Taken by a branch whose condition is falsy
+    False,
+    /// A jump, or leaving the function
+    Unconditional,
+    /// Falling through into the next block
+    Fallthrough,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Direction {
     Forward = 0,
     Backward = 1,
 }
 
+/// A solver giving up on a loop whose values keep changing, as they do when a transfer
+/// isn't monotone.
+#[derive(Debug, Error, PartialEq)]
+pub enum ConvergenceError {
+    #[error("block {node:?} still changed after {sweeps} sweeps of its loop")]
+    NotConverged { node: NodeIndex<u32>, sweeps: usize },
+}
+
 pub trait BlockUpdate<T: SemiLattice> {
     fn initialize(&mut self, direction: Direction);
     fn update(&mut self, direction: Direction) -> bool;
-    fn converge(&mut self, direction: Direction);
+    fn converge(&mut self, direction: Direction) -> usize;
+    fn converge_capped(
+        &mut self,
+        direction: Direction,
+        max_sweeps: usize,
+    ) -> Result<usize, ConvergenceError>;
+    fn converge_uniform(&mut self, direction: Direction) -> usize;
+}
+
+/// Two analyses solved in one pass over the graph, each in its own direction.
+///
+/// Together they are the product of their lattices: a block is transferred for both
+/// at once, and each component settles where it would settle alone.
+pub trait JointBlockUpdate<A: SemiLattice, B: SemiLattice> {
+    fn converge_jointly(&mut self, directions: (Direction, Direction)) -> usize;
 }
 
 pub trait BlockTransfer<SemiLatticeType: SemiLattice, BlockType: Block, GraphWeight>:
@@ -70,9 +109,12 @@ impl<
 {
 }
 
-#[derive(Debug)]
+/// `graph` is a `StableDiGraph`, so removing a node leaves the indices of the others
+/// valid. Cloning copies the graph, its node data and the weight as they are, so node
+/// indices of the copy are those of the original.
+#[derive(Debug, Clone)]
 pub struct DataFlowGraph<BlockType: Block, Weight = ()> {
-    pub graph: StableDiGraph<BlockType, ()>,
+    pub graph: StableDiGraph<BlockType, EdgeKind>,
     pub entry: NodeIndex<u32>,
     pub exit: NodeIndex<u32>,
     pub weight: Weight,
@@ -92,6 +134,45 @@ impl<BlockType: Block, Weight> DataFlowGraph<BlockType, Weight> {
             weight,
         }
     }
+
+    /// Removes `node` and its edges, returning its data. Every other index keeps
+    /// referring to the same node. `entry` and `exit` are never removed.
+    pub fn remove_node(&mut self, node: NodeIndex<u32>) -> Option<BlockType> {
+        if node == self.entry || node == self.exit {
+            return None;
+        }
+        self.graph.remove_node(node)
+    }
+
+    /// The graph with every edge flipped, so walking it from `exit` follows
+    /// control flow backward. The graph is borrowed, not copied.
+    pub fn reversed(&self) -> Reversed<&StableDiGraph<BlockType, EdgeKind>> {
+        Reversed(&self.graph)
+    }
+
+    /// Strongly connected components of the graph, found with Tarjan's algorithm.
+    ///
+    /// Components are in topological order, so the one containing `entry` comes
+    /// before anything it reaches. A loop shows up as a component with more than
+    /// one node, or a single node with an edge to itself.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<NodeIndex<u32>>> {
+        self.strongly_connected_components_along(Direction::Forward)
+    }
+
+    /// Strongly connected components in topological order along `direction`:
+    /// of the graph for `Forward`, and of its [`reversed`](Self::reversed) view,
+    /// starting from the component containing `exit`, for `Backward`.
+    pub fn strongly_connected_components_along(
+        &self,
+        direction: Direction,
+    ) -> Vec<Vec<NodeIndex<u32>>> {
+        let mut components = match direction {
+            Direction::Forward => tarjan_scc(&self.graph),
+            Direction::Backward => tarjan_scc(self.reversed()),
+        };
+        components.reverse();
+        components
+    }
 }
 
 impl<SemiLatticeType, BlockType, Weight> BlockUpdate<SemiLatticeType>
@@ -101,15 +182,15 @@ where
     BlockType: FullBlock<SemiLatticeType, BlockType, Weight>,
 {
     /// The `initialize` function initializes the graph by setting the `out` or `in` values of each node
-    /// based on the given direction.
-    /// 
+    /// based on the given direction. Nodes unreachable from the entry are initialized too, as the
+    /// solvers visit every strongly connected component.
+    ///
     /// Arguments:
-    /// 
+    ///
     /// * `direction`: The `direction` parameter is an enum called `Direction`. It can have two possible
     /// values: `Forward` or `Backward`. This parameter determines the direction in which the update
     /// operation will be performed.
     fn initialize(&mut self, direction: Direction) {
-        let mut bfs = Bfs::new(&self.graph, self.entry);
         match direction {
             Direction::Forward => {
                 let init_out = BlockType::entry_out(self);
@@ -126,81 +207,251 @@ where
                     .set_in(init_in);
             }
         }
-        while let Some(nx) = bfs.next(&self.graph) {
+        let nodes: Vec<_> = self.graph.node_indices().collect();
+        for nx in nodes {
             let val = BlockType::top(self);
             match direction {
-                Direction::Forward => self.graph.node_weight_mut(nx).unwrap().set_out(val),
-                Direction::Backward => self.graph.node_weight_mut(nx).unwrap().set_in(val),
+                Direction::Forward if nx != self.entry => {
+                    self.graph.node_weight_mut(nx).unwrap().set_out(val)
+                }
+                Direction::Backward if nx != self.exit => {
+                    self.graph.node_weight_mut(nx).unwrap().set_in(val)
+                }
+                _ => {}
             }
         }
     }
 
     /// The `update` function performs a breadth-first search on a graph and updates the input and
     /// output values of each node based on the given direction.
-    /// 
+    ///
     /// Arguments:
-    /// 
+    ///
     /// * `direction`: The `direction` parameter is an enum called `Direction`. It can have two possible
     /// values: `Forward` or `Backward`. This parameter determines the direction in which the update
     /// operation will be performed.
-    /// 
+    ///
     /// Returns:
-    /// 
+    ///
     /// a boolean value indicating whether any changes were made during the update process.
     fn update(&mut self, direction: Direction) -> bool {
         let mut bfs = Bfs::new(&self.graph, self.entry);
         let mut changed = false;
         while let Some(nx) = bfs.next(&self.graph) {
-            let (res_in, res_out) = match direction {
-                Direction::Forward => {
-                    let res_in = self
-                        .graph
-                        .neighbors_directed(nx, petgraph::Direction::Incoming)
-                        .fold(BlockType::top(self), |cur, neighbor_index| {
-                            let block = &self.graph[neighbor_index];
-                            cur.meet(block.get_out())
-                        });
-                    let res_out = self.graph[nx].transfer_forward(&res_in, self, nx);
-                    (res_in, res_out)
+            changed |= update_node(self, nx, direction);
+        }
+        changed
+    }
+
+    /// The `converge` function solves the data flow equations one strongly connected component
+    /// at a time, in topological order along the direction of the analysis, so backward ones
+    /// walk the reversed view of the graph from the exit. Acyclic components are transferred
+    /// once; the others are swept until they stop changing, which only takes more than one
+    /// sweep inside loops.
+    ///
+    /// Arguments:
+    ///
+    /// * `direction`: The `direction` parameter is of type `Direction`. It is used to specify the
+    ///   direction in which the update operation should be performed.
+    ///
+    /// Returns:
+    ///
+    /// the number of block transfers performed.
+    fn converge(&mut self, direction: Direction) -> usize {
+        self.converge_capped(direction, usize::MAX)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// The `converge_capped` function solves the data flow equations like `converge`, but
+    /// gives up on a loop once it has been swept `max_sweeps` times and still changes.
+    ///
+    /// Arguments:
+    ///
+    /// * `direction`: The direction in which the update operation should be performed.
+    /// * `max_sweeps`: The most sweeps each strongly connected component may take.
+    ///
+    /// Returns:
+    ///
+    /// the number of block transfers performed, or the last block of the loop that changed
+    /// in its final sweep.
+    fn converge_capped(
+        &mut self,
+        direction: Direction,
+        max_sweeps: usize,
+    ) -> Result<usize, ConvergenceError> {
+        self.initialize(direction);
+        let components = self.strongly_connected_components_along(direction);
+        let mut transfers = 0;
+        for component in components {
+            let is_cyclic =
+                component.len() > 1 || self.graph.find_edge(component[0], component[0]).is_some();
+            let mut sweeps = 0;
+            let mut changed = true;
+            while changed {
+                changed = false;
+                sweeps += 1;
+                let mut last_changed = None;
+                for &nx in &component {
+                    if !is_boundary(self, nx, direction) {
+                        transfers += 1;
+                        if update_node(self, nx, direction) {
+                            changed = true;
+                            last_changed = Some(nx);
+                        }
+                    }
                 }
-                Direction::Backward => {
-                    let res_out = self
-                        .graph
-                        .neighbors_directed(nx, petgraph::Direction::Outgoing)
-                        .fold(BlockType::top(self), |cur, neighbor_index| {
-                            let block = &self.graph[neighbor_index];
-                            cur.meet(block.get_in())
-                        });
-                    let res_in = self.graph[nx].transfer_backward(&res_out, self, nx);
-                    (res_in, res_out)
+                changed &= is_cyclic;
+                if let Some(node) = last_changed.filter(|_| changed && sweeps >= max_sweeps) {
+                    return Err(ConvergenceError::NotConverged { node, sweeps });
                 }
-            };
-
-            changed = changed
-                || res_in != *self.graph[nx].get_in()
-                || res_out != *self.graph[nx].get_out();
-            self.graph[nx].set_in(res_in);
-            self.graph[nx].set_out(res_out);
+            }
         }
-        changed
+        Ok(transfers)
     }
 
-    /// The `converge` function iteratively updates the state of an object until no further changes
-    /// occur.
-    /// 
+    /// The `converge_uniform` function iteratively updates every block in breadth-first order
+    /// until no further changes occur, without regard for the graph's structure.
+    ///
     /// Arguments:
-    /// 
+    ///
     /// * `direction`: The `direction` parameter is of type `Direction`. It is used to specify the
-    /// direction in which the update operation should be performed.
-    fn converge(&mut self, direction: Direction) {
+    ///   direction in which the update operation should be performed.
+    ///
+    /// Returns:
+    ///
+    /// the number of block transfers performed.
+    fn converge_uniform(&mut self, direction: Direction) -> usize {
         self.initialize(direction);
+        let mut transfers = 0;
         let mut changed = true;
         while changed {
             changed = self.update(direction);
+            transfers += Bfs::new(&self.graph, self.entry)
+                .iter(&self.graph)
+                .filter(|nx| !is_boundary(self, *nx, direction))
+                .count();
         }
+        transfers
     }
 }
 
+impl<A, B, BlockType, Weight> JointBlockUpdate<A, B> for DataFlowGraph<BlockType, Weight>
+where
+    A: SemiLattice,
+    B: SemiLattice,
+    BlockType: FullBlock<A, BlockType, Weight> + FullBlock<B, BlockType, Weight>,
+{
+    /// The `converge_jointly` function solves both analyses from a single worklist. Every
+    /// block, unreachable ones included, starts on it in topological order from the entry,
+    /// and each one taken off it is transferred for both analyses. A block whose value changes for one of them puts
+    /// the blocks it flows into, along that analysis' direction, back on the worklist.
+    ///
+    /// Arguments:
+    ///
+    /// * `directions`: The directions of `A` and of `B`, which may differ.
+    ///
+    /// Returns:
+    ///
+    /// the number of block transfers performed, for either analysis.
+    fn converge_jointly(&mut self, (a, b): (Direction, Direction)) -> usize {
+        <Self as BlockUpdate<A>>::initialize(self, a);
+        <Self as BlockUpdate<B>>::initialize(self, b);
+        let mut worklist: VecDeque<NodeIndex<u32>> = self
+            .strongly_connected_components_along(Direction::Forward)
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut queued: HashSet<NodeIndex<u32>> = worklist.iter().copied().collect();
+        let mut transfers = 0;
+        while let Some(nx) = worklist.pop_front() {
+            queued.remove(&nx);
+            let mut changed = vec![];
+            for (direction, update) in [
+                (
+                    a,
+                    update_node::<A, BlockType, Weight> as fn(&mut Self, _, _) -> bool,
+                ),
+                (b, update_node::<B, BlockType, Weight>),
+            ] {
+                if !is_boundary(self, nx, direction) {
+                    transfers += 1;
+                    if update(self, nx, direction) {
+                        changed.push(direction);
+                    }
+                }
+            }
+            for direction in changed {
+                let along = match direction {
+                    Direction::Forward => Outgoing,
+                    Direction::Backward => Incoming,
+                };
+                for next in self.graph.neighbors_directed(nx, along) {
+                    if queued.insert(next) {
+                        worklist.push_back(next);
+                    }
+                }
+            }
+        }
+        transfers
+    }
+}
+
+/// The boundary node keeps the value given by `initialize`
+fn is_boundary<BlockType: Block, Weight>(
+    graph: &DataFlowGraph<BlockType, Weight>,
+    nx: NodeIndex<u32>,
+    direction: Direction,
+) -> bool {
+    match direction {
+        Direction::Forward => nx == graph.entry,
+        Direction::Backward => nx == graph.exit,
+    }
+}
+
+/// Recomputes the in and out values of `nx` from its neighbours and returns whether they changed.
+fn update_node<SemiLatticeType, BlockType, Weight>(
+    graph: &mut DataFlowGraph<BlockType, Weight>,
+    nx: NodeIndex<u32>,
+    direction: Direction,
+) -> bool
+where
+    SemiLatticeType: SemiLattice,
+    BlockType: FullBlock<SemiLatticeType, BlockType, Weight>,
+{
+    if is_boundary(graph, nx, direction) {
+        return false;
+    }
+    let (res_in, res_out) = match direction {
+        Direction::Forward => {
+            let res_in = graph
+                .graph
+                .neighbors_directed(nx, petgraph::Direction::Incoming)
+                .fold(BlockType::top(graph), |cur, neighbor_index| {
+                    let block = &graph.graph[neighbor_index];
+                    cur.meet(block.get_out())
+                });
+            let res_out = graph.graph[nx].transfer_forward(&res_in, graph, nx);
+            (res_in, res_out)
+        }
+        Direction::Backward => {
+            let res_out = graph
+                .reversed()
+                .neighbors_directed(nx, petgraph::Direction::Incoming)
+                .fold(BlockType::top(graph), |cur, neighbor_index| {
+                    let block = &graph.graph[neighbor_index];
+                    cur.meet(block.get_in())
+                });
+            let res_in = graph.graph[nx].transfer_backward(&res_out, graph, nx);
+            (res_in, res_out)
+        }
+    };
+
+    let changed = res_in != *graph.graph[nx].get_in() || res_out != *graph.graph[nx].get_out();
+    graph.graph[nx].set_in(res_in);
+    graph.graph[nx].set_out(res_out);
+    changed
+}
+
 impl<BlockType: Block + Display, Weight: Display> Display for DataFlowGraph<BlockType, Weight> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         writeln!(
@@ -215,8 +466,15 @@ impl<BlockType: Block + Display, Weight: Display> Display for DataFlowGraph<Bloc
         });
         writeln!(f, "Edges:").expect("");
         self.graph.node_indices().for_each(|n| {
-            self.graph.neighbors_directed(n, Outgoing).for_each(|m| {
-                writeln!(f, "{:} -> {:}", n.index(), m.index()).expect("");
+            self.graph.edges_directed(n, Outgoing).for_each(|e| {
+                writeln!(
+                    f,
+                    "{:} -> {:} ({:?})",
+                    n.index(),
+                    e.target().index(),
+                    e.weight()
+                )
+                .expect("");
             });
         });
         Ok(())
@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+
+use petgraph::visit::{Bfs, Walker};
+use petgraph::Direction::Incoming;
+
+use crate::ir::{AddressMarker, BlockNameId, Function, JumpOperation, IR};
+
+/// Simplifies the control flow of `function` by folding constant branches, removing
+/// unreachable blocks and merging chains of blocks, over and over until none of them
+/// changes anything: folding a branch can leave a block unreachable, and removing a
+/// block can leave its successor with a single predecessor to merge into.
+///
+/// Returns whether anything changed.
+pub fn simplify_cfg(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let folded = fold_constant_branches(function);
+        let removed = remove_unreachable_blocks(function);
+        let merged = merge_blocks(function);
+        if !(folded || removed || merged) {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Replaces each branch on a constant, or to the same block either way, with a jump to
+/// the block it always goes to.
+///
+/// Returns whether any branch was replaced.
+pub fn fold_constant_branches(function: &mut Function) -> bool {
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let target = match function.blocks.get_from_id(id).unwrap().terminator() {
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _)
+                if true_addr.block_id == false_addr.block_id =>
+            {
+                true_addr.block_id
+            }
+            IR::Jump(JumpOperation::Branch(cond, true_addr, false_addr), _) => {
+                match function
+                    .const_value(*cond)
+                    .and_then(|value| value.is_truthy())
+                {
+                    Some(true) => true_addr.block_id,
+                    Some(false) => false_addr.block_id,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        let info = *block.terminator.info();
+        block.terminator = IR::Jump(
+            JumpOperation::Unconditional(AddressMarker::new(target)),
+            info,
+        );
+        changed = true;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
+
+/// Removes the blocks no path from the entry reaches.
+///
+/// Returns whether any block was removed.
+pub fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let graph = &function.graph;
+    let reachable: HashSet<_> = Bfs::new(&graph.graph, graph.entry)
+        .iter(&graph.graph)
+        .collect();
+    let unreachable: Vec<BlockNameId> = graph
+        .weight
+        .block_nodes
+        .iter()
+        .filter(|(_, node)| !reachable.contains(node))
+        .map(|(name_id, _)| *name_id)
+        .collect();
+    for name_id in &unreachable {
+        let name = function.blocks.get_name(name_id).unwrap().clone();
+        function.blocks.unbind(&name);
+    }
+    if !unreachable.is_empty() {
+        function.build_graph();
+    }
+    !unreachable.is_empty()
+}
+
+/// Moves each block only entered by a jump or fallthrough from one other block to the
+/// end of that block, which takes over its terminator. A `next` taken over becomes a
+/// jump to the block it fell into, or `end` after the last block.
+///
+/// Returns whether any block was merged.
+pub fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    while let Some((from, to)) = find_chain(function) {
+        let name_ids = function.block_name_ids();
+        let to_id = function.blocks.get_id_from_name_id(&to).unwrap();
+        let (irs, terminator) = {
+            let block = function.blocks.get_from_id(to_id).unwrap();
+            let terminator = match block.terminator() {
+                IR::Jump(JumpOperation::Next, info) => {
+                    let position = name_ids.iter().position(|name_id| *name_id == to);
+                    let jump = match position.and_then(|position| name_ids.get(position + 1)) {
+                        Some(next) => JumpOperation::Unconditional(AddressMarker::new(*next)),
+                        None => JumpOperation::End,
+                    };
+                    IR::Jump(jump, *info)
+                }
+                terminator => terminator.clone(),
+            };
+            (block.irs().to_vec(), terminator)
+        };
+        let from_id = function.blocks.get_id_from_name_id(&from).unwrap();
+        let mut block = function.blocks.get_mut_from_id(from_id).unwrap();
+        block.irs_mut().extend(irs);
+        block.terminator = terminator;
+        drop(block);
+        let name = function.blocks.get_name(&to).unwrap().clone();
+        function.blocks.unbind(&name);
+        function.build_graph();
+        changed = true;
+    }
+    changed
+}
+
+/// A block and the block it alone jumps or falls into, which nothing else enters.
+fn find_chain(function: &Function) -> Option<(BlockNameId, BlockNameId)> {
+    let graph = &function.graph;
+    let name_ids = function.block_name_ids();
+    for (i, from) in name_ids.iter().enumerate() {
+        let id = function.blocks.get_id_from_name_id(from).unwrap();
+        let to = match function.blocks.get_from_id(id).unwrap().terminator() {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => addr.block_id,
+            IR::Jump(JumpOperation::Next, _) => match name_ids.get(i + 1) {
+                Some(next) => *next,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let node = graph.weight.block_nodes[&to];
+        if to != *from
+            && to != name_ids[0]
+            && graph.graph.neighbors_directed(node, Incoming).count() == 1
+        {
+            return Some((*from, to));
+        }
+    }
+    None
+}
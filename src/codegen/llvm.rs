@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ir::{
+    ops::{BinaryOp, CompareType, DataType, ScalarType, UnaryOp},
+    type_check::infer_types,
+    BlockNameId, CommandOperation, Function, FunctionNameId, JumpOperation, Operation, SpaceNameId,
+    Value, IR,
+};
+
+use super::{callee_symbol, constant, symbol};
+
+/// The LLVM type of `data_type`.
+pub fn llvm_type(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Bool => "i1".to_string(),
+        DataType::F64 => "double".to_string(),
+        DataType::Str => "ptr".to_string(),
+        DataType::Void => "void".to_string(),
+        DataType::Array(element, len) => format!("[{} x {}]", len, llvm_type(element)),
+        DataType::Struct(fields) => {
+            let fields: Vec<_> = fields.iter().map(llvm_type).collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        int => format!("i{}", int.int_type().unwrap().bits()),
+    }
+}
+
+/// Emits `function` as a textual LLVM IR definition.
+///
+/// The IR here is not in SSA form, so every local gets an `alloca` slot that is
+/// loaded before each read and stored after each write; LLVM's `mem2reg` turns
+/// them back into registers. Once the IR itself is converted to SSA, its values can
+/// be emitted directly instead. Spaces whose type cannot be inferred are `i64`.
+/// Slots are typed `ptr`, so the output needs LLVM 15 or later.
+pub fn emit_llvm_ir(function: &Function) -> String {
+    Emitter {
+        function,
+        types: infer_types(function),
+        temps: 0,
+        params: vec![],
+        out: String::new(),
+    }
+    .emit()
+}
+
+struct Emitter<'a> {
+    function: &'a Function,
+    types: HashMap<SpaceNameId, DataType>,
+    temps: usize,
+    /// Operands of the `param`s since the last call
+    params: Vec<SpaceNameId>,
+    out: String,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(mut self) -> String {
+        let params: Vec<_> = self
+            .function
+            .params
+            .iter()
+            .map(|param| format!("{} %arg{}", llvm_type(&self.type_of(*param)), param))
+            .collect();
+        self.line(&format!(
+            "define {} @{}({}) {{",
+            llvm_type(&self.function.return_type),
+            symbol(&self.function.name),
+            params.join(", ")
+        ));
+        // The entry block cannot be a branch target, so slots are set up in a block of their own
+        self.line("entry:");
+        let mut locals = self.function.params.clone();
+        for (_, _, block) in self.function.blocks.iter() {
+            let Some(block) = block else {
+                continue;
+            };
+            for ir in block.irs().iter().chain(std::iter::once(&block.terminator)) {
+                locals.extend(ir.used_spaces().into_iter().chain(ir.defined_space()));
+            }
+        }
+        locals.retain(|space| self.function.is_local(*space));
+        locals.sort();
+        locals.dedup();
+        for space in locals {
+            let ty = llvm_type(&self.type_of(space));
+            self.op(&format!("%s{space} = alloca {ty}"));
+        }
+        for param in self.function.params.clone() {
+            let ty = llvm_type(&self.type_of(param));
+            self.op(&format!("store {ty} %arg{param}, ptr %s{param}"));
+        }
+        let block_name_ids = self.function.block_name_ids();
+        if let Some(first) = block_name_ids.first() {
+            self.op(&format!("br label %{}", self.label(*first)));
+        }
+        for (i, name_id) in block_name_ids.iter().enumerate() {
+            let id = self.function.blocks.get_id_from_name_id(name_id).unwrap();
+            let block = self.function.blocks.get_from_id(id).unwrap();
+            self.line(&format!("{}:", self.label(*name_id)));
+            for ir in block.irs().iter() {
+                self.instruction(ir);
+            }
+            self.terminator(&block.terminator, block_name_ids.get(i + 1).copied());
+        }
+        self.line("}");
+        self.out
+    }
+
+    fn instruction(&mut self, ir: &IR) {
+        let (dst, value) = match ir {
+            IR::Assignment(dst, op, _) => (*dst, self.operation(*dst, op)),
+            IR::Command(CommandOperation::Store(dst, src), _) => (*dst, self.read(*src)),
+            IR::Jump(..) => unreachable!("Terminator inside a block"),
+        };
+        self.write(dst, &value);
+    }
+
+    /// Emits `op` and returns the value it produced.
+    fn operation(&mut self, dst: SpaceNameId, op: &Operation) -> String {
+        match op {
+            Operation::Binary(op, a, b) => {
+                let ty = self.type_of(*a);
+                let (a, b) = (self.read(*a), self.read(*b));
+                let instruction = binary_instruction(*op, &ty);
+                self.temp(&format!("{instruction} {} {a}, {b}", llvm_type(&ty)))
+            }
+            Operation::Compare(cmp, a, b) => {
+                let ty = self.type_of(*a);
+                let (a, b) = (self.read(*a), self.read(*b));
+                let instruction = compare_instruction(*cmp, &ty);
+                self.temp(&format!("{instruction} {} {a}, {b}", llvm_type(&ty)))
+            }
+            Operation::Unary(op, a) => {
+                let ty = self.type_of(*a);
+                let value = self.read(*a);
+                let llvm_ty = llvm_type(&ty);
+                match op {
+                    UnaryOp::Not if ty == DataType::Bool => {
+                        self.temp(&format!("xor i1 {value}, true"))
+                    }
+                    UnaryOp::Not => self.temp(&format!("xor {llvm_ty} {value}, -1")),
+                    UnaryOp::Negative if ty == DataType::F64 => {
+                        self.temp(&format!("fneg double {value}"))
+                    }
+                    UnaryOp::Negative => self.temp(&format!("sub {llvm_ty} 0, {value}")),
+                    UnaryOp::Load => {
+                        let pointer = self.temp(&format!("inttoptr {llvm_ty} {value} to ptr"));
+                        let loaded = llvm_type(&self.type_of(dst));
+                        self.temp(&format!("load {loaded}, ptr {pointer}"))
+                    }
+                    UnaryOp::Param => {
+                        self.params.push(*a);
+                        value
+                    }
+                    UnaryOp::Unit => value,
+                    UnaryOp::Cast(to) => self.cast(&ty, *to, &value),
+                }
+            }
+            Operation::Call(name_id) => self.call(dst, *name_id),
+            Operation::Select(cond, a, b) => {
+                let cond_ty = self.type_of(*cond);
+                let mut cond = self.read(*cond);
+                if cond_ty != DataType::Bool {
+                    cond = self.temp(&format!("icmp ne {} {cond}, 0", llvm_type(&cond_ty)));
+                }
+                let ty = llvm_type(&self.type_of(*a));
+                let (a, b) = (self.read(*a), self.read(*b));
+                self.temp(&format!("select i1 {cond}, {ty} {a}, {ty} {b}"))
+            }
+        }
+    }
+
+    fn cast(&mut self, from: &DataType, to: ScalarType, value: &str) -> String {
+        let to_type: DataType = to.into();
+        let (from_llvm, to_llvm) = (llvm_type(from), llvm_type(&to_type));
+        let instruction = match (from.scalar_type(), to) {
+            (Some(ScalarType::Int(from)), ScalarType::Int(to)) if from.bits() > to.bits() => {
+                "trunc"
+            }
+            (Some(ScalarType::Int(from)), ScalarType::Int(to)) if from.bits() < to.bits() => {
+                if from.is_signed() {
+                    "sext"
+                } else {
+                    "zext"
+                }
+            }
+            (Some(ScalarType::Int(from)), ScalarType::F64) if from.is_signed() => "sitofp",
+            (Some(ScalarType::Int(_)), ScalarType::F64) => "uitofp",
+            (Some(ScalarType::F64), ScalarType::Int(to)) if to.is_signed() => "fptosi",
+            (Some(ScalarType::F64), ScalarType::Int(_)) => "fptoui",
+            (Some(ScalarType::Bool), ScalarType::Int(_)) => "zext",
+            (Some(ScalarType::Bool), ScalarType::F64) => "uitofp",
+            (Some(ScalarType::Int(_)), ScalarType::Bool) => {
+                return self.temp(&format!("icmp ne {from_llvm} {value}, 0"))
+            }
+            (Some(ScalarType::F64), ScalarType::Bool) => {
+                return self.temp(&format!("fcmp une double {value}, 0.0"))
+            }
+            _ => return value.to_string(),
+        };
+        self.temp(&format!("{instruction} {from_llvm} {value} to {to_llvm}"))
+    }
+
+    fn call(&mut self, dst: SpaceNameId, name_id: FunctionNameId) -> String {
+        let args: Vec<_> = std::mem::take(&mut self.params)
+            .into_iter()
+            .map(|arg| format!("{} {}", llvm_type(&self.type_of(arg)), self.read(arg)))
+            .collect();
+        let ty = llvm_type(&self.type_of(dst));
+        let callee = callee_symbol(self.function, name_id);
+        self.temp(&format!("call {ty} @{callee}({})", args.join(", ")))
+    }
+
+    fn terminator(&mut self, ir: &IR, next: Option<BlockNameId>) {
+        let IR::Jump(jump, _) = ir else {
+            unreachable!("Block does not end with a jump")
+        };
+        match jump {
+            JumpOperation::Unconditional(target) => {
+                self.op(&format!("br label %{}", self.label(target.block_id)))
+            }
+            JumpOperation::Branch(cond, true_target, false_target) => {
+                let ty = self.type_of(*cond);
+                let mut value = self.read(*cond);
+                if ty != DataType::Bool {
+                    value = self.temp(&format!("icmp ne {} {value}, 0", llvm_type(&ty)));
+                }
+                self.op(&format!(
+                    "br i1 {value}, label %{}, label %{}",
+                    self.label(true_target.block_id),
+                    self.label(false_target.block_id)
+                ));
+            }
+            JumpOperation::Next if next.is_some() => {
+                self.op(&format!("br label %{}", self.label(next.unwrap())))
+            }
+            JumpOperation::Ret(v) => {
+                let value = self.read(*v);
+                self.op(&format!(
+                    "ret {} {value}",
+                    llvm_type(&self.function.return_type)
+                ));
+            }
+            JumpOperation::Next | JumpOperation::End => {
+                if self.function.return_type == DataType::Void {
+                    self.op("ret void")
+                } else {
+                    self.op("unreachable")
+                }
+            }
+        }
+    }
+
+    /// The LLVM value of `space`, loading it from its slot if it is not a constant.
+    fn read(&mut self, space: SpaceNameId) -> String {
+        match constant(self.function, space) {
+            Some(Value::Int(int)) => int.value.to_string(),
+            Some(Value::Bool(b)) => b.to_string(),
+            // Hexadecimal is the only exact notation for any double
+            Some(Value::Float(float)) => format!("0x{:016X}", float.value.to_bits()),
+            Some(value) => panic!("{value:?} cannot be an LLVM operand"),
+            None => {
+                let ty = llvm_type(&self.type_of(space));
+                let slot = self.slot(space);
+                self.temp(&format!("load {ty}, ptr {slot}"))
+            }
+        }
+    }
+
+    fn write(&mut self, space: SpaceNameId, value: &str) {
+        let ty = llvm_type(&self.type_of(space));
+        let slot = self.slot(space);
+        self.op(&format!("store {ty} {value}, ptr {slot}"));
+    }
+
+    fn slot(&self, space: SpaceNameId) -> String {
+        if self.function.is_local(space) {
+            format!("%s{space}")
+        } else {
+            format!("@g{space}")
+        }
+    }
+
+    fn type_of(&self, space: SpaceNameId) -> DataType {
+        self.types.get(&space).cloned().unwrap_or(DataType::I64)
+    }
+
+    /// Emits `instruction` into a fresh temporary and returns its name.
+    fn temp(&mut self, instruction: &str) -> String {
+        let name = format!("%t{}", self.temps);
+        self.temps += 1;
+        self.op(&format!("{name} = {instruction}"));
+        name
+    }
+
+    fn label(&self, block: BlockNameId) -> String {
+        let name = self
+            .function
+            .blocks
+            .get_name(&block)
+            .map_or_else(|| block.to_string(), |name| symbol(name).to_string());
+        format!("b.{name}")
+    }
+
+    fn op(&mut self, instruction: &str) {
+        self.line(&format!("  {instruction}"));
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{line}").unwrap();
+    }
+}
+
+fn binary_instruction(op: BinaryOp, ty: &DataType) -> &'static str {
+    let signed = ty.int_type().is_none_or(|int_type| int_type.is_signed());
+    match (op, ty) {
+        (BinaryOp::Add, DataType::F64) => "fadd",
+        (BinaryOp::Sub, DataType::F64) => "fsub",
+        (BinaryOp::Mul, DataType::F64) => "fmul",
+        (BinaryOp::Div, DataType::F64) => "fdiv",
+        (BinaryOp::Add, _) => "add",
+        (BinaryOp::Sub, _) => "sub",
+        (BinaryOp::Mul, _) => "mul",
+        (BinaryOp::Div, _) if signed => "sdiv",
+        (BinaryOp::Div, _) => "udiv",
+        (BinaryOp::And, _) => "and",
+        (BinaryOp::Or, _) => "or",
+        (BinaryOp::Xor, _) => "xor",
+    }
+}
+
+fn compare_instruction(cmp: CompareType, ty: &DataType) -> &'static str {
+    if *ty == DataType::F64 {
+        return match cmp {
+            CompareType::Less => "fcmp olt",
+            CompareType::Greater => "fcmp ogt",
+            CompareType::Eq => "fcmp oeq",
+            CompareType::NotEq => "fcmp une",
+            CompareType::LessEqual => "fcmp ole",
+            CompareType::GreaterEqual => "fcmp oge",
+        };
+    }
+    let signed = ty.int_type().is_none_or(|int_type| int_type.is_signed());
+    match (cmp, signed) {
+        (CompareType::Eq, _) => "icmp eq",
+        (CompareType::NotEq, _) => "icmp ne",
+        (CompareType::Less, true) => "icmp slt",
+        (CompareType::Greater, true) => "icmp sgt",
+        (CompareType::LessEqual, true) => "icmp sle",
+        (CompareType::GreaterEqual, true) => "icmp sge",
+        (CompareType::Less, false) => "icmp ult",
+        (CompareType::Greater, false) => "icmp ugt",
+        (CompareType::LessEqual, false) => "icmp ule",
+        (CompareType::GreaterEqual, false) => "icmp uge",
+    }
+}
@@ -0,0 +1,26 @@
+pub mod llvm;
+pub mod wasm;
+pub mod x86_64;
+
+use crate::ir::{Function, SpaceNameId, Value};
+
+/// `name` without its sigil, e.g. `add` for `$add` and `entry` for `#entry`.
+pub(crate) fn symbol(name: &str) -> &str {
+    name.trim_start_matches(['$', '#', '@', '%'])
+}
+
+/// The value of `space` if it is a constant, see `Function::const_value`. Globals are
+/// read from memory even when they have an initializer.
+pub(crate) fn constant(function: &Function, space: SpaceNameId) -> Option<Value> {
+    function.const_value(space)
+}
+
+/// The name of the function `name_id` refers to, without its sigil.
+pub(crate) fn callee_symbol(function: &Function, name_id: crate::ir::FunctionNameId) -> String {
+    let program = function.program().borrow();
+    let name = program
+        .functions
+        .get_name(&name_id)
+        .expect("Callee is not declared");
+    symbol(name).to_string()
+}
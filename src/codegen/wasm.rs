@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ir::{
+    ops::{BinaryOp, CompareType, DataType, ScalarType, UnaryOp},
+    type_check::infer_types,
+    CommandOperation, Function, Operation, SpaceNameId, Value, IR,
+};
+use crate::relooper::{reloop, Label, StructuredNode};
+
+use super::{callee_symbol, constant, symbol};
+
+/// The WebAssembly type of `data_type`, or `None` for `void`.
+/// Integers of every width and booleans are `i64`.
+pub fn wasm_type(data_type: &DataType) -> Option<&'static str> {
+    match data_type {
+        DataType::Void => None,
+        DataType::F64 => Some("f64"),
+        DataType::Array(..) | DataType::Struct(..) => {
+            panic!("{data_type:?} has no WebAssembly value type")
+        }
+        _ => Some("i64"),
+    }
+}
+
+/// Emits `function` as a WebAssembly text format `func`.
+///
+/// Every local space becomes a local, globals are expected to be declared as
+/// `$g<id>` and `load` reads the default memory. Only scalar code is supported:
+/// integers of every width are computed as 64 bits, like the x86-64 target.
+///
+/// Control flow is structured by [`reloop`], whose loops and blocks map to
+/// `loop` and `block` and whose branches map to `br` and `br_if`.
+/// Irreducible CFGs cannot be structured and panic.
+pub fn emit_wat(function: &Function) -> String {
+    let mut emitter = Emitter {
+        function,
+        types: infer_types(function),
+        context: vec![],
+        depth: 1,
+        out: String::new(),
+    };
+    emitter.emit();
+    emitter.out
+}
+
+struct Emitter<'a> {
+    function: &'a Function,
+    types: HashMap<SpaceNameId, DataType>,
+    /// Labels of the enclosing constructs, innermost last; `None` for an `if`
+    context: Vec<Option<Label>>,
+    depth: usize,
+    out: String,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(&mut self) {
+        let mut header = format!("(func ${}", symbol(&self.function.name));
+        for param in &self.function.params {
+            let ty = wasm_type(&self.type_of(*param)).expect("Parameter is void");
+            write!(header, " (param $s{param} {ty})").unwrap();
+        }
+        if let Some(ty) = wasm_type(&self.function.return_type) {
+            write!(header, " (result {ty})").unwrap();
+        }
+        self.out.push_str(&header);
+        self.out.push('\n');
+
+        let mut locals = vec![];
+        for (_, _, block) in self.function.blocks.iter() {
+            let Some(block) = block else {
+                continue;
+            };
+            for ir in block.irs().iter() {
+                locals.extend(ir.used_spaces().into_iter().chain(ir.defined_space()));
+            }
+            locals.extend(block.terminator.used_spaces());
+        }
+        locals.retain(|space| {
+            self.function.is_local(*space) && !self.function.params.contains(space)
+        });
+        locals.sort();
+        locals.dedup();
+        for space in locals {
+            if let Some(ty) = wasm_type(&self.type_of(space)) {
+                self.line(&format!("(local $s{space} {ty})"));
+            }
+        }
+
+        let nodes = reloop(self.function).unwrap_or_else(|error| panic!("{error}"));
+        self.nodes(&nodes);
+        // Validation treats the code after an `end` as reachable
+        if self.out.ends_with("end\n") && self.function.return_type != DataType::Void {
+            self.line("unreachable");
+        }
+        self.out.push_str(")\n");
+    }
+
+    fn nodes(&mut self, nodes: &[StructuredNode]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &StructuredNode) {
+        match node {
+            StructuredNode::Code(name_id) => {
+                let id = self.function.blocks.get_id_from_name_id(name_id).unwrap();
+                let block = self.function.blocks.get_from_id(id).unwrap();
+                for ir in block.irs().iter() {
+                    self.instruction(ir);
+                }
+            }
+            StructuredNode::Loop { header, body } => {
+                self.open("loop", Some(Label::Loop(*header)));
+                self.nodes(body);
+                self.close();
+            }
+            StructuredNode::Block { follower, body } => {
+                self.open("block", Some(Label::Block(*follower)));
+                self.nodes(body);
+                self.close();
+            }
+            StructuredNode::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.condition(*cond, false);
+                self.open("if", None);
+                self.nodes(then);
+                self.depth -= 1;
+                self.line("else");
+                self.depth += 1;
+                self.nodes(otherwise);
+                self.close();
+            }
+            StructuredNode::Branch(label) => {
+                self.line(&format!("br {}", self.label_depth(*label)));
+            }
+            StructuredNode::BranchIf {
+                cond,
+                negated,
+                label,
+            } => {
+                self.condition(*cond, *negated);
+                self.line(&format!("br_if {}", self.label_depth(*label)));
+            }
+            StructuredNode::Return(Some(v)) if self.function.return_type != DataType::Void => {
+                self.push(*v);
+                self.line("return");
+            }
+            StructuredNode::Return(_) if self.function.return_type == DataType::Void => {
+                self.line("return")
+            }
+            StructuredNode::Return(_) => self.line("unreachable"),
+        }
+    }
+
+    /// The relative depth of `label` from the innermost construct.
+    fn label_depth(&self, label: Label) -> usize {
+        let position = self
+            .context
+            .iter()
+            .rposition(|containing| *containing == Some(label))
+            .expect("Branch outside of its construct");
+        self.context.len() - 1 - position
+    }
+
+    fn open(&mut self, construct: &str, label: Option<Label>) {
+        self.line(construct);
+        self.depth += 1;
+        self.context.push(label);
+    }
+
+    fn close(&mut self) {
+        self.context.pop();
+        self.depth -= 1;
+        self.line("end");
+    }
+
+    fn instruction(&mut self, ir: &IR) {
+        match ir {
+            IR::Assignment(dst, op, _) => {
+                let produced = self.operation(*dst, op);
+                if produced {
+                    self.set(*dst);
+                }
+            }
+            IR::Command(CommandOperation::Store(dst, src), _) => {
+                self.push(*src);
+                self.set(*dst);
+            }
+            IR::Jump(..) => unreachable!("Terminator inside a block"),
+        }
+    }
+
+    /// Emits `op` and returns whether it left a value on the stack.
+    fn operation(&mut self, dst: SpaceNameId, op: &Operation) -> bool {
+        match op {
+            Operation::Binary(op, a, b) => {
+                let ty = self.type_of(*a);
+                self.push(*a);
+                self.push(*b);
+                self.line(&binary_instruction(*op, &ty));
+            }
+            Operation::Compare(cmp, a, b) => {
+                let ty = self.type_of(*a);
+                self.push(*a);
+                self.push(*b);
+                self.line(&compare_instruction(*cmp, &ty));
+                self.line("i64.extend_i32_u");
+            }
+            Operation::Unary(op, a) => {
+                let ty = self.type_of(*a);
+                match op {
+                    UnaryOp::Not if ty == DataType::Bool => {
+                        self.push(*a);
+                        self.line("i64.eqz");
+                        self.line("i64.extend_i32_u");
+                    }
+                    UnaryOp::Not => {
+                        self.push(*a);
+                        self.line("i64.const -1");
+                        self.line("i64.xor");
+                    }
+                    UnaryOp::Negative if ty == DataType::F64 => {
+                        self.push(*a);
+                        self.line("f64.neg");
+                    }
+                    UnaryOp::Negative => {
+                        self.line("i64.const 0");
+                        self.push(*a);
+                        self.line("i64.sub");
+                    }
+                    UnaryOp::Load => {
+                        self.push(*a);
+                        self.line("i32.wrap_i64");
+                        let loaded = wasm_type(&self.type_of(dst)).unwrap_or("i64");
+                        self.line(&format!("{loaded}.load"));
+                    }
+                    // Arguments stay on the stack until the call consumes them
+                    UnaryOp::Param => {
+                        self.push(*a);
+                        return false;
+                    }
+                    UnaryOp::Unit => self.push(*a),
+                    UnaryOp::Cast(to) => {
+                        self.push(*a);
+                        self.cast(&ty, *to);
+                    }
+                }
+            }
+            Operation::Call(name_id) => {
+                let callee = callee_symbol(self.function, *name_id);
+                self.line(&format!("call ${callee}"));
+                return self.type_of(dst) != DataType::Void;
+            }
+            // `select` takes an i32 condition after both arms
+            Operation::Select(cond, a, b) => {
+                self.push(*a);
+                self.push(*b);
+                self.condition(*cond, false);
+                self.line("select");
+            }
+        }
+        true
+    }
+
+    fn cast(&mut self, from: &DataType, to: ScalarType) {
+        let instructions: &[&str] = match (from.scalar_type(), to) {
+            (Some(ScalarType::Int(from)), ScalarType::F64) if from.is_signed() => {
+                &["f64.convert_i64_s"]
+            }
+            (Some(ScalarType::Int(_) | ScalarType::Bool), ScalarType::F64) => {
+                &["f64.convert_i64_u"]
+            }
+            (Some(ScalarType::F64), ScalarType::Int(to)) if to.is_signed() => &["i64.trunc_f64_s"],
+            (Some(ScalarType::F64), ScalarType::Int(_)) => &["i64.trunc_f64_u"],
+            (Some(ScalarType::Int(_)), ScalarType::Bool) => {
+                &["i64.const 0", "i64.ne", "i64.extend_i32_u"]
+            }
+            (Some(ScalarType::F64), ScalarType::Bool) => {
+                &["f64.const 0", "f64.ne", "i64.extend_i32_u"]
+            }
+            _ => &[],
+        };
+        for instruction in instructions {
+            self.line(instruction);
+        }
+    }
+
+    /// Pushes `space` as an `i32` that is nonzero when it is truthy, or falsy if `negated`.
+    fn condition(&mut self, space: SpaceNameId, negated: bool) {
+        self.push(space);
+        let instructions: &[&str] = match (self.type_of(space) == DataType::F64, negated) {
+            (true, false) => &["f64.const 0", "f64.ne"],
+            (true, true) => &["f64.const 0", "f64.eq"],
+            (false, false) => &["i64.eqz", "i32.eqz"],
+            (false, true) => &["i64.eqz"],
+        };
+        for instruction in instructions {
+            self.line(instruction);
+        }
+    }
+
+    fn push(&mut self, space: SpaceNameId) {
+        let instruction = match constant(self.function, space) {
+            Some(Value::Int(int)) => format!("i64.const {}", int.value),
+            Some(Value::Bool(b)) => format!("i64.const {}", b as i64),
+            Some(Value::Float(float)) => format!("f64.const {}", float_literal(float.value)),
+            Some(value) => panic!("{value:?} is not a scalar"),
+            None if self.function.is_local(space) => format!("local.get $s{space}"),
+            None => format!("global.get $g{space}"),
+        };
+        self.line(&instruction);
+    }
+
+    fn set(&mut self, space: SpaceNameId) {
+        if self.function.is_local(space) {
+            self.line(&format!("local.set $s{space}"));
+        } else {
+            self.line(&format!("global.set $g{space}"));
+        }
+    }
+
+    fn type_of(&self, space: SpaceNameId) -> DataType {
+        self.types.get(&space).cloned().unwrap_or(DataType::I64)
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{}{line}", "  ".repeat(self.depth)).unwrap();
+    }
+}
+
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+fn binary_instruction(op: BinaryOp, ty: &DataType) -> String {
+    if *ty == DataType::F64 {
+        let name = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            op => panic!("{op:?} is not defined on f64"),
+        };
+        return format!("f64.{name}");
+    }
+    let signed = ty.int_type().is_none_or(|int_type| int_type.is_signed());
+    let name = match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div if signed => "div_s",
+        BinaryOp::Div => "div_u",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::Xor => "xor",
+    };
+    format!("i64.{name}")
+}
+
+fn compare_instruction(cmp: CompareType, ty: &DataType) -> String {
+    if *ty == DataType::F64 {
+        let name = match cmp {
+            CompareType::Less => "lt",
+            CompareType::Greater => "gt",
+            CompareType::Eq => "eq",
+            CompareType::NotEq => "ne",
+            CompareType::LessEqual => "le",
+            CompareType::GreaterEqual => "ge",
+        };
+        return format!("f64.{name}");
+    }
+    let signed = ty.int_type().is_none_or(|int_type| int_type.is_signed());
+    let suffix = if signed { "s" } else { "u" };
+    let name = match cmp {
+        CompareType::Eq => "eq".to_string(),
+        CompareType::NotEq => "ne".to_string(),
+        CompareType::Less => format!("lt_{suffix}"),
+        CompareType::Greater => format!("gt_{suffix}"),
+        CompareType::LessEqual => format!("le_{suffix}"),
+        CompareType::GreaterEqual => format!("ge_{suffix}"),
+    };
+    format!("i64.{name}")
+}
@@ -0,0 +1,290 @@
+use std::fmt::Write;
+
+use crate::ir::{
+    ops::{BinaryOp, CompareType, UnaryOp},
+    CommandOperation, Function, JumpOperation, Operation, SpaceNameId, Value, IR,
+};
+use crate::register_allocation::{Allocation, Location};
+
+use super::{callee_symbol, constant, symbol};
+
+/// Registers handed out by the allocator, in order of `Location::Register`.
+/// All of them are callee-saved so values survive calls without extra moves.
+pub const REGISTERS: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+const ARGUMENT_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+/// Emits Intel-syntax x86-64 assembly for `function` following the System V calling convention.
+///
+/// `allocation` places every local; `Location::Register(i)` is `REGISTERS[i]` and
+/// spill slots live below the saved registers in the frame. Only integer code is
+/// supported, and every integer is treated as 64 bits wide. Compares are signed.
+pub fn emit_x86_64(function: &Function, allocation: &Allocation) -> String {
+    Emitter {
+        function,
+        allocation,
+        saved: saved_registers(allocation),
+        params: vec![],
+        out: String::new(),
+    }
+    .emit()
+}
+
+struct Emitter<'a> {
+    function: &'a Function,
+    allocation: &'a Allocation,
+    saved: Vec<&'static str>,
+    /// Operands of the `param`s since the last call
+    params: Vec<SpaceNameId>,
+    out: String,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(mut self) -> String {
+        let name = symbol(&self.function.name).to_string();
+        self.line(&format!("    .globl {name}"));
+        self.line(&format!("{name}:"));
+        self.prologue();
+        let block_name_ids = self.function.block_name_ids();
+        for (i, name_id) in block_name_ids.iter().enumerate() {
+            let id = self.function.blocks.get_id_from_name_id(name_id).unwrap();
+            let block = self.function.blocks.get_from_id(id).unwrap();
+            self.line(&format!("{}:", self.label(*name_id)));
+            for ir in block.irs().iter() {
+                self.instruction(ir);
+            }
+            self.terminator(&block.terminator, block_name_ids.get(i + 1).copied());
+        }
+        self.line(&format!("{}:", self.return_label()));
+        self.epilogue();
+        self.out
+    }
+
+    fn prologue(&mut self) {
+        self.op("push rbp");
+        self.op("mov rbp, rsp");
+        for register in self.saved.clone() {
+            self.op(&format!("push {register}"));
+        }
+        // Keep rsp 16-byte aligned at calls
+        let mut frame = 8 * self.allocation.spill_slots;
+        if !(self.saved.len() * 8 + frame).is_multiple_of(16) {
+            frame += 8;
+        }
+        if frame > 0 {
+            self.op(&format!("sub rsp, {frame}"));
+        }
+        for (i, param) in self.function.params.iter().enumerate() {
+            if !self.allocation.locations.contains_key(param) {
+                continue;
+            }
+            match ARGUMENT_REGISTERS.get(i) {
+                Some(register) => self.store(*param, register),
+                None => {
+                    let offset = 16 + 8 * (i - ARGUMENT_REGISTERS.len());
+                    self.op(&format!("mov rax, qword ptr [rbp + {offset}]"));
+                    self.store(*param, "rax");
+                }
+            }
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.op(&format!("lea rsp, [rbp - {}]", 8 * self.saved.len()));
+        for register in self.saved.clone().iter().rev() {
+            self.op(&format!("pop {register}"));
+        }
+        self.op("pop rbp");
+        self.op("ret");
+    }
+
+    fn instruction(&mut self, ir: &IR) {
+        match ir {
+            IR::Assignment(dst, op, _) => {
+                match op {
+                    Operation::Binary(op, a, b) => {
+                        self.load("rax", *a);
+                        self.load("rcx", *b);
+                        match op {
+                            BinaryOp::Div => {
+                                self.op("cqo");
+                                self.op("idiv rcx");
+                            }
+                            op => self.op(&format!("{} rax, rcx", binary_mnemonic(*op))),
+                        }
+                    }
+                    Operation::Compare(cmp, a, b) => {
+                        self.load("rax", *a);
+                        self.load("rcx", *b);
+                        self.op("cmp rax, rcx");
+                        self.op(&format!("set{} al", condition_code(*cmp)));
+                        self.op("movzx eax, al");
+                    }
+                    Operation::Unary(op, a) => {
+                        self.load("rax", *a);
+                        match op {
+                            UnaryOp::Not => self.op("not rax"),
+                            UnaryOp::Negative => self.op("neg rax"),
+                            UnaryOp::Load => self.op("mov rax, qword ptr [rax]"),
+                            UnaryOp::Param => self.params.push(*a),
+                            UnaryOp::Unit | UnaryOp::Cast(_) => {}
+                        }
+                    }
+                    Operation::Call(name_id) => self.call(*name_id),
+                    Operation::Select(cond, a, b) => {
+                        self.load("rax", *b);
+                        self.load("rcx", *a);
+                        self.load("rdx", *cond);
+                        self.op("test rdx, rdx");
+                        self.op("cmovne rax, rcx");
+                    }
+                }
+                self.store(*dst, "rax");
+            }
+            IR::Command(CommandOperation::Store(dst, src), _) => {
+                self.load("rax", *src);
+                self.store(*dst, "rax");
+            }
+            IR::Jump(..) => unreachable!("Terminator inside a block"),
+        }
+    }
+
+    fn call(&mut self, name_id: crate::ir::FunctionNameId) {
+        let args = std::mem::take(&mut self.params);
+        let stack_args = args.len().saturating_sub(ARGUMENT_REGISTERS.len());
+        if stack_args % 2 == 1 {
+            self.op("sub rsp, 8");
+        }
+        for arg in args.iter().skip(ARGUMENT_REGISTERS.len()).rev() {
+            self.load("rax", *arg);
+            self.op("push rax");
+        }
+        for (arg, register) in args.iter().zip(ARGUMENT_REGISTERS) {
+            self.load(register, *arg);
+        }
+        self.op(&format!("call {}", callee_symbol(self.function, name_id)));
+        let pushed = stack_args + stack_args % 2;
+        if pushed > 0 {
+            self.op(&format!("add rsp, {}", 8 * pushed));
+        }
+    }
+
+    fn terminator(&mut self, ir: &IR, next: Option<crate::ir::BlockNameId>) {
+        let IR::Jump(jump, _) = ir else {
+            unreachable!("Block does not end with a jump")
+        };
+        match jump {
+            JumpOperation::Unconditional(target) if Some(target.block_id) == next => {}
+            JumpOperation::Unconditional(target) => {
+                self.op(&format!("jmp {}", self.label(target.block_id)))
+            }
+            JumpOperation::Branch(cond, true_target, false_target) => {
+                self.load("rax", *cond);
+                self.op("test rax, rax");
+                self.op(&format!("jne {}", self.label(true_target.block_id)));
+                if Some(false_target.block_id) != next {
+                    self.op(&format!("jmp {}", self.label(false_target.block_id)));
+                }
+            }
+            JumpOperation::Next if next.is_some() => {}
+            JumpOperation::Ret(v) => {
+                self.load("rax", *v);
+                self.op(&format!("jmp {}", self.return_label()));
+            }
+            JumpOperation::Next | JumpOperation::End => {
+                self.op(&format!("jmp {}", self.return_label()))
+            }
+        }
+    }
+
+    /// Loads `space` into `register`.
+    fn load(&mut self, register: &str, space: SpaceNameId) {
+        let operand = match constant(self.function, space) {
+            Some(Value::Int(int)) => int.value.to_string(),
+            Some(Value::Bool(b)) => (b as i64).to_string(),
+            Some(value) => panic!("{value:?} is not an integer"),
+            None => self.location(space),
+        };
+        if operand != register {
+            self.op(&format!("mov {register}, {operand}"));
+        }
+    }
+
+    fn store(&mut self, space: SpaceNameId, register: &str) {
+        let location = self.location(space);
+        if location != register {
+            self.op(&format!("mov {location}, {register}"));
+        }
+    }
+
+    fn location(&self, space: SpaceNameId) -> String {
+        match self.allocation.locations.get(&space) {
+            Some(Location::Register(register)) => REGISTERS[*register].to_string(),
+            Some(Location::Spill(slot)) => {
+                format!("qword ptr [rbp - {}]", 8 * (self.saved.len() + slot + 1))
+            }
+            // Globals live in the data section
+            None => format!("qword ptr [rip + g{space}]"),
+        }
+    }
+
+    fn label(&self, block: crate::ir::BlockNameId) -> String {
+        let name = self
+            .function
+            .blocks
+            .get_name(&block)
+            .map_or_else(|| block.to_string(), |name| symbol(name).to_string());
+        format!(".L{}_{}", symbol(&self.function.name), name)
+    }
+
+    fn return_label(&self) -> String {
+        format!(".L{}_return", symbol(&self.function.name))
+    }
+
+    fn op(&mut self, instruction: &str) {
+        self.line(&format!("    {instruction}"));
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{line}").unwrap();
+    }
+}
+
+fn saved_registers(allocation: &Allocation) -> Vec<&'static str> {
+    let mut used: Vec<usize> = allocation
+        .locations
+        .values()
+        .filter_map(|location| match location {
+            Location::Register(register) => Some(*register),
+            Location::Spill(_) => None,
+        })
+        .collect();
+    used.sort();
+    used.dedup();
+    used.into_iter()
+        .map(|register| REGISTERS[register])
+        .collect()
+}
+
+fn binary_mnemonic(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "imul",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::Xor => "xor",
+        BinaryOp::Div => unreachable!("Division needs rdx"),
+    }
+}
+
+fn condition_code(cmp: CompareType) -> &'static str {
+    match cmp {
+        CompareType::Less => "l",
+        CompareType::Greater => "g",
+        CompareType::Eq => "e",
+        CompareType::NotEq => "ne",
+        CompareType::LessEqual => "le",
+        CompareType::GreaterEqual => "ge",
+    }
+}
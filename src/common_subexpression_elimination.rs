@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use crate::effects::effect_tokens;
+use crate::escape::escaped_spaces;
+use crate::ir::{
+    ops::{BinaryOp, CompareType, UnaryOp},
+    CommandOperation, Function, FunctionNameId, Operation, SpaceNameId, IR,
+};
+use crate::mem2reg::shared_spaces;
+
+/// An operation by what it computes from its operands.
+#[derive(Debug, PartialEq)]
+enum Expression {
+    Binary(BinaryOp, SpaceNameId, SpaceNameId),
+    Compare(CompareType, SpaceNameId, SpaceNameId),
+    Unary(UnaryOp, SpaceNameId),
+    Select(SpaceNameId, SpaceNameId, SpaceNameId),
+    /// A call to a pure function, with the operands of its `param`s
+    Call(FunctionNameId, Vec<SpaceNameId>),
+}
+
+impl Expression {
+    fn operands(&self) -> Vec<SpaceNameId> {
+        match self {
+            Expression::Binary(_, a, b) | Expression::Compare(_, a, b) => vec![*a, *b],
+            Expression::Unary(_, a) => vec![*a],
+            Expression::Select(cond, a, b) => vec![*cond, *a, *b],
+            Expression::Call(_, args) => args.clone(),
+        }
+    }
+}
+
+/// Replaces each assignment recomputing an expression already held by a local
+/// earlier in the same block with a copy of that local.
+///
+/// An expression stays available until one of its operands or the local holding it
+/// is assigned again. One that reads a global or a local passed to a call is only
+/// available under the effect token it was computed at, as any store or call to a
+/// function not marked pure may change it. Calls to pure functions with the same
+/// arguments are expressions too: a repeated one becomes a copy and its `param`s are
+/// removed. `load`s and expressions over spaces sharing storage with others are never
+/// reused. Instructions are only replaced and never moved, so effects keep their order.
+///
+/// Returns whether any instruction was replaced.
+pub fn eliminate_common_subexpressions(function: &mut Function) -> bool {
+    let shared = shared_spaces(function);
+    let escaped = escaped_spaces(function);
+    let program = function.program().clone();
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let mut irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        let tokens = effect_tokens(function, &irs);
+        // Each expression with its holder, and the token it is tied to if any
+        let mut available: Vec<(Expression, SpaceNameId, Option<usize>)> = vec![];
+        let mut params = vec![];
+        let mut removed = HashSet::new();
+        for (index, ir) in irs.iter_mut().enumerate() {
+            let (dst, info, expression) = match ir {
+                IR::Assignment(dst, op, info) => {
+                    let expression = match op {
+                        Operation::Binary(op, a, b) => Some(Expression::Binary(*op, *a, *b)),
+                        Operation::Compare(cmp, a, b) => Some(Expression::Compare(*cmp, *a, *b)),
+                        Operation::Unary(UnaryOp::Param, arg) => {
+                            params.push((index, *arg));
+                            None
+                        }
+                        Operation::Unary(UnaryOp::Load | UnaryOp::Unit, _) => None,
+                        Operation::Unary(op, a) => Some(Expression::Unary(*op, *a)),
+                        Operation::Select(cond, a, b) => Some(Expression::Select(*cond, *a, *b)),
+                        Operation::Call(callee) if program.borrow().is_pure(*callee) => {
+                            let args = params.iter().map(|(_, arg)| *arg).collect();
+                            Some(Expression::Call(*callee, args))
+                        }
+                        Operation::Call(_) => {
+                            params.clear();
+                            None
+                        }
+                    };
+                    (*dst, *info, expression)
+                }
+                IR::Command(CommandOperation::Store(dst, _), info) => (*dst, *info, None),
+                IR::Jump(..) => continue,
+            };
+            let token = tokens[index];
+            let call_params = match expression {
+                Some(Expression::Call(..)) => std::mem::take(&mut params),
+                _ => vec![],
+            };
+            let expression = expression
+                .filter(|expression| !expression.operands().iter().any(|a| shared.contains(a)));
+            let holder = expression.as_ref().and_then(|expression| {
+                available
+                    .iter()
+                    .find(|(candidate, _, tied_to)| {
+                        candidate == expression && tied_to.is_none_or(|tied_to| tied_to == token)
+                    })
+                    .map(|(_, holder, _)| *holder)
+            });
+            if let Some(holder) = holder {
+                *ir = IR::Assignment(dst, Operation::Unary(UnaryOp::Unit, holder), info);
+                removed.extend(call_params.iter().map(|(index, _)| *index));
+                changed = true;
+            }
+            available.retain(|(expression, holder, _)| {
+                *holder != dst && !expression.operands().contains(&dst)
+            });
+            if let Some(expression) = expression.filter(|expression| {
+                holder.is_none()
+                    && function.is_local(dst)
+                    && !shared.contains(&dst)
+                    && !expression.operands().contains(&dst)
+            }) {
+                let depends_on_effects = |space: SpaceNameId| {
+                    escaped.contains(&space)
+                        || (!function.is_local(space) && function.const_value(space).is_none())
+                };
+                let tied_to = (depends_on_effects(dst)
+                    || expression.operands().into_iter().any(depends_on_effects))
+                .then_some(token);
+                available.push((expression, dst, tied_to));
+            }
+        }
+        if !removed.is_empty() {
+            let mut index = 0;
+            irs.retain(|_| {
+                index += 1;
+                !removed.contains(&(index - 1))
+            });
+        }
+        *function.blocks.get_mut_from_id(id).unwrap().irs_mut() = irs;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    block::CodeBlockId, ops::UnaryOp, CommandOperation, Function, Operation, Scope, SpaceNameId,
+    Value, IR,
+};
+use crate::semilattice::{FlatLattice, SemiLattice};
+
+/// Flow-insensitive constant propagation over the locals of `function`.
+///
+/// Every local's `Space::value` ends up as the meet of all values assigned to it,
+/// starting from `Top`. Parameters and anything loaded or returned by a call are
+/// `Bottom`. Assignments whose destination is a known constant are then rewritten
+/// into a copy of the interned constant, and the other selects on a known condition
+/// into a copy of the arm they pick.
+///
+/// A global with an initializer starts from it and meets every value the function
+/// stores to it, so the function is assumed to be entered with globals as initialized.
+/// That only holds for globals no function of the program writes, as any of them may
+/// have run before; the others are `Bottom`. Functions making calls know no globals,
+/// and neither do functions whose program cannot be scanned while they are borrowed.
+///
+/// Returns whether any instruction was rewritten.
+pub fn propagate_constants(function: &mut Function) -> bool {
+    let block_ids: Vec<_> = function
+        .block_name_ids()
+        .iter()
+        .map(|name_id| function.blocks.get_id_from_name_id(name_id).unwrap())
+        .collect();
+    let assigned: Vec<SpaceNameId> = block_ids
+        .iter()
+        .flat_map(|id| {
+            let block = function.blocks.get_from_id(*id).unwrap();
+            block
+                .irs()
+                .iter()
+                .filter_map(|ir| ir.defined_space())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for space in assigned {
+        if let Some(mut space_ref) = space_mut(function, space) {
+            space_ref.value = FlatLattice::Top;
+        }
+    }
+    for param in function.params.clone() {
+        if let Some(mut space_ref) = space_mut(function, param) {
+            space_ref.value = FlatLattice::Bottom;
+        }
+    }
+
+    let mut globals = initialized_globals(function, &block_ids);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in &block_ids {
+            let block = function.blocks.get_from_id(*id).unwrap();
+            for ir in block.irs().iter() {
+                let (dst, value) = match ir {
+                    IR::Assignment(dst, op, _) => (*dst, evaluate(function, &globals, op)),
+                    IR::Command(CommandOperation::Store(dst, src), _) => {
+                        (*dst, lattice_value(function, &globals, *src))
+                    }
+                    IR::Jump(..) => continue,
+                };
+                if !function.is_local(dst) {
+                    if let Some(global) = globals.get_mut(&dst) {
+                        changed |= global.meet_with(&value);
+                    }
+                    continue;
+                }
+                let space_id = function.locals.get_id_from_name_id(&dst).unwrap();
+                let mut space_ref = function.locals.get_mut_from_id(space_id).unwrap();
+                changed |= space_ref.value.meet_with(&value);
+            }
+        }
+    }
+
+    let mut rewrites = vec![];
+    for id in &block_ids {
+        let block = function.blocks.get_from_id(*id).unwrap();
+        for (i, ir) in block.irs().iter().enumerate() {
+            let IR::Assignment(dst, op, _) = ir else {
+                continue;
+            };
+            let constant = match lattice_value(function, &globals, *dst) {
+                FlatLattice::Value(value) => intern(function, value),
+                _ => None,
+            };
+            let Some(constant) = constant.or_else(|| selected_arm(function, &globals, op)) else {
+                continue;
+            };
+            if !matches!(op, Operation::Unary(UnaryOp::Unit, src) if *src == constant) {
+                rewrites.push((*id, i, constant));
+            }
+        }
+    }
+    for (id, i, constant) in &rewrites {
+        let mut block = function.blocks.get_mut_from_id(*id).unwrap();
+        if let IR::Assignment(dst, _, info) = block.irs()[*i] {
+            block.irs_mut()[*i] =
+                IR::Assignment(dst, Operation::Unary(UnaryOp::Unit, *constant), info);
+        }
+    }
+    if rewrites.is_empty() {
+        return false;
+    }
+    // Interned constants may lie beyond the space count of the old graph
+    function.build_graph();
+    true
+}
+
+/// The arm a `select` on a known condition picks.
+fn selected_arm(function: &Function, globals: &Globals, op: &Operation) -> Option<SpaceNameId> {
+    let Operation::Select(cond, a, b) = op else {
+        return None;
+    };
+    let FlatLattice::Value(cond) = lattice_value(function, globals, *cond) else {
+        return None;
+    };
+    cond.is_truthy().map(|truthy| if truthy { *a } else { *b })
+}
+
+/// Lattice values of the globals with an initializer used by a function.
+type Globals = HashMap<SpaceNameId, FlatLattice<Value>>;
+
+fn initialized_globals(function: &Function, block_ids: &[CodeBlockId]) -> Globals {
+    let Some(written) = written_globals(function) else {
+        return Globals::new();
+    };
+    let mut spaces = vec![];
+    for id in block_ids {
+        let block = function.blocks.get_from_id(*id).unwrap();
+        for ir in block.irs().iter().chain([&block.terminator]) {
+            if matches!(ir, IR::Assignment(_, Operation::Call(_), _)) {
+                return Globals::new();
+            }
+            spaces.extend(ir.defined_space());
+            spaces.extend(ir.used_spaces());
+        }
+    }
+    spaces
+        .into_iter()
+        .filter(|space| !function.is_local(*space) && function.const_value(*space).is_none())
+        .filter(|space| !written.contains(space))
+        .filter_map(|space| {
+            let id = function.locals.get_id_from_name_id(&space)?;
+            let space_ref = function.locals.get_from_id(id)?;
+            match &space_ref.value {
+                FlatLattice::Value(value) => Some((space, FlatLattice::Value(value.clone()))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The globals stored to by any function of the program `function` belongs to.
+///
+/// `None` if the function pool is borrowed, as it is while one of its functions is
+/// being rewritten in place.
+fn written_globals(function: &Function) -> Option<HashSet<SpaceNameId>> {
+    let pool = function.program().borrow().function_pool.clone();
+    let pool = pool.try_borrow().ok()?;
+    let arena = pool.arena().try_borrow().ok()?;
+    let mut written = HashSet::new();
+    for (_, other) in arena.iter() {
+        for name_id in other.block_name_ids() {
+            let id = other.blocks.get_id_from_name_id(&name_id)?;
+            let block = other.blocks.get_from_id(id)?;
+            written.extend(
+                block
+                    .irs()
+                    .iter()
+                    .filter_map(|ir| ir.defined_space())
+                    .filter(|space| !other.is_local(*space)),
+            );
+        }
+    }
+    Some(written)
+}
+
+fn evaluate(function: &Function, globals: &Globals, op: &Operation) -> FlatLattice<Value> {
+    evaluate_with(function, op, |space| {
+        lattice_value(function, globals, space)
+    })
+}
+
+/// Folds `op` given the lattice value of each operand.
+///
+/// A `select` on a known condition is the arm it picks, whatever the other one is.
+pub(crate) fn evaluate_with(
+    function: &Function,
+    op: &Operation,
+    operand: impl Fn(SpaceNameId) -> FlatLattice<Value>,
+) -> FlatLattice<Value> {
+    if let Operation::Select(cond, a, b) = op {
+        return match operand(*cond) {
+            FlatLattice::Value(cond) => match cond.is_truthy() {
+                Some(true) => operand(*a),
+                Some(false) => operand(*b),
+                None => FlatLattice::Bottom,
+            },
+            FlatLattice::Top => FlatLattice::Top,
+            FlatLattice::Bottom => operand(*a).meet(&operand(*b)),
+        };
+    }
+    let operands: Vec<FlatLattice<Value>> = op.operands().into_iter().map(operand).collect();
+    if operands.iter().any(|v| matches!(v, FlatLattice::Bottom)) {
+        return FlatLattice::Bottom;
+    }
+    if operands.iter().any(|v| matches!(v, FlatLattice::Top)) {
+        return FlatLattice::Top;
+    }
+    let values: Vec<&Value> = operands
+        .iter()
+        .filter_map(|v| match v {
+            FlatLattice::Value(v) => Some(v),
+            _ => None,
+        })
+        .collect();
+    let result = match op {
+        Operation::Binary(op, _, _) => {
+            let mode = function.program().borrow().overflow;
+            values[0].binary_in(*op, values[1], mode)
+        }
+        Operation::Compare(cmp, _, _) => values[0].compare(*cmp, values[1]),
+        Operation::Unary(UnaryOp::Load | UnaryOp::Param, _) | Operation::Call(_) => None,
+        Operation::Unary(op, _) => values[0].unary(*op),
+        Operation::Select(..) => unreachable!("Selects are folded first"),
+    };
+    result.map_or(FlatLattice::Bottom, FlatLattice::Value)
+}
+
+/// The lattice value of a space. Globals other than constants and `globals` are unknown.
+fn lattice_value(function: &Function, globals: &Globals, space: SpaceNameId) -> FlatLattice<Value> {
+    if let Some(value) = function.const_value(space) {
+        return FlatLattice::Value(value);
+    }
+    if let Some(value) = globals.get(&space) {
+        return value.clone();
+    }
+    let Some(id) = function.locals.get_id_from_name_id(&space) else {
+        return FlatLattice::Bottom;
+    };
+    let space = function.locals.get_from_id(id).unwrap();
+    match space.scope {
+        Scope::Local { .. } => space.value.clone(),
+        Scope::Global => FlatLattice::Bottom,
+    }
+}
+
+fn space_mut(
+    function: &mut Function,
+    space: SpaceNameId,
+) -> Option<std::cell::RefMut<'_, crate::ir::Space>> {
+    let id = function.locals.get_id_from_name_id(&space)?;
+    function.locals.get_mut_from_id(id)
+}
+
+/// The interned constant holding `value`, if it is a scalar.
+pub(crate) fn intern(function: &Function, value: Value) -> Option<SpaceNameId> {
+    if !matches!(
+        value,
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Custom(_)
+    ) {
+        return None;
+    }
+    Some(
+        function
+            .program()
+            .borrow_mut()
+            .lookup_or_insert_constant(value)
+            .0,
+    )
+}
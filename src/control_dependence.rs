@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use petgraph::algo::dominators::simple_fast;
+use petgraph::stable_graph::NodeIndex;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+
+use crate::ir::{BlockNameId, Function};
+
+/// The immediate post-dominator of every block of `function`: the first block after
+/// it on every path to the exit.
+///
+/// Computed on the reversed graph from the exit node. A block whose only
+/// post-dominator is the exit node itself has no entry, nor do blocks that can't reach
+/// the exit. A function leaving from several blocks must go through
+/// `Function::add_virtual_exit` first, so that paths out of it meet in a block;
+/// debug builds panic otherwise.
+pub fn post_dominators(function: &Function) -> HashMap<BlockNameId, BlockNameId> {
+    let graph = &function.graph;
+    let exits = graph
+        .graph
+        .neighbors_directed(graph.exit, petgraph::Direction::Incoming)
+        .count();
+    debug_assert!(
+        exits <= 1,
+        "{} blocks leave {}, add a virtual exit first",
+        exits,
+        function.name
+    );
+    let post_dominators = simple_fast(graph.reversed(), graph.exit);
+    let names: HashMap<NodeIndex, BlockNameId> = graph
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| (*node, *name_id))
+        .collect();
+    names
+        .iter()
+        .filter_map(|(node, name_id)| {
+            let post_dominator = post_dominators.immediate_dominator(*node)?;
+            Some((*name_id, *names.get(&post_dominator)?))
+        })
+        .collect()
+}
+
+/// Whether every path from the entry to the instruction at index `j` of block `b` goes
+/// through the instruction at index `i` of block `a` first, where terminators are at
+/// `irs_range.len()`. Within a block earlier instructions dominate later ones, and
+/// each one dominates itself. Across blocks this is dominance of `a` over `b`, so no
+/// other block dominates a point in a block unreachable from the entry.
+pub fn dominates_point(
+    function: &Function,
+    (a, i): (BlockNameId, usize),
+    (b, j): (BlockNameId, usize),
+) -> bool {
+    if a == b {
+        return i <= j;
+    }
+    let graph = &function.graph;
+    let (Some(a), Some(b)) = (
+        graph.weight.block_nodes.get(&a),
+        graph.weight.block_nodes.get(&b),
+    ) else {
+        return false;
+    };
+    simple_fast(&graph.graph, graph.entry)
+        .dominators(*b)
+        .is_some_and(|mut dominators| dominators.any(|node| node == *a))
+}
+
+/// The blocks each block of `function` is control dependent on, ascending.
+///
+/// A block depends on a branching block when one of the branch's targets leads to it
+/// on every path to the exit, but the branch itself doesn't: which way the branch goes
+/// decides whether the block runs. Found from the post-dominator tree: along an edge
+/// from `a` to `b` not post-dominated by `b`, every block from `b` up to the immediate
+/// post-dominator of `a`, excluded, depends on `a`.
+///
+/// Every block has an entry. Blocks that always run once entered, and blocks that can't
+/// reach the exit, depend on nothing.
+pub fn control_dependence_graph(function: &Function) -> HashMap<BlockNameId, Vec<BlockNameId>> {
+    let graph = &function.graph;
+    let post_dominators = simple_fast(graph.reversed(), graph.exit);
+    let names: HashMap<NodeIndex, BlockNameId> = graph
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| (*node, *name_id))
+        .collect();
+    let mut dependences: HashMap<BlockNameId, Vec<BlockNameId>> =
+        names.values().map(|name_id| (*name_id, vec![])).collect();
+    for edge in graph.graph.edge_references() {
+        let (from, to) = (edge.source(), edge.target());
+        let Some(branch) = names.get(&from) else {
+            continue;
+        };
+        let Some(stop) = post_dominators.immediate_dominator(from) else {
+            continue;
+        };
+        if post_dominators.dominators(to).is_none() {
+            continue;
+        }
+        let mut node = Some(to);
+        while let Some(current) = node.filter(|current| *current != stop) {
+            if let Some(dependent) = names.get(&current) {
+                dependences.get_mut(dependent).unwrap().push(*branch);
+            }
+            node = post_dominators.immediate_dominator(current);
+        }
+    }
+    for branches in dependences.values_mut() {
+        branches.sort();
+        branches.dedup();
+    }
+    dependences
+}
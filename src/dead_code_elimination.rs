@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::algo::dominators::simple_fast;
+
+use crate::block::{BlockUpdate, DataFlowGraph, Direction};
+use crate::control_dependence::control_dependence_graph;
+use crate::def_use::{build_def_use_chains, UseSite};
+use crate::ir::block::{CodeBlockAnalysisNode, CodeBlockGraphWeight};
+use crate::ir::{
+    ops::UnaryOp, AddressMarker, BlockNameId, Function, IRInformation, JumpOperation, Operation, IR,
+};
+use crate::live_lattice::LiveLattice;
+use crate::mem2reg::pinned_spaces;
+
+/// Removes assignments to locals that are never read afterwards.
+///
+/// Calls to functions not marked pure and `param` are kept since they have effects
+/// beyond their destination, and so are writes to globals and aliased spaces. A pure
+/// call whose result is never read goes together with its `param`s. Liveness is
+/// recomputed until nothing more can be removed, so chains of dead assignments
+/// disappear together.
+///
+/// Returns whether any instruction was removed.
+pub fn eliminate_dead_code(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<LiveLattice>>::converge(
+            &mut function.graph,
+            Direction::Backward,
+        );
+        let mut removed = false;
+        for node in function.graph.graph.node_weights() {
+            let Some(block_id) = node.block else {
+                continue;
+            };
+            let weight = &function.graph.weight;
+            let mut blocks = weight.blocks.borrow_mut();
+            let block = &mut blocks[block_id];
+            let mut live = node.live_out.value.clone();
+            live.union_with(&LiveLattice::use_var(&block.terminator, weight).value);
+            let mut kept = Vec::with_capacity(block.irs().len());
+            // Whether the `param`s being visited belong to a removed call
+            let mut dead_call = false;
+            for ir in block.irs_mut().drain(..).rev() {
+                let dead = match &ir {
+                    IR::Assignment(_, Operation::Unary(UnaryOp::Param, _), _) => dead_call,
+                    IR::Assignment(_, Operation::Call(_), _) => {
+                        dead_call = is_dead(function, &live, &ir);
+                        dead_call
+                    }
+                    ir => is_dead(function, &live, ir),
+                };
+                if dead {
+                    removed = true;
+                    continue;
+                }
+                live.intersect_with(&LiveLattice::def_mask_var(&ir, weight).value);
+                live.union_with(&LiveLattice::use_var(&ir, weight).value);
+                kept.push(ir);
+            }
+            kept.reverse();
+            *block.irs_mut() = kept;
+        }
+        if !removed {
+            break;
+        }
+        changed = true;
+        function.build_graph();
+    }
+    changed
+}
+
+fn is_dead(function: &Function, live: &fixedbitset::FixedBitSet, ir: &IR) -> bool {
+    let IR::Assignment(dst, op, _) = ir else {
+        return false;
+    };
+    let has_effect = match op {
+        Operation::Call(callee) => !function.program().borrow().is_pure(*callee),
+        Operation::Unary(UnaryOp::Param, _) => true,
+        _ => false,
+    };
+    !has_effect
+        && !live.contains(*dst)
+        && function.is_local(*dst)
+        && !function.graph.weight.aliased_spaces.contains(dst)
+}
+
+/// Removes every instruction that doesn't contribute to what `function` returns or
+/// does, unlike `eliminate_dead_code` assuming an instruction is dead until shown live.
+///
+/// Returns, calls to functions not marked pure and writes to globals or to locals
+/// sharing storage with other spaces are live. So are the definitions reaching the
+/// operands of a live instruction, the `param`s of a live call, and the branches
+/// deciding whether a block with live instructions runs, by control dependence. Loops
+/// computing nothing live thus go away with their branches: a dead branch becomes a
+/// jump to its nearest post-dominator with anything live in it, and the blocks skipped
+/// keep only their terminators. Branches that can't reach the exit are kept.
+///
+/// Returns whether anything was removed or rewritten.
+pub fn eliminate_dead_code_aggressively(function: &mut Function) -> bool {
+    let chains = build_def_use_chains(function);
+    let dependences = control_dependence_graph(function);
+    let post_dominators = simple_fast(function.graph.reversed(), function.graph.exit);
+    let pinned = pinned_spaces(function);
+    let graph = &function.graph;
+
+    let mut positions = HashMap::new();
+    let mut lengths = HashMap::new();
+    let mut call_params = HashMap::new();
+    let mut worklist = vec![];
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        let mut params = vec![];
+        for (index, ir) in block.irs().iter().enumerate() {
+            if let Some(declaration_number) = ir.info().declaration_number {
+                positions.insert(declaration_number, (name_id, index));
+            }
+            let has_effect = match ir {
+                IR::Assignment(_, Operation::Unary(UnaryOp::Param, _), _) => {
+                    params.push((name_id, index));
+                    false
+                }
+                IR::Assignment(_, Operation::Call(callee), _) => {
+                    call_params.insert((name_id, index), std::mem::take(&mut params));
+                    !function.program().borrow().is_pure(*callee)
+                }
+                ir => ir
+                    .defined_space()
+                    .is_some_and(|dst| !function.is_local(dst) || pinned.contains(&dst)),
+            };
+            if has_effect {
+                worklist.push((name_id, index));
+            }
+        }
+        let terminator = block.irs().len();
+        lengths.insert(name_id, terminator);
+        let node = graph.weight.block_nodes[&name_id];
+        let is_root = match block.terminator() {
+            IR::Jump(JumpOperation::Ret(_) | JumpOperation::End, _) => true,
+            IR::Jump(JumpOperation::Branch(..), _) => post_dominators.dominators(node).is_none(),
+            _ => false,
+        };
+        if is_root {
+            worklist.push((name_id, terminator));
+        }
+    }
+
+    let mut live = HashSet::new();
+    while let Some((block, index)) = worklist.pop() {
+        if !live.insert((block, index)) {
+            continue;
+        }
+        let id = function.blocks.get_id_from_name_id(&block).unwrap();
+        let code_block = function.blocks.get_from_id(id).unwrap();
+        let ir = code_block
+            .irs()
+            .get(index)
+            .unwrap_or(code_block.terminator());
+        for space in ir.used_spaces() {
+            let site = UseSite {
+                block,
+                index,
+                space,
+            };
+            for (_, declaration_number) in chains.defs.get(&site).into_iter().flatten() {
+                worklist.push(positions[declaration_number]);
+            }
+        }
+        worklist.extend(call_params.get(&(block, index)).into_iter().flatten());
+        for branch in &dependences[&block] {
+            worklist.push((*branch, lengths[branch]));
+        }
+    }
+
+    let live_blocks: HashSet<BlockNameId> = live.iter().map(|(block, _)| *block).collect();
+    let names: HashMap<_, _> = graph
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| (*node, *name_id))
+        .collect();
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let node = graph.weight.block_nodes[&name_id];
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        let before = block.irs().len();
+        let mut index = 0;
+        block.irs_mut().retain(|_| {
+            index += 1;
+            live.contains(&(name_id, index - 1))
+        });
+        changed |= block.irs().len() != before;
+        if !matches!(block.terminator, IR::Jump(JumpOperation::Branch(..), _))
+            || live.contains(&(name_id, before))
+        {
+            continue;
+        }
+        let mut target = post_dominators.immediate_dominator(node);
+        while let Some(next) = target.filter(|next| {
+            names
+                .get(next)
+                .is_some_and(|name_id| !live_blocks.contains(name_id))
+        }) {
+            target = post_dominators.immediate_dominator(next);
+        }
+        let jump = match target.and_then(|target| names.get(&target)) {
+            Some(target) => JumpOperation::Unconditional(AddressMarker::new(*target)),
+            None => JumpOperation::End,
+        };
+        block.terminator = IR::Jump(jump, IRInformation::default());
+        changed = true;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+
+use crate::escape::escaped_spaces;
+use crate::ir::{CommandOperation, Function, IR};
+use crate::mem2reg::shared_spaces;
+
+/// Removes each store to a local that is written again later in the same block before
+/// anything reads it, by a store or an assignment.
+///
+/// Aliasing is treated conservatively: stores to globals, to locals passed to calls
+/// and to locals sharing storage with others are kept, as other functions or other
+/// spaces may read them, and writes to spaces sharing storage don't count as
+/// overwriting, as they may be partial. A local that is still to be read in another
+/// block, by `ret` included, is never written again before the end of this one, so
+/// its last store is kept.
+///
+/// Returns whether any store was removed.
+pub fn eliminate_dead_stores(function: &mut Function) -> bool {
+    let mut escaping = shared_spaces(function);
+    escaping.extend(escaped_spaces(function));
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        // Spaces written again below before any read
+        let mut overwritten: HashSet<_> = HashSet::new();
+        let mut kept = Vec::with_capacity(irs.len());
+        for ir in irs.into_iter().rev() {
+            if let IR::Command(CommandOperation::Store(dst, _), _) = ir {
+                if overwritten.contains(&dst) {
+                    changed = true;
+                    continue;
+                }
+            }
+            if let Some(dst) = ir.defined_space() {
+                if function.is_local(dst) && !escaping.contains(&dst) {
+                    overwritten.insert(dst);
+                }
+            }
+            for space in ir.used_spaces() {
+                overwritten.remove(&space);
+            }
+            kept.push(ir);
+        }
+        kept.reverse();
+        *function.blocks.get_mut_from_id(id).unwrap().irs_mut() = kept;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
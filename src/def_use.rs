@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use fixedbitset::FixedBitSet;
+use petgraph::stable_graph::NodeIndex;
+use petgraph::visit::Bfs;
+
+use crate::block::{BlockTransfer, BlockUpdate, DataFlowGraph, Direction, JointBlockUpdate};
+use crate::gen_kill::transfer_all;
+use crate::ir::block::{CodeBlockAnalysisNode, CodeBlockGraphWeight};
+use crate::ir::{BlockNameId, Function, SpaceNameId};
+use crate::live_lattice::LiveLattice;
+use crate::reach_lattice::ReachLattice;
+use crate::semilattice::SemiLattice;
+
+/// A definition, by its block and declaration number.
+pub type Def = (BlockNameId, usize);
+
+/// A read of `space` by the instruction at `index` of `block`.
+/// The terminator is at `irs_range.len()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UseSite {
+    pub block: BlockNameId,
+    pub index: usize,
+    pub space: SpaceNameId,
+}
+
+#[derive(Debug, Default)]
+pub struct DefUseChains {
+    /// The uses each definition reaches, in block and instruction order.
+    pub uses: HashMap<Def, Vec<UseSite>>,
+    /// The definitions reaching each use, by declaration number.
+    pub defs: HashMap<UseSite, Vec<Def>>,
+}
+
+/// Solves reaching definitions over `function.graph`, leaving the result in each node's
+/// `reach_in` and `reach_out` for `reaching_defs_of_use`.
+pub fn solve_reaching_definitions(function: &mut Function) {
+    <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<ReachLattice>>::converge(
+        &mut function.graph,
+        Direction::Forward,
+    );
+}
+
+/// Solves liveness over `function.graph`, leaving the result in each node's `live_in`
+/// and `live_out` for `is_live_at`.
+pub fn solve_liveness(function: &mut Function) {
+    <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<LiveLattice>>::converge(
+        &mut function.graph,
+        Direction::Backward,
+    );
+}
+
+/// Solves reaching definitions and liveness over `function.graph` in one pass, leaving
+/// each node's `reach_in`, `reach_out`, `live_in` and `live_out` as solving them one
+/// after the other would. Returns the number of block transfers performed.
+pub fn solve_reaching_definitions_and_liveness(function: &mut Function) -> usize {
+    <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as JointBlockUpdate<
+        ReachLattice,
+        LiveLattice,
+    >>::converge_jointly(
+        &mut function.graph,
+        (Direction::Forward, Direction::Backward),
+    )
+}
+
+/// Brings the reaching definitions left by `solve_reaching_definitions` up to date after
+/// the instructions of the block `edited`, and only those, were changed.
+///
+/// Rebuilds the graph, which renumbers the definitions, and carries the prior solution
+/// over to the blocks `edited` cannot reach before or after the edit, since nothing
+/// flowing into them has changed. The blocks it can reach are reset and re-propagated
+/// from a worklist until they settle. The result is the one a full solve would give.
+///
+/// Returns the number of block transfers performed.
+pub fn update_reaching_definitions(function: &mut Function, edited: BlockNameId) -> usize {
+    let old = &function.graph;
+    let Some(&old_edited) = old.weight.block_nodes.get(&edited) else {
+        function.build_graph();
+        return <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<
+            ReachLattice,
+        >>::converge(&mut function.graph, Direction::Forward);
+    };
+    let old_names: HashMap<NodeIndex, BlockNameId> = old
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| (*node, *name_id))
+        .collect();
+    // The entry can't be reached, so the only node without a block name is the exit
+    let mut old_reached = vec![];
+    let mut bfs = Bfs::new(&old.graph, old_edited);
+    while let Some(node) = bfs.next(&old.graph) {
+        old_reached.push(old_names.get(&node).copied());
+    }
+    let mut old_values = HashMap::new();
+    for node in old.graph.node_indices() {
+        let weight = &old.graph[node];
+        let key = if node == old.exit {
+            Some(None)
+        } else {
+            old_names.get(&node).map(|name_id| Some(*name_id))
+        };
+        if let Some(key) = key {
+            old_values.insert(key, (weight.reach_in.clone(), weight.reach_out.clone()));
+        }
+    }
+    let declaration_numbers = |function: &Function| {
+        let mut numbers = vec![];
+        for name_id in function.block_name_ids() {
+            if name_id == edited {
+                continue;
+            }
+            let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+            let block = function.blocks.get_from_id(id).unwrap();
+            numbers.extend(
+                block
+                    .irs()
+                    .iter()
+                    .filter_map(|ir| ir.info().declaration_number),
+            );
+        }
+        numbers
+    };
+    let old_numbers = declaration_numbers(function);
+
+    function.build_graph();
+    let block_name_ids = function.block_name_ids();
+    let renumbered: HashMap<usize, usize> = old_numbers
+        .into_iter()
+        .zip(declaration_numbers(function))
+        .collect();
+    let graph = &mut function.graph;
+    let node_of = |name_id: Option<BlockNameId>| match name_id {
+        Some(name_id) => graph.weight.block_nodes.get(&name_id).copied(),
+        None => Some(graph.exit),
+    };
+    let mut affected: HashSet<NodeIndex> = old_reached.into_iter().filter_map(node_of).collect();
+    let mut bfs = Bfs::new(&graph.graph, graph.weight.block_nodes[&edited]);
+    while let Some(node) = bfs.next(&graph.graph) {
+        affected.insert(node);
+    }
+    affected.remove(&graph.entry);
+
+    let count = graph.weight.assignment_count;
+    let translate = |old: &ReachLattice| {
+        let mut value = ReachLattice::new(count);
+        for number in old.value.ones() {
+            if let Some(number) = renumbered.get(&number) {
+                value.value.insert(*number);
+            }
+        }
+        value
+    };
+    let mut carried = vec![];
+    for (key, (reach_in, reach_out)) in &old_values {
+        if let Some(node) = node_of(*key).filter(|node| !affected.contains(node)) {
+            carried.push((node, translate(reach_in), translate(reach_out)));
+        }
+    }
+    let nodes: Vec<NodeIndex> = graph.graph.node_indices().collect();
+    for node in nodes {
+        graph.graph[node].reach_in = ReachLattice::new(count);
+        graph.graph[node].reach_out = ReachLattice::new(count);
+    }
+    for (node, reach_in, reach_out) in carried {
+        graph.graph[node].reach_in = reach_in;
+        graph.graph[node].reach_out = reach_out;
+    }
+
+    let mut worklist: VecDeque<NodeIndex> = block_name_ids
+        .iter()
+        .map(|name_id| graph.weight.block_nodes[name_id])
+        .chain([graph.exit])
+        .filter(|node| affected.contains(node))
+        .collect();
+    let mut queued: HashSet<NodeIndex> = worklist.iter().copied().collect();
+    let mut transfers = 0;
+    while let Some(node) = worklist.pop_front() {
+        queued.remove(&node);
+        transfers += 1;
+        let reach_in = graph
+            .graph
+            .neighbors_directed(node, petgraph::Direction::Incoming)
+            .fold(ReachLattice::new(count), |cur, predecessor| {
+                cur.meet(&graph.graph[predecessor].reach_out)
+            });
+        let reach_out = graph.graph[node].transfer_forward(&reach_in, graph, node);
+        let changed = reach_out != graph.graph[node].reach_out;
+        graph.graph[node].reach_in = reach_in;
+        graph.graph[node].reach_out = reach_out;
+        if changed {
+            let successors: Vec<NodeIndex> = graph.graph.neighbors(node).collect();
+            for successor in successors {
+                if affected.contains(&successor) && queued.insert(successor) {
+                    worklist.push_back(successor);
+                }
+            }
+        }
+    }
+    transfers
+}
+
+/// The declaration numbers of the definitions of `site.space` reaching `site`, ascending.
+///
+/// Reads the solution left by `solve_reaching_definitions`, which must be current. A site
+/// past the end of its block, or in a block outside the graph, is reached by nothing.
+pub fn reaching_defs_of_use(function: &Function, site: UseSite) -> Vec<usize> {
+    let weight = &function.graph.weight;
+    let (Some(node), Some(id)) = (
+        weight.block_nodes.get(&site.block),
+        function.blocks.get_id_from_name_id(&site.block),
+    ) else {
+        return vec![];
+    };
+    let block = function.blocks.get_from_id(id).unwrap();
+    if site.index > block.irs().len() {
+        return vec![];
+    }
+    let mut reaching = function.graph.graph[*node].reach_in.value.clone();
+    for ir in &block.irs()[..site.index] {
+        ReachLattice::transfer_var(ir, weight, &mut reaching);
+    }
+    reaching_defs_of(weight, &reaching, site.space)
+}
+
+/// Whether `space` may be read after control reaches the instruction at `index` of
+/// `block`, before that instruction runs. The terminator is at `irs_range.len()`.
+///
+/// Reads the solution left by `solve_liveness`, which must be current. A point past the
+/// end of its block, or in a block outside the graph, has nothing live.
+pub fn is_live_at(
+    function: &Function,
+    space: SpaceNameId,
+    block: BlockNameId,
+    index: usize,
+) -> bool {
+    let weight = &function.graph.weight;
+    let (Some(node), Some(id)) = (
+        weight.block_nodes.get(&block),
+        function.blocks.get_id_from_name_id(&block),
+    ) else {
+        return false;
+    };
+    let block = function.blocks.get_from_id(id).unwrap();
+    if index > block.irs().len() {
+        return false;
+    }
+    let mut live = function.graph.graph[*node].live_out.value.clone();
+    let irs = block.irs()[index..].iter();
+    transfer_all::<LiveLattice>(irs.chain([&block.terminator]), weight, &mut live);
+    live.contains(space)
+}
+
+/// A table of the reaching definitions left by `solve_reaching_definitions`, which
+/// must be current: a line naming each block in the graph, followed by its in-set and
+/// out-set. A definition is listed by declaration number and defining instruction,
+/// in ascending order, e.g. `out: 0 (3 = 2 Add 4), 1 (5 = Unit 3)`. An empty set is `-`.
+pub fn print_analysis(function: &Function) -> String {
+    let mut instructions = HashMap::new();
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for ir in block.irs() {
+            if let Some(declaration_number) = ir.info().declaration_number {
+                instructions.insert(declaration_number, ir.to_string());
+            }
+        }
+    }
+    let list = |set: &FixedBitSet| {
+        let defs: Vec<_> = set
+            .ones()
+            .map(|number| match instructions.get(&number) {
+                Some(ir) => format!("{number} ({ir})"),
+                None => number.to_string(),
+            })
+            .collect();
+        if defs.is_empty() {
+            "-".to_string()
+        } else {
+            defs.join(", ")
+        }
+    };
+    let mut table = String::new();
+    for name_id in function.block_name_ids() {
+        let Some(node) = function.graph.weight.block_nodes.get(&name_id) else {
+            continue;
+        };
+        let node = &function.graph.graph[*node];
+        table += &format!("{}\n", function.blocks.get_name(&name_id).unwrap());
+        table += &format!("    in: {}\n", list(&node.reach_in.value));
+        table += &format!("    out: {}\n", list(&node.reach_out.value));
+    }
+    table
+}
+
+fn reaching_defs_of(
+    weight: &CodeBlockGraphWeight,
+    reaching: &FixedBitSet,
+    space: SpaceNameId,
+) -> Vec<usize> {
+    let mut defs: Vec<usize> = weight
+        .variable_assignment_map
+        .get(&space)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|declaration_number| reaching.contains(*declaration_number))
+        .collect();
+    defs.sort();
+    defs
+}
+
+/// Links every definition of `function` to the uses it reaches and back, from
+/// reaching definitions. A use reached by several definitions is in the chain of each.
+///
+/// Definitions are numbered by `Function::build_graph`, so the graph must be up to date.
+pub fn build_def_use_chains(function: &mut Function) -> DefUseChains {
+    solve_reaching_definitions(function);
+    let weight = &function.graph.weight;
+    let mut def_blocks = HashMap::new();
+    for name_id in function.block_name_ids() {
+        let block = function
+            .blocks
+            .get_from_id(function.blocks.get_id_from_name_id(&name_id).unwrap())
+            .unwrap();
+        for ir in block.irs() {
+            if let Some(declaration_number) = ir.info().declaration_number {
+                def_blocks.insert(declaration_number, name_id);
+            }
+        }
+    }
+
+    let mut chains = DefUseChains::default();
+    for name_id in function.block_name_ids() {
+        let Some(node) = weight.block_nodes.get(&name_id) else {
+            continue;
+        };
+        let block = function
+            .blocks
+            .get_from_id(function.blocks.get_id_from_name_id(&name_id).unwrap())
+            .unwrap();
+        let mut reaching = function.graph.graph[*node].reach_in.value.clone();
+        for (index, ir) in block.irs().iter().chain([&block.terminator]).enumerate() {
+            let mut spaces = ir.used_spaces();
+            spaces.dedup();
+            for space in spaces {
+                let site = UseSite {
+                    block: name_id,
+                    index,
+                    space,
+                };
+                let defs: Vec<Def> = reaching_defs_of(weight, &reaching, space)
+                    .into_iter()
+                    .map(|declaration_number| (def_blocks[&declaration_number], declaration_number))
+                    .collect();
+                for def in &defs {
+                    chains.uses.entry(*def).or_default().push(site);
+                }
+                chains.defs.insert(site, defs);
+            }
+            ReachLattice::transfer_var(ir, weight, &mut reaching);
+        }
+    }
+    chains
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use petgraph::algo::dominators::simple_fast;
+
+use crate::ir::Function;
+
+/// Renders the immediate-dominator tree of `function` as a Graphviz digraph.
+///
+/// Every block reachable from the entry is a node `b<name id>` labeled by its name,
+/// with an edge from its immediate dominator. The first block is the root, since the
+/// entry node it hangs from is not a block. Blocks are listed in `block_name_ids`
+/// order, then the edges in the order of the blocks they lead to.
+pub fn dominator_tree_to_dot(function: &Function) -> String {
+    let graph = &function.graph;
+    let dominators = simple_fast(&graph.graph, graph.entry);
+    let blocks: Vec<_> = function
+        .block_name_ids()
+        .into_iter()
+        .filter(|name_id| {
+            dominators
+                .dominators(graph.weight.block_nodes[name_id])
+                .is_some()
+        })
+        .collect();
+    let mut dot = format!("digraph {:?} {{\n", function.name);
+    for name_id in &blocks {
+        let name = function.blocks.get_name(name_id).unwrap();
+        writeln!(dot, "    b{} [label={:?}];", name_id, name).unwrap();
+    }
+    let names: HashMap<_, _> = graph
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| (*node, *name_id))
+        .collect();
+    for name_id in &blocks {
+        let node = graph.weight.block_nodes[name_id];
+        if let Some(idom) = dominators
+            .immediate_dominator(node)
+            .and_then(|idom| names.get(&idom))
+        {
+            writeln!(dot, "    b{} -> b{};", idom, name_id).unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,46 @@
+use crate::ir::{ops::UnaryOp, CommandOperation, Function, Operation, IR};
+
+/// Whether `ir` touches state besides the locals it names, so that it must stay in
+/// order with every other instruction that does: stores, `load`s, `param`s, calls to
+/// functions not marked pure and writes to globals.
+pub fn has_effect(function: &Function, ir: &IR) -> bool {
+    match ir {
+        IR::Assignment(_, Operation::Unary(UnaryOp::Load | UnaryOp::Param, _), _) => true,
+        ir => writes_state(function, ir),
+    }
+}
+
+/// Whether `ir` may write state besides its destination, or a global destination:
+/// stores, calls to functions not marked pure and writes to globals.
+fn writes_state(function: &Function, ir: &IR) -> bool {
+    match ir {
+        IR::Command(CommandOperation::Store(..), _) => true,
+        IR::Assignment(_, Operation::Call(callee), _)
+            if !function.program().borrow().is_pure(*callee) =>
+        {
+            true
+        }
+        IR::Assignment(dst, ..) => !function.is_local(*dst),
+        IR::Jump(..) => false,
+    }
+}
+
+/// The effect token of each of `irs`: the number of instructions before it that may
+/// write state, threading an implicit dependency on memory through the block.
+/// Instructions of the same token see the same globals and memory.
+///
+/// A pass may only move an instruction reading such state, or reuse a value read
+/// elsewhere in its place, between instructions of the same token. Instructions with
+/// an effect are never reordered.
+pub fn effect_tokens(function: &Function, irs: &[IR]) -> Vec<usize> {
+    let mut token = 0;
+    irs.iter()
+        .map(|ir| {
+            let current = token;
+            if writes_state(function, ir) {
+                token += 1;
+            }
+            current
+        })
+        .collect()
+}
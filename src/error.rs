@@ -0,0 +1,106 @@
+use std::fmt::{Display, Formatter};
+
+use crate::ir::{
+    lexer::{Cursor, TokenKind},
+    parser::ParseError,
+    type_check::TypeError,
+    verify::VerifyError,
+};
+
+/// The source text between two cursors, `end` being just past its last character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Cursor,
+    pub end: Cursor,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+/// The stage of compilation an error comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Lex,
+    Parse,
+    Type,
+    Verify,
+}
+
+impl Display for ErrorCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorCategory::Lex => "lex",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Type => "type",
+            ErrorCategory::Verify => "verify",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An error from any stage of compilation, with where in the source it was found.
+///
+/// Rendered as `category: message @ line:col`, or without the location if the error
+/// has none, e.g. one found in a function built without the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub category: ErrorCategory,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.category, self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " @ {span}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl CompileError {
+    pub fn new(category: ErrorCategory, message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            category,
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+/// A parse error on a token the lexer could not make sense of is a lex error.
+impl From<ParseError> for CompileError {
+    fn from(error: ParseError) -> Self {
+        let token = error.token();
+        let span = token.map(|token| Span {
+            start: token.start,
+            end: token.end,
+        });
+        match token {
+            Some(token) if token.kind == TokenKind::Error => Self::new(
+                ErrorCategory::Lex,
+                format!("invalid token `{}`", token.content),
+                span,
+            ),
+            _ => Self::new(ErrorCategory::Parse, error.kind().to_string(), span),
+        }
+    }
+}
+
+impl From<TypeError> for CompileError {
+    fn from(error: TypeError) -> Self {
+        Self::new(ErrorCategory::Type, error.to_string(), None)
+    }
+}
+
+impl From<VerifyError> for CompileError {
+    fn from(error: VerifyError) -> Self {
+        Self::new(ErrorCategory::Verify, error.to_string(), None)
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashSet;
+
+use crate::ir::{ops::UnaryOp, Function, Operation, SpaceNameId, SpaceSignature, IR};
+
+/// The locals of `function` that may be reached from outside it, and so must live in
+/// memory rather than in a register.
+///
+/// A local escapes when its value is passed to a call through `param`. The analysis is
+/// conservative about aggregates: a local escapes with any space sharing its storage,
+/// so the members of an escaping aggregate and the space an escaping `Offset` points
+/// into escape as well. Globals and constants are never part of the result.
+pub fn escaped_spaces(function: &Function) -> HashSet<SpaceNameId> {
+    let mut pending = vec![];
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for ir in block.irs() {
+            if let IR::Assignment(_, Operation::Unary(UnaryOp::Param, space), _) = ir {
+                pending.push(*space);
+            }
+        }
+    }
+    let mut escaped = HashSet::new();
+    while let Some(space) = pending.pop() {
+        if !function.is_local(space) || !escaped.insert(space) {
+            continue;
+        }
+        let id = function.locals.get_id_from_name_id(&space).unwrap();
+        let signature = &function.locals.get_from_id(id).unwrap().signature;
+        pending.extend(signature.members());
+        if let SpaceSignature::Offset(base, ..) = signature {
+            pending.push(*base);
+        }
+    }
+    escaped
+}
@@ -0,0 +1,59 @@
+use fixedbitset::FixedBitSet;
+
+use crate::block::Direction;
+use crate::ir::block::CodeBlockGraphWeight;
+use crate::ir::IR;
+
+/// A bit-vector analysis whose transfer through an instruction is
+/// `out = gen ∪ (in − kill)` when `DIRECTION` is `Forward`, or its dual
+/// `in = gen ∪ (out − kill)` when `Backward`.
+pub trait GenKillAnalysis {
+    const DIRECTION: Direction;
+    /// The number of bits in a set of this analysis.
+    fn capacity(weight: &CodeBlockGraphWeight) -> usize;
+    /// The bits `ir` sets.
+    fn gen(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet;
+    /// The bits `ir` clears, before setting those of `gen`.
+    fn kill(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet;
+    /// Applies `ir` to `value` in place. Analyses can override this to avoid building
+    /// `gen` and `kill` for each instruction.
+    fn transfer(ir: &IR, weight: &CodeBlockGraphWeight, value: &mut FixedBitSet) {
+        value.difference_with(&Self::kill(ir, weight));
+        value.union_with(&Self::gen(ir, weight));
+    }
+    /// Adds the bits of `kill` to `set`. Analyses can override this like `transfer`.
+    fn add_kill(ir: &IR, weight: &CodeBlockGraphWeight, set: &mut FixedBitSet) {
+        set.union_with(&Self::kill(ir, weight));
+    }
+}
+
+/// Applies `irs`, listed in program order, to `value` in the direction of `A`.
+pub fn transfer_all<'a, A: GenKillAnalysis>(
+    irs: impl DoubleEndedIterator<Item = &'a IR>,
+    weight: &CodeBlockGraphWeight,
+    value: &mut FixedBitSet,
+) {
+    match A::DIRECTION {
+        Direction::Forward => irs.for_each(|ir| A::transfer(ir, weight, value)),
+        Direction::Backward => irs.rev().for_each(|ir| A::transfer(ir, weight, value)),
+    }
+}
+
+/// The gen and kill sets of `irs` as a whole, listed in program order, so that
+/// applying them is applying each instruction in the direction of `A`.
+pub fn gen_kill_all<'a, A: GenKillAnalysis>(
+    irs: impl DoubleEndedIterator<Item = &'a IR>,
+    weight: &CodeBlockGraphWeight,
+) -> (FixedBitSet, FixedBitSet) {
+    let mut gen = FixedBitSet::with_capacity(A::capacity(weight));
+    let mut kill = gen.clone();
+    let mut apply = |ir: &IR| {
+        A::transfer(ir, weight, &mut gen);
+        A::add_kill(ir, weight, &mut kill);
+    };
+    match A::DIRECTION {
+        Direction::Forward => irs.for_each(&mut apply),
+        Direction::Backward => irs.rev().for_each(&mut apply),
+    }
+    (gen, kill)
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use petgraph::Direction::Incoming;
+
+use crate::effects::has_effect;
+use crate::ir::{
+    ops::{BinaryOp, OverflowMode, UnaryOp},
+    AddressMarker, BlockNameId, Function, IRInformation, JumpOperation, Operation, Scope,
+    SpaceNameId, IR,
+};
+use crate::mem2reg::shared_spaces;
+
+/// The most instructions an arm may have. Both arms run whichever way the branch
+/// goes, so longer ones cost more than the branch saves.
+const MAX_ARM_LENGTH: usize = 8;
+
+/// A branch on `cond` whose arms `arms` both jump to `join`.
+struct Diamond {
+    head: BlockNameId,
+    cond: SpaceNameId,
+    arms: [BlockNameId; 2],
+    join: BlockNameId,
+}
+
+/// Collapses every diamond of `function` whose arms are short and free of side effects
+/// into straight-line code choosing the results with `select`.
+///
+/// A diamond is a block branching to two other blocks, its arms, that are only reached
+/// from it and both jump to the same block. An arm can't have effects, see
+/// `has_effect`, nor anything that may fail when run on the path not taken: a division,
+/// or arithmetic when overflow is `Checked`. Neither may define a space sharing storage
+/// with others.
+///
+/// The instructions of both arms are moved into the branching block, each writing a
+/// fresh temporary in place of what it defined. Every space either arm defined is
+/// then assigned a `select` on the condition between what the true and the false arm
+/// left in it, and the block jumps to the join. The arms are removed.
+///
+/// Returns whether any diamond was collapsed.
+pub fn if_convert(function: &mut Function) -> bool {
+    let mut changed = false;
+    while let Some(diamond) = find_diamond(function) {
+        convert(function, diamond);
+        function.build_graph();
+        changed = true;
+    }
+    changed
+}
+
+fn find_diamond(function: &Function) -> Option<Diamond> {
+    let shared = shared_spaces(function);
+    let graph = &function.graph;
+    let is_arm = |head: BlockNameId, arm: BlockNameId| -> Option<BlockNameId> {
+        let node = *graph.weight.block_nodes.get(&arm)?;
+        if arm == head || graph.graph.neighbors_directed(node, Incoming).count() != 1 {
+            return None;
+        }
+        let id = function.blocks.get_id_from_name_id(&arm)?;
+        let block = function.blocks.get_from_id(id)?;
+        let IR::Jump(JumpOperation::Unconditional(join), _) = block.terminator() else {
+            return None;
+        };
+        let speculable = block.irs().len() <= MAX_ARM_LENGTH
+            && block.irs().iter().all(|ir| {
+                !has_effect(function, ir)
+                    && !may_fail(function, ir)
+                    && ir.defined_space().is_none_or(|dst| !shared.contains(&dst))
+            });
+        (speculable && join.block_id != arm).then_some(join.block_id)
+    };
+    function.block_name_ids().into_iter().find_map(|head| {
+        let id = function.blocks.get_id_from_name_id(&head)?;
+        let block = function.blocks.get_from_id(id)?;
+        let IR::Jump(JumpOperation::Branch(cond, true_arm, false_arm), _) = block.terminator()
+        else {
+            return None;
+        };
+        let arms = [true_arm.block_id, false_arm.block_id];
+        if arms[0] == arms[1] {
+            return None;
+        }
+        let join = is_arm(head, arms[0])?;
+        (is_arm(head, arms[1])? == join).then_some(Diamond {
+            head,
+            cond: *cond,
+            arms,
+            join,
+        })
+    })
+}
+
+/// Whether `ir` may fail when run on a path where it wasn't meant to be.
+fn may_fail(function: &Function, ir: &IR) -> bool {
+    let checked = function.program().borrow().overflow == OverflowMode::Checked;
+    match ir {
+        IR::Assignment(_, op, _) => match op {
+            Operation::Binary(BinaryOp::Div, _, _) => true,
+            Operation::Binary(BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul, _, _)
+            | Operation::Unary(UnaryOp::Negative, _) => checked,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn convert(function: &mut Function, diamond: Diamond) {
+    let scope = Scope::Local {
+        fn_name_id: function.name_id,
+    };
+    let mut irs = vec![];
+    // What each arm left in each space it defined
+    let mut results: [HashMap<SpaceNameId, SpaceNameId>; 2] = Default::default();
+    let mut defined = vec![];
+    for (arm, results) in diamond.arms.iter().zip(results.iter_mut()) {
+        let id = function.blocks.get_id_from_name_id(arm).unwrap();
+        let arm_irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        for mut ir in arm_irs {
+            let IR::Assignment(original, ..) = ir else {
+                unreachable!("Arms only hold assignments")
+            };
+            ir.map_spaces(|space| results.get(&space).copied().unwrap_or(space));
+            let data_type = function
+                .locals
+                .get_id_from_name_id(&original)
+                .and_then(|id| function.locals.get_from_id(id))
+                .and_then(|space| space.signature.get_type());
+            let (temporary, _) = function.declare_space(data_type, scope.clone());
+            if let IR::Assignment(dst, ..) = &mut ir {
+                *dst = temporary;
+            }
+            results.insert(original, temporary);
+            if !defined.contains(&original) {
+                defined.push(original);
+            }
+            irs.push(ir);
+        }
+    }
+    // The condition is read by every select, so it is assigned last
+    defined.sort_by_key(|space| *space == diamond.cond);
+    for space in defined {
+        let [a, b] = results
+            .each_ref()
+            .map(|results| results.get(&space).copied());
+        irs.push(IR::Assignment(
+            space,
+            Operation::Select(diamond.cond, a.unwrap_or(space), b.unwrap_or(space)),
+            IRInformation::default(),
+        ));
+    }
+    let id = function.blocks.get_id_from_name_id(&diamond.head).unwrap();
+    let mut block = function.blocks.get_mut_from_id(id).unwrap();
+    block.irs_mut().extend(irs);
+    block.terminator = IR::Jump(
+        JumpOperation::Unconditional(AddressMarker::new(diamond.join)),
+        IRInformation::default(),
+    );
+    drop(block);
+    for arm in diamond.arms {
+        let name = function.blocks.get_name(&arm).unwrap().clone();
+        function.blocks.unbind(&name);
+    }
+}
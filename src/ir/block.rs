@@ -1,29 +1,92 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{write, Debug, Display, Formatter};
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
 use fixedbitset::FixedBitSet;
-use id_arena::{ArenaBehavior, DefaultArenaBehavior, Id};
-use petgraph::graph::NodeIndex;
+use id_arena::{Arena, Id};
+use petgraph::graph::{EdgeIndex, NodeIndex};
 
 type GraphBlockID = NodeIndex<u32>;
 
-use crate::block::{Block, DataFlowGraph};
+use crate::block::{Block, DataFlowGraph, EdgeKind};
+use crate::live_lattice::LiveLattice;
 use crate::reach_lattice::ReachLattice;
+use crate::semilattice::FlatLattice;
+use crate::util::RcRef;
 
 use super::{
-    AddressMarker, BlockNameId, BlockType, IRInformation, JumpOperation, SpaceId, SpaceSignature,
-    IR,
+    AddressMarker, ArrayValue, BlockNameId, BlockType, DataType, Function, FunctionNameId,
+    IRInformation, JumpOperation, Operation, Scope, Space, SpaceNameId, SpaceSignature,
+    StructValue, UnaryOp, Value, IR,
 };
 
 pub type CodeBlockId = Id<CodeBlock>;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct CodeBlockGraphWeight {
     pub assignment_count: usize,
-    pub variable_assignment_map: HashMap<SpaceSignature, Vec<usize>>,
+    pub variable_assignment_map: HashMap<SpaceNameId, Vec<usize>>,
+    /// Spaces whose signature is an `Offset` into another space.
+    /// A definition of such a space may alias, so it never kills other definitions.
+    pub aliased_spaces: HashSet<SpaceNameId>,
+    /// One past the largest space name id referenced by the function.
+    pub space_count: usize,
+    pub block_nodes: HashMap<BlockNameId, GraphBlockID>,
+    pub blocks: RcRef<Arena<CodeBlock>>,
+}
+
+impl CodeBlockGraphWeight {
+    pub fn new(blocks: RcRef<Arena<CodeBlock>>) -> Self {
+        Self {
+            blocks,
+            ..Default::default()
+        }
+    }
+    /// Numbers the definitions of `function` from scratch, block by block in
+    /// `block_name_ids` order, writing each instruction's `declaration_number`.
+    /// Refills `variable_assignment_map`, `assignment_count`, `aliased_spaces` and
+    /// `space_count` to match, and binds `blocks` to the function's blocks. The graph
+    /// and `block_nodes` are left alone, see `Function::build_graph`.
+    pub fn recompute(&mut self, function: &Function) {
+        self.blocks = function.blocks.arena().clone();
+        self.assignment_count = 0;
+        self.variable_assignment_map.clear();
+        self.aliased_spaces.clear();
+        self.space_count = 0;
+        let block_name_ids = function.block_name_ids();
+        let mut blocks = self.blocks.borrow_mut();
+        for name_id in block_name_ids {
+            let block = &mut blocks[function.blocks.get_id_from_name_id(&name_id).unwrap()];
+            for ir in block.irs_mut().iter_mut() {
+                let spaces = ir.used_spaces().into_iter().chain(ir.defined_space());
+                self.space_count = spaces.fold(self.space_count, |cur, space| cur.max(space + 1));
+                if let Some(var) = ir.defined_space() {
+                    let declaration_number = self.assignment_count;
+                    ir.info_mut().declaration_number = Some(declaration_number);
+                    self.variable_assignment_map
+                        .entry(var)
+                        .or_default()
+                        .push(declaration_number);
+                    self.assignment_count += 1;
+                }
+            }
+            if let Some(space) = block.terminator.used_spaces().first() {
+                self.space_count = self.space_count.max(space + 1);
+            }
+        }
+        for var in self.variable_assignment_map.keys() {
+            let is_offset = function
+                .locals
+                .get_id_from_name_id(var)
+                .and_then(|id| function.locals.get_from_id(id))
+                .is_some_and(|space| matches!(space.signature, SpaceSignature::Offset(..)));
+            if is_offset {
+                self.aliased_spaces.insert(*var);
+            }
+        }
+    }
 }
 
 impl Display for CodeBlockGraphWeight {
@@ -135,19 +198,129 @@ impl From<Vec<IR>> for DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight
     }
 }
 
-#[derive(Debug)]
+impl DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> {
+    /// Adds a node for the block `name_id`, stored at `block_id` in `weight.blocks`, and
+    /// maps the block to it both ways: `weight.block_nodes` from the block and the
+    /// node's `block` back. The block's `block_type` is set to `block_type`.
+    ///
+    /// A block already in the graph keeps its node, which is returned. The node has
+    /// no edges: see `connect`.
+    pub fn add_block(
+        &mut self,
+        name_id: BlockNameId,
+        block_id: CodeBlockId,
+        block_type: BlockType,
+    ) -> GraphBlockID {
+        self.weight.blocks.borrow_mut()[block_id].block_type = block_type;
+        if let Some(index) = self.weight.block_nodes.get(&name_id) {
+            return *index;
+        }
+        let index = self.graph.add_node(CodeBlockAnalysisNode::new(
+            Some(block_id),
+            NodeIndex::new(0),
+        ));
+        self.graph[index].set_node_index(index);
+        self.weight.block_nodes.insert(name_id, index);
+        index
+    }
+
+    /// Adds an edge of `kind` from `from` to `to`. Nothing is added and `None` is
+    /// returned if either node is missing, rather than panicking half way through.
+    pub fn connect(
+        &mut self,
+        from: GraphBlockID,
+        to: GraphBlockID,
+        kind: EdgeKind,
+    ) -> Option<EdgeIndex> {
+        (self.graph.contains_node(from) && self.graph.contains_node(to))
+            .then(|| self.graph.add_edge(from, to, kind))
+    }
+
+    /// Panics if the graph is inconsistent: `entry` or `exit` missing or standing for a
+    /// block, an edge to a removed node, a node not knowing its index or standing for no
+    /// live block, or a block of `weight.block_nodes` without its node. Meant to be
+    /// called after mutating the graph; it does nothing in release builds.
+    pub fn debug_assert_consistent(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        for (terminal, name) in [(self.entry, "entry"), (self.exit, "exit")] {
+            let node = self.graph.node_weight(terminal);
+            assert!(node.is_some(), "{name} node {terminal:?} is missing");
+            assert!(
+                node.unwrap().block.is_none(),
+                "{name} node {terminal:?} stands for a block"
+            );
+        }
+        for edge in self.graph.edge_indices() {
+            let (source, target) = self.graph.edge_endpoints(edge).unwrap();
+            assert!(
+                self.graph.contains_node(source) && self.graph.contains_node(target),
+                "edge {edge:?} from {source:?} to {target:?} is dangling"
+            );
+        }
+        let blocks = self.weight.blocks.borrow();
+        for index in self.graph.node_indices() {
+            let node = &self.graph[index];
+            assert_eq!(node.node_index, index, "node {index:?} has a stale index");
+            if index == self.entry || index == self.exit {
+                continue;
+            }
+            let block = node.block;
+            assert!(
+                block.is_some_and(|id| blocks.get(id).is_some()),
+                "node {index:?} stands for no live block"
+            );
+        }
+        for (name_id, index) in &self.weight.block_nodes {
+            let block = self.graph.node_weight(*index).and_then(|node| node.block);
+            assert!(
+                block.is_some(),
+                "block {name_id} maps to node {index:?}, which is missing"
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct CodeBlock {
     pub id: CodeBlockId,
     pub block_type: BlockType,
-    pub irs_range: Vec<IR>,
+    irs_range: Vec<IR>,
     pub terminator: IR,
+    /// The gen set and kill mask of `irs_range` for reaching definitions, computed by
+    /// the first forward transfer and dropped whenever the instructions are borrowed
+    /// mutably, as renumbering them does too.
+    reach_transfer: OnceCell<(ReachLattice, ReachLattice)>,
 }
 
+#[derive(Clone)]
 pub struct CodeBlockAnalysisNode {
-    pub block: CodeBlockId,
+    /// `None` for the synthetic entry and exit nodes.
+    pub block: Option<CodeBlockId>,
     pub reach_in: ReachLattice,
     pub reach_out: ReachLattice,
+    pub live_in: LiveLattice,
+    pub live_out: LiveLattice,
     pub node_index: NodeIndex,
+    /// How many times the block is expected to run per call, from profile data or
+    /// [`estimate_frequencies`](crate::loops::estimate_frequencies). `None` until set,
+    /// and lost when the graph is rebuilt.
+    pub frequency: Option<f64>,
+}
+
+impl CodeBlockAnalysisNode {
+    pub fn new(block: Option<CodeBlockId>, node_index: NodeIndex) -> Self {
+        Self {
+            block,
+            reach_in: ReachLattice::new(0),
+            reach_out: ReachLattice::new(0),
+            live_in: LiveLattice::new(0),
+            live_out: LiveLattice::new(0),
+            node_index,
+            frequency: None,
+        }
+    }
 }
 
 impl CodeBlock {
@@ -157,49 +330,502 @@ impl CodeBlock {
             block_type,
             irs_range: irs,
             terminator,
+            reach_transfer: OnceCell::new(),
         }
     }
+    /// The instructions before the terminator, in order.
+    pub fn irs(&self) -> &[IR] {
+        &self.irs_range
+    }
+    /// The instructions before the terminator, to edit. Drops the cached transfer.
+    pub fn irs_mut(&mut self) -> &mut Vec<IR> {
+        self.reach_transfer.take();
+        &mut self.irs_range
+    }
+    /// The gen set and kill mask of the instructions for reaching definitions, numbered
+    /// as in `weight`. Computed once per edit of the block.
+    pub(crate) fn reach_transfer(
+        &self,
+        weight: &CodeBlockGraphWeight,
+    ) -> &(ReachLattice, ReachLattice) {
+        self.reach_transfer
+            .get_or_init(|| ReachLattice::gen_kill_mask_block(&self.irs_range, weight))
+    }
+    /// The jump ending the block.
+    pub fn terminator(&self) -> &IR {
+        &self.terminator
+    }
+    pub fn terminator_mut(&mut self) -> &mut IR {
+        &mut self.terminator
+    }
+    /// Removes and returns the instruction at `index`, shifting the later ones up.
+    /// The terminator, at `irs_range.len()`, is never removed: `None` for it and past it.
+    pub fn remove_instruction(&mut self, index: usize) -> Option<IR> {
+        (index < self.irs_range.len()).then(|| self.irs_mut().remove(index))
+    }
+    /// Inserts `ir` at `index`, before the instruction there, or last if `index` is
+    /// the terminator's. Jumps and positions after the terminator are rejected and
+    /// `ir` is given back. Declaration numbers are only fixed by `Function::build_graph`.
+    pub fn insert_before(&mut self, index: usize, ir: IR) -> Result<(), IR> {
+        if matches!(ir, IR::Jump(..)) || index > self.irs_range.len() {
+            return Err(ir);
+        }
+        self.irs_mut().insert(index, ir);
+        Ok(())
+    }
+    /// The sum of `IR::cost` over the block, terminator included.
+    pub fn estimated_cost(&self) -> usize {
+        self.irs_range
+            .iter()
+            .chain([&self.terminator])
+            .map(IR::cost)
+            .sum()
+    }
+    /// Inserts `ir` after the instruction at `index`, see `insert_before`.
+    /// Nothing can follow the terminator.
+    pub fn insert_after(&mut self, index: usize, ir: IR) -> Result<(), IR> {
+        if index >= self.irs_range.len() {
+            return Err(ir);
+        }
+        self.insert_before(index + 1, ir)
+    }
 }
 
 impl Display for CodeBlock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Block {}:", self.id.index())?;
-        self.irs_range
-            .iter()
-            .for_each(|ir| writeln!(f, "    {}", ir).expect(""));
-        writeln!(f, "=> {}", self.terminator)?;
+        if f.alternate() {
+            self.irs_range
+                .iter()
+                .for_each(|ir| writeln!(f, "    {:#}", ir).expect(""));
+            writeln!(f, "=> {:#}", self.terminator)?;
+        } else {
+            self.irs_range
+                .iter()
+                .for_each(|ir| writeln!(f, "    {}", ir).expect(""));
+            writeln!(f, "=> {}", self.terminator)?;
+        }
         Ok(())
     }
 }
 impl Display for CodeBlockAnalysisNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", self.block.index())?;
+        match self.block {
+            Some(block) => writeln!(f, "{}", block.index())?,
+            None => writeln!(f, "-")?,
+        }
 
         writeln!(f, "IN = {}, OUT = {}", self.reach_in, self.reach_out)?;
+        writeln!(
+            f,
+            "LIVE IN = {}, LIVE OUT = {}",
+            self.live_in, self.live_out
+        )?;
         Ok(())
     }
 }
 
 impl Block for CodeBlockAnalysisNode {
     fn entry() -> Self {
-        Self {
-            block: DefaultArenaBehavior::new_id(0, 0),
-            reach_in: ReachLattice::new(0),
-            reach_out: ReachLattice::new(0),
-            node_index: NodeIndex::new(0),
-        }
+        Self::new(None, NodeIndex::new(0))
     }
 
     fn exit() -> Self {
-        Self {
-            block: DefaultArenaBehavior::new_id(0, 0),
-            reach_in: ReachLattice::new(0),
-            reach_out: ReachLattice::new(0),
-            node_index: NodeIndex::new(1),
-        }
+        Self::new(None, NodeIndex::new(1))
     }
 
     fn set_node_index(&mut self, index: NodeIndex<u32>) {
         self.node_index = index
     }
 }
+
+impl Function {
+    /// Block name ids of this function in declaration order.
+    pub fn block_name_ids(&self) -> Vec<BlockNameId> {
+        let mut name_ids: Vec<BlockNameId> =
+            self.blocks.iter().map(|(_, name_id, _)| *name_id).collect();
+        name_ids.sort();
+        name_ids
+    }
+
+    /// Rebuilds `graph` from the current blocks.
+    ///
+    /// The first block is connected from the entry node, `ret`/`end` (and a
+    /// trailing `next`) are connected to the exit node. Each block's
+    /// `block_type` is set to match: `Entry` for the first block, `Exit` for the
+    /// others connected to the exit node and `Normal` for the rest. Every
+    /// assignment and store is given a fresh declaration number on the way.
+    pub fn build_graph(&mut self) {
+        let block_name_ids = self.block_name_ids();
+        let mut graph = DataFlowGraph::new(CodeBlockGraphWeight::new(self.blocks.arena().clone()));
+        graph.weight.recompute(self);
+        for name_id in &block_name_ids {
+            let id = self.blocks.get_id_from_name_id(name_id).unwrap();
+            // The type is settled below, once the block's successors are known
+            graph.add_block(*name_id, id, BlockType::Normal);
+        }
+        if let Some(first) = block_name_ids.first() {
+            let first_index = graph.weight.block_nodes[first];
+            graph.connect(graph.entry, first_index, EdgeKind::Fallthrough);
+        }
+        for (i, name_id) in block_name_ids.iter().enumerate() {
+            let index = graph.weight.block_nodes[name_id];
+            let id = self.blocks.get_id_from_name_id(name_id).unwrap();
+            let mut block = self.blocks.get_mut_from_id(id).unwrap();
+            let successors = match &block.terminator {
+                IR::Jump(JumpOperation::Unconditional(addr), _) => vec![(
+                    graph.weight.block_nodes[&addr.block_id],
+                    EdgeKind::Unconditional,
+                )],
+                IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => vec![
+                    (
+                        graph.weight.block_nodes[&true_addr.block_id],
+                        EdgeKind::True,
+                    ),
+                    (
+                        graph.weight.block_nodes[&false_addr.block_id],
+                        EdgeKind::False,
+                    ),
+                ],
+                IR::Jump(JumpOperation::Next, _) => vec![(
+                    block_name_ids
+                        .get(i + 1)
+                        .map_or(graph.exit, |next| graph.weight.block_nodes[next]),
+                    EdgeKind::Fallthrough,
+                )],
+                _ => vec![(graph.exit, EdgeKind::Unconditional)],
+            };
+            // A block leaving the function is an exit, unless it is also the entry
+            block.block_type = if i == 0 {
+                BlockType::Entry
+            } else if successors
+                .iter()
+                .any(|(successor, _)| *successor == graph.exit)
+            {
+                BlockType::Exit
+            } else {
+                BlockType::Normal
+            };
+            for (successor, kind) in successors {
+                graph.connect(index, successor, kind);
+            }
+        }
+        self.graph = graph;
+        self.graph.debug_assert_consistent();
+    }
+
+    /// Adds a block that becomes the only one leaving the function.
+    ///
+    /// Every block ending in `ret`, `end` or a trailing `next` jumps to it instead,
+    /// after copying the value it returned into a fresh return space, which the new
+    /// block returns. Functions returning `void` get no return space and the new
+    /// block ends with `end`. It is called `#exit`, or `#exit1`, `#exit2`, ... if
+    /// that is taken. Returns its name id.
+    pub fn add_virtual_exit(&mut self) -> BlockNameId {
+        let block_name_ids = self.block_name_ids();
+        let name = (0..)
+            .map(|i| match i {
+                0 => "#exit".to_string(),
+                _ => format!("#exit{i}"),
+            })
+            .find(|name| self.blocks.get_name_id(name).is_none())
+            .unwrap();
+        let (exit, exit_id) = self.lookup_or_insert_block(name);
+        let return_space = (self.return_type != DataType::Void).then(|| {
+            let scope = Scope::Local {
+                fn_name_id: self.name_id,
+            };
+            self.declare_space(Some(self.return_type.clone()), scope).0
+        });
+        for name_id in &block_name_ids {
+            let id = self.blocks.get_id_from_name_id(name_id).unwrap();
+            let mut block = self.blocks.get_mut_from_id(id).unwrap();
+            let returned = match block.terminator {
+                IR::Jump(JumpOperation::Ret(value), _) => Some(value),
+                IR::Jump(JumpOperation::End, _) => None,
+                IR::Jump(JumpOperation::Next, _) if block_name_ids.last() == Some(name_id) => None,
+                _ => continue,
+            };
+            if let (Some(value), Some(return_space)) = (returned, return_space) {
+                block.irs_mut().push(IR::Assignment(
+                    return_space,
+                    Operation::Unary(UnaryOp::Unit, value),
+                    IRInformation::default(),
+                ));
+            }
+            block.terminator = IR::Jump(
+                JumpOperation::Unconditional(AddressMarker::new(exit)),
+                IRInformation::default(),
+            );
+        }
+        let terminator = match return_space {
+            Some(space) => JumpOperation::Ret(space),
+            None => JumpOperation::End,
+        };
+        self.blocks.get_mut_from_id(exit_id).unwrap().terminator =
+            IR::Jump(terminator, IRInformation::default());
+        self.build_graph();
+        exit
+    }
+    /// The sum of `CodeBlock::estimated_cost` over the blocks of the function. Each
+    /// block counts once, whether or not it is in a loop, so this measures size rather
+    /// than running time.
+    pub fn estimated_cost(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter_map(|(_, _, block)| block.map(|block| block.estimated_cost()))
+            .sum()
+    }
+    /// `CodeBlock::remove_instruction` on `block`, then rebuilds the graph so the
+    /// remaining declarations are renumbered. `None` if nothing was removed.
+    pub fn remove_instruction(&mut self, block: BlockNameId, index: usize) -> Option<IR> {
+        let id = self.blocks.get_id_from_name_id(&block)?;
+        let removed = self.blocks.get_mut_from_id(id)?.remove_instruction(index)?;
+        self.build_graph();
+        Some(removed)
+    }
+    /// `CodeBlock::insert_before` on `block`, then rebuilds the graph so the
+    /// declarations are renumbered.
+    pub fn insert_instruction(
+        &mut self,
+        block: BlockNameId,
+        index: usize,
+        ir: IR,
+    ) -> Result<(), IR> {
+        let Some(id) = self.blocks.get_id_from_name_id(&block) else {
+            return Err(ir);
+        };
+        self.blocks
+            .get_mut_from_id(id)
+            .unwrap()
+            .insert_before(index, ir)?;
+        self.build_graph();
+        Ok(())
+    }
+    /// Computes `op` into a fresh temporary of type `data_type`, inserted before the
+    /// instruction at `index` of `block`. Returns the temporary, or `None` without
+    /// declaring it if there is no such position.
+    pub fn insert_assignment(
+        &mut self,
+        block: BlockNameId,
+        index: usize,
+        data_type: Option<DataType>,
+        op: Operation,
+    ) -> Option<SpaceNameId> {
+        let id = self.blocks.get_id_from_name_id(&block)?;
+        if index > self.blocks.get_from_id(id)?.irs().len() {
+            return None;
+        }
+        let scope = Scope::Local {
+            fn_name_id: self.name_id,
+        };
+        let (space, _) = self.declare_space(data_type, scope);
+        let ir = IR::Assignment(space, op, IRInformation::default());
+        self.insert_instruction(block, index, ir).ok()?;
+        Some(space)
+    }
+}
+
+/// A copy to try a transformation on, which can be dropped or kept without affecting
+/// the original.
+///
+/// Blocks and locals are shared through the program's pools, so the copy gets fresh
+/// entries in them, with fresh name ids but the same names, and its code and `graph`
+/// (node data and weight included) are rewritten to use them. Globals, constants and
+/// callees belong to the `Program` and stay shared: a store to a global or a newly
+/// interned constant is seen by both. The copy is not added to `Program::functions`,
+/// see `Program::clone_function` for that.
+impl Clone for Function {
+    fn clone(&self) -> Self {
+        self.clone_as(self.name.clone(), self.name_id)
+    }
+}
+
+impl Function {
+    /// `clone`, naming the copy `name` with name id `name_id`. Its locals are scoped
+    /// to `name_id`; calls, recursive ones included, keep their callees.
+    pub(super) fn clone_as(&self, name: String, name_id: FunctionNameId) -> Self {
+        let mut clone = Function::new(
+            self.program.clone(),
+            name,
+            name_id,
+            self.locals.empty_sibling(),
+            self.blocks.empty_sibling(),
+        );
+        clone.return_type = self.return_type.clone();
+        clone.is_declared = self.is_declared;
+        clone.is_extern = self.is_extern;
+        clone.is_variadic = self.is_variadic;
+        clone.is_defined = self.is_defined;
+        clone.is_pure = self.is_pure;
+
+        let mut spaces = HashMap::new();
+        for space in self.local_spaces() {
+            let id = self.locals.get_id_from_name_id(&space).unwrap();
+            let copy = self.locals.get_from_id(id).unwrap().clone();
+            spaces.insert(space, clone.locals.insert_nameless(copy).0);
+        }
+        let remap = |space: SpaceNameId| spaces.get(&space).copied().unwrap_or(space);
+        for copy in spaces.values() {
+            let id = clone.locals.get_id_from_name_id(copy).unwrap();
+            let mut copy = clone.locals.get_mut_from_id(id).unwrap();
+            copy.scope = Scope::Local {
+                fn_name_id: name_id,
+            };
+            remap_references(&mut copy, remap);
+        }
+        for (name, space) in self.locals.names() {
+            clone.locals.bind(name.clone(), remap(*space));
+        }
+        clone.params = self.params.iter().map(|param| remap(*param)).collect();
+
+        let mut blocks = HashMap::new();
+        let mut block_ids = HashMap::new();
+        for name_id in self.block_name_ids() {
+            let name = self.blocks.get_name(&name_id).unwrap().clone();
+            let (copy, copy_id) = clone.lookup_or_insert_block(name);
+            blocks.insert(name_id, copy);
+            block_ids.insert(self.blocks.get_id_from_name_id(&name_id).unwrap(), copy_id);
+        }
+        let retarget = |marker: &mut AddressMarker| marker.block_id = blocks[&marker.block_id];
+        for (id, copy_id) in &block_ids {
+            let block = self.blocks.get_from_id(*id).unwrap();
+            let (block_type, mut irs, mut terminator) = (
+                block.block_type,
+                block.irs().to_vec(),
+                block.terminator.clone(),
+            );
+            drop(block);
+            irs.iter_mut().for_each(|ir| ir.map_spaces(remap));
+            terminator.map_spaces(remap);
+            match &mut terminator {
+                IR::Jump(JumpOperation::Unconditional(marker), _) => retarget(marker),
+                IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                    retarget(true_addr);
+                    retarget(false_addr);
+                }
+                _ => {}
+            }
+            let mut copy = clone.blocks.get_mut_from_id(*copy_id).unwrap();
+            copy.block_type = block_type;
+            *copy.irs_mut() = irs;
+            copy.terminator = terminator;
+        }
+
+        let mut graph = self.graph.clone();
+        let space_count = (0..graph.weight.space_count)
+            .map(remap)
+            .max()
+            .map_or(0, |space| space + 1);
+        let remap_bits = |bits: &FixedBitSet| {
+            let mut remapped =
+                FixedBitSet::with_capacity(if bits.is_empty() { 0 } else { space_count });
+            for space in bits.ones().map(remap) {
+                remapped.grow(space + 1);
+                remapped.insert(space);
+            }
+            remapped
+        };
+        for node in graph.graph.node_weights_mut() {
+            node.block = node.block.map(|id| block_ids[&id]);
+            node.live_in.value = remap_bits(&node.live_in.value);
+            node.live_out.value = remap_bits(&node.live_out.value);
+        }
+        let weight = &mut graph.weight;
+        weight.space_count = space_count;
+        weight.block_nodes = weight
+            .block_nodes
+            .drain()
+            .map(|(name_id, node)| (blocks[&name_id], node))
+            .collect();
+        weight.variable_assignment_map = weight
+            .variable_assignment_map
+            .drain()
+            .map(|(space, declarations)| (remap(space), declarations))
+            .collect();
+        weight.aliased_spaces = weight.aliased_spaces.drain().map(remap).collect();
+        clone.graph = graph;
+        clone
+    }
+
+    /// Replaces each space in `map` with the one it maps to wherever the function refers
+    /// to it: in every instruction and terminator, in `params`, and in the members and
+    /// bases of the signatures and values of its locals. Spaces missing from `map` stay.
+    /// Names stay bound to the name ids they had, and the graph is rebuilt.
+    pub fn remap_spaces(&mut self, map: &HashMap<SpaceNameId, SpaceNameId>) {
+        let remap = |space: SpaceNameId| map.get(&space).copied().unwrap_or(space);
+        for space in self.local_spaces() {
+            let id = self.locals.get_id_from_name_id(&space).unwrap();
+            remap_references(&mut self.locals.get_mut_from_id(id).unwrap(), remap);
+        }
+        self.params
+            .iter_mut()
+            .for_each(|param| *param = remap(*param));
+        for name_id in self.block_name_ids() {
+            let id = self.blocks.get_id_from_name_id(&name_id).unwrap();
+            let mut block = self.blocks.get_mut_from_id(id).unwrap();
+            block
+                .irs_mut()
+                .iter_mut()
+                .for_each(|ir| ir.map_spaces(remap));
+            block.terminator.map_spaces(remap);
+        }
+        self.build_graph();
+    }
+
+    /// The locals reachable from the parameters, the bound names and the code, with
+    /// the members and bases of their signatures and values, in name id order.
+    fn local_spaces(&self) -> BTreeSet<SpaceNameId> {
+        let mut pending: Vec<SpaceNameId> = self.params.clone();
+        pending.extend(self.locals.names().map(|(_, space)| *space));
+        for (_, _, block) in self.blocks.iter() {
+            let Some(block) = block else {
+                continue;
+            };
+            for ir in block.irs().iter().chain([&block.terminator]) {
+                pending.extend(ir.used_spaces().into_iter().chain(ir.defined_space()));
+            }
+        }
+        let mut locals = BTreeSet::new();
+        while let Some(space) = pending.pop() {
+            if !self.is_local(space) || !locals.insert(space) {
+                continue;
+            }
+            let id = self.locals.get_id_from_name_id(&space).unwrap();
+            let space = self.locals.get_from_id(id).unwrap();
+            pending.extend(space.signature.members());
+            if let SpaceSignature::Offset(base, ..) = space.signature {
+                pending.push(base);
+            }
+            if let FlatLattice::Value(Value::Array(ArrayValue { value: members }))
+            | FlatLattice::Value(Value::Struct(StructValue { value: members })) = &space.value
+            {
+                pending.extend(members);
+            }
+        }
+        locals
+    }
+}
+
+/// Rewrites the spaces `space` refers to, the members and base of its signature and
+/// the members of an aggregate it holds, with `remap`.
+fn remap_references(space: &mut Space, remap: impl Fn(SpaceNameId) -> SpaceNameId) {
+    match &mut space.signature {
+        SpaceSignature::Normal(_, members) => members.iter_mut().for_each(|member| {
+            *member = remap(*member);
+        }),
+        SpaceSignature::Offset(base, _, _, members) => {
+            *base = remap(*base);
+            members
+                .iter_mut()
+                .for_each(|member| *member = remap(*member));
+        }
+    }
+    if let FlatLattice::Value(Value::Array(ArrayValue { value: members }))
+    | FlatLattice::Value(Value::Struct(StructValue { value: members })) = &mut space.value
+    {
+        members
+            .iter_mut()
+            .for_each(|member| *member = remap(*member));
+    }
+}
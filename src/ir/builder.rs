@@ -0,0 +1,151 @@
+use super::{
+    block::CodeBlockId, AddressMarker, BinaryOp, BlockNameId, CommandOperation, CompareType,
+    DataType, Function, IRInformation, IntValue, JumpOperation, Operation, ScalarType, Scope,
+    SpaceNameId, UnaryOp, Value, IR,
+};
+
+/// Appends instructions to one block of a [`Function`].
+///
+/// Value-producing methods return the name id of the space they assign to.
+/// Terminators consume the builder, write the block back and rebuild the
+/// function's graph, which also renumbers every declaration.
+pub struct BlockBuilder<'f> {
+    function: &'f mut Function,
+    block: CodeBlockId,
+    irs: Vec<IR>,
+}
+
+impl Function {
+    /// Declares a parameter local and appends it to `params`.
+    pub fn declare_param(&mut self, name: String, data_type: DataType) -> SpaceNameId {
+        let (name_id, _) = self.declare_local(name, Some(data_type));
+        self.params.push(name_id);
+        name_id
+    }
+    /// Starts appending to the block called `name`, creating it if needed.
+    pub fn build_block(&mut self, name: &str) -> BlockBuilder<'_> {
+        let (_, block) = self.lookup_or_insert_block(name.to_string());
+        BlockBuilder {
+            function: self,
+            block,
+            irs: vec![],
+        }
+    }
+}
+
+impl<'f> BlockBuilder<'f> {
+    /// Interns an integer constant.
+    pub fn int(&mut self, value: i64) -> SpaceNameId {
+        self.function
+            .program
+            .borrow_mut()
+            .lookup_or_insert_constant(Value::Int(IntValue::i64(value)))
+            .0
+    }
+    /// Looks up the space called `name`, declaring a local if it does not exist.
+    pub fn space(&mut self, name: &str) -> SpaceNameId {
+        self.function.lookup_or_insert_space(name.to_string()).0
+    }
+    /// Declares a nameless local of the given type.
+    pub fn temp(&mut self, data_type: DataType) -> SpaceNameId {
+        let scope = Scope::Local {
+            fn_name_id: self.function.name_id,
+        };
+        self.function.declare_space(Some(data_type), scope).0
+    }
+    pub fn assign(&mut self, dst: &str, op: Operation) -> SpaceNameId {
+        let dst = self.space(dst);
+        self.push_assignment(dst, op)
+    }
+    pub fn binary(
+        &mut self,
+        dst: &str,
+        op: BinaryOp,
+        a: SpaceNameId,
+        b: SpaceNameId,
+    ) -> SpaceNameId {
+        self.assign(dst, Operation::Binary(op, a, b))
+    }
+    pub fn add(&mut self, dst: &str, a: SpaceNameId, b: SpaceNameId) -> SpaceNameId {
+        self.binary(dst, BinaryOp::Add, a, b)
+    }
+    pub fn sub(&mut self, dst: &str, a: SpaceNameId, b: SpaceNameId) -> SpaceNameId {
+        self.binary(dst, BinaryOp::Sub, a, b)
+    }
+    pub fn mul(&mut self, dst: &str, a: SpaceNameId, b: SpaceNameId) -> SpaceNameId {
+        self.binary(dst, BinaryOp::Mul, a, b)
+    }
+    pub fn div(&mut self, dst: &str, a: SpaceNameId, b: SpaceNameId) -> SpaceNameId {
+        self.binary(dst, BinaryOp::Div, a, b)
+    }
+    pub fn unary(&mut self, dst: &str, op: UnaryOp, a: SpaceNameId) -> SpaceNameId {
+        self.assign(dst, Operation::Unary(op, a))
+    }
+    /// `dst = cast to src`
+    pub fn cast(&mut self, dst: &str, to: ScalarType, src: SpaceNameId) -> SpaceNameId {
+        self.unary(dst, UnaryOp::Cast(to), src)
+    }
+    /// `dst = src`
+    pub fn copy(&mut self, dst: &str, src: SpaceNameId) -> SpaceNameId {
+        self.unary(dst, UnaryOp::Unit, src)
+    }
+    /// Compares into a fresh `bool` temporary.
+    pub fn compare(&mut self, cmp: CompareType, a: SpaceNameId, b: SpaceNameId) -> SpaceNameId {
+        let dst = self.temp(DataType::Bool);
+        self.push_assignment(dst, Operation::Compare(cmp, a, b))
+    }
+    /// `dst <- src`
+    pub fn store(&mut self, dst: SpaceNameId, src: SpaceNameId) -> &mut Self {
+        self.irs.push(IR::Command(
+            CommandOperation::Store(dst, src),
+            IRInformation::default(),
+        ));
+        self
+    }
+    pub fn jump(mut self, target: &str) {
+        let target = self.block_name_id(target);
+        self.terminate(JumpOperation::Unconditional(AddressMarker::new(target)))
+    }
+    pub fn branch(mut self, cond: SpaceNameId, true_target: &str, false_target: &str) {
+        let true_target = self.block_name_id(true_target);
+        let false_target = self.block_name_id(false_target);
+        self.terminate(JumpOperation::Branch(
+            cond,
+            AddressMarker::new(true_target),
+            AddressMarker::new(false_target),
+        ))
+    }
+    pub fn ret(self, value: SpaceNameId) {
+        self.terminate(JumpOperation::Ret(value))
+    }
+    pub fn end(self) {
+        self.terminate(JumpOperation::End)
+    }
+    pub fn next(self) {
+        self.terminate(JumpOperation::Next)
+    }
+
+    fn push_assignment(&mut self, dst: SpaceNameId, op: Operation) -> SpaceNameId {
+        self.irs
+            .push(IR::Assignment(dst, op, IRInformation::default()));
+        dst
+    }
+    /// Blocks created here are filled in by a later builder.
+    fn block_name_id(&mut self, name: &str) -> BlockNameId {
+        self.function.lookup_or_insert_block(name.to_string()).0
+    }
+    fn terminate(self, terminator: JumpOperation) {
+        let BlockBuilder {
+            function,
+            block,
+            irs,
+        } = self;
+        {
+            let mut code_block = function.blocks.get_mut_from_id(block).unwrap();
+            code_block.irs_mut().extend(irs);
+            code_block.terminator = IR::Jump(terminator, IRInformation::default());
+        }
+        function.is_defined = true;
+        function.build_graph();
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use super::{
+    BlockNameId, CommandOperation, Function, FunctionNameId, JumpOperation, Operation, Program,
+    SpaceNameId, Value, IR,
+};
+
+/// Matches the spaces of two functions against each other. Name ids differ between
+/// builds, so locals are paired by first occurrence and must keep pairing consistently,
+/// while globals are compared by name and constants by value.
+struct Matcher<'a> {
+    left: &'a Function,
+    right: &'a Function,
+    locals: HashMap<SpaceNameId, SpaceNameId>,
+    locals_back: HashMap<SpaceNameId, SpaceNameId>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(left: &'a Function, right: &'a Function) -> Self {
+        Self {
+            left,
+            right,
+            locals: HashMap::new(),
+            locals_back: HashMap::new(),
+        }
+    }
+    fn space(&mut self, a: SpaceNameId, b: SpaceNameId) -> bool {
+        match (self.left.is_local(a), self.right.is_local(b)) {
+            (true, true) => self.local(a, b),
+            (false, false) => self.global(a, b),
+            _ => false,
+        }
+    }
+    fn local(&mut self, a: SpaceNameId, b: SpaceNameId) -> bool {
+        if let Some(paired) = self.locals.get(&a) {
+            return *paired == b;
+        }
+        if self.locals_back.contains_key(&b) {
+            return false;
+        }
+        let signature = |function: &Function, space| {
+            let id = function.locals.get_id_from_name_id(&space)?;
+            let space = function.locals.get_from_id(id)?;
+            Some((space.signature.get_type(), space.signature.members()))
+        };
+        let (Some((left_type, left_members)), Some((right_type, right_members))) =
+            (signature(self.left, a), signature(self.right, b))
+        else {
+            return false;
+        };
+        if left_type != right_type
+            || self.left.locals.get_name(&a) != self.right.locals.get_name(&b)
+        {
+            return false;
+        }
+        self.locals.insert(a, b);
+        self.locals_back.insert(b, a);
+        self.spaces(&left_members, &right_members)
+    }
+    fn global(&mut self, a: SpaceNameId, b: SpaceNameId) -> bool {
+        match (self.left.const_value(a), self.right.const_value(b)) {
+            (Some(left), Some(right)) => self.value(&left, &right),
+            (None, None) => {
+                let name = |function: &Function, space| {
+                    function
+                        .program()
+                        .borrow()
+                        .globals
+                        .get_name(&space)
+                        .cloned()
+                };
+                let left = name(self.left, a);
+                left.is_some() && left == name(self.right, b)
+            }
+            _ => false,
+        }
+    }
+    fn value(&mut self, a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Array(left), Value::Array(right)) => self.spaces(&left.value, &right.value),
+            (Value::Struct(left), Value::Struct(right)) => self.spaces(&left.value, &right.value),
+            _ => a == b,
+        }
+    }
+    fn spaces(&mut self, a: &[SpaceNameId], b: &[SpaceNameId]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.space(*a, *b))
+    }
+    fn block(&self, a: BlockNameId, b: BlockNameId) -> bool {
+        self.left.blocks.get_name(&a) == self.right.blocks.get_name(&b)
+    }
+    fn function(&self, a: FunctionNameId, b: FunctionNameId) -> bool {
+        let name = |function: &Function, callee| {
+            function
+                .program()
+                .borrow()
+                .functions
+                .get_name(&callee)
+                .cloned()
+        };
+        name(self.left, a) == name(self.right, b)
+    }
+    fn ir(&mut self, a: &IR, b: &IR) -> bool {
+        match (a, b) {
+            (IR::Assignment(left, left_op, _), IR::Assignment(right, right_op, _)) => {
+                self.space(*left, *right) && self.operation(left_op, right_op)
+            }
+            (
+                IR::Command(CommandOperation::Store(left_dst, left_src), _),
+                IR::Command(CommandOperation::Store(right_dst, right_src), _),
+            ) => self.space(*left_dst, *right_dst) && self.space(*left_src, *right_src),
+            (IR::Jump(left, _), IR::Jump(right, _)) => self.jump(left, right),
+            _ => false,
+        }
+    }
+    fn operation(&mut self, a: &Operation, b: &Operation) -> bool {
+        match (a, b) {
+            (Operation::Binary(left, a1, a2), Operation::Binary(right, b1, b2)) => {
+                left == right && self.space(*a1, *b1) && self.space(*a2, *b2)
+            }
+            (Operation::Compare(left, a1, a2), Operation::Compare(right, b1, b2)) => {
+                left == right && self.space(*a1, *b1) && self.space(*a2, *b2)
+            }
+            (Operation::Unary(left, a), Operation::Unary(right, b)) => {
+                left == right && self.space(*a, *b)
+            }
+            (Operation::Call(a), Operation::Call(b)) => self.function(*a, *b),
+            (Operation::Select(a, a1, a2), Operation::Select(b, b1, b2)) => {
+                self.space(*a, *b) && self.space(*a1, *b1) && self.space(*a2, *b2)
+            }
+            _ => false,
+        }
+    }
+    fn jump(&mut self, a: &JumpOperation, b: &JumpOperation) -> bool {
+        match (a, b) {
+            (JumpOperation::Unconditional(a), JumpOperation::Unconditional(b)) => {
+                self.block(a.block_id, b.block_id)
+            }
+            (
+                JumpOperation::Branch(a, a_true, a_false),
+                JumpOperation::Branch(b, b_true, b_false),
+            ) => {
+                self.space(*a, *b)
+                    && self.block(a_true.block_id, b_true.block_id)
+                    && self.block(a_false.block_id, b_false.block_id)
+            }
+            (JumpOperation::Ret(a), JumpOperation::Ret(b)) => self.space(*a, *b),
+            (JumpOperation::Next, JumpOperation::Next)
+            | (JumpOperation::End, JumpOperation::End) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Function {
+    /// Whether `self` and `other` have the same signature, blocks and code, up to the
+    /// name ids their spaces were given. The functions may belong to different programs.
+    ///
+    /// Blocks are compared in `block_name_ids` order and must have the same names; locals
+    /// must have the same names and types and be used in the same places.
+    pub fn structurally_eq(&self, other: &Function) -> bool {
+        if self.name != other.name
+            || self.return_type != other.return_type
+            || self.is_variadic != other.is_variadic
+            || self.is_declared != other.is_declared
+            || self.is_extern != other.is_extern
+            || self.is_defined != other.is_defined
+            || self.is_pure != other.is_pure
+        {
+            return false;
+        }
+        let mut matcher = Matcher::new(self, other);
+        if !matcher.spaces(&self.params, &other.params) {
+            return false;
+        }
+        let (left_blocks, right_blocks) = (self.block_name_ids(), other.block_name_ids());
+        left_blocks.len() == right_blocks.len()
+            && left_blocks.iter().zip(&right_blocks).all(|(a, b)| {
+                let (Some(left), Some(right)) = (
+                    self.blocks
+                        .get_name(a)
+                        .and_then(|name| self.blocks.get(name)),
+                    other
+                        .blocks
+                        .get_name(b)
+                        .and_then(|name| other.blocks.get(name)),
+                ) else {
+                    return false;
+                };
+                matcher.block(*a, *b)
+                    && left.block_type == right.block_type
+                    && left.irs().len() == right.irs().len()
+                    && left
+                        .irs()
+                        .iter()
+                        .zip(right.irs())
+                        .all(|(a, b)| matcher.ir(a, b))
+                    && matcher.ir(&left.terminator, &right.terminator)
+            })
+    }
+}
+
+impl Program {
+    /// Whether both programs define the same functions, pairwise `Function::structurally_eq`.
+    pub fn structurally_eq(&self, other: &Program) -> bool {
+        let functions = |program: &Program| {
+            let mut names: Vec<_> = program
+                .functions
+                .iter()
+                .map(|(name, _, _)| name.clone())
+                .collect();
+            names.sort();
+            names
+        };
+        let names = functions(self);
+        names == functions(other)
+            && names.iter().all(|name| {
+                match (self.functions.get(name), other.functions.get(name)) {
+                    (Some(left), Some(right)) => left.structurally_eq(&right),
+                    _ => false,
+                }
+            })
+    }
+}
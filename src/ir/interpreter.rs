@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::semilattice::FlatLattice;
+
+use super::{
+    BlockNameId, CommandOperation, Function, FunctionNameId, JumpOperation, Operation, ProgramRef,
+    Scope, SpaceNameId, UnaryOp, Value, IR,
+};
+
+/// Upper bound on executed instructions, so a non-terminating function fails instead of hanging.
+const STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum InterpretError {
+    #[error("read of undefined space {space}")]
+    UndefinedSpace { space: SpaceNameId },
+    #[error("undefined function {name_id}")]
+    UndefinedFunction { name_id: FunctionNameId },
+    #[error("function {name} is declared but not defined")]
+    NotDefined { name: String },
+    #[error("wrong number of arguments: expected {expected}, found {found}")]
+    ArityMismatch { expected: usize, found: usize },
+    #[error("invalid operation: {ir}")]
+    InvalidOperation { ir: String },
+    #[error("step limit exceeded")]
+    StepLimitExceeded,
+}
+
+/// Executes `function` with `args` bound to its parameters and returns the value it returns.
+///
+/// Calls follow the three-address convention: the arguments of a `call` are the
+/// values passed through `param` since the previous call. `end` returns `Value::Void`.
+pub fn interpret(function: &Function, args: &[Value]) -> Result<Value, InterpretError> {
+    Interpreter {
+        program: function.program.clone(),
+        globals: HashMap::new(),
+        steps: 0,
+    }
+    .call(function, args)
+}
+
+struct Interpreter {
+    program: ProgramRef,
+    globals: HashMap<SpaceNameId, Value>,
+    steps: usize,
+}
+
+struct Frame {
+    locals: HashMap<SpaceNameId, Value>,
+    params: Vec<Value>,
+}
+
+impl Interpreter {
+    fn call(&mut self, function: &Function, args: &[Value]) -> Result<Value, InterpretError> {
+        if !function.is_defined {
+            return Err(InterpretError::NotDefined {
+                name: function.name.clone(),
+            });
+        }
+        if function.params.len() != args.len() {
+            return Err(InterpretError::ArityMismatch {
+                expected: function.params.len(),
+                found: args.len(),
+            });
+        }
+        let mut frame = Frame {
+            locals: function
+                .params
+                .iter()
+                .copied()
+                .zip(args.iter().cloned())
+                .collect(),
+            params: vec![],
+        };
+        let block_name_ids = function.block_name_ids();
+        let Some(mut current) = block_name_ids.first().copied() else {
+            return Ok(Value::Void);
+        };
+        loop {
+            let block_id = function.blocks.get_id_from_name_id(&current).unwrap();
+            let block = function.blocks.get_from_id(block_id).unwrap();
+            for ir in block.irs().iter() {
+                self.step()?;
+                self.execute(function, &mut frame, ir)?;
+            }
+            self.step()?;
+            let next = match &block.terminator {
+                IR::Jump(JumpOperation::Unconditional(addr), _) => addr.block_id,
+                IR::Jump(JumpOperation::Branch(cond, true_addr, false_addr), _) => {
+                    let cond = self.read(function, &frame, *cond)?;
+                    match cond.is_truthy() {
+                        Some(true) => true_addr.block_id,
+                        Some(false) => false_addr.block_id,
+                        None => return Err(invalid(&block.terminator)),
+                    }
+                }
+                IR::Jump(JumpOperation::Next, _) => match next_block(&block_name_ids, current) {
+                    Some(next) => next,
+                    None => return Ok(Value::Void),
+                },
+                IR::Jump(JumpOperation::Ret(v), _) => return self.read(function, &frame, *v),
+                IR::Jump(JumpOperation::End, _) => return Ok(Value::Void),
+                ir => return Err(invalid(ir)),
+            };
+            current = next;
+        }
+    }
+
+    fn execute(
+        &mut self,
+        function: &Function,
+        frame: &mut Frame,
+        ir: &IR,
+    ) -> Result<(), InterpretError> {
+        match ir {
+            IR::Assignment(dst, op, _) => {
+                let value = match op {
+                    Operation::Binary(op, a, b) => {
+                        let a = self.read(function, frame, *a)?;
+                        let b = self.read(function, frame, *b)?;
+                        a.binary_in(*op, &b, function.program().borrow().overflow)
+                    }
+                    Operation::Compare(cmp, a, b) => {
+                        let a = self.read(function, frame, *a)?;
+                        let b = self.read(function, frame, *b)?;
+                        a.compare(*cmp, &b)
+                    }
+                    Operation::Unary(op, a) => {
+                        let a = self.read(function, frame, *a)?;
+                        if let UnaryOp::Param = op {
+                            frame.params.push(a.clone());
+                        }
+                        a.unary(*op)
+                    }
+                    Operation::Call(name_id) => {
+                        let args = std::mem::take(&mut frame.params);
+                        Some(self.call_by_name_id(*name_id, &args)?)
+                    }
+                    Operation::Select(cond, a, b) => {
+                        match self.read(function, frame, *cond)?.is_truthy() {
+                            Some(true) => Some(self.read(function, frame, *a)?),
+                            Some(false) => Some(self.read(function, frame, *b)?),
+                            None => None,
+                        }
+                    }
+                }
+                .ok_or_else(|| invalid(ir))?;
+                self.write(function, frame, *dst, value);
+            }
+            IR::Command(CommandOperation::Store(dst, src), _) => {
+                let value = self.read(function, frame, *src)?;
+                self.write(function, frame, *dst, value);
+            }
+            IR::Jump(..) => return Err(invalid(ir)),
+        }
+        Ok(())
+    }
+
+    fn call_by_name_id(
+        &mut self,
+        name_id: FunctionNameId,
+        args: &[Value],
+    ) -> Result<Value, InterpretError> {
+        let function_pool = self.program.borrow().function_pool.clone();
+        let function_pool = function_pool.borrow();
+        let callee = function_pool
+            .get_id(&name_id)
+            .and_then(|id| function_pool.get_from_id(*id))
+            .ok_or(InterpretError::UndefinedFunction { name_id })?;
+        self.call(&callee, args)
+    }
+
+    fn read(
+        &self,
+        function: &Function,
+        frame: &Frame,
+        space: SpaceNameId,
+    ) -> Result<Value, InterpretError> {
+        if let Some(value) = frame
+            .locals
+            .get(&space)
+            .or_else(|| self.globals.get(&space))
+        {
+            return Ok(value.clone());
+        }
+        // Constants and initialized globals carry their value in the space itself
+        let id = function.locals.get_id_from_name_id(&space);
+        match id.and_then(|id| function.locals.get_from_id(id)).as_deref() {
+            Some(super::Space {
+                scope: Scope::Global,
+                value: FlatLattice::Value(value),
+                ..
+            }) => Ok(value.clone()),
+            _ => Err(InterpretError::UndefinedSpace { space }),
+        }
+    }
+
+    fn write(&mut self, function: &Function, frame: &mut Frame, space: SpaceNameId, value: Value) {
+        let id = function.locals.get_id_from_name_id(&space);
+        let is_global = id
+            .and_then(|id| function.locals.get_from_id(id))
+            .is_some_and(|space| space.scope == Scope::Global);
+        if is_global {
+            self.globals.insert(space, value);
+        } else {
+            frame.locals.insert(space, value);
+        }
+    }
+
+    fn step(&mut self) -> Result<(), InterpretError> {
+        self.steps += 1;
+        if self.steps > STEP_LIMIT {
+            Err(InterpretError::StepLimitExceeded)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn next_block(block_name_ids: &[BlockNameId], current: BlockNameId) -> Option<BlockNameId> {
+    let position = block_name_ids.iter().position(|id| *id == current)?;
+    block_name_ids.get(position + 1).copied()
+}
+
+fn invalid(ir: &IR) -> InterpretError {
+    InterpretError::InvalidOperation { ir: ir.to_string() }
+}
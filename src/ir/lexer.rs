@@ -8,12 +8,15 @@ pub enum TokenKind {
     FunctionId,
     Dot,
     Colon,
+    Semicolon,
     Comma,
     QuestionMark,
     Fn,
     Stub,
     Impl,
     Extern,
+    Let,
+    Global,
     OpenParen,
     CloseParen,
     OpenBrace,
@@ -41,20 +44,35 @@ pub enum TokenKind {
     Store,
     Load,
     Param,
+    Pure,
     Call,
+    Cast,
+    Select,
+    I8,
+    I16,
+    I32,
     I64,
+    U8,
+    U16,
+    U32,
+    U64,
     IntLiteral,
     IntBinLiteral,
     IntHexLiteral,
+    IntOctLiteral,
     RealLiteral,
+    StringLiteral,
+    True,
+    False,
     F64,
     Bool,
+    Str,
     Void,
     Error,
     Eof,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Cursor {
     pub line: usize,
     pub column: usize,
@@ -93,25 +111,82 @@ impl Display for Token {
     }
 }
 
+impl Token {
+    /// The value of an integer literal, or `None` if it does not fit in an `i64`.
+    ///
+    /// Accepts an optional `-`, then `0x`, `0b` or `0o` (or the older `h` and `b`)
+    /// prefixes in either case, or decimal digits.
+    pub fn int_value(&self) -> Option<i64> {
+        let (sign, literal) = match self.content.strip_prefix('-') {
+            Some(literal) => ("-", literal),
+            None => ("", self.content.as_str()),
+        };
+        let prefixes = [
+            (["0x", "0X"], 16),
+            (["0b", "0B"], 2),
+            (["0o", "0O"], 8),
+            (["h", "H"], 16),
+            (["b", "B"], 2),
+        ];
+        let (digits, radix) = prefixes
+            .iter()
+            .find_map(|(prefix, radix)| {
+                prefix
+                    .iter()
+                    .find_map(|prefix| literal.strip_prefix(prefix))
+                    .map(|digits| (digits, *radix))
+            })
+            .unwrap_or((literal, 10));
+        if digits.is_empty() || digits.starts_with(['+', '-']) {
+            return None;
+        }
+        i64::from_str_radix(&format!("{sign}{digits}"), radix).ok()
+    }
+    /// The contents of a string literal with its escapes (`\n`, `\t`, `\"` and `\\`)
+    /// replaced, or `None` if it is unterminated or has any other escape.
+    pub fn string_value(&self) -> Option<String> {
+        let literal = self.content.strip_prefix('"')?.strip_suffix('"')?;
+        let mut value = String::new();
+        let mut chars = literal.chars();
+        while let Some(c) = chars.next() {
+            value.push(match c {
+                '\\' => match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    '"' => '"',
+                    '\\' => '\\',
+                    _ => return None,
+                },
+                '"' => return None,
+                c => c,
+            });
+        }
+        Some(value)
+    }
+}
+
 pub struct Tokenize<Iter: Iterator<Item = char>> {
     iter_source: Peekable<Iter>,
     cursor: Cursor,
-    buffer: [char; 256],
+    buffer: Vec<char>,
     current_buffer_length: usize,
     buffer_start_cursor: Cursor,
     buffer_end_cursor: Cursor,
     finished: bool,
+    /// Whether the last token ends an operand, after which `-` is a subtraction
+    after_operand: bool,
 }
 impl<Iter: Iterator<Item = char>> Tokenize<Iter> {
     fn new(iter_source: Peekable<Iter>) -> Self {
         Self {
             iter_source,
             cursor: Cursor { line: 0, column: 0 },
-            buffer: ['\0'; 256],
+            buffer: Vec::new(),
             current_buffer_length: 0,
             buffer_start_cursor: Cursor { line: 0, column: 0 },
             buffer_end_cursor: Cursor { line: 0, column: 0 },
             finished: false,
+            after_operand: false,
         }
     }
     fn peek_char(&mut self) -> Option<&char> {
@@ -119,7 +194,9 @@ impl<Iter: Iterator<Item = char>> Tokenize<Iter> {
     }
     fn consume_char(&mut self) -> Option<char> {
         let next = self.iter_source.next()?;
-        self.buffer[self.current_buffer_length] = next;
+        // String literals can be longer than any earlier token, so the buffer grows
+        self.buffer.truncate(self.current_buffer_length);
+        self.buffer.push(next);
         self.current_buffer_length += 1;
         self.buffer_end_cursor = self.cursor;
         match next {
@@ -203,20 +280,51 @@ impl<Iter: Iterator<Item = char>> Tokenize<Iter> {
     fn error_token(&self) -> Token {
         self.create_token(TokenKind::Error)
     }
-    fn match_num(&mut self) -> TokenKind {
-        if self.match_one_or_more_range('0'..='9') {
-            if self.match_char('.').is_some() {
-                self.match_one_or_more_range('0'..='9');
-                TokenKind::RealLiteral
-            } else {
-                TokenKind::IntLiteral
+    /// Matches a numeric literal, or returns `None` without consuming anything but
+    /// a literal prefix if there is none.
+    fn match_num(&mut self) -> Option<TokenKind> {
+        let kind = if self.match_char('0').is_some() {
+            match self.match_char_of("xXbBoO".chars()) {
+                Some('x' | 'X') => self.match_radix_digits(16, TokenKind::IntHexLiteral),
+                Some('b' | 'B') => self.match_radix_digits(2, TokenKind::IntBinLiteral),
+                Some(_) => self.match_radix_digits(8, TokenKind::IntOctLiteral),
+                None => self.match_decimal(),
             }
+        } else if self.match_one_or_more_range('1'..='9') {
+            self.match_decimal()
         } else if self.match_char_of("hH".chars()).is_some() {
-            self.match_one_or_more_fn(|c| c.is_ascii_hexdigit());
+            // Without digits the prefix starts a keyword, which is lexed from the buffer
+            if !self.match_one_or_more_fn(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
             TokenKind::IntHexLiteral
         } else if self.match_char_of("bB".chars()).is_some() {
-            self.match_one_or_more_fn(|c| *c == '0' || *c == '1');
+            if !self.match_one_or_more_fn(|c| *c == '0' || *c == '1') {
+                return None;
+            }
             TokenKind::IntBinLiteral
+        } else {
+            return None;
+        };
+        let is_int = !matches!(kind, TokenKind::RealLiteral | TokenKind::Error);
+        // Literals that overflow an i64 are errors spanning the whole literal
+        if is_int && self.create_token(kind.clone()).int_value().is_none() {
+            return Some(TokenKind::Error);
+        }
+        Some(kind)
+    }
+    fn match_decimal(&mut self) -> TokenKind {
+        while self.match_char_range('0'..='9').is_some() {}
+        if self.match_char('.').is_some() {
+            self.match_one_or_more_range('0'..='9');
+            TokenKind::RealLiteral
+        } else {
+            TokenKind::IntLiteral
+        }
+    }
+    fn match_radix_digits(&mut self, radix: u32, kind: TokenKind) -> TokenKind {
+        if self.match_one_or_more_fn(|c| c.is_digit(radix)) {
+            kind
         } else {
             TokenKind::Error
         }
@@ -227,16 +335,40 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let token = self.next_token()?;
+        self.after_operand = matches!(
+            token.kind,
+            TokenKind::SpaceId
+                | TokenKind::IntLiteral
+                | TokenKind::IntBinLiteral
+                | TokenKind::IntHexLiteral
+                | TokenKind::IntOctLiteral
+                | TokenKind::RealLiteral
+                | TokenKind::StringLiteral
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::CloseParen
+                | TokenKind::CloseBracket
+                | TokenKind::CloseBrace
+        );
+        Some(token)
+    }
+}
+
+impl<T: Iterator<Item = char>> Tokenize<T> {
+    fn next_token(&mut self) -> Option<Token> {
         if self.finished {
             return None;
         }
         self.skip_ws();
         self.clear_buffer();
-        match self.match_num() {
-            TokenKind::Error => {}
-            kind => return Some(self.create_token(kind)),
+        if let Some(kind) = self.match_num() {
+            return Some(self.create_token(kind));
         }
-        let c = self.consume_char();
+        let c = match self.current_buffer_length {
+            0 => self.consume_char(),
+            _ => Some(self.buffer[0]),
+        };
         if c.is_none() {
             self.finished = true;
             return Some(self.create_token(TokenKind::Eof));
@@ -250,6 +382,19 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
             '}' => self.create_token(TokenKind::CloseBrace),
             '/' => self.create_token(TokenKind::Div),
             '?' => self.create_token(TokenKind::QuestionMark),
+            '"' => {
+                while let Some(c) = self.match_fn(|c| *c != '"') {
+                    if c == '\\' {
+                        self.consume_char();
+                    }
+                }
+                self.match_char('"');
+                let token = self.create_token(TokenKind::StringLiteral);
+                match token.string_value() {
+                    Some(_) => token,
+                    None => self.error_token(),
+                }
+            }
             '=' => {
                 if self.match_char('=').is_some() {
                     self.create_token(TokenKind::Eq)
@@ -261,11 +406,11 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
             }
             '+' => self.create_token(TokenKind::Add),
             '*' => self.create_token(TokenKind::Mul),
+            // A minus after an operand is infix, otherwise it may start a negative literal
+            '-' if self.after_operand => self.create_token(TokenKind::Sub),
             '-' => match self.match_num() {
-                TokenKind::Error => {
-                    self.create_token(TokenKind::Sub)
-                }
-                kind => self.create_token(kind),
+                None => self.create_token(TokenKind::Sub),
+                Some(kind) => self.create_token(kind),
             },
             '<' => {
                 if self.match_char('-').is_some() {
@@ -287,15 +432,6 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
                 while self.match_alnum().is_some() {}
                 self.create_token(TokenKind::SpaceId)
             }
-            '0'..='9' => {
-                self.match_one_or_more_range('0'..='9');
-                if self.match_char('.').is_some() {
-                    self.match_one_or_more_range('0'..='9');
-                    self.create_token(TokenKind::RealLiteral)
-                } else {
-                    self.create_token(TokenKind::IntLiteral)
-                }
-            }
             '#' => {
                 while self.match_alnum().is_some() {}
                 self.create_token(TokenKind::BlockId)
@@ -328,8 +464,12 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
                 }
             }
             'c' => {
-                if self.match_string("all").is_some() {
+                if self.match_char('a').is_none() {
+                    self.error_token()
+                } else if self.match_string("ll").is_some() {
                     self.create_token(TokenKind::Call)
+                } else if self.match_string("st").is_some() {
+                    self.create_token(TokenKind::Cast)
                 } else {
                     self.error_token()
                 }
@@ -348,6 +488,15 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
                     self.create_token(TokenKind::Fn)
                 } else if self.match_string("64").is_some() {
                     self.create_token(TokenKind::F64)
+                } else if self.match_string("alse").is_some() {
+                    self.create_token(TokenKind::False)
+                } else {
+                    self.error_token()
+                }
+            }
+            'g' => {
+                if self.match_string("lobal").is_some() {
+                    self.create_token(TokenKind::Global)
                 } else {
                     self.error_token()
                 }
@@ -362,6 +511,12 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
             'i' => {
                 if self.match_string("mpl").is_some() {
                     self.create_token(TokenKind::Impl)
+                } else if self.match_string("8").is_some() {
+                    self.create_token(TokenKind::I8)
+                } else if self.match_string("16").is_some() {
+                    self.create_token(TokenKind::I16)
+                } else if self.match_string("32").is_some() {
+                    self.create_token(TokenKind::I32)
                 } else if self.match_string("64").is_some() {
                     self.create_token(TokenKind::I64)
                 } else {
@@ -371,6 +526,8 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
             'l' => {
                 if self.match_string("oad").is_some() {
                     self.create_token(TokenKind::Load)
+                } else if self.match_string("et").is_some() {
+                    self.create_token(TokenKind::Let)
                 } else {
                     self.error_token()
                 }
@@ -392,6 +549,8 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
             'p' => {
                 if self.match_string("aram").is_some() {
                     self.create_token(TokenKind::Param)
+                } else if self.match_string("ure").is_some() {
+                    self.create_token(TokenKind::Pure)
                 } else {
                     self.error_token()
                 }
@@ -404,8 +563,34 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
                 }
             }
             's' => {
-                if self.match_string("tub").is_some() {
+                if self.match_string("elect").is_some() {
+                    self.create_token(TokenKind::Select)
+                } else if self.match_char('t').is_none() {
+                    self.error_token()
+                } else if self.match_string("ub").is_some() {
                     self.create_token(TokenKind::Stub)
+                } else if self.match_char('r').is_some() {
+                    self.create_token(TokenKind::Str)
+                } else {
+                    self.error_token()
+                }
+            }
+            't' => {
+                if self.match_string("rue").is_some() {
+                    self.create_token(TokenKind::True)
+                } else {
+                    self.error_token()
+                }
+            }
+            'u' => {
+                if self.match_string("8").is_some() {
+                    self.create_token(TokenKind::U8)
+                } else if self.match_string("16").is_some() {
+                    self.create_token(TokenKind::U16)
+                } else if self.match_string("32").is_some() {
+                    self.create_token(TokenKind::U32)
+                } else if self.match_string("64").is_some() {
+                    self.create_token(TokenKind::U64)
                 } else {
                     self.error_token()
                 }
@@ -417,9 +602,24 @@ impl<T: Iterator<Item = char>> Iterator for Tokenize<T> {
                     self.error_token()
                 }
             }
+            '&' => {
+                if self.match_char('&').is_some() {
+                    self.create_token(TokenKind::And)
+                } else {
+                    self.error_token()
+                }
+            }
+            '|' => {
+                if self.match_char('|').is_some() {
+                    self.create_token(TokenKind::Or)
+                } else {
+                    self.error_token()
+                }
+            }
             '.' => self.create_token(TokenKind::Dot),
             ',' => self.create_token(TokenKind::Comma),
             ':' => self.create_token(TokenKind::Colon),
+            ';' => self.create_token(TokenKind::Semicolon),
 
             _ => self.error_token(),
         };
@@ -456,6 +656,94 @@ mod tests {
         }));
     }
     #[test]
+    fn tokenization_literal_prefixes() {
+        let kinds: Vec<_> = "bool b01 h1F".chars().tokenize().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Bool,
+                TokenKind::IntBinLiteral,
+                TokenKind::IntHexLiteral,
+                TokenKind::Eof
+            ]
+        );
+    }
+    #[test]
+    fn tokenization_int_bases() {
+        let tokens: Vec<_> = "0xFF 0b1010 0o17 255 0XfF 0B1010 0O17 = -0x80"
+            .chars()
+            .tokenize()
+            .collect();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            kinds[..4],
+            [
+                TokenKind::IntHexLiteral,
+                TokenKind::IntBinLiteral,
+                TokenKind::IntOctLiteral,
+                TokenKind::IntLiteral
+            ]
+        );
+        assert_eq!(kinds[..3], kinds[4..7]);
+        let values: Vec<_> = tokens[..9]
+            .iter()
+            .filter(|t| t.kind != TokenKind::Assign)
+            .map(|t| t.int_value())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                Some(255),
+                Some(10),
+                Some(15),
+                Some(255),
+                Some(255),
+                Some(10),
+                Some(15),
+                Some(-128)
+            ]
+        );
+    }
+    #[test]
+    fn tokenization_minus() {
+        let kinds = |src: &str| -> Vec<_> { src.chars().tokenize().map(|t| t.kind).collect() };
+        assert_eq!(
+            kinds("%x = -5"),
+            vec![
+                TokenKind::SpaceId,
+                TokenKind::Assign,
+                TokenKind::IntLiteral,
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(
+            kinds("%a -5 %a - -5"),
+            vec![
+                TokenKind::SpaceId,
+                TokenKind::Sub,
+                TokenKind::IntLiteral,
+                TokenKind::SpaceId,
+                TokenKind::Sub,
+                TokenKind::IntLiteral,
+                TokenKind::Eof
+            ]
+        );
+    }
+    #[test]
+    fn tokenization_int_overflow() {
+        let tokens: Vec<_> = "%x = 0x8000000000000000 -0x8000000000000000 0b"
+            .chars()
+            .tokenize()
+            .collect();
+        let overflow = &tokens[2];
+        assert_eq!(overflow.kind, TokenKind::Error);
+        assert_eq!(overflow.content, "0x8000000000000000");
+        assert_eq!((overflow.start.column, overflow.end.column), (5, 22));
+        assert_eq!(tokens[3].kind, TokenKind::IntHexLiteral);
+        assert_eq!(tokens[3].int_value(), Some(i64::MIN));
+        assert_eq!(tokens[4].kind, TokenKind::Error);
+    }
+    #[test]
     fn tokenization_err() {
         let src = "fnaesrys75i 9    uhh 9[]((";
         src.chars().tokenize().for_each(|t| println!("{}", t))
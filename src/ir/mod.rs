@@ -1,7 +1,9 @@
 use std::{
+    any::{Any, TypeId},
     cell::{Ref, RefCell, RefMut},
-    collections::HashMap,
+    collections::HashSet,
     fmt::{Debug, Display, Formatter},
+    hash::{Hash, Hasher},
     ops::Deref,
     rc::{Rc, Weak},
 };
@@ -15,6 +17,7 @@ use ops::*;
 
 use crate::{
     block::DataFlowGraph,
+    error::Span,
     semilattice::{FlatLattice, SemiLattice},
     util::{
         FromInner, MonotonicIdGenerator, MonotonicNameMap, MonotonicNamedPool,
@@ -24,16 +27,22 @@ use crate::{
 
 use self::block::{CodeBlock, CodeBlockAnalysisNode, CodeBlockGraphWeight, CodeBlockId};
 pub mod block;
+pub mod builder;
+pub mod compare;
+pub mod interpreter;
+pub mod stats;
+pub mod type_check;
+pub mod verify;
 
 #[cfg(test)]
 mod tests;
 
-type GraphBlockID = NodeIndex<u32>;
-type SpaceId = Id<Space>;
-type SpaceNameId = usize;
-type FunctionNameId = usize;
-type FunctionId = Id<Function>;
-type BlockNameId = usize;
+pub type GraphBlockID = NodeIndex<u32>;
+pub type SpaceId = Id<Space>;
+pub type SpaceNameId = usize;
+pub type FunctionNameId = usize;
+pub type FunctionId = Id<Function>;
+pub type BlockNameId = usize;
 type WeakSpaceRef = WeakRef<SpaceSignature>;
 type AddressMarkerRef = RcRef<AddressMarker>;
 
@@ -52,7 +61,36 @@ impl Display for Scope {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Scope {
+    /// Displays local scopes by the name of their function in `program`, such as `$f`,
+    /// instead of its name id. Ids `program` doesn't know are still written as numbers.
+    pub fn display_with<'a>(&'a self, program: &'a Program) -> ScopeDisplay<'a> {
+        ScopeDisplay {
+            scope: self,
+            program,
+        }
+    }
+}
+
+/// See `Scope::display_with`.
+pub struct ScopeDisplay<'a> {
+    scope: &'a Scope,
+    program: &'a Program,
+}
+
+impl Display for ScopeDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.scope {
+            Scope::Local { fn_name_id } => match self.program.functions.get_name(fn_name_id) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{}", self.scope),
+            },
+            Scope::Global => write!(f, "{}", self.scope),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockType {
     Entry,
     Exit,
@@ -72,7 +110,15 @@ impl SpaceSignature {
             SpaceSignature::Offset(_, _, ty, _) => ty.clone(),
         }
     }
+    pub fn members(&self) -> Vec<SpaceNameId> {
+        match self {
+            SpaceSignature::Normal(_, members) | SpaceSignature::Offset(_, _, _, members) => {
+                members.clone()
+            }
+        }
+    }
 }
+#[derive(Clone)]
 pub struct Space {
     pub signature: SpaceSignature,
     pub scope: Scope,
@@ -116,18 +162,146 @@ pub trait Literal: Debug {
     where
         Self: Sized;
 }
+/// The object-safe face of a `Literal`, so literal kinds defined outside this crate can be
+/// stored in `Value::Custom`. Implemented for every `Literal` that is `Clone`, `Eq` and `Hash`.
+///
+/// Operations between literals of different concrete types are undefined.
+pub trait DynLiteral: Debug {
+    fn dyn_get_type(&self) -> DataType;
+    fn dyn_binary(&self, op: BinaryOp, other: &dyn DynLiteral) -> Option<Box<dyn DynLiteral>>;
+    fn dyn_unary(&self, op: UnaryOp) -> Box<dyn DynLiteral>;
+    fn dyn_cmp(&self, cmp: CompareType, other: &dyn DynLiteral) -> Option<bool>;
+    fn dyn_clone(&self) -> Box<dyn DynLiteral>;
+    fn dyn_eq(&self, other: &dyn DynLiteral) -> bool;
+    fn dyn_hash(&self, state: &mut dyn Hasher);
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Literal + Clone + Eq + Hash + 'static> DynLiteral for T {
+    fn dyn_get_type(&self) -> DataType {
+        self.get_type()
+    }
+    fn dyn_binary(&self, op: BinaryOp, other: &dyn DynLiteral) -> Option<Box<dyn DynLiteral>> {
+        let other = other.as_any().downcast_ref::<T>()?;
+        Some(Box::new(self.clone().binary(op, Some(other.clone()))))
+    }
+    fn dyn_unary(&self, op: UnaryOp) -> Box<dyn DynLiteral> {
+        Box::new(self.clone().unary(op, None))
+    }
+    fn dyn_cmp(&self, cmp: CompareType, other: &dyn DynLiteral) -> Option<bool> {
+        let other = other.as_any().downcast_ref::<T>()?;
+        Some(self.static_cmp(cmp, Some(other.clone())))
+    }
+    fn dyn_clone(&self) -> Box<dyn DynLiteral> {
+        Box::new(self.clone())
+    }
+    fn dyn_eq(&self, other: &dyn DynLiteral) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+    fn dyn_hash(&self, mut state: &mut dyn Hasher) {
+        TypeId::of::<T>().hash(&mut state);
+        self.hash(&mut state);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn DynLiteral> {
+    fn clone(&self) -> Self {
+        self.dyn_clone()
+    }
+}
+impl PartialEq for Box<dyn DynLiteral> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dyn_eq(other.as_ref())
+    }
+}
+impl Eq for Box<dyn DynLiteral> {}
+impl Hash for Box<dyn DynLiteral> {
+    fn hash<H: Hasher>(&self, mut state: &mut H) {
+        self.dyn_hash(&mut state)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub enum Value {
     Int(IntValue),
+    Float(FloatValue),
+    Bool(bool),
+    Str(String),
     Array(ArrayValue),
     Struct(StructValue),
+    /// A literal of a kind defined outside this crate, see `DynLiteral`.
+    Custom(Box<dyn DynLiteral>),
     Void,
 }
 
+/// An integer of type `int_type`. `value` is always wrapped to the width of the type.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IntValue {
     pub value: i64,
+    pub int_type: IntType,
+}
+
+impl IntValue {
+    pub fn new(int_type: IntType, value: i64) -> Self {
+        Self {
+            value: int_type.wrap(value),
+            int_type,
+        }
+    }
+    pub fn i64(value: i64) -> Self {
+        Self::new(IntType::I64, value)
+    }
+    /// Evaluates `self op other` under `mode`. `None` if the result overflows in
+    /// `OverflowMode::Checked`, or on a division by zero.
+    pub fn binary_in(&self, op: BinaryOp, other: &IntValue, mode: OverflowMode) -> Option<Self> {
+        if matches!(op, BinaryOp::Div) && other.value == 0 {
+            return None;
+        }
+        let wrapped = self.clone().binary(op, Some(other.clone()));
+        if mode == OverflowMode::Wrapping {
+            return Some(wrapped);
+        }
+        let a = self.int_type.exact(self.value);
+        let b = other.int_type.exact(other.value);
+        let exact = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            // Only `u64 * u64` can leave `i128`, and then the result is too large
+            BinaryOp::Mul => Some(a.checked_mul(b).unwrap_or(i128::MAX)),
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => return Some(wrapped),
+        }?;
+        let (min, max) = self.int_type.range();
+        match mode {
+            _ if (min..=max).contains(&exact) => Some(wrapped),
+            OverflowMode::Saturating => {
+                Some(IntValue::new(self.int_type, exact.clamp(min, max) as i64))
+            }
+            _ => None,
+        }
+    }
+}
+/// An `f64`. Equality and hashing go by the bit pattern so constants can be interned.
+#[derive(Debug, Clone, Copy)]
+pub struct FloatValue {
+    pub value: f64,
+}
+
+impl PartialEq for FloatValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.value.to_bits() == other.value.to_bits()
+    }
+}
+impl Eq for FloatValue {}
+impl std::hash::Hash for FloatValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.value.to_bits().hash(state)
+    }
 }
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ArrayValue {
     pub value: Vec<SpaceNameId>,
@@ -137,58 +311,315 @@ pub struct StructValue {
     pub value: Vec<SpaceNameId>,
 }
 
+/// The base integers are written in when displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    #[default]
+    Decimal,
+    /// Prefixed with `0x` after any sign, e.g. `-0x2a`, as the lexer reads them. Unsigned
+    /// integers are written from their bits, without one
+    Hex,
+}
+
+impl Value {
+    /// Displays integers in `radix` instead of decimal. Other values are unchanged.
+    pub fn display_in(&self, radix: Radix) -> ValueDisplay<'_> {
+        ValueDisplay { value: self, radix }
+    }
+}
+
+/// See `Value::display_in`.
+pub struct ValueDisplay<'a> {
+    value: &'a Value,
+    radix: Radix,
+}
+
+impl Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.value, self.radix) {
+            (Value::Int(int), Radix::Hex) if int.int_type.is_signed() && int.value < 0 => {
+                write!(f, "-{:#x}", int.value.unsigned_abs())
+            }
+            (Value::Int(int), Radix::Hex) => write!(f, "{:#x}", int.value as u64),
+            (value, _) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Scalars are written as literals, floats always with a fractional part, and strings
+/// quoted with their escapes. Arrays and structs list the name ids of their member
+/// spaces, e.g. `[3, 4]` and `{3, 4}`.
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let members = |f: &mut Formatter<'_>, members: &[SpaceNameId]| {
+            let members: Vec<String> = members.iter().map(|member| member.to_string()).collect();
+            write!(f, "{}", members.join(", "))
+        };
+        match self {
+            Value::Int(int) => write!(f, "{}", int.int_type.exact(int.value)),
+            Value::Float(float) => write!(f, "{:?}", float.value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{:?}", value),
+            Value::Array(array) => {
+                write!(f, "[")?;
+                members(f, &array.value)?;
+                write!(f, "]")
+            }
+            Value::Struct(value) => {
+                write!(f, "{{")?;
+                members(f, &value.value)?;
+                write!(f, "}}")
+            }
+            Value::Custom(literal) => write!(f, "{:?}", literal),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
 impl Literal for IntValue {
     fn get_type(&self) -> DataType {
-        DataType::I64
+        self.int_type.into()
     }
 
     fn binary(&mut self, op: BinaryOp, other: Option<Self>) -> Self
     where
         Self: Sized,
     {
-        match op {
-            BinaryOp::Add => IntValue {
-                value: self.value + other.unwrap().value,
-            },
-            BinaryOp::Sub => todo!(),
-            BinaryOp::Mul => todo!(),
-            BinaryOp::Div => todo!(),
-            BinaryOp::And => todo!(),
-            BinaryOp::Or => todo!(),
-            BinaryOp::Xor => todo!(),
-        }
+        let other = other.unwrap().value;
+        let value = match op {
+            BinaryOp::Add => self.value.wrapping_add(other),
+            BinaryOp::Sub => self.value.wrapping_sub(other),
+            BinaryOp::Mul => self.value.wrapping_mul(other),
+            BinaryOp::Div if self.int_type.is_signed() => self.value.wrapping_div(other),
+            BinaryOp::Div => (self.value as u64 / other as u64) as i64,
+            BinaryOp::And => self.value & other,
+            BinaryOp::Or => self.value | other,
+            BinaryOp::Xor => self.value ^ other,
+        };
+        IntValue::new(self.int_type, value)
     }
 
     fn static_cmp(&self, cmp: CompareType, other: Option<Self>) -> bool
     where
         Self: Sized,
     {
-        todo!()
+        let other = other.unwrap().value;
+        let ordering = if self.int_type.is_signed() {
+            self.value.cmp(&other)
+        } else {
+            (self.value as u64).cmp(&(other as u64))
+        };
+        match cmp {
+            CompareType::Less => ordering.is_lt(),
+            CompareType::Greater => ordering.is_gt(),
+            CompareType::Eq => ordering.is_eq(),
+            CompareType::NotEq => ordering.is_ne(),
+            CompareType::LessEqual => ordering.is_le(),
+            CompareType::GreaterEqual => ordering.is_ge(),
+        }
     }
 
-    fn unary(&mut self, op: UnaryOp, other: Option<Self>) -> Self
+    fn unary(&mut self, op: UnaryOp, _other: Option<Self>) -> Self
     where
         Self: Sized,
     {
-        todo!()
+        match op {
+            UnaryOp::Not => IntValue::new(self.int_type, !self.value),
+            UnaryOp::Negative => IntValue::new(self.int_type, self.value.wrapping_neg()),
+            UnaryOp::Cast(ScalarType::Int(int_type)) => IntValue::new(int_type, self.value),
+            UnaryOp::Cast(to) => {
+                unreachable!("cast to {:?} is not an integer, see Value::cast", to)
+            }
+            UnaryOp::Load | UnaryOp::Param | UnaryOp::Unit => self.clone(),
+        }
+    }
+}
+
+impl Value {
+    /// The type of the value. Members of arrays and structs are spaces, so their types are
+    /// looked up in `program`; `None` if a member is untyped or an array is empty.
+    pub fn get_type(&self, program: &Program) -> Option<DataType> {
+        let member_type = |member: &SpaceNameId| {
+            let id = program.lookup_space(*member)?;
+            program
+                .space_pool
+                .borrow()
+                .get_from_id(id)?
+                .signature
+                .get_type()
+        };
+        match self {
+            Value::Int(a) => Some(a.get_type()),
+            Value::Float(_) => Some(DataType::F64),
+            Value::Bool(_) => Some(DataType::Bool),
+            Value::Str(_) => Some(DataType::Str),
+            Value::Custom(a) => Some(a.dyn_get_type()),
+            Value::Array(ArrayValue { value }) => {
+                let element_type = member_type(value.first()?)?;
+                Some(DataType::Array(Box::new(element_type), value.len()))
+            }
+            Value::Struct(StructValue { value }) => {
+                Some(DataType::Struct(value.iter().map(member_type).collect::<Option<_>>()?))
+            }
+            Value::Void => Some(DataType::Void),
+        }
+    }
+    /// Evaluates `self op other`, wrapping on integer overflow.
+    /// Returns `None` if the operation is undefined for the operands, e.g. a division by zero.
+    pub fn binary(&self, op: BinaryOp, other: &Value) -> Option<Value> {
+        self.binary_in(op, other, OverflowMode::Wrapping)
+    }
+    /// Evaluates `self op other` with integer overflow handled according to `mode`.
+    pub fn binary_in(&self, op: BinaryOp, other: &Value, mode: OverflowMode) -> Option<Value> {
+        match (self, other) {
+            // Widths never mix implicitly, see `UnaryOp::Cast`
+            (Value::Int(a), Value::Int(b)) if a.int_type != b.int_type => None,
+            (Value::Int(a), Value::Int(b)) => a.binary_in(op, b, mode).map(Value::Int),
+            (Value::Float(a), Value::Float(b)) => {
+                let value = match op {
+                    BinaryOp::Add => a.value + b.value,
+                    BinaryOp::Sub => a.value - b.value,
+                    BinaryOp::Mul => a.value * b.value,
+                    BinaryOp::Div => a.value / b.value,
+                    BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => return None,
+                };
+                Some(Value::Float(FloatValue { value }))
+            }
+            (Value::Custom(a), Value::Custom(b)) => a.dyn_binary(op, b.as_ref()).map(Value::Custom),
+            (Value::Bool(a), Value::Bool(b)) => match op {
+                BinaryOp::And => Some(Value::Bool(*a && *b)),
+                BinaryOp::Or => Some(Value::Bool(*a || *b)),
+                BinaryOp::Xor => Some(Value::Bool(a ^ b)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    pub fn unary(&self, op: UnaryOp) -> Option<Value> {
+        match (self, op) {
+            (_, UnaryOp::Load | UnaryOp::Param | UnaryOp::Unit) => Some(self.clone()),
+            (_, UnaryOp::Cast(to)) => self.cast(to),
+            (Value::Int(a), _) => Some(Value::Int(a.clone().unary(op, None))),
+            (Value::Float(a), UnaryOp::Negative) => {
+                Some(Value::Float(FloatValue { value: -a.value }))
+            }
+            (Value::Bool(a), UnaryOp::Not) => Some(Value::Bool(!a)),
+            (Value::Custom(a), _) => Some(Value::Custom(a.dyn_unary(op))),
+            _ => None,
+        }
+    }
+    pub fn compare(&self, cmp: CompareType, other: &Value) -> Option<Value> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) if a.int_type == b.int_type => {
+                Some(Value::Bool(a.static_cmp(cmp, Some(b.clone()))))
+            }
+            (Value::Float(a), Value::Float(b)) => Some(Value::Bool(match cmp {
+                CompareType::Less => a.value < b.value,
+                CompareType::Greater => a.value > b.value,
+                CompareType::Eq => a.value == b.value,
+                CompareType::NotEq => a.value != b.value,
+                CompareType::LessEqual => a.value <= b.value,
+                CompareType::GreaterEqual => a.value >= b.value,
+            })),
+            (Value::Custom(a), Value::Custom(b)) => a.dyn_cmp(cmp, b.as_ref()).map(Value::Bool),
+            (Value::Bool(a), Value::Bool(b)) => match cmp {
+                CompareType::Eq => Some(Value::Bool(a == b)),
+                CompareType::NotEq => Some(Value::Bool(a != b)),
+                _ => None,
+            },
+            (Value::Str(a), Value::Str(b)) => match cmp {
+                CompareType::Eq => Some(Value::Bool(a == b)),
+                CompareType::NotEq => Some(Value::Bool(a != b)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    /// Converts a scalar to `to`. Floats are truncated toward zero when converted to
+    /// integers, saturating at the bounds of `i64`/`u64` before wrapping to the target width.
+    pub fn cast(&self, to: ScalarType) -> Option<Value> {
+        let value = match (self, to) {
+            (Value::Int(a), ScalarType::Int(int_type)) => {
+                Value::Int(IntValue::new(int_type, a.value))
+            }
+            (Value::Int(a), ScalarType::F64) => Value::Float(FloatValue {
+                value: if a.int_type.is_signed() {
+                    a.value as f64
+                } else {
+                    a.value as u64 as f64
+                },
+            }),
+            (Value::Int(a), ScalarType::Bool) => Value::Bool(a.value != 0),
+            (Value::Float(a), ScalarType::Int(int_type)) if int_type.is_signed() => {
+                Value::Int(IntValue::new(int_type, a.value as i64))
+            }
+            (Value::Float(a), ScalarType::Int(int_type)) => {
+                Value::Int(IntValue::new(int_type, a.value as u64 as i64))
+            }
+            (Value::Float(a), ScalarType::F64) => Value::Float(*a),
+            (Value::Float(a), ScalarType::Bool) => Value::Bool(a.value != 0.0),
+            (Value::Bool(a), ScalarType::Int(int_type)) => {
+                Value::Int(IntValue::new(int_type, *a as i64))
+            }
+            (Value::Bool(a), ScalarType::F64) => Value::Float(FloatValue {
+                value: *a as i64 as f64,
+            }),
+            (Value::Bool(a), ScalarType::Bool) => Value::Bool(*a),
+            _ => return None,
+        };
+        Some(value)
+    }
+    /// Whether a branch on this value takes the true target.
+    pub fn is_truthy(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Int(IntValue { value, .. }) => Some(*value != 0),
+            _ => None,
+        }
+    }
+}
+
+impl DataType {
+    /// The value a space of this type starts out as when zero-initialized. The members
+    /// of arrays and structs are their zero values interned as constants in `program`.
+    pub fn zero_value(&self, program: &mut Program) -> Value {
+        let mut member = |data_type: &DataType| {
+            let zero = data_type.zero_value(program);
+            program.lookup_or_insert_constant(zero).0
+        };
+        match self {
+            DataType::F64 => Value::Float(FloatValue { value: 0.0 }),
+            DataType::Bool => Value::Bool(false),
+            DataType::Str => Value::Str(String::new()),
+            DataType::Void => Value::Void,
+            DataType::Array(element, len) => Value::Array(ArrayValue {
+                value: (0..*len).map(|_| member(element)).collect(),
+            }),
+            DataType::Struct(fields) => Value::Struct(StructValue {
+                value: fields.iter().map(member).collect(),
+            }),
+            int => Value::Int(IntValue::new(int.int_type().unwrap(), 0)),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum Operation {
     Binary(BinaryOp, SpaceNameId, SpaceNameId),
     Unary(UnaryOp, SpaceNameId),
     Compare(CompareType, SpaceNameId, SpaceNameId),
     Call(FunctionNameId),
+    /// `Select(cond, a, b)` is `a` if `cond` is truthy, otherwise `b`
+    Select(SpaceNameId, SpaceNameId, SpaceNameId),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CommandOperation {
     Store(SpaceNameId, SpaceNameId),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum JumpOperation {
     Unconditional(AddressMarker),
@@ -201,6 +632,8 @@ pub enum JumpOperation {
 #[derive(Debug, Copy, Clone, Default)]
 pub struct IRInformation {
     pub declaration_number: Option<usize>,
+    /// Where the instruction was parsed from, if it was
+    pub span: Option<Span>,
 }
 
 impl Display for IRInformation {
@@ -214,7 +647,7 @@ impl Display for IRInformation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum IR {
     Assignment(SpaceNameId, Operation, IRInformation),
@@ -222,6 +655,7 @@ pub enum IR {
     Command(CommandOperation, IRInformation),
 }
 
+/// The alternate form (`{:#}`) appends the `IRInformation`, e.g. `%x = %a Add %b {DECL = 0}`.
 impl Display for IR {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -236,6 +670,9 @@ impl Display for IR {
                     write!(f, "{:?} = {:?} {:?} {:?}", var, v1, cmp, v2)
                 }
                 Operation::Call(function_id) => write!(f, "{:?} = call {:?}", var, function_id),
+                Operation::Select(cond, a, b) => {
+                    write!(f, "{:?} = select {:?} ? {:?} : {:?}", var, cond, a, b)
+                }
             },
             IR::Jump(JumpOperation::Branch(v, true_br, false_br), _info) => {
                 write!(f, "{:?} ? {} : {}", v, true_br, false_br)
@@ -245,6 +682,112 @@ impl Display for IR {
             IR::Jump(JumpOperation::End, _) => write!(f, "end"),
             IR::Jump(JumpOperation::Ret(v), _) => write!(f, "ret {:?}", v),
             IR::Command(op, _) => write!(f, "{:?}", op),
+        }?;
+        if f.alternate() {
+            write!(f, " {}", self.info())?;
+        }
+        Ok(())
+    }
+}
+
+impl Operation {
+    /// Spaces read by this operation.
+    pub fn operands(&self) -> Vec<SpaceNameId> {
+        match self {
+            Operation::Binary(_, a, b) | Operation::Compare(_, a, b) => vec![*a, *b],
+            Operation::Unary(_, a) => vec![*a],
+            Operation::Call(_) => vec![],
+            Operation::Select(cond, a, b) => vec![*cond, *a, *b],
+        }
+    }
+}
+
+impl IR {
+    pub fn info(&self) -> &IRInformation {
+        match self {
+            IR::Assignment(_, _, info) | IR::Jump(_, info) | IR::Command(_, info) => info,
+        }
+    }
+    pub fn info_mut(&mut self) -> &mut IRInformation {
+        match self {
+            IR::Assignment(_, _, info) | IR::Jump(_, info) | IR::Command(_, info) => info,
+        }
+    }
+    /// The space written by this IR, if any.
+    ///
+    /// A store is modeled as a copy of its source into its destination,
+    /// so it defines the destination just like an assignment does.
+    pub fn defined_space(&self) -> Option<SpaceNameId> {
+        match self {
+            IR::Assignment(var, _, _) => Some(*var),
+            IR::Command(CommandOperation::Store(dst, _), _) => Some(*dst),
+            IR::Jump(_, _) => None,
+        }
+    }
+    /// Spaces read by this IR.
+    pub fn used_spaces(&self) -> Vec<SpaceNameId> {
+        match self {
+            IR::Assignment(_, op, _) => op.operands(),
+            IR::Command(CommandOperation::Store(_, src), _) => vec![*src],
+            IR::Jump(JumpOperation::Branch(cond, _, _), _) => vec![*cond],
+            IR::Jump(JumpOperation::Ret(v), _) => vec![*v],
+            IR::Jump(_, _) => vec![],
+        }
+    }
+    /// A rough estimate of the size and time this IR costs once compiled, for passes
+    /// weighing whether copying code is worth it. Arithmetic and copies cost 1, memory
+    /// accesses and divisions more, branches more than jumps, and calls the most, as
+    /// they save and restore registers. Falling through to the next block is free.
+    pub fn cost(&self) -> usize {
+        match self {
+            IR::Assignment(_, Operation::Call(_), _) => 10,
+            IR::Assignment(_, Operation::Binary(BinaryOp::Div, _, _), _) => 4,
+            IR::Assignment(_, Operation::Unary(UnaryOp::Load, _), _) => 2,
+            IR::Assignment(..) => 1,
+            IR::Command(CommandOperation::Store(..), _) => 2,
+            IR::Jump(JumpOperation::Branch(..), _) => 3,
+            IR::Jump(JumpOperation::Unconditional(_) | JumpOperation::Ret(_), _) => 1,
+            IR::Jump(JumpOperation::Next | JumpOperation::End, _) => 0,
+        }
+    }
+    /// `(dst, src)` if this IR is a plain copy `dst = src`.
+    pub fn as_copy(&self) -> Option<(SpaceNameId, SpaceNameId)> {
+        match self {
+            IR::Assignment(dst, Operation::Unary(UnaryOp::Unit, src), _) => Some((*dst, *src)),
+            _ => None,
+        }
+    }
+    /// Replaces every read and write of `from` with `to`.
+    pub fn replace_space(&mut self, from: SpaceNameId, to: SpaceNameId) {
+        self.map_spaces(|space| if space == from { to } else { space });
+    }
+    /// Replaces every space read or written with `f` of it.
+    pub fn map_spaces(&mut self, f: impl Fn(SpaceNameId) -> SpaceNameId) {
+        let replace = |space: &mut SpaceNameId| *space = f(*space);
+        match self {
+            IR::Assignment(dst, op, _) => {
+                replace(dst);
+                match op {
+                    Operation::Binary(_, a, b) | Operation::Compare(_, a, b) => {
+                        replace(a);
+                        replace(b);
+                    }
+                    Operation::Unary(_, a) => replace(a),
+                    Operation::Call(_) => {}
+                    Operation::Select(cond, a, b) => {
+                        replace(cond);
+                        replace(a);
+                        replace(b);
+                    }
+                }
+            }
+            IR::Command(CommandOperation::Store(dst, src), _) => {
+                replace(dst);
+                replace(src);
+            }
+            IR::Jump(JumpOperation::Branch(cond, _, _), _) => replace(cond),
+            IR::Jump(JumpOperation::Ret(v), _) => replace(v),
+            IR::Jump(_, _) => {}
         }
     }
 }
@@ -259,7 +802,14 @@ pub struct Function {
     pub graph: DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
     pub is_declared: bool,
     pub is_extern: bool,
+    /// Whether calls may pass more arguments than `params`, as to a C `printf`. Only
+    /// extern functions can be, declared with a trailing `...` in their parameters.
+    pub is_variadic: bool,
     pub is_defined: bool,
+    /// Whether the function has no effects and its result depends only on its
+    /// arguments, so calls to it can be removed or reused. Set with `Program::mark_pure`,
+    /// which passes read, since other functions can't be borrowed while one is.
+    pub is_pure: bool,
     program: ProgramRef,
 }
 
@@ -271,8 +821,10 @@ impl Display for Function {
             self.name, self.params, self.return_type
         )?;
         for (_, _, b) in self.blocks.iter() {
-            if let Some(block) = b {
-                writeln!(f, "{}", block)?;
+            match b {
+                Some(block) if f.alternate() => writeln!(f, "{:#}", block)?,
+                Some(block) => writeln!(f, "{}", block)?,
+                None => {}
             }
         }
         write!(f, "}}")?;
@@ -297,10 +849,39 @@ impl Function {
             graph: DataFlowGraph::new(CodeBlockGraphWeight::default()),
             is_declared: false,
             is_extern: false,
+            is_variadic: false,
             is_defined: false,
+            is_pure: false,
             program: program.clone(),
         }
     }
+    pub fn program(&self) -> &ProgramRef {
+        &self.program
+    }
+    /// Whether `space` is a local of this function rather than a global or constant.
+    pub fn is_local(&self, space: SpaceNameId) -> bool {
+        self.locals
+            .get_id_from_name_id(&space)
+            .and_then(|id| self.locals.get_from_id(id))
+            .is_some_and(|space| matches!(space.scope, Scope::Local { .. }))
+    }
+    /// The value of `space` if it is known: an interned constant, or a local whose
+    /// `Space::value` is a single value, as left by constant propagation.
+    ///
+    /// Globals are not constants even when they carry an initial value. Spaces
+    /// live behind the program's `RefCell`s, so the value is cloned.
+    pub fn const_value(&self, space: SpaceNameId) -> Option<Value> {
+        let id = self.locals.get_id_from_name_id(&space)?;
+        let space_ref = self.locals.get_from_id(id)?;
+        let FlatLattice::Value(value) = &space_ref.value else {
+            return None;
+        };
+        let is_constant = match space_ref.scope {
+            Scope::Local { .. } => true,
+            Scope::Global => self.program.borrow().constants.get_name_id(value) == Some(&space),
+        };
+        is_constant.then(|| value.clone())
+    }
     pub fn lookup_space(&mut self, name_id: SpaceNameId) -> Option<SpaceId> {
         self.locals
             .get_id_from_name_id(&name_id)
@@ -357,6 +938,17 @@ impl Function {
         };
         self.locals.insert_nameless(space)
     }
+    /// Declares a new block called `prefix` followed by `_` and the first number giving
+    /// a name no block has yet, such as `#then_0` and then `#then_1` for `#then`. Unlike
+    /// `lookup_or_insert_block`, this never returns an existing block, so front ends
+    /// can generate labels without tracking which they have used.
+    pub fn declare_fresh_block(&mut self, prefix: &str) -> (BlockNameId, CodeBlockId) {
+        let name = (0..)
+            .map(|suffix| format!("{prefix}_{suffix}"))
+            .find(|name| self.blocks.get_name_id(name).is_none())
+            .unwrap();
+        self.lookup_or_insert_block(name)
+    }
     pub fn lookup_or_insert_block(&mut self, name: String) -> (BlockNameId, CodeBlockId) {
         self.blocks.get_id_or_insert(name, |name_id, id| {
             CodeBlock::new(
@@ -377,6 +969,10 @@ pub struct Program {
     pub function_pool: RcRef<MonotonicNamedPool<FunctionNameId, Function>>,
     pub functions: MonotonicNameMap<String, FunctionNameId, Function>,
     pub constants: MonotonicNameMap<Value, SpaceNameId, Space>,
+    /// How constant folding and the interpreter treat integer overflow.
+    pub overflow: OverflowMode,
+    /// The functions whose `is_pure` is set.
+    pure_functions: HashSet<FunctionNameId>,
     weak_self: WeakRef<Self>,
 }
 
@@ -403,6 +999,8 @@ impl Program {
                 globals: space_pool.clone().borrow().create_map(),
                 functions: function_pool.clone().borrow().create_map(),
                 constants: space_pool.clone().borrow().create_map(),
+                overflow: OverflowMode::default(),
+                pure_functions: HashSet::new(),
                 space_pool,
                 block_pool,
                 function_pool,
@@ -413,6 +1011,28 @@ impl Program {
     pub fn lookup_space(&self, name_id: SpaceNameId) -> Option<SpaceId> {
         self.space_pool.borrow().get_id(&name_id).copied()
     }
+    /// The lattice value of any space of the program, local or global. `None` if there
+    /// is no such space. The whole space pool stays borrowed while it is held.
+    pub fn space_value(&self, name_id: SpaceNameId) -> Option<Ref<'_, FlatLattice<Value>>> {
+        let id = self.lookup_space(name_id)?;
+        Ref::filter_map(self.globals.arena().borrow(), |arena| {
+            arena.get(id).map(|space| &space.value)
+        })
+        .ok()
+    }
+    /// Replaces the lattice value of the space `name_id`. Returns whether there is one.
+    pub fn set_space_value(&self, name_id: SpaceNameId, value: FlatLattice<Value>) -> bool {
+        let Some(id) = self.lookup_space(name_id) else {
+            return false;
+        };
+        match self.globals.arena().borrow_mut().get_mut(id) {
+            Some(space) => {
+                space.value = value;
+                true
+            }
+            None => false,
+        }
+    }
     pub fn lookup_global_by_name(&self, name: &String) -> Option<(SpaceNameId, SpaceId)> {
         self.globals.get_name_id_and_id(name)
     }
@@ -420,20 +1040,22 @@ impl Program {
         self.lookup_global_by_name(name)
             .unwrap_or_else(|| self.declare_global(name.clone(), None))
     }
-    pub fn lookup_or_insert_constant(
-        &mut self,
-        data_type: DataType,
-        value: Value,
-    ) -> (SpaceNameId, SpaceId) {
+    /// Interns `value`, typed by `Value::get_type`.
+    pub fn lookup_or_insert_constant(&mut self, value: Value) -> (SpaceNameId, SpaceId) {
+        let data_type = value.get_type(self);
         self.constants.get_id_or_insert(value.clone(), |_, _| {
             let members = match &value {
-                Value::Int(_) => vec![],
+                Value::Int(_)
+                | Value::Float(_)
+                | Value::Bool(_)
+                | Value::Str(_)
+                | Value::Custom(_) => vec![],
                 Value::Array(ArrayValue { value, .. }) => value.clone(),
                 Value::Struct(StructValue { value, .. }) => value.clone(),
                 Value::Void => vec![],
             };
             Space {
-                signature: SpaceSignature::Normal(Some(data_type), members),
+                signature: SpaceSignature::Normal(data_type, members),
                 scope: Scope::Global,
                 value: FlatLattice::Value(value),
             }
@@ -448,6 +1070,17 @@ impl Program {
         self.globals.bind(name.clone(), name_id);
         (name_id, id)
     }
+    /// Declares a global starting out as `initializer`, which is kept as its `Space::value`.
+    pub fn define_global(
+        &mut self,
+        name: String,
+        data_type: Option<DataType>,
+        initializer: Value,
+    ) -> (SpaceNameId, SpaceId) {
+        let (name_id, id) = self.declare_global(name, data_type);
+        self.set_space_value(name_id, FlatLattice::Value(initializer));
+        (name_id, id)
+    }
     pub fn declare_space(
         &mut self,
         data_type: Option<DataType>,
@@ -485,4 +1118,53 @@ impl Program {
                 )
             })
     }
+    /// Adds a copy of the function `name_id` called `new_name`, to be specialized
+    /// without touching the original, and returns its name id. See `Function::clone`
+    /// for what the copy shares with the original; calls in it, recursive ones
+    /// included, still go to their original callees.
+    ///
+    /// `None` if there is no such function or `new_name` is taken.
+    pub fn clone_function(
+        &mut self,
+        name_id: FunctionNameId,
+        new_name: String,
+    ) -> Option<FunctionNameId> {
+        if self.functions.get_name_id(&new_name).is_some() {
+            return None;
+        }
+        let function_pool = self.function_pool.clone();
+        let original = *function_pool.borrow().get_id(&name_id)?;
+        let (new_name_id, _) =
+            self.functions
+                .get_id_or_insert(new_name.clone(), |new_name_id, _| {
+                    let function_pool = function_pool.borrow();
+                    let original = function_pool.get_from_id(original).unwrap();
+                    original.clone_as(new_name, new_name_id)
+                });
+        if self.is_pure(name_id) {
+            self.pure_functions.insert(new_name_id);
+        }
+        Some(new_name_id)
+    }
+    /// Sets `is_pure` on the function `name_id`. Returns whether there is one.
+    pub fn mark_pure(&mut self, name_id: FunctionNameId) -> bool {
+        let mut function_pool = self.function_pool.borrow_mut();
+        let Some(id) = function_pool.get_id(&name_id).copied() else {
+            return false;
+        };
+        function_pool.get_mut_from_id(id).unwrap().is_pure = true;
+        self.pure_functions.insert(name_id);
+        true
+    }
+    /// The function called `name`, such as `$f`, if there is one. Functions live in the
+    /// function pool rather than behind a `FunctionRef` each, so this borrows the pool
+    /// until the handle is dropped, like `functions.get` does.
+    pub fn function_by_name(&self, name: &str) -> Option<Ref<'_, Function>> {
+        self.functions.get(&name.to_string())
+    }
+    /// Whether the function `name_id` is marked pure. Unlike reading `is_pure`, this
+    /// works while any function is borrowed, as it is during a pass.
+    pub fn is_pure(&self, name_id: FunctionNameId) -> bool {
+        self.pure_functions.contains(&name_id)
+    }
 }
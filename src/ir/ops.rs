@@ -4,15 +4,187 @@ use std::fmt::Display;
 #[allow(dead_code)]
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
 pub enum DataType {
+    I8,
+    I16,
+    I32,
     I64,
+    U8,
+    U16,
+    U32,
+    U64,
     F64,
     Bool,
+    /// A handle to an immutable string, interned as a constant.
+    Str,
     Array(Box<DataType>, usize),
     Void,
     Struct(Vec<DataType>),
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Width and signedness of an integer.
+#[derive(Clone, Copy, Debug, PartialEq, Hash, Eq)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntType {
+    pub fn bits(self) -> u32 {
+        match self {
+            IntType::I8 | IntType::U8 => 8,
+            IntType::I16 | IntType::U16 => 16,
+            IntType::I32 | IntType::U32 => 32,
+            IntType::I64 | IntType::U64 => 64,
+        }
+    }
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64
+        )
+    }
+    /// Truncates `value` to this width, then sign- or zero-extends it back to 64 bits.
+    /// `u64` values keep their bit pattern.
+    pub fn wrap(self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+        if self.is_signed() {
+            (value << shift) >> shift
+        } else {
+            ((value as u64) << shift >> shift) as i64
+        }
+    }
+    /// The smallest and largest value of this type.
+    pub fn range(self) -> (i128, i128) {
+        if self.is_signed() {
+            (-(1 << (self.bits() - 1)), (1 << (self.bits() - 1)) - 1)
+        } else {
+            (0, (1 << self.bits()) - 1)
+        }
+    }
+    /// The number a wrapped `value` of this type stands for.
+    pub fn exact(self, value: i64) -> i128 {
+        if self.is_signed() {
+            value as i128
+        } else {
+            value as u64 as i128
+        }
+    }
+}
+
+/// What integer arithmetic does when the exact result does not fit its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Keeps the low bits, like two's complement hardware.
+    #[default]
+    Wrapping,
+    /// Clamps to the nearest bound of the type.
+    Saturating,
+    /// Fails: `IntValue::binary_in` returns `None`, which the interpreter reports as an
+    /// `InterpretError::InvalidOperation` and constant propagation leaves unfolded.
+    Checked,
+}
+
+/// A type that fits in one register, i.e. a possible target of `UnaryOp::Cast`.
+#[derive(Clone, Copy, Debug, PartialEq, Hash, Eq)]
+pub enum ScalarType {
+    Int(IntType),
+    F64,
+    Bool,
+}
+
+impl From<ScalarType> for DataType {
+    fn from(scalar_type: ScalarType) -> Self {
+        match scalar_type {
+            ScalarType::Int(int_type) => int_type.into(),
+            ScalarType::F64 => DataType::F64,
+            ScalarType::Bool => DataType::Bool,
+        }
+    }
+}
+
+impl From<IntType> for DataType {
+    fn from(int_type: IntType) -> Self {
+        match int_type {
+            IntType::I8 => DataType::I8,
+            IntType::I16 => DataType::I16,
+            IntType::I32 => DataType::I32,
+            IntType::I64 => DataType::I64,
+            IntType::U8 => DataType::U8,
+            IntType::U16 => DataType::U16,
+            IntType::U32 => DataType::U32,
+            IntType::U64 => DataType::U64,
+        }
+    }
+}
+
+impl DataType {
+    pub fn int_type(&self) -> Option<IntType> {
+        match self {
+            DataType::I8 => Some(IntType::I8),
+            DataType::I16 => Some(IntType::I16),
+            DataType::I32 => Some(IntType::I32),
+            DataType::I64 => Some(IntType::I64),
+            DataType::U8 => Some(IntType::U8),
+            DataType::U16 => Some(IntType::U16),
+            DataType::U32 => Some(IntType::U32),
+            DataType::U64 => Some(IntType::U64),
+            _ => None,
+        }
+    }
+    pub fn scalar_type(&self) -> Option<ScalarType> {
+        match self {
+            DataType::F64 => Some(ScalarType::F64),
+            DataType::Bool => Some(ScalarType::Bool),
+            data_type => data_type.int_type().map(ScalarType::Int),
+        }
+    }
+    /// Size in bytes. Aggregates are packed without padding.
+    pub fn size_of(&self) -> usize {
+        match self {
+            DataType::F64 => 8,
+            DataType::Bool => 1,
+            DataType::Str => 8,
+            DataType::Void => 0,
+            DataType::Array(element, len) => element.size_of() * len,
+            DataType::Struct(fields) => fields.iter().map(DataType::size_of).sum(),
+            int => int.int_type().unwrap().bits() as usize / 8,
+        }
+    }
+    /// Whether a value of this type converts to `other` without losing information, so
+    /// a cast to it may be inserted implicitly. Integers widen to integers of the same
+    /// signedness, and unsigned ones to wider signed ones. Nothing else converts between
+    /// integers, floats and bools, and aggregates only coerce to themselves.
+    pub fn can_coerce_to(&self, other: &DataType) -> bool {
+        if self == other {
+            return true;
+        }
+        match (self.int_type(), other.int_type()) {
+            (Some(from), Some(to)) if from.is_signed() == to.is_signed() => {
+                from.bits() <= to.bits()
+            }
+            (Some(from), Some(to)) => !from.is_signed() && from.bits() < to.bits(),
+            _ => false,
+        }
+    }
+    /// The type both `a` and `b` coerce to, the wider of the two, if there is one.
+    pub fn common_type(a: &DataType, b: &DataType) -> Option<DataType> {
+        if a.can_coerce_to(b) {
+            Some(b.clone())
+        } else if b.can_coerce_to(a) {
+            Some(a.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[allow(dead_code)]
 pub enum CompareType {
     Less,
@@ -23,15 +195,29 @@ pub enum CompareType {
     GreaterEqual,
 }
 
+impl CompareType {
+    /// The comparison giving the same result with its operands swapped.
+    pub fn swapped(self) -> Self {
+        match self {
+            CompareType::Less => CompareType::Greater,
+            CompareType::Greater => CompareType::Less,
+            CompareType::LessEqual => CompareType::GreaterEqual,
+            CompareType::GreaterEqual => CompareType::LessEqual,
+            cmp => cmp,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[allow(dead_code)]
 pub enum Op {
     Binary(BinaryOp),
-    Unary(UnaryOp)
+    Unary(UnaryOp),
+    Compare(CompareType),
 }
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(dead_code)]
 pub enum BinaryOp {
     Add,
@@ -43,7 +229,14 @@ pub enum BinaryOp {
     Xor,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl BinaryOp {
+    /// Whether swapping the operands never changes the result.
+    pub fn is_commutative(self) -> bool {
+        !matches!(self, BinaryOp::Sub | BinaryOp::Div)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(dead_code)]
 pub enum UnaryOp {
     Not,
@@ -51,15 +244,26 @@ pub enum UnaryOp {
     Load,
     Param,
     Unit, // no-op
+    /// Converts to another scalar type.
+    /// Integers are truncated or extended by the source's signedness, floats are truncated toward zero
+    Cast(ScalarType),
 }
 
 
 impl Display for DataType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            DataType::I8 => write!(f, "i8"),
+            DataType::I16 => write!(f, "i16"),
+            DataType::I32 => write!(f, "i32"),
             DataType::I64 => write!(f, "i64"),
+            DataType::U8 => write!(f, "u8"),
+            DataType::U16 => write!(f, "u16"),
+            DataType::U32 => write!(f, "u32"),
+            DataType::U64 => write!(f, "u64"),
             DataType::F64 => write!(f, "f64"),
             DataType::Bool => write!(f, "bool"),
+            DataType::Str => write!(f, "str"),
             DataType::Array(t, n) => write!(f, "[{}; {}]", t, n),
             DataType::Void => write!(f, "void"),
             DataType::Struct(fields) => {
@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
     iter::Peekable,
     str::FromStr,
@@ -8,20 +8,22 @@ use std::{
 use thiserror::Error;
 
 use crate::{
+    error::Span,
     ir::{
         ops::{BinaryOp, Op, UnaryOp},
-        BlockType, IRInformation, Operation, IR,
+        BlockType, CompareType, IRInformation, Operation, IR,
     },
+    semilattice::FlatLattice,
     util::{MonotonicNameMap, MonotonicNamedPool, RcRef},
 };
 
 use super::{
     block::{CodeBlock, CodeBlockId},
-    lexer::{Token, TokenKind},
+    lexer::{Cursor, Token, TokenKind},
     ops::DataType,
-    AddressMarker, ArrayValue, BlockNameId, Function, FunctionId, FunctionNameId, IntValue,
-    JumpOperation, ProgramRef, Scope, Space, SpaceId, SpaceNameId, SpaceSignature, StructValue,
-    Value, WeakSpaceRef,
+    AddressMarker, ArrayValue, BlockNameId, CommandOperation, Function, FunctionId, FunctionNameId,
+    FloatValue, IntValue, JumpOperation, ProgramRef, Scope, Space, SpaceId, SpaceNameId, SpaceSignature,
+    StructValue, Value, WeakSpaceRef,
 };
 
 pub struct Parser<T: Iterator<Item = Token>> {
@@ -32,6 +34,21 @@ pub struct Parser<T: Iterator<Item = Token>> {
     function_pool: RcRef<MonotonicNamedPool<FunctionNameId, Function>>,
     preloaded_tokens: VecDeque<Token>,
     buffer: VecDeque<VecDeque<Token>>,
+    /// Errors recovered from so far, in the order they were found
+    errors: Vec<ParseError>,
+    /// Where the last token consumed ends
+    last_end: Cursor,
+    /// Whether a block label given a body twice is an error rather than continuing
+    /// the first block, see `reject_duplicate_blocks`
+    reject_duplicate_blocks: bool,
+    /// The blocks given a body so far
+    defined_blocks: HashSet<BlockNameId>,
+}
+
+/// A parsed expression, before its value is assigned to a space.
+enum Expression {
+    Value(Option<DataType>, SpaceNameId),
+    Operation(Option<DataType>, Operation),
 }
 
 #[derive(Debug, Error)]
@@ -53,8 +70,14 @@ pub enum ParseErrorKind {
     FunctionAlreadyDeclared { name: String },
     #[error("block already declared: {name}")]
     BlockAlreadyDeclared { name: String },
+    #[error("space already declared: {name}")]
+    SpaceAlreadyDeclared { name: String },
+    #[error("struct literal has {found} members, expected {expected}")]
+    MemberCountMismatch { expected: usize, found: usize },
     #[error("data type is not consistent: expected {expected}, found {found}")]
     InconsistentDataType { expected: DataType, found: DataType },
+    #[error("only extern functions can be variadic: {name}")]
+    VariadicNotExtern { name: String },
 }
 #[derive(Error, Debug)]
 #[error("parse error at {}: {kind}", .current_token.clone().expect(""))]
@@ -70,6 +93,13 @@ impl ParseError {
             current_token,
         }
     }
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+    /// The token the error was found at.
+    pub fn token(&self) -> Option<&Token> {
+        self.current_token.as_ref()
+    }
 }
 impl<T: Iterator<Item = Token>> Parser<T> {
     pub fn new(tokens: T) -> Self {
@@ -82,8 +112,18 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             function_pool: program.clone().borrow().function_pool.clone(),
             program,
             buffer: VecDeque::from([VecDeque::new()]),
+            errors: vec![],
+            last_end: Cursor::default(),
+            reject_duplicate_blocks: false,
+            defined_blocks: HashSet::new(),
         }
     }
+    /// Makes giving a block label a body twice in one function a `BlockAlreadyDeclared`
+    /// error, instead of appending the second body to the first block.
+    pub fn reject_duplicate_blocks(mut self) -> Self {
+        self.reject_duplicate_blocks = true;
+        self
+    }
     fn peek(&mut self) -> &Token {
         self.preloaded_tokens
             .back()
@@ -97,6 +137,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             .or_else(|| self.token_iter.next());
         match (token.clone(), self.buffer.back_mut()) {
             (Some(t), Some(back)) => {
+                self.last_end = t.end;
                 back.push_back(t);
                 back.back().expect("End")
             }
@@ -132,6 +173,23 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             ))
         }
     }
+    /// Skips tokens until one of `stops` that is not inside braces opened
+    /// meanwhile, or until the end of input.
+    fn synchronize(&mut self, stops: &[TokenKind]) {
+        let mut depth = 0usize;
+        loop {
+            let kind = self.peek().kind.clone();
+            if kind == TokenKind::Eof || (depth == 0 && stops.contains(&kind)) {
+                break;
+            }
+            match kind {
+                TokenKind::OpenBrace => depth += 1,
+                TokenKind::CloseBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            self.consume();
+        }
+    }
     fn put_back(&mut self, token: Token) {
         self.preloaded_tokens.push_back(token);
     }
@@ -176,6 +234,14 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
     }
 
+    /// The span from `start` to the end of the last token consumed.
+    fn span_from(&self, start: Cursor) -> Span {
+        Span {
+            start,
+            end: self.last_end,
+        }
+    }
+
     fn format_error(&mut self) -> ParseError {
         ParseError::new(ParseErrorKind::Format, Some(self.peek().clone()))
     }
@@ -226,38 +292,42 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
         Ok((name_id, id))
     }
-    fn match_int(&mut self) -> Result<(DataType, (SpaceNameId, SpaceId)), ParseError> {
+    /// Matches an integer literal as a constant of `data_type`, which must be an integer
+    /// type. Literals it can't hold are `ParseErrorKind::OutOfRange`.
+    fn match_int(
+        &mut self,
+        data_type: DataType,
+    ) -> Result<(DataType, (SpaceNameId, SpaceId)), ParseError> {
+        let int_type = data_type.int_type().unwrap();
+        let value = match self.peek().kind.clone() {
+            TokenKind::IntLiteral
+            | TokenKind::IntBinLiteral
+            | TokenKind::IntHexLiteral
+            | TokenKind::IntOctLiteral => {
+                let token = self.consume().clone();
+                let value = token.int_value().ok_or_else(|| self.format_error())?;
+                let (min, max) = int_type.range();
+                if !(min..=max).contains(&(value as i128)) {
+                    return Err(ParseError::new(
+                        ParseErrorKind::OutOfRange { got: value },
+                        Some(token),
+                    ));
+                }
+                IntValue::new(int_type, value)
+            }
+            _ => {
+                return Err(ParseError::new(
+                    ParseErrorKind::Format,
+                    Some(self.peek().clone()),
+                ))
+            }
+        };
         Ok((
-            DataType::I64,
-            self.program.clone().borrow_mut().lookup_or_insert_constant(
-                DataType::I64,
-                Value::Int(match self.peek().kind.clone() {
-                    TokenKind::IntLiteral => {
-                        let value = self
-                            .consume()
-                            .content
-                            .parse()
-                            .map_err(|_| self.format_error())?;
-                        IntValue { value }
-                    }
-                    TokenKind::IntBinLiteral => {
-                        let value = i64::from_str_radix(&self.consume().content[1..], 2)
-                            .map_err(|_| self.format_error())?;
-                        IntValue { value }
-                    }
-                    TokenKind::IntHexLiteral => {
-                        let value = i64::from_str_radix(&self.consume().content[1..], 16)
-                            .map_err(|_| self.format_error())?;
-                        IntValue { value }
-                    }
-                    _ => {
-                        return Err(ParseError::new(
-                            ParseErrorKind::Format,
-                            Some(self.peek().clone()),
-                        ))
-                    }
-                }),
-            ),
+            data_type,
+            self.program
+                .clone()
+                .borrow_mut()
+                .lookup_or_insert_constant(Value::Int(value)),
         ))
     }
     fn match_value(
@@ -279,17 +349,55 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     (name_id, id),
                 ))
             }
-            TokenKind::IntBinLiteral | TokenKind::IntHexLiteral | TokenKind::IntLiteral => {
-                self.match_int().map(|(data_type, (name_id, id))| (Some(data_type), (name_id, id)))
+            TokenKind::IntBinLiteral
+            | TokenKind::IntHexLiteral
+            | TokenKind::IntOctLiteral
+            | TokenKind::IntLiteral => self
+                .match_int(DataType::I64)
+                .map(|(data_type, (name_id, id))| (Some(data_type), (name_id, id))),
+            TokenKind::RealLiteral => {
+                let value = self
+                    .consume()
+                    .content
+                    .parse()
+                    .map_err(|_| self.format_error())?;
+                Ok((
+                    Some(DataType::F64),
+                    self.program
+                        .borrow_mut()
+                        .lookup_or_insert_constant(Value::Float(FloatValue { value })),
+                ))
+            }
+            TokenKind::StringLiteral => {
+                let value = self
+                    .consume()
+                    .string_value()
+                    .ok_or_else(|| self.format_error())?;
+                Ok((
+                    Some(DataType::Str),
+                    self.program
+                        .borrow_mut()
+                        .lookup_or_insert_constant(Value::Str(value)),
+                ))
+            }
+            TokenKind::True | TokenKind::False => {
+                let value = self.consume().kind == TokenKind::True;
+                Ok((
+                    Some(DataType::Bool),
+                    self.program
+                        .borrow_mut()
+                        .lookup_or_insert_constant(Value::Bool(value)),
+                ))
             }
             TokenKind::OpenBrace => {
+                self.consume();
                 let mut members_names = Vec::new();
                 let mut members = Vec::new();
                 let mut function = function;
                 while self.match_token(TokenKind::CloseBrace).is_err() {
                     let (data_type, (name_id, _)) = self.match_value(function.as_deref_mut())?;
                     members_names.push(name_id);
-                    members.push(data_type.unwrap());
+                    members.push(data_type.ok_or_else(|| self.format_error())?);
                     let _ = self.match_token(TokenKind::Comma);
                 }
 
@@ -301,16 +409,17 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     Some(data_type.clone()),
                     self.program
                         .borrow_mut()
-                        .lookup_or_insert_constant(data_type.clone(), Value::Struct(value)),
+                        .lookup_or_insert_constant(Value::Struct(value)),
                 ))
             }
             TokenKind::OpenBracket => {
+                self.consume();
                 let mut members_names = Vec::new();
                 let mut element_type = None;
                 let mut function = function;
                 while self.match_token(TokenKind::CloseBracket).is_err() {
                     let (data_type, (name_id, _)) = self.match_value(function.as_deref_mut())?;
-                    let data_type = data_type.unwrap();
+                    let data_type = data_type.ok_or_else(|| self.format_error())?;
                     members_names.push(name_id);
                     match element_type {
                         Some(dt) if dt != data_type => {
@@ -339,7 +448,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     Some(data_type.clone()),
                     self.program
                         .borrow_mut()
-                        .lookup_or_insert_constant(data_type.clone(), Value::Array(value)),
+                        .lookup_or_insert_constant(Value::Array(value)),
                 ))
             }
             _ => Err(self.format_error()),
@@ -348,13 +457,21 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     fn match_data_type(&mut self) -> Result<DataType, ParseError> {
         let first = self.consume();
         match first.kind {
+            TokenKind::I8 => Ok(DataType::I8),
+            TokenKind::I16 => Ok(DataType::I16),
+            TokenKind::I32 => Ok(DataType::I32),
             TokenKind::I64 => Ok(DataType::I64),
+            TokenKind::U8 => Ok(DataType::U8),
+            TokenKind::U16 => Ok(DataType::U16),
+            TokenKind::U32 => Ok(DataType::U32),
+            TokenKind::U64 => Ok(DataType::U64),
             TokenKind::F64 => Ok(DataType::F64),
             TokenKind::Bool => Ok(DataType::Bool),
+            TokenKind::Str => Ok(DataType::Str),
             TokenKind::Void => Ok(DataType::Void),
             TokenKind::OpenBracket => {
                 let inner = self.match_data_type()?;
-                self.match_token(TokenKind::Comma)?;
+                self.match_one_of(vec![TokenKind::Comma, TokenKind::Semicolon])?;
                 let (_, len) = self.match_parse()?;
                 self.match_token(TokenKind::CloseBracket)?;
                 Ok(DataType::Array(Box::new(inner), len))
@@ -374,11 +491,19 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             _ => Err(ParseError::new(
                 ParseErrorKind::UnexpectedToken {
                     expected: vec![
+                        TokenKind::I8,
+                        TokenKind::I16,
+                        TokenKind::I32,
                         TokenKind::I64,
+                        TokenKind::U8,
+                        TokenKind::U16,
+                        TokenKind::U32,
+                        TokenKind::U64,
                         TokenKind::F64,
                         TokenKind::OpenBrace,
                         TokenKind::OpenBracket,
                         TokenKind::Bool,
+                        TokenKind::Str,
                         TokenKind::Void,
                     ],
                     found: first.kind.clone(),
@@ -391,7 +516,9 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         let (function_name, fn_name_id, fn_id) = self.match_fn_id()?;
         self.match_token(TokenKind::OpenParen)?;
 
-        self.program
+        let mut is_pure = false;
+        let header = self
+            .program
             .clone()
             .borrow_mut()
             .functions
@@ -407,6 +534,14 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                 } else {
                     function.is_declared = true;
                     while self.match_token(TokenKind::CloseParen).is_err() {
+                        // ...)
+                        if self.match_token(TokenKind::Dot).is_ok() {
+                            self.match_token(TokenKind::Dot)?;
+                            self.match_token(TokenKind::Dot)?;
+                            self.match_token(TokenKind::CloseParen)?;
+                            function.is_variadic = true;
+                            break;
+                        }
                         let name_id = self.match_fn_param(&mut function)?;
                         function.params.push(name_id);
                         let _ = self.match_token(TokenKind::Comma);
@@ -414,6 +549,15 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     self.match_token(TokenKind::Colon)?;
                     function.return_type = self.match_data_type()?;
                     function.is_extern = self.match_token(TokenKind::Extern).is_ok();
+                    if function.is_variadic && !function.is_extern {
+                        return Err(ParseError::new(
+                            ParseErrorKind::VariadicNotExtern {
+                                name: function_name.clone(),
+                            },
+                            Some(self.peek().clone()),
+                        ));
+                    }
+                    is_pure = self.match_token(TokenKind::Pure).is_ok();
                     Ok((
                         fn_name_id,
                         fn_id,
@@ -421,78 +565,216 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     ))
                 }
             })
-            .unwrap()
+            .unwrap()?;
+        if is_pure {
+            self.program.borrow_mut().mark_pure(fn_name_id);
+        }
+        Ok(header)
     }
-    fn match_instruction(&mut self, function: &mut Function) -> Result<IR, ParseError> {
+    /// Matches an instruction, preceded by the instructions computing the
+    /// intermediate values of its expression.
+    fn match_instruction(&mut self, function: &mut Function) -> Result<Vec<IR>, ParseError> {
         // %x
-        if let Ok((assign_space_name_id, _)) = self.match_space(Some(function)) {
+        if let Ok((assign_space_name_id, assign_space_id)) = self.match_space(Some(function)) {
+            // %x <- %a
+            if self.match_token(TokenKind::Store).is_ok() {
+                let (_, (src_space_name_id, _)) = self.match_value(Some(function))?;
+                return Ok(vec![IR::Command(
+                    CommandOperation::Store(assign_space_name_id, src_space_name_id),
+                    IRInformation::default(),
+                )]);
+            }
             // %x =
             self.match_token(TokenKind::Assign)?;
-            // %x = %a
-            if let Ok((_, (left_space_name_id, _))) = self.match_value(Some(function)) {
-                // %x = %a + %b
+            let token_kind = self.peek().kind.clone();
+            let valued_expr = match token_kind {
+                TokenKind::Load => Some(UnaryOp::Load),
+                TokenKind::Param => Some(UnaryOp::Param),
+                _ => None,
+            };
+            let mut instructions = vec![];
+            let operation = if let Some(op) = valued_expr {
+                // %x = load %a
+                self.consume();
+                Operation::Unary(op, self.match_value(Some(function))?.1 .0)
+            } else if let TokenKind::Cast = token_kind {
+                // %x = cast f64 %a
+                self.consume();
+                let to = self
+                    .match_data_type()?
+                    .scalar_type()
+                    .ok_or_else(|| self.format_error())?;
+                let src = self.match_value(Some(function))?.1 .0;
+                Operation::Unary(UnaryOp::Cast(to), src)
+            } else if let TokenKind::Select = token_kind {
+                // %x = select %c ? %a : %b
+                self.consume();
+                let cond = self.match_value(Some(function))?.1 .0;
+                self.match_token(TokenKind::QuestionMark)?;
+                let a = self.match_value(Some(function))?.1 .0;
+                self.match_token(TokenKind::Colon)?;
+                let b = self.match_value(Some(function))?.1 .0;
+                Operation::Select(cond, a, b)
+            } else if let TokenKind::Call = token_kind {
+                self.match_token(TokenKind::Call)?;
+                let (_, fn_name_id, _) = self.match_fn_id()?;
+                Operation::Call(fn_name_id)
+            } else {
+                // %x = %a + %b * -(%c - 1)
                 // or %x = %a
-                let op = match self.peek().kind {
-                    TokenKind::Add => Op::Binary(BinaryOp::Add),
-                    TokenKind::Sub => Op::Binary(BinaryOp::Sub),
-                    TokenKind::Mul => Op::Binary(BinaryOp::Mul),
-                    TokenKind::Div => Op::Binary(BinaryOp::Div),
-                    _ => Op::Unary(UnaryOp::Unit),
-                };
-                if let Op::Binary(op) = op {
-                    self.consume();
-                    let right_space_name_id = self.match_value(Some(function))?.1 .0;
-                    Ok(IR::Assignment(
-                        assign_space_name_id,
-                        Operation::Binary(op, left_space_name_id, right_space_name_id),
-                        IRInformation::default(),
-                    ))
-                } else if let Op::Unary(op) = op {
-                    Ok(IR::Assignment(
-                        assign_space_name_id,
-                        Operation::Unary(op, left_space_name_id),
-                        IRInformation::default(),
-                    ))
-                } else {
-                    unreachable!()
+                match self.match_expression(function, &mut instructions, 0)? {
+                    Expression::Value(data_type, name_id) => {
+                        // %x = { 1, true }
+                        if token_kind == TokenKind::OpenBrace {
+                            self.check_struct_literal(assign_space_id, data_type.unwrap())?;
+                        }
+                        Operation::Unary(UnaryOp::Unit, name_id)
+                    }
+                    Expression::Operation(_, operation) => operation,
                 }
-            } else {
-                // %x = + %a
-                let token_kind = self.peek().kind.clone();
-                let valued_expr = match token_kind {
-                    TokenKind::Not => Some(UnaryOp::Not),
-                    TokenKind::Sub => Some(UnaryOp::Negative),
-                    TokenKind::Load => Some(UnaryOp::Load),
-                    TokenKind::Param => Some(UnaryOp::Param),
-                    _ => None,
+            };
+            instructions.push(IR::Assignment(
+                assign_space_name_id,
+                operation,
+                IRInformation::default(),
+            ));
+            Ok(instructions)
+        } else {
+            let token = self.peek().clone();
+            Err(ParseError::new(
+                ParseErrorKind::UnexpectedToken {
+                    expected: vec![TokenKind::SpaceId],
+                    found: token.kind.clone(),
+                },
+                Some(token),
+            ))
+        }
+    }
+    /// Matches operators binding at least as tight as `min_binding` by
+    /// precedence climbing. Every operand that is itself an operation is
+    /// computed into a temporary by an instruction appended to `instructions`.
+    fn match_expression(
+        &mut self,
+        function: &mut Function,
+        instructions: &mut Vec<IR>,
+        min_binding: u8,
+    ) -> Result<Expression, ParseError> {
+        let mut left = self.match_operand(function, instructions)?;
+        while let Some((binding, op)) = Self::infix_operator(&self.peek().kind) {
+            if binding < min_binding {
+                break;
+            }
+            self.consume();
+            let (left_type, left_name_id) = Self::materialize(function, instructions, left);
+            // Operands binding as tight as `op` group to the left
+            let right = self.match_expression(function, instructions, binding + 1)?;
+            let (right_type, right_name_id) = Self::materialize(function, instructions, right);
+            left = match op {
+                Op::Binary(op) => Expression::Operation(
+                    left_type.or(right_type),
+                    Operation::Binary(op, left_name_id, right_name_id),
+                ),
+                Op::Compare(cmp) => Expression::Operation(
+                    Some(DataType::Bool),
+                    Operation::Compare(cmp, left_name_id, right_name_id),
+                ),
+                Op::Unary(_) => unreachable!(),
+            };
+        }
+        Ok(left)
+    }
+    fn match_operand(
+        &mut self,
+        function: &mut Function,
+        instructions: &mut Vec<IR>,
+    ) -> Result<Expression, ParseError> {
+        let kind = self.peek().kind.clone();
+        match kind {
+            // (%a + %b)
+            TokenKind::OpenParen => {
+                self.consume();
+                let expression = self.match_expression(function, instructions, 0)?;
+                self.match_token(TokenKind::CloseParen)?;
+                Ok(expression)
+            }
+            // -%a or !%a
+            TokenKind::Sub | TokenKind::Not => {
+                self.consume();
+                let op = match kind {
+                    TokenKind::Sub => UnaryOp::Negative,
+                    _ => UnaryOp::Not,
                 };
-                if let Some(op) = valued_expr {
-                    self.consume();
-                    Ok(IR::Assignment(
-                        assign_space_name_id,
-                        Operation::Unary(op, self.match_value(Some(function))?.1 .0),
-                        IRInformation::default(),
-                    ))
-                } else if let TokenKind::Call = token_kind {
-                    self.match_token(TokenKind::Call)?;
-                    let (_, fn_name_id, _) = self.match_fn_id()?;
-                    Ok(IR::Assignment(
-                        assign_space_name_id,
-                        Operation::Call(fn_name_id),
-                        IRInformation::default(),
-                    ))
-                } else {
-                    Err(ParseError::new(
-                        ParseErrorKind::UnexpectedToken {
-                            expected: vec![TokenKind::Not, TokenKind::Sub, TokenKind::SpaceId],
-                            found: token_kind,
-                        },
-                        Some(self.peek().clone()),
-                    ))
-                }
+                let operand = self.match_operand(function, instructions)?;
+                let (data_type, name_id) = Self::materialize(function, instructions, operand);
+                Ok(Expression::Operation(
+                    data_type,
+                    Operation::Unary(op, name_id),
+                ))
+            }
+            TokenKind::SpaceId
+            | TokenKind::IntLiteral
+            | TokenKind::IntBinLiteral
+            | TokenKind::IntHexLiteral
+            | TokenKind::IntOctLiteral
+            | TokenKind::RealLiteral
+            | TokenKind::StringLiteral
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::OpenBrace
+            | TokenKind::OpenBracket => {
+                let (data_type, (name_id, _)) = self.match_value(Some(function))?;
+                Ok(Expression::Value(data_type, name_id))
+            }
+            found => Err(ParseError::new(
+                ParseErrorKind::UnexpectedToken {
+                    expected: vec![
+                        TokenKind::OpenParen,
+                        TokenKind::Not,
+                        TokenKind::Sub,
+                        TokenKind::SpaceId,
+                    ],
+                    found,
+                },
+                Some(self.peek().clone()),
+            )),
+        }
+    }
+    /// The binding of an infix operator, higher binding tighter, and the
+    /// operation it lowers to.
+    fn infix_operator(kind: &TokenKind) -> Option<(u8, Op)> {
+        Some(match kind {
+            TokenKind::Or => (1, Op::Binary(BinaryOp::Or)),
+            TokenKind::And => (2, Op::Binary(BinaryOp::And)),
+            TokenKind::Less => (3, Op::Compare(CompareType::Less)),
+            TokenKind::Greater => (3, Op::Compare(CompareType::Greater)),
+            TokenKind::Eq => (3, Op::Compare(CompareType::Eq)),
+            TokenKind::Ne => (3, Op::Compare(CompareType::NotEq)),
+            TokenKind::LessEq => (3, Op::Compare(CompareType::LessEqual)),
+            TokenKind::GreaterEq => (3, Op::Compare(CompareType::GreaterEqual)),
+            TokenKind::Add => (4, Op::Binary(BinaryOp::Add)),
+            TokenKind::Sub => (4, Op::Binary(BinaryOp::Sub)),
+            TokenKind::Mul => (5, Op::Binary(BinaryOp::Mul)),
+            TokenKind::Div => (5, Op::Binary(BinaryOp::Div)),
+            _ => return None,
+        })
+    }
+    /// Assigns an operation to a fresh temporary, returning the space holding
+    /// the value of `expression`.
+    fn materialize(
+        function: &mut Function,
+        instructions: &mut Vec<IR>,
+        expression: Expression,
+    ) -> (Option<DataType>, SpaceNameId) {
+        match expression {
+            Expression::Value(data_type, name_id) => (data_type, name_id),
+            Expression::Operation(data_type, operation) => {
+                let scope = Scope::Local {
+                    fn_name_id: function.name_id,
+                };
+                let (name_id, _) = function.declare_space(data_type.clone(), scope);
+                instructions.push(IR::Assignment(name_id, operation, IRInformation::default()));
+                (data_type, name_id)
             }
-        } else {
-            todo!("commands")
         }
     }
     fn match_terminator(&mut self, function: &mut Function) -> Result<IR, ParseError> {
@@ -527,22 +809,170 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             Ok(IR::Jump(JumpOperation::End, IRInformation::default()))
         }
     }
+    /// Checks a struct literal against the declared type of the space it is assigned to.
+    fn check_struct_literal(
+        &mut self,
+        assign_space_id: SpaceId,
+        found: DataType,
+    ) -> Result<(), ParseError> {
+        let declared = self
+            .space_pool
+            .borrow()
+            .get_from_id(assign_space_id)
+            .unwrap()
+            .signature
+            .get_type();
+        let (Some(DataType::Struct(expected_members)), DataType::Struct(found_members)) =
+            (&declared, &found)
+        else {
+            return Ok(());
+        };
+        let kind = if expected_members.len() != found_members.len() {
+            ParseErrorKind::MemberCountMismatch {
+                expected: expected_members.len(),
+                found: found_members.len(),
+            }
+        } else if expected_members != found_members {
+            ParseErrorKind::InconsistentDataType {
+                expected: declared.unwrap(),
+                found,
+            }
+        } else {
+            return Ok(());
+        };
+        Err(ParseError::new(kind, Some(self.peek().clone())))
+    }
+    // let %x: i64
+    fn match_declaration(&mut self, function: &mut Function) -> Result<(), ParseError> {
+        let name_token = self.match_token(TokenKind::SpaceId)?.clone();
+        if function.locals.get_name_id(&name_token.content).is_some() {
+            return Err(ParseError::new(
+                ParseErrorKind::SpaceAlreadyDeclared {
+                    name: name_token.content.clone(),
+                },
+                Some(name_token),
+            ));
+        }
+        self.match_token(TokenKind::Colon)?;
+        let data_type = self.match_data_type()?;
+        function.declare_local(name_token.content, Some(data_type));
+        Ok(())
+    }
+    // global @name: i64
+    // global @name: i64 = 5
+    fn match_global(&mut self) -> Result<SpaceNameId, ParseError> {
+        self.match_token(TokenKind::Global)?;
+        let name_token = self.match_token(TokenKind::SpaceId)?.clone();
+        let name = name_token.content.clone();
+        if self.program.borrow().lookup_global_by_name(&name).is_some() {
+            return Err(ParseError::new(
+                ParseErrorKind::SpaceAlreadyDeclared { name },
+                Some(name_token),
+            ));
+        }
+        self.match_token(TokenKind::Colon)?;
+        let data_type = self.match_data_type()?;
+        if self.match_token(TokenKind::Assign).is_err() {
+            return Ok(self
+                .program
+                .borrow_mut()
+                .declare_global(name, Some(data_type))
+                .0);
+        }
+        let value_token = self.peek().clone();
+        // Integer literals are read at the declared type rather than as `i64`
+        let (found, (_, id)) = match (data_type.int_type(), &value_token.kind) {
+            (
+                Some(_),
+                TokenKind::IntLiteral
+                | TokenKind::IntBinLiteral
+                | TokenKind::IntHexLiteral
+                | TokenKind::IntOctLiteral,
+            ) => {
+                let (found, space) = self.match_int(data_type.clone())?;
+                (Some(found), space)
+            }
+            _ => self.match_value(None)?,
+        };
+        if found.as_ref() != Some(&data_type) {
+            return Err(ParseError::new(
+                ParseErrorKind::InconsistentDataType {
+                    expected: data_type,
+                    found: found.unwrap_or(DataType::Void),
+                },
+                Some(value_token),
+            ));
+        }
+        let FlatLattice::Value(value) = self
+            .space_pool
+            .borrow()
+            .get_from_id(id)
+            .unwrap()
+            .value
+            .clone()
+        else {
+            return Err(ParseError::new(ParseErrorKind::Format, Some(value_token)));
+        };
+        Ok(self
+            .program
+            .borrow_mut()
+            .define_global(name, Some(data_type), value)
+            .0)
+    }
     fn match_block(&mut self, function: &mut Function) -> Result<(), ParseError> {
-        let id = self.match_block_id(function)?.2;
+        let label = self.peek().clone();
+        let (name, name_id, id) = self.match_block_id(function)?;
+        // The body is still parsed, to go on from its end
+        if !self.defined_blocks.insert(name_id) && self.reject_duplicate_blocks {
+            self.errors.push(ParseError::new(
+                ParseErrorKind::BlockAlreadyDeclared { name },
+                Some(label),
+            ));
+        }
         self.match_token(TokenKind::OpenBrace)?;
         if let Some(mut block) = self.block_pool.clone().borrow_mut().get_mut_from_id(id) {
             while self.match_token(TokenKind::Terminator).is_err() {
-                let instruction = self.match_instruction(function)?;
-                block.irs_range.push(instruction);
+                let start = self.peek().start;
+                let result = if self.match_token(TokenKind::Let).is_ok() {
+                    self.match_declaration(function)
+                } else {
+                    self.match_instruction(function).map(|instructions| {
+                        let span = self.span_from(start);
+                        block
+                            .irs_mut()
+                            .extend(instructions.into_iter().map(|mut ir| {
+                                ir.info_mut().span = Some(span);
+                                ir
+                            }))
+                    })
+                };
+                if let Err(error) = result {
+                    self.errors.push(error);
+                    // Resume at the terminator, or give up on the block at its end
+                    self.synchronize(&[TokenKind::Terminator, TokenKind::CloseBrace]);
+                    if self.peek().kind != TokenKind::Terminator {
+                        self.match_token(TokenKind::CloseBrace)?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        let start = self.peek().start;
+        match self.match_terminator(function) {
+            Ok(mut terminator) => {
+                terminator.info_mut().span = Some(self.span_from(start));
+                self.block_pool
+                    .clone()
+                    .borrow_mut()
+                    .get_mut_from_id(id)
+                    .unwrap()
+                    .terminator = terminator
+            }
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize(&[TokenKind::CloseBrace]);
             }
         }
-        let terminator = self.match_terminator(function)?;
-        self.block_pool
-            .clone()
-            .borrow_mut()
-            .get_mut_from_id(id)
-            .unwrap()
-            .terminator = terminator;
         self.match_token(TokenKind::CloseBrace)?;
         Ok(())
     }
@@ -559,9 +989,16 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     fn match_fn_body(&mut self, function: &mut Function) -> Result<(), ParseError> {
         self.match_token(TokenKind::OpenBrace)?;
         while self.match_token(TokenKind::CloseBrace).is_err() {
-            self.match_block(function)?;
+            if let Err(error) = self.match_block(function) {
+                if self.peek().kind == TokenKind::Eof {
+                    return Err(error);
+                }
+                self.errors.push(error);
+                self.synchronize(&[TokenKind::BlockId, TokenKind::CloseBrace]);
+            }
         }
         function.is_defined = true;
+        function.build_graph();
         Ok(())
     }
     fn match_fn(&mut self) -> Result<(FunctionNameId, FunctionId), ParseError> {
@@ -573,18 +1010,21 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             // fn $fn_name (i64 x, i64 y, ...) : bool { ... }
             // fn $fn_name ([i64, 3] a, ...) : i64 stub
             // fn $fn_name ([i64, 3] a, ...) : f64 ext
+            // fn $fn_name (f64 x) : f64 ext pure
+            // fn $fn_name (str format, ...) : i64 ext
             self.match_token(TokenKind::Fn)?;
             let (fn_name_id, fn_id, match_body) = self.match_fn_header()?;
 
             ((fn_name_id, fn_id), match_body)
         };
         if match_body {
-            self.function_pool
-                .clone()
-                .borrow_mut()
-                .get_mut_from_id(fn_id)
-                .map(|mut function| self.match_fn_body(&mut function))
-                .expect("Function is not declared unexpectedly")?;
+            // Only the arena is borrowed so calls in the body can still look up functions
+            let arena = self.function_pool.borrow().arena().clone();
+            let mut arena = arena.borrow_mut();
+            let function = arena
+                .get_mut(fn_id)
+                .expect("Function is not declared unexpectedly");
+            self.match_fn_body(function)?;
         }
         Ok((fn_name_id, fn_id))
     }
@@ -598,18 +1038,39 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             .lookup_or_insert_function(function_name.clone());
         Ok((function_name, fn_name_id, fn_id))
     }
+    /// Parses the whole program, failing with the first error found.
     pub fn match_program(&mut self) -> Result<ProgramRef, ParseError> {
-        // todo!
+        self.match_program_recovering()
+            .map_err(|mut errors| errors.remove(0))
+    }
+    /// Parses the whole program, reporting every error found.
+    ///
+    /// After an error the parser skips to the next statement terminator, block
+    /// or function and carries on from there.
+    pub fn match_program_recovering(&mut self) -> Result<ProgramRef, Vec<ParseError>> {
         while self.match_token(TokenKind::Eof).is_err() {
-            self.match_fn()?;
+            let result = if self.peek().kind == TokenKind::Global {
+                self.match_global().map(|_| ())
+            } else {
+                self.match_fn().map(|_| ())
+            };
+            if let Err(error) = result {
+                self.errors.push(error);
+                self.synchronize(&[TokenKind::Fn, TokenKind::Impl, TokenKind::Global]);
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(self.program.clone())
+        } else {
+            Err(std::mem::take(&mut self.errors))
         }
-        Ok(self.program.clone())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::ir::lexer::Tokenizer;
+    use crate::semilattice::FlatLattice;
 
     use super::*;
     #[test]
@@ -647,6 +1108,334 @@ mod tests {
         println!("{}", r.err().unwrap());
     }
     #[test]
+    fn test_parser_declarations() {
+        let src = "fn $f() : i64 {
+            #entry {
+                let %x: i64
+                let %a: [i64; 4]
+                let %s: {i64, bool}
+                => ret %x
+            }
+        }";
+        let program = Parser::new(src.chars().tokenize()).match_program().unwrap();
+        let program = program.borrow();
+        let function = program.functions.get(&"$f".to_string()).unwrap();
+        let signature = |name: &str| {
+            function
+                .locals
+                .get(&name.to_string())
+                .unwrap()
+                .signature
+                .clone()
+        };
+        let SpaceSignature::Normal(Some(DataType::I64), members) = signature("%x") else {
+            panic!("%x is not an i64");
+        };
+        assert!(members.is_empty());
+        let SpaceSignature::Normal(Some(DataType::Array(element, 4)), members) = signature("%a")
+        else {
+            panic!("%a is not an array");
+        };
+        assert_eq!(*element, DataType::I64);
+        assert_eq!(members.len(), 4);
+        let SpaceSignature::Normal(Some(DataType::Struct(fields)), members) = signature("%s")
+        else {
+            panic!("%s is not a struct");
+        };
+        assert_eq!(fields, vec![DataType::I64, DataType::Bool]);
+        let member_types: Vec<_> = members
+            .iter()
+            .map(|member| program.lookup_space(*member).unwrap())
+            .map(|id| {
+                function
+                    .locals
+                    .get_from_id(id)
+                    .unwrap()
+                    .signature
+                    .get_type()
+            })
+            .collect();
+        assert_eq!(
+            member_types,
+            vec![Some(DataType::I64), Some(DataType::Bool)]
+        );
+
+        let src = "fn $g() : void {
+            #entry {
+                let %x: i64
+                let %x: bool
+                => end
+            }
+        }";
+        let r = Parser::new(src.chars().tokenize()).match_program();
+        assert!(matches!(
+            r,
+            Err(ParseError {
+                kind: ParseErrorKind::SpaceAlreadyDeclared { .. },
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn test_parser_array_literal() {
+        let mut parser = Parser::new("[1, 2, 3] [[1, 2], [1, 2]]".chars().tokenize());
+        let value = |parser: &Parser<_>, id| {
+            let pool = parser.space_pool.borrow();
+            let space = pool.get_from_id(id).unwrap();
+            match &space.value {
+                FlatLattice::Value(value) => value.clone(),
+                other => panic!("{:?} is not a constant", other),
+            }
+        };
+        let element = |parser: &Parser<_>, name_id| {
+            let id = parser.program.borrow().lookup_space(name_id).unwrap();
+            value(parser, id)
+        };
+
+        let (data_type, (_, id)) = parser.match_value(None).unwrap();
+        assert_eq!(data_type, Some(DataType::Array(Box::new(DataType::I64), 3)));
+        let Value::Array(array) = value(&parser, id) else {
+            panic!("not an array");
+        };
+        let elements: Vec<_> = array
+            .value
+            .iter()
+            .map(|name_id| element(&parser, *name_id))
+            .collect();
+        assert_eq!(
+            elements,
+            vec![
+                Value::Int(IntValue::i64(1)),
+                Value::Int(IntValue::i64(2)),
+                Value::Int(IntValue::i64(3))
+            ]
+        );
+
+        let (data_type, (_, id)) = parser.match_value(None).unwrap();
+        let pair = DataType::Array(Box::new(DataType::I64), 2);
+        assert_eq!(data_type, Some(DataType::Array(Box::new(pair), 2)));
+        let Value::Array(array) = value(&parser, id) else {
+            panic!("not an array");
+        };
+        // Equal rows are interned as one constant whose elements are shared with `[1, 2, 3]`
+        assert_eq!(array.value[0], array.value[1]);
+        let Value::Array(row) = element(&parser, array.value[0]) else {
+            panic!("not an array");
+        };
+        let constant = |value| {
+            let program = parser.program.borrow();
+            *program
+                .constants
+                .get_name_id(&Value::Int(IntValue::i64(value)))
+                .unwrap()
+        };
+        assert_eq!(row.value, vec![constant(1), constant(2)]);
+    }
+    #[test]
+    fn test_parser_struct_literal() {
+        let src = "fn $f() : void {
+            #entry {
+                let %s: {i64, bool}
+                %s = { 1, true }
+                => end
+            }
+        }";
+        let program = Parser::new(src.chars().tokenize()).match_program().unwrap();
+        let program = program.borrow();
+        let function = program.functions.get(&"$f".to_string()).unwrap();
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        let IR::Assignment(_, Operation::Unary(UnaryOp::Unit, literal), _) = block.irs()[0] else {
+            panic!("{} is not a copy", block.irs()[0]);
+        };
+        let value = |name_id| {
+            let id = program.lookup_space(name_id).unwrap();
+            let space = function.locals.get_from_id(id).unwrap();
+            match &space.value {
+                FlatLattice::Value(value) => value.clone(),
+                other => panic!("{:?} is not a constant", other),
+            }
+        };
+        let Value::Struct(literal) = value(literal) else {
+            panic!("not a struct");
+        };
+        let members: Vec<_> = literal.value.iter().map(|member| value(*member)).collect();
+        assert_eq!(
+            members,
+            vec![Value::Int(IntValue::i64(1)), Value::Bool(true)]
+        );
+
+        let src = "fn $g() : void {
+            #entry {
+                let %s: {i64, bool}
+                %s = { 1, true, 2 }
+                => end
+            }
+        }";
+        let r = Parser::new(src.chars().tokenize()).match_program();
+        assert!(matches!(
+            r,
+            Err(ParseError {
+                kind: ParseErrorKind::MemberCountMismatch {
+                    expected: 2,
+                    found: 3
+                },
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn test_parser_minus() {
+        let src = "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = -5
+                %y = @a - 5
+                %z = @a - -5
+                %w = -@a
+                => ret %z
+            }
+        }";
+        let program = Parser::new(src.chars().tokenize()).match_program().unwrap();
+        let program = program.borrow();
+        let function = program.functions.get(&"$f".to_string()).unwrap();
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        let constant = |value| {
+            *program
+                .constants
+                .get_name_id(&Value::Int(IntValue::i64(value)))
+                .unwrap()
+        };
+        let a = *function.locals.get_name_id(&"@a".to_string()).unwrap();
+        let (minus_five, five) = (constant(-5), constant(5));
+        let operation = |index: usize| match &block.irs()[index] {
+            IR::Assignment(_, operation, _) => operation,
+            other => panic!("{} is not an assignment", other),
+        };
+        assert!(matches!(
+            operation(0),
+            Operation::Unary(UnaryOp::Unit, c) if *c == minus_five
+        ));
+        assert!(matches!(
+            operation(1),
+            Operation::Binary(BinaryOp::Sub, l, r) if *l == a && *r == five
+        ));
+        assert!(matches!(
+            operation(2),
+            Operation::Binary(BinaryOp::Sub, l, r) if *l == a && *r == minus_five
+        ));
+        assert!(matches!(
+            operation(3),
+            Operation::Unary(UnaryOp::Negative, v) if *v == a
+        ));
+    }
+    #[test]
+    fn test_parser_precedence() {
+        let src = "fn $f(i64 @a, i64 @b, i64 @c) : bool {
+            #entry {
+                %x = @a + @b * @c
+                %y = @a - @b - @c
+                %z = (@a + @b) * @c
+                %w = @a < @b + 1 && @c > 0 || @a == @b
+                => ret %w
+            }
+        }";
+        let program = Parser::new(src.chars().tokenize()).match_program().unwrap();
+        let program = program.borrow();
+        let function = program.functions.get(&"$f".to_string()).unwrap();
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        let local = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let (a, b, c) = (local("@a"), local("@b"), local("@c"));
+        let (x, y, z) = (local("%x"), local("%y"), local("%z"));
+        let constant = |value| {
+            *program
+                .constants
+                .get_name_id(&Value::Int(IntValue::i64(value)))
+                .unwrap()
+        };
+        let (zero, one) = (constant(0), constant(1));
+        let ir = |index: usize| match &block.irs()[index] {
+            IR::Assignment(dst, operation, _) => (*dst, operation),
+            other => panic!("{} is not an assignment", other),
+        };
+
+        // %x = @a + @b * @c
+        let (t, mul) = ir(0);
+        assert!(matches!(mul, Operation::Binary(BinaryOp::Mul, l, r) if (*l, *r) == (b, c)));
+        assert!(
+            matches!(ir(1), (dst, Operation::Binary(BinaryOp::Add, l, r))
+            if dst == x && (*l, *r) == (a, t))
+        );
+        // %y = (@a - @b) - @c
+        let (t, sub) = ir(2);
+        assert!(matches!(sub, Operation::Binary(BinaryOp::Sub, l, r) if (*l, *r) == (a, b)));
+        assert!(
+            matches!(ir(3), (dst, Operation::Binary(BinaryOp::Sub, l, r))
+            if dst == y && (*l, *r) == (t, c))
+        );
+        // %z = (@a + @b) * @c
+        let (t, add) = ir(4);
+        assert!(matches!(add, Operation::Binary(BinaryOp::Add, l, r) if (*l, *r) == (a, b)));
+        assert!(
+            matches!(ir(5), (dst, Operation::Binary(BinaryOp::Mul, l, r))
+            if dst == z && (*l, *r) == (t, c))
+        );
+        // %w = ((@a < (@b + 1)) && (@c > 0)) || (@a == @b)
+        let (sum, add) = ir(6);
+        assert!(matches!(add, Operation::Binary(BinaryOp::Add, l, r) if (*l, *r) == (b, one)));
+        let (less, cmp) = ir(7);
+        assert!(matches!(cmp, Operation::Compare(CompareType::Less, l, r) if (*l, *r) == (a, sum)));
+        let (greater, cmp) = ir(8);
+        assert!(matches!(cmp, Operation::Compare(CompareType::Greater, l, r)
+            if (*l, *r) == (c, zero)));
+        let (and, op) = ir(9);
+        assert!(matches!(op, Operation::Binary(BinaryOp::And, l, r)
+            if (*l, *r) == (less, greater)));
+        let (eq, cmp) = ir(10);
+        assert!(matches!(cmp, Operation::Compare(CompareType::Eq, l, r) if (*l, *r) == (a, b)));
+        assert!(matches!(ir(11).1, Operation::Binary(BinaryOp::Or, l, r) if (*l, *r) == (and, eq)));
+        assert_eq!(block.irs().len(), 12);
+    }
+    #[test]
+    fn test_parser_recovery() {
+        let src = "fn $a() : i64 {
+            #entry {
+                %x = + 1
+                => ret %x
+            }
+        }
+        fn $b() : i64 {
+            #entry {
+                %y = 1
+                => ret %y
+            }
+        }
+        fn $c() : i64 {
+            #entry {
+                %z = 1 +
+                => ret %z
+            }
+        }";
+        let errors = Parser::new(src.chars().tokenize())
+            .match_program_recovering()
+            .err()
+            .unwrap();
+        let lines: Vec<_> = errors
+            .iter()
+            .map(|error| error.current_token.as_ref().unwrap().start.line)
+            .collect();
+        assert_eq!(lines, vec![2, 15]);
+        assert!(matches!(
+            errors[0].kind,
+            ParseErrorKind::UnexpectedToken {
+                found: TokenKind::Add,
+                ..
+            }
+        ));
+
+        let r = Parser::new(src.chars().tokenize()).match_program();
+        assert!(matches!(r, Err(ParseError { ref current_token, .. })
+            if current_token.as_ref().unwrap().start.line == 2));
+    }
+    #[test]
     fn test_parser_space() {
         let mut parser = Parser::new("%abc %abc %def".chars().tokenize());
         let r = parser.match_space(None);
@@ -0,0 +1,83 @@
+use super::{block::CodeBlock, Function, JumpOperation, Operation, Program, IR};
+
+/// Sizes of a program or function, to compare before and after a pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// Functions with a body. Extern ones aren't counted, nor are their blocks.
+    pub functions: usize,
+    pub blocks: usize,
+    /// Instructions assigning a space, calls included.
+    pub assignments: usize,
+    pub calls: usize,
+    pub stores: usize,
+    /// Terminators branching on a condition.
+    pub branches: usize,
+}
+
+impl ProgramStats {
+    /// Instructions other than terminators.
+    pub fn instructions(&self) -> usize {
+        self.assignments + self.stores
+    }
+
+    /// The mean number of instructions in a block, terminators excluded, or 0 without
+    /// any blocks.
+    pub fn average_block_size(&self) -> f64 {
+        match self.blocks {
+            0 => 0.0,
+            blocks => self.instructions() as f64 / blocks as f64,
+        }
+    }
+
+    fn add_block(&mut self, block: &CodeBlock) {
+        self.blocks += 1;
+        for ir in block.irs() {
+            match ir {
+                IR::Assignment(_, op, _) => {
+                    self.assignments += 1;
+                    self.calls += matches!(op, Operation::Call(_)) as usize;
+                }
+                IR::Command(..) => self.stores += 1,
+                IR::Jump(..) => {}
+            }
+        }
+        self.branches +=
+            matches!(block.terminator(), IR::Jump(JumpOperation::Branch(..), _)) as usize;
+    }
+}
+
+impl Function {
+    /// The counts of `ProgramStats` over this function's blocks.
+    pub fn stats(&self) -> ProgramStats {
+        let mut stats = ProgramStats::default();
+        if self.is_defined {
+            stats.functions = 1;
+            self.blocks
+                .iter()
+                .filter_map(|(_, _, block)| block)
+                .for_each(|block| stats.add_block(&block));
+        }
+        stats
+    }
+}
+
+impl Program {
+    /// The counts of `ProgramStats` summed over every function. Each function is
+    /// borrowed in turn, so none may be borrowed mutably meanwhile.
+    pub fn stats(&self) -> ProgramStats {
+        let mut stats = ProgramStats::default();
+        for (_, _, function) in self.functions.iter() {
+            let Some(function) = function else {
+                continue;
+            };
+            let function = function.stats();
+            stats.functions += function.functions;
+            stats.blocks += function.blocks;
+            stats.assignments += function.assignments;
+            stats.calls += function.calls;
+            stats.stores += function.stores;
+            stats.branches += function.branches;
+        }
+        stats
+    }
+}
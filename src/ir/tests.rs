@@ -1,6 +1,1708 @@
+use std::collections::{HashMap, HashSet};
 use std::{fs::File, io::Read, path::PathBuf};
 
+use petgraph::visit::EdgeRef;
+
+use super::{
+    ArrayValue, BinaryOp, BlockType, CommandOperation, CompareType, DataType, FloatValue, Function,
+    IntType, IntValue, JumpOperation, Operation, OverflowMode, Program, Radix, Scope,
+    StructValue, UnaryOp, Value, IR,
+};
+use super::interpreter::{interpret, InterpretError};
+use super::lexer::Tokenizer;
+use super::parser::{ParseErrorKind, Parser};
+use super::stats::ProgramStats;
+use super::type_check::{check_function, check_function_located, insert_casts, TypeError};
+use super::verify::{
+    verify_function, verify_function_located, verify_warnings, VerifyError, VerifyWarning,
+};
+use crate::block::EdgeKind;
+use crate::constant_propagation::propagate_constants;
+use crate::dead_code_elimination::eliminate_dead_code;
+use crate::error::{CompileError, ErrorCategory};
+use crate::semilattice::FlatLattice;
+use crate::tests::{parse_program, with_function};
+
 
 #[test]
 fn data_type_test() {
 }
+
+#[test]
+fn builder_max() {
+    let program = Program::new();
+    let (_, fn_id) = program
+        .borrow_mut()
+        .lookup_or_insert_function("$max".to_string());
+    let function_pool = program.borrow().function_pool.clone();
+    let mut function_pool = function_pool.borrow_mut();
+    let mut function = function_pool.get_mut_from_id(fn_id).unwrap();
+    function.return_type = DataType::I64;
+    let a = function.declare_param("@a".to_string(), DataType::I64);
+    let b = function.declare_param("@b".to_string(), DataType::I64);
+
+    let mut entry = function.build_block("#entry");
+    let one = entry.int(1);
+    let a1 = entry.add("%a1", a, one);
+    let cond = entry.compare(CompareType::Greater, a1, b);
+    entry.branch(cond, "#then", "#else");
+    function.build_block("#then").ret(a1);
+    let mut else_block = function.build_block("#else");
+    let b1 = else_block.copy("%b1", b);
+    else_block.ret(b1);
+
+    println!("{}", *function);
+    assert_eq!(function.graph.weight.assignment_count, 3);
+    assert_eq!(function.graph.weight.block_nodes.len(), 3);
+    let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+    let entry_id = function.blocks.get_id_from_name_id(&entry).unwrap();
+    let entry_block = function.blocks.get_from_id(entry_id).unwrap();
+    let declaration_numbers: Vec<_> = entry_block
+        .irs()
+        .iter()
+        .map(|ir| ir.info().declaration_number)
+        .collect();
+    assert_eq!(declaration_numbers, vec![Some(0), Some(1)]);
+    assert!(matches!(
+        entry_block.terminator,
+        IR::Jump(JumpOperation::Branch(c, _, _), _) if c == cond
+    ));
+    let entry_node = function.graph.weight.block_nodes[&entry];
+    assert_eq!(function.graph.graph.neighbors(entry_node).count(), 2);
+}
+
+#[test]
+fn graph_edge_kinds() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #then : #else
+            }
+            #then {
+                => #else
+            }
+            #else {
+                => next
+            }
+            #last {
+                => ret @a
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let graph = &function.graph;
+        let node = |name: &str| {
+            let name_id = function.blocks.get_name_id(&name.to_string()).unwrap();
+            graph.weight.block_nodes[name_id]
+        };
+        let edges = |from| {
+            let mut edges: Vec<_> = graph
+                .graph
+                .edges(from)
+                .map(|edge| (edge.target(), *edge.weight()))
+                .collect();
+            edges.sort_by_key(|(target, _)| target.index());
+            edges
+        };
+        assert_eq!(
+            edges(node("#entry")),
+            vec![
+                (node("#then"), EdgeKind::True),
+                (node("#else"), EdgeKind::False)
+            ]
+        );
+        assert_eq!(
+            edges(node("#then")),
+            vec![(node("#else"), EdgeKind::Unconditional)]
+        );
+        assert_eq!(
+            edges(node("#else")),
+            vec![(node("#last"), EdgeKind::Fallthrough)]
+        );
+        assert_eq!(
+            edges(node("#last")),
+            vec![(graph.exit, EdgeKind::Unconditional)]
+        );
+        assert_eq!(
+            edges(graph.entry),
+            vec![(node("#entry"), EdgeKind::Fallthrough)]
+        );
+    });
+}
+
+#[test]
+fn graph_block_types() {
+    let program = parse_program(
+        "fn $abs(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #positive : #negative
+            }
+            #positive {
+                => ret @a
+            }
+            #negative {
+                => #negate
+            }
+            #negate {
+                %n = -@a
+                => ret %n
+            }
+        }",
+    );
+    with_function(&program, "$abs", |function| {
+        let block_type = |name: &str| function.blocks.get(&name.to_string()).unwrap().block_type;
+        assert_eq!(block_type("#entry"), BlockType::Entry);
+        assert_eq!(block_type("#positive"), BlockType::Exit);
+        assert_eq!(block_type("#negative"), BlockType::Normal);
+        assert_eq!(block_type("#negate"), BlockType::Exit);
+        let graph = &function.graph;
+        let exits: Vec<_> = graph
+            .graph
+            .neighbors_directed(graph.exit, petgraph::Direction::Incoming)
+            .collect();
+        assert_eq!(exits.len(), 2);
+        let node = |name: &str| {
+            graph.weight.block_nodes[function.blocks.get_name_id(&name.to_string()).unwrap()]
+        };
+        assert!(exits.contains(&node("#positive")) && exits.contains(&node("#negate")));
+    });
+}
+
+#[test]
+fn virtual_exit() {
+    let program = parse_program(
+        "fn $abs(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #positive : #negative
+            }
+            #positive {
+                => ret @a
+            }
+            #negative {
+                %n = -@a
+                => ret %n
+            }
+        }",
+    );
+    with_function(&program, "$abs", |function| {
+        let exit = function.add_virtual_exit();
+        let exit_name = function.blocks.get_name(&exit).unwrap().clone();
+        assert_eq!(exit_name, "#exit");
+        let graph = &function.graph;
+        let node = |name: &str| {
+            graph.weight.block_nodes[function.blocks.get_name_id(&name.to_string()).unwrap()]
+        };
+        let predecessors = |node| {
+            let mut predecessors: Vec<_> = graph
+                .graph
+                .neighbors_directed(node, petgraph::Direction::Incoming)
+                .collect();
+            predecessors.sort();
+            predecessors
+        };
+        assert_eq!(predecessors(graph.exit), vec![node("#exit")]);
+        assert_eq!(
+            predecessors(node("#exit")),
+            vec![node("#positive"), node("#negative")]
+        );
+        assert_eq!(
+            function.blocks.get(&exit_name).unwrap().block_type,
+            BlockType::Exit
+        );
+        for (input, output) in [(3, 3), (-4, 4)] {
+            assert_eq!(interpret(function, &[int(input)]), Ok(int(output)));
+        }
+    });
+}
+
+#[test]
+fn remove_instruction() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %b = %a * 2
+                %c = @x - 1
+                => ret %c
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+        let c = *function.locals.get_name_id(&"%c".to_string()).unwrap();
+        // The terminator sits right after the instructions and stays put
+        assert!(function.remove_instruction(entry, 3).is_none());
+        let removed = function.remove_instruction(entry, 1).unwrap();
+        assert!(matches!(
+            removed,
+            IR::Assignment(_, Operation::Binary(BinaryOp::Mul, _, _), _)
+        ));
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        assert_eq!(block.irs().len(), 2);
+        assert!(matches!(block.terminator, IR::Jump(JumpOperation::Ret(v), _) if v == c));
+        let declaration_numbers: Vec<_> = block
+            .irs()
+            .iter()
+            .map(|ir| ir.info().declaration_number)
+            .collect();
+        assert_eq!(declaration_numbers, vec![Some(0), Some(1)]);
+        assert_eq!(function.graph.weight.assignment_count, 2);
+        drop(block);
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(4)));
+    });
+}
+
+#[test]
+fn insert_instruction() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %c = %a - 1
+                => ret %c
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+        let a = *function.locals.get_name_id(&"%a".to_string()).unwrap();
+        let c = *function.locals.get_name_id(&"%c".to_string()).unwrap();
+        let doubled = function
+            .insert_assignment(
+                entry,
+                1,
+                Some(DataType::I64),
+                Operation::Binary(BinaryOp::Mul, a, a),
+            )
+            .unwrap();
+        let store = IR::Command(CommandOperation::Store(c, doubled), Default::default());
+        {
+            let id = function.blocks.get_id_from_name_id(&entry).unwrap();
+            let mut block = function.blocks.get_mut_from_id(id).unwrap();
+            let jump = IR::Jump(JumpOperation::End, Default::default());
+            assert!(block.insert_before(0, jump).is_err());
+            // Index 3 is the terminator, nothing goes after it
+            let store = block.insert_after(3, store).unwrap_err();
+            block.insert_after(2, store).unwrap();
+        }
+        assert!(function
+            .insert_assignment(entry, 5, None, Operation::Unary(UnaryOp::Unit, a))
+            .is_none());
+        function.build_graph();
+
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        let defined: Vec<_> = block.irs().iter().map(|ir| ir.defined_space()).collect();
+        assert_eq!(defined, vec![Some(a), Some(doubled), Some(c), Some(c)]);
+        let declaration_numbers: Vec<_> = block
+            .irs()
+            .iter()
+            .map(|ir| ir.info().declaration_number)
+            .collect();
+        assert_eq!(
+            declaration_numbers,
+            vec![Some(0), Some(1), Some(2), Some(3)]
+        );
+        drop(block);
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(36)));
+    });
+}
+
+#[test]
+fn block_accessors() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %b = %a * 2
+                => ret %b
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let b = *function.locals.get_name_id(&"%b".to_string()).unwrap();
+        let mut block = function.blocks.get_mut(&"#entry".to_string()).unwrap();
+        let ops: Vec<_> = block
+            .irs()
+            .iter()
+            .map(|ir| match ir {
+                IR::Assignment(_, Operation::Binary(op, _, _), _) => *op,
+                _ => panic!("unexpected {}", ir),
+            })
+            .collect();
+        assert_eq!(ops, vec![BinaryOp::Add, BinaryOp::Mul]);
+        assert!(matches!(block.terminator(), IR::Jump(JumpOperation::Ret(v), _) if *v == b));
+
+        if let IR::Assignment(_, Operation::Binary(op, _, _), _) = &mut block.irs_mut()[1] {
+            *op = BinaryOp::Add;
+        }
+        *block.terminator_mut() = IR::Jump(JumpOperation::End, Default::default());
+        assert!(matches!(
+            block.irs()[1],
+            IR::Assignment(_, Operation::Binary(BinaryOp::Add, _, _), _)
+        ));
+        assert!(matches!(
+            block.terminator(),
+            IR::Jump(JumpOperation::End, _)
+        ));
+    });
+}
+
+#[test]
+fn clone_function() {
+    let source = "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = 2
+                %b = %a * 3
+                %c = @x > 0
+                => %c ? #then : #done
+            }
+            #then {
+                %b = %b + @x
+                => #done
+            }
+            #done {
+                => ret %b
+            }
+        }";
+    let program = parse_program(source);
+    with_function(&program, "$f", |function| {
+        let mut copy = function.clone();
+        assert!(copy.structurally_eq(function));
+        let local = |function: &Function, name: &str| {
+            *function.locals.get_name_id(&name.to_string()).unwrap()
+        };
+        assert_ne!(local(&copy, "%a"), local(function, "%a"));
+        assert_ne!(local(&copy, "@x"), local(function, "@x"));
+        assert_eq!(copy.params, vec![local(&copy, "@x")]);
+        let then = *copy.blocks.get_name_id(&"#then".to_string()).unwrap();
+        assert_ne!(
+            Some(&then),
+            function.blocks.get_name_id(&"#then".to_string())
+        );
+        // The copied graph points at the copied blocks
+        for name_id in copy.block_name_ids() {
+            let node = copy.graph.weight.block_nodes[&name_id];
+            assert_eq!(
+                copy.graph.graph[node].block,
+                copy.blocks.get_id_from_name_id(&name_id)
+            );
+        }
+
+        propagate_constants(&mut copy);
+        assert!(copy.remove_instruction(then, 0).is_some());
+        assert_eq!(interpret(&copy, &[int(5)]), Ok(int(6)));
+        assert_eq!(copy.const_value(local(&copy, "%a")), Some(int(2)));
+
+        assert!(!copy.structurally_eq(function));
+        assert_eq!(function.const_value(local(function, "%a")), None);
+        let then = function.blocks.get(&"#then".to_string()).unwrap();
+        assert_eq!(then.irs().len(), 1);
+        drop(then);
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(11)));
+        // Still the function as parsed
+        let parsed = parse_program(source);
+        let parsed = parsed.borrow();
+        assert!(parsed
+            .functions
+            .get(&"$f".to_string())
+            .unwrap()
+            .structurally_eq(function));
+    });
+}
+
+#[test]
+fn remapped_spaces_are_replaced_everywhere() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %c = %x > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                => ret %x
+            }
+            #neg {
+                %y = %x - 1
+                => ret %y
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let local = |function: &Function, name: &str| {
+            *function.locals.get_name_id(&name.to_string()).unwrap()
+        };
+        let (a, x, c, y) = (
+            local(function, "@a"),
+            local(function, "%x"),
+            local(function, "%c"),
+            local(function, "%y"),
+        );
+        let scope = Scope::Local {
+            fn_name_id: function.name_id,
+        };
+        let (u, _) = function.declare_space(Some(DataType::I64), scope.clone());
+        let (d, _) = function.declare_space(Some(DataType::Bool), scope);
+        function.remap_spaces(&HashMap::from([(x, u), (c, d)]));
+
+        let block = |name: &str| function.blocks.get(&name.to_string()).unwrap().clone();
+        let spaces: HashSet<_> = ["#entry", "#pos", "#neg"]
+            .into_iter()
+            .flat_map(|name| {
+                let block = block(name);
+                let irs = block.irs().iter().chain([&block.terminator]);
+                irs.flat_map(|ir| ir.used_spaces().into_iter().chain(ir.defined_space()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert!(!spaces.contains(&x) && !spaces.contains(&c));
+        assert!([a, u, d, y].iter().all(|space| spaces.contains(space)));
+        assert!(matches!(
+            block("#entry").terminator,
+            IR::Jump(JumpOperation::Branch(cond, ..), _) if cond == d
+        ));
+        assert!(matches!(
+            block("#pos").terminator,
+            IR::Jump(JumpOperation::Ret(value), _) if value == u
+        ));
+        assert_eq!(function.params, vec![a]);
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(6)));
+        assert_eq!(interpret(function, &[int(-5)]), Ok(int(-5)));
+    });
+}
+
+#[test]
+fn clone_function_into_program() {
+    let program = parse_program(
+        "fn $sum(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #head
+            }
+            #head {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s + %i
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %s
+            }
+        }",
+    );
+    let sum = *program
+        .borrow()
+        .functions
+        .get_name_id(&"$sum".to_string())
+        .unwrap();
+    let copy = program
+        .borrow_mut()
+        .clone_function(sum, "$sum2".to_string())
+        .unwrap();
+    assert_ne!(copy, sum);
+    assert!(program
+        .borrow_mut()
+        .clone_function(sum, "$sum2".to_string())
+        .is_none());
+    assert!(program
+        .borrow_mut()
+        .clone_function(copy + 100, "$sum3".to_string())
+        .is_none());
+
+    let program = program.borrow();
+    let original = program.functions.get(&"$sum".to_string()).unwrap();
+    let clone = program.functions.get(&"$sum2".to_string()).unwrap();
+    assert_eq!((clone.name.as_str(), clone.name_id), ("$sum2", copy));
+    let spaces = |function: &Function| {
+        let mut spaces: HashSet<_> = function.params.iter().copied().collect();
+        for name_id in function.block_name_ids() {
+            let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+            let block = function.blocks.get_from_id(id).unwrap();
+            for ir in block.irs().iter().chain([block.terminator()]) {
+                spaces.extend(ir.used_spaces().into_iter().chain(ir.defined_space()));
+            }
+        }
+        spaces.retain(|space| function.is_local(*space));
+        spaces
+    };
+    assert!(spaces(&original).is_disjoint(&spaces(&clone)));
+    let i = *clone.locals.get_name_id(&"%i".to_string()).unwrap();
+    let i = clone
+        .locals
+        .get_from_id(clone.locals.get_id_from_name_id(&i).unwrap())
+        .unwrap();
+    assert_eq!(i.scope, Scope::Local { fn_name_id: copy });
+    drop(i);
+
+    let mut renamed = clone.clone();
+    renamed.name = original.name.clone();
+    assert!(renamed.structurally_eq(&original));
+    assert_eq!(interpret(&clone, &[int(5)]), Ok(int(10)));
+    assert_eq!(interpret(&original, &[int(5)]), Ok(int(10)));
+}
+
+#[test]
+fn const_value() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 5
+                => ret %x
+            }
+        }",
+    );
+    let five = *program.borrow().constants.get_name_id(&int(5)).unwrap();
+    with_function(&program, "$f", |function| {
+        let local = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        assert_eq!(function.const_value(five), Some(int(5)));
+        assert_eq!(function.const_value(local("%x")), None);
+        assert_eq!(function.const_value(local("@a")), None);
+    });
+}
+
+#[test]
+fn value_types() {
+    let program = Program::new();
+    let mut program = program.borrow_mut();
+    assert_eq!(int(5).get_type(&program), Some(DataType::I64));
+    let byte = Value::Int(IntValue::new(IntType::U8, 5));
+    assert_eq!(byte.get_type(&program), Some(DataType::U8));
+    assert_eq!(Value::Bool(true).get_type(&program), Some(DataType::Bool));
+
+    let (one, _) = program.lookup_or_insert_constant(int(1));
+    let (two, _) = program.lookup_or_insert_constant(int(2));
+    let (yes, _) = program.lookup_or_insert_constant(Value::Bool(true));
+    let array = Value::Array(ArrayValue {
+        value: vec![one, two],
+    });
+    let pair = DataType::Array(Box::new(DataType::I64), 2);
+    assert_eq!(array.get_type(&program), Some(pair.clone()));
+    let (array, _) = program.lookup_or_insert_constant(array);
+    let nested = Value::Struct(StructValue {
+        value: vec![array, yes],
+    });
+    assert_eq!(
+        nested.get_type(&program),
+        Some(DataType::Struct(vec![pair, DataType::Bool]))
+    );
+    let empty = Value::Array(ArrayValue { value: vec![] });
+    assert_eq!(empty.get_type(&program), None);
+}
+
+#[test]
+fn structural_equality() {
+    let source = "fn $f(i64 @a, i64 @b) : i64 {
+            #entry {
+                %c = @a > @b
+                => %c ? #then : #else
+            }
+            #then {
+                %x = @a * 2 + 5
+                => ret %x
+            }
+            #else {
+                => ret @b
+            }
+        }";
+    let first = parse_program(source);
+    // A leading function shifts every name id of the second build
+    let second = parse_program(&format!(
+        "fn $g(i64 @z) : i64 {{
+            #start {{
+                %y = @z - 1
+                => ret %y
+            }}
+        }}
+        {source}"
+    ));
+    let changed = parse_program(&source.replace("2 + 5", "2 + 6"));
+    let swapped = parse_program(&source.replace("@a > @b", "@b > @a"));
+    let first_program = first.borrow();
+    let first_function = first_program.functions.get(&"$f".to_string()).unwrap();
+    for (other, expected) in [(&second, true), (&changed, false), (&swapped, false)] {
+        let other = other.borrow();
+        let function = other.functions.get(&"$f".to_string()).unwrap();
+        assert_eq!(first_function.structurally_eq(&function), expected);
+    }
+    assert!(first_program.structurally_eq(&parse_program(source).borrow()));
+    assert!(!first_program.structurally_eq(&second.borrow()));
+}
+
+fn int(value: i64) -> Value {
+    Value::Int(IntValue::i64(value))
+}
+
+#[test]
+fn interpret_add() {
+    let program = parse_program(
+        "fn $add(i64 @a, i64 @b) : i64 {
+            #entry {
+                %s = @a + @b
+                => ret %s
+            }
+        }",
+    );
+    with_function(&program, "$add", |function| {
+        assert_eq!(interpret(function, &[int(2), int(3)]), Ok(int(5)));
+        assert_eq!(
+            interpret(function, &[int(2)]),
+            Err(InterpretError::ArityMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+    });
+}
+
+#[test]
+fn interpret_sum_loop() {
+    let program = Program::new();
+    with_function(&program, "$sum", |function| {
+        function.return_type = DataType::I64;
+        let n = function.declare_param("@n".to_string(), DataType::I64);
+
+        let mut entry = function.build_block("#entry");
+        let zero = entry.int(0);
+        let one = entry.int(1);
+        let i = entry.copy("%i", one);
+        let s = entry.copy("%s", zero);
+        entry.next();
+        let mut cond = function.build_block("#cond");
+        let c = cond.compare(CompareType::LessEqual, i, n);
+        cond.branch(c, "#body", "#done");
+        let mut body = function.build_block("#body");
+        body.add("%s", s, i);
+        body.add("%i", i, one);
+        body.jump("#cond");
+        function.build_block("#done").ret(s);
+
+        assert_eq!(interpret(function, &[int(10)]), Ok(int(55)));
+        assert_eq!(interpret(function, &[int(0)]), Ok(int(0)));
+    });
+}
+
+#[test]
+fn interpret_call() {
+    let program = parse_program(
+        "fn $add(i64 @a, i64 @b) : i64 {
+            #entry {
+                %s = @a + @b
+                => ret %s
+            }
+        }
+        fn $main(i64 @x) : i64 {
+            #entry {
+                %p0 = param @x
+                %p1 = param 2
+                %r = call $add
+                => ret %r
+            }
+        }",
+    );
+    let program = program.borrow();
+    let main = program.functions.get(&"$main".to_string()).unwrap();
+    assert_eq!(interpret(&main, &[int(40)]), Ok(int(42)));
+}
+
+#[test]
+fn interpret_undefined_space() {
+    let program = parse_program(
+        "fn $f() : i64 {
+            #entry {
+                => ret %x
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let x = *function.locals.get_name_id(&"%x".to_string()).unwrap();
+        assert_eq!(
+            interpret(function, &[]),
+            Err(InterpretError::UndefinedSpace { space: x })
+        );
+    });
+}
+
+#[test]
+fn return_struct() {
+    let program = parse_program(
+        "fn $pair(i64 @a) : {i64, i64} {
+            #entry {
+                %p = {@a, 1}
+                => ret %p
+            }
+        }
+        fn $triple(i64 @a) : {i64, i64, i64} {
+            #entry {
+                => ret {@a, 1}
+            }
+        }
+        fn $scalar(i64 @a) : i64 {
+            #entry {
+                => ret {@a, 1}
+            }
+        }",
+    );
+    let program = program.borrow();
+    let pair = program.functions.get(&"$pair".to_string()).unwrap();
+    assert_eq!(check_function(&pair), Ok(()));
+    let triple = program.functions.get(&"$triple".to_string()).unwrap();
+    assert_eq!(
+        check_function(&triple),
+        Err(TypeError::ReturnArityMismatch {
+            expected: 3,
+            found: 2
+        })
+    );
+    let scalar = program.functions.get(&"$scalar".to_string()).unwrap();
+    assert_eq!(
+        check_function(&scalar),
+        Err(TypeError::ReturnMismatch {
+            expected: DataType::I64,
+            found: DataType::Struct(vec![DataType::I64, DataType::I64])
+        })
+    );
+}
+
+#[test]
+fn trailing_next_returns_void() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                => next
+            }
+            #last {
+                %x = @a + 1
+                => next
+            }
+        }
+        fn $g(i64 @a) : void {
+            #entry {
+                %x = @a + 1
+                => next
+            }
+        }",
+    );
+    let program = program.borrow();
+    let f = program.functions.get(&"$f".to_string()).unwrap();
+    assert_eq!(
+        check_function(&f),
+        Err(TypeError::ReturnMismatch {
+            expected: DataType::I64,
+            found: DataType::Void
+        })
+    );
+    let g = program.functions.get(&"$g".to_string()).unwrap();
+    assert_eq!(check_function(&g), Ok(()));
+}
+
+#[test]
+fn annotated_dump() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %y = %x * 2
+                => ret %y
+            }
+        }",
+    );
+    let program = program.borrow();
+    let function = program.functions.get(&"$f".to_string()).unwrap();
+    let plain = function.to_string();
+    let annotated = format!("{:#}", *function);
+    assert!(!plain.contains("DECL"));
+    assert!(annotated.contains("{DECL = 0}"));
+    assert!(annotated.contains("{DECL = 1}"));
+    let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+    assert!(format!("{:#}", entry.irs()[1]).ends_with(" {DECL = 1}"));
+    assert!(format!("{:#}", entry.terminator).ends_with(" {}"));
+}
+
+#[test]
+fn narrow_int_wraps() {
+    let binary = |int_type, a, op, b| {
+        let a = Value::Int(IntValue::new(int_type, a));
+        let b = Value::Int(IntValue::new(int_type, b));
+        match a.binary(op, &b) {
+            Some(Value::Int(IntValue { value, .. })) => value,
+            other => panic!("{:?}", other),
+        }
+    };
+    assert_eq!(binary(IntType::I8, 127, BinaryOp::Add, 1), -128);
+    assert_eq!(binary(IntType::I8, -128, BinaryOp::Sub, 1), 127);
+    assert_eq!(binary(IntType::I8, 16, BinaryOp::Mul, 16), 0);
+    assert_eq!(binary(IntType::U8, 255, BinaryOp::Add, 1), 0);
+    assert_eq!(binary(IntType::U8, 0, BinaryOp::Sub, 1), 255);
+    assert_eq!(binary(IntType::I16, 32767, BinaryOp::Add, 1), -32768);
+    assert_eq!(binary(IntType::U32, -1, BinaryOp::Div, 2), 0x7FFF_FFFF);
+    assert_eq!(IntValue::new(IntType::I8, 200).value, -56);
+
+    let max = Value::Int(IntValue::new(IntType::U64, -1));
+    let one = Value::Int(IntValue::new(IntType::U64, 1));
+    assert_eq!(max.compare(CompareType::Greater, &one), Some(Value::Bool(true)));
+    assert_eq!(max.binary(BinaryOp::Add, &int(1)), None);
+}
+
+#[test]
+fn overflow_modes() {
+    let binary = |int_type, a, op, b, mode| {
+        let a = Value::Int(IntValue::new(int_type, a));
+        let b = Value::Int(IntValue::new(int_type, b));
+        match a.binary_in(op, &b, mode) {
+            Some(Value::Int(IntValue { value, .. })) => Some(value),
+            None => None,
+            other => panic!("{:?}", other),
+        }
+    };
+    let max_plus_one = |mode| binary(IntType::I64, i64::MAX, BinaryOp::Add, 1, mode);
+    assert_eq!(max_plus_one(OverflowMode::Wrapping), Some(i64::MIN));
+    assert_eq!(max_plus_one(OverflowMode::Saturating), Some(i64::MAX));
+    assert_eq!(max_plus_one(OverflowMode::Checked), None);
+
+    let saturating = OverflowMode::Saturating;
+    assert_eq!(
+        binary(IntType::U8, 250, BinaryOp::Add, 10, saturating),
+        Some(255)
+    );
+    assert_eq!(
+        binary(IntType::U8, 0, BinaryOp::Sub, 1, saturating),
+        Some(0)
+    );
+    assert_eq!(
+        binary(IntType::I8, -100, BinaryOp::Mul, 2, saturating),
+        Some(-128)
+    );
+    assert_eq!(
+        binary(IntType::U64, -1, BinaryOp::Mul, -1, saturating),
+        Some(-1)
+    );
+    assert_eq!(
+        binary(IntType::I64, i64::MIN, BinaryOp::Div, -1, saturating),
+        Some(i64::MAX)
+    );
+    let checked = OverflowMode::Checked;
+    assert_eq!(
+        binary(IntType::I8, 100, BinaryOp::Add, 27, checked),
+        Some(127)
+    );
+    assert_eq!(binary(IntType::U32, 0, BinaryOp::Sub, 1, checked), None);
+    assert_eq!(
+        binary(IntType::I8, -1, BinaryOp::Xor, 127, checked),
+        Some(-128)
+    );
+}
+
+#[test]
+fn overflow_mode_of_program() {
+    // Folding rewrites `%b`, so every mode gets a fresh program
+    let fold = |mode| {
+        let program = parse_program(
+            "fn $f() : i64 {
+                #entry {
+                    %a = 0x7FFFFFFFFFFFFFFF
+                    %b = %a + 1
+                    => ret %b
+                }
+            }",
+        );
+        program.borrow_mut().overflow = mode;
+        let mut folded = None;
+        with_function(&program, "$f", |function| {
+            let b = *function.locals.get_name_id(&"%b".to_string()).unwrap();
+            let result = interpret(function, &[]);
+            propagate_constants(function);
+            folded = Some((result, function.const_value(b)));
+        });
+        folded.unwrap()
+    };
+    let (result, folded) = fold(OverflowMode::Wrapping);
+    assert_eq!((result, folded), (Ok(int(i64::MIN)), Some(int(i64::MIN))));
+    let (result, folded) = fold(OverflowMode::Saturating);
+    assert_eq!((result, folded), (Ok(int(i64::MAX)), Some(int(i64::MAX))));
+    let (result, folded) = fold(OverflowMode::Checked);
+    assert!(result.is_err());
+    assert_eq!(folded, None);
+}
+
+#[test]
+fn select_on_constant_condition_folds() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = 1 < 2
+                %x = select %c ? @a : 7
+                %y = select false ? 3 : 4
+                %z = %x + %y
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let a = function.params[0];
+        let y = *function.locals.get_name_id(&"%y".to_string()).unwrap();
+        assert_eq!(check_function(function), Ok(()));
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(9)));
+        assert!(propagate_constants(function));
+        let id = function.blocks.get_id(&"#entry".to_string()).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        assert!(matches!(
+            block.irs()[1],
+            IR::Assignment(_, Operation::Unary(UnaryOp::Unit, src), _) if src == a
+        ));
+        drop(block);
+        assert_eq!(function.const_value(y), Some(int(4)));
+        assert_eq!(interpret(function, &[int(5)]), Ok(int(9)));
+    });
+}
+
+#[test]
+fn select_arms_must_share_a_type() {
+    let program = parse_program(
+        "fn $mixed(i64 @a, f64 @b) : i64 {
+            #entry {
+                %c = @a > 0
+                %x = select %c ? @a : @b
+                => ret %x
+            }
+        }
+        fn $int(i64 @a) : i64 {
+            #entry {
+                %x = select @a ? @a : 0
+                => ret %x
+            }
+        }",
+    );
+    let program = program.borrow();
+    let mixed = program.functions.get(&"$mixed".to_string()).unwrap();
+    assert!(matches!(
+        check_function(&mixed),
+        Err(TypeError::OperandMismatch {
+            left: DataType::I64,
+            right: DataType::F64,
+            ..
+        })
+    ));
+    let int = program.functions.get(&"$int".to_string()).unwrap();
+    assert!(matches!(
+        check_function(&int),
+        Err(TypeError::NonBoolCondition {
+            found: DataType::I64,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn interpret_narrow_int() {
+    let program = parse_program(
+        "fn $f(i8 @a) : i64 {
+            #entry {
+                %x = @a + @a
+                %y = cast i64 %x
+                %z = %y + 200
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let a = Value::Int(IntValue::new(IntType::I8, 100));
+        assert_eq!(interpret(function, &[a]), Ok(int(144)));
+        assert_eq!(check_function(function), Ok(()));
+    });
+}
+
+#[test]
+fn data_type_size() {
+    assert_eq!(DataType::I8.size_of(), 1);
+    assert_eq!(DataType::U16.size_of(), 2);
+    assert_eq!(DataType::I32.size_of(), 4);
+    assert_eq!(DataType::U64.size_of(), 8);
+    assert_eq!(DataType::Bool.size_of(), 1);
+    let record = DataType::Struct(vec![
+        DataType::I8,
+        DataType::I32,
+        DataType::Array(Box::new(DataType::U16), 3),
+    ]);
+    assert_eq!(record.size_of(), 11);
+}
+
+#[test]
+fn mixed_operands_need_cast() {
+    let program = parse_program(
+        "fn $mixed(i64 @a) : f64 {
+            #entry {
+                %b = @a * 2.5
+                => ret %b
+            }
+        }
+        fn $cast(i64 @a) : f64 {
+            #entry {
+                %f = cast f64 @a
+                %b = %f * 2.5
+                => ret %b
+            }
+        }",
+    );
+    let program = program.borrow();
+    let mixed = program.functions.get(&"$mixed".to_string()).unwrap();
+    assert!(matches!(
+        check_function(&mixed),
+        Err(TypeError::OperandMismatch {
+            left: DataType::I64,
+            right: DataType::F64,
+            ..
+        })
+    ));
+    let cast = program.functions.get(&"$cast".to_string()).unwrap();
+    assert_eq!(check_function(&cast), Ok(()));
+}
+
+#[test]
+fn integers_widen_implicitly_but_not_to_floats() {
+    assert!(DataType::I32.can_coerce_to(&DataType::I64));
+    assert!(DataType::U8.can_coerce_to(&DataType::I16));
+    assert!(!DataType::I64.can_coerce_to(&DataType::I32));
+    assert!(!DataType::I8.can_coerce_to(&DataType::U64));
+    assert!(!DataType::U32.can_coerce_to(&DataType::I32));
+    assert_eq!(
+        DataType::common_type(&DataType::I32, &DataType::I64),
+        Some(DataType::I64)
+    );
+    assert_eq!(
+        DataType::common_type(&DataType::U16, &DataType::I32),
+        Some(DataType::I32)
+    );
+    assert_eq!(DataType::common_type(&DataType::U64, &DataType::I64), None);
+
+    assert!(!DataType::I64.can_coerce_to(&DataType::F64));
+    assert!(!DataType::F64.can_coerce_to(&DataType::I64));
+    assert!(!DataType::Bool.can_coerce_to(&DataType::I8));
+    assert_eq!(DataType::common_type(&DataType::I32, &DataType::F64), None);
+
+    let pair = DataType::Struct(vec![DataType::I32, DataType::I32]);
+    let wide_pair = DataType::Struct(vec![DataType::I64, DataType::I64]);
+    assert!(pair.can_coerce_to(&pair));
+    assert!(!pair.can_coerce_to(&wide_pair));
+    assert_eq!(DataType::common_type(&pair, &wide_pair), None);
+}
+
+#[test]
+fn casts_are_inserted_where_operands_widen() {
+    let program = parse_program(
+        "fn $widen(i32 @a, i64 @b) : i64 {
+            #entry {
+                %s = @a + @b
+                => ret %s
+            }
+        }
+        fn $mixed(i64 @a) : f64 {
+            #entry {
+                %b = @a * 2.5
+                => ret %b
+            }
+        }",
+    );
+    with_function(&program, "$widen", |function| {
+        assert!(check_function(function).is_err());
+        assert!(insert_casts(function));
+        assert_eq!(check_function(function), Ok(()));
+        let inputs = [
+            Value::Int(IntValue::new(IntType::I32, -3)),
+            Value::Int(IntValue::new(IntType::I64, 1 << 40)),
+        ];
+        assert_eq!(
+            interpret(function, &inputs),
+            Ok(Value::Int(IntValue::new(IntType::I64, (1 << 40) - 3)))
+        );
+    });
+    with_function(&program, "$mixed", |function| {
+        assert!(!insert_casts(function));
+        assert!(check_function(function).is_err());
+    });
+}
+
+#[test]
+fn branch_condition_must_be_bool() {
+    let program = parse_program(
+        "fn $int(i64 @a) : i64 {
+            #entry {
+                => @a ? #yes : #no
+            }
+            #yes {
+                => ret 1
+            }
+            #no {
+                => ret 0
+            }
+        }
+        fn $compared(i64 @a) : i64 {
+            #entry {
+                %c = @a != 0
+                => %c ? #yes : #no
+            }
+            #yes {
+                => ret 1
+            }
+            #no {
+                => ret 0
+            }
+        }",
+    );
+    let program = program.borrow();
+    let int = program.functions.get(&"$int".to_string()).unwrap();
+    assert!(matches!(
+        check_function(&int),
+        Err(TypeError::NonBoolCondition {
+            found: DataType::I64,
+            ..
+        })
+    ));
+    let compared = program.functions.get(&"$compared".to_string()).unwrap();
+    assert_eq!(check_function(&compared), Ok(()));
+}
+
+#[test]
+fn duplicated_declaration_number_fails_verification() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %y = %x * 2
+                => ret %y
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        assert_eq!(verify_function(function), Ok(()));
+        let id = function.blocks.get_id(&"#entry".to_string()).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        block.irs_mut()[1].info_mut().declaration_number = Some(0);
+        drop(block);
+        assert!(matches!(
+            verify_function(function),
+            Err(VerifyError::DuplicateDeclarationNumber { number: 0, .. })
+        ));
+        let error = verify_function_located(function).unwrap_err();
+        assert_eq!(error.category, ErrorCategory::Verify);
+        assert_eq!(error.span.unwrap().start.line, 3);
+
+        function.build_graph();
+        assert_eq!(verify_function(function), Ok(()));
+    });
+}
+
+#[test]
+fn parse_and_type_errors_are_compile_errors() {
+    let error: CompileError = Parser::new(
+        "fn $f() : i64 {\n    #entry {\n        %x = + 1\n"
+            .chars()
+            .tokenize(),
+    )
+    .match_program()
+    .err()
+    .unwrap()
+    .into();
+    assert_eq!(error.category, ErrorCategory::Parse);
+    assert_eq!(error.span.unwrap().start.line, 2);
+    assert!(error.to_string().starts_with("parse: unexpected token"));
+    assert!(error.to_string().ends_with("@ 2:13"));
+
+    let program = parse_program(
+        "fn $mixed(i64 @a, f64 @b) : f64 {
+            #entry {
+                %x = @a
+                %y = %x + @b
+                => ret %y
+            }
+        }",
+    );
+    let program = program.borrow();
+    let mixed = program.functions.get(&"$mixed".to_string()).unwrap();
+    let error = check_function_located(&mixed).unwrap_err();
+    assert_eq!(error.category, ErrorCategory::Type);
+    let span = error.span.unwrap();
+    assert_eq!((span.start.line, span.end.line), (3, 3));
+    assert!(span.start.column < span.end.column);
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "type: {} @ 3:{}",
+            check_function(&mixed).unwrap_err(),
+            span.start.column
+        )
+    );
+}
+
+#[test]
+fn display_values() {
+    let rendered = [
+        (Value::Int(IntValue::i64(5)), "5"),
+        (Value::Int(IntValue::new(IntType::I8, -3)), "-3"),
+        (Value::Int(IntValue::new(IntType::U64, -1)), "18446744073709551615"),
+        (Value::Float(FloatValue { value: 2.0 }), "2.0"),
+        (Value::Float(FloatValue { value: -0.5 }), "-0.5"),
+        (Value::Bool(true), "true"),
+        (Value::Bool(false), "false"),
+        (Value::Array(ArrayValue { value: vec![3, 4] }), "[3, 4]"),
+        (Value::Array(ArrayValue { value: vec![] }), "[]"),
+        (Value::Struct(StructValue { value: vec![7, 1] }), "{7, 1}"),
+        (Value::Str("a\"b\n".to_string()), "\"a\\\"b\\n\""),
+        (Value::Void, "void"),
+    ];
+    for (value, expected) in rendered {
+        assert_eq!(value.to_string(), expected);
+    }
+}
+
+#[test]
+fn local_scope_displays_function_name() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                => ret %x
+            }
+        }",
+    );
+    let program = program.borrow();
+    let function = program.functions.get(&"$f".to_string()).unwrap();
+    let x = function.locals.get(&"%x".to_string()).unwrap();
+    assert_eq!(
+        x.scope,
+        Scope::Local {
+            fn_name_id: function.name_id
+        }
+    );
+    assert_eq!(x.scope.display_with(&program).to_string(), "$f");
+    assert_eq!(Scope::Global.display_with(&program).to_string(), "global");
+    let unknown = Scope::Local {
+        fn_name_id: function.name_id + 100,
+    };
+    assert_eq!(
+        unknown.display_with(&program).to_string(),
+        (function.name_id + 100).to_string()
+    );
+}
+
+#[test]
+fn struct_zero_value() {
+    let program = Program::new();
+    let mut program = program.borrow_mut();
+    let bytes = DataType::Array(Box::new(DataType::U8), 2);
+    let data_type = DataType::Struct(vec![
+        DataType::I64,
+        DataType::F64,
+        DataType::Bool,
+        bytes.clone(),
+    ]);
+    let Value::Struct(StructValue { value: members }) = data_type.zero_value(&mut program) else {
+        panic!("not a struct");
+    };
+    let constant = |space| program.constants.get_name(&space).unwrap().clone();
+    assert_eq!(constant(members[0]), Value::Int(IntValue::i64(0)));
+    assert_eq!(
+        constant(members[1]),
+        Value::Float(FloatValue { value: 0.0 })
+    );
+    assert_eq!(constant(members[2]), Value::Bool(false));
+    let Value::Array(ArrayValue { value: elements }) = constant(members[3]) else {
+        panic!("not an array");
+    };
+    assert_eq!(elements.len(), 2);
+    for element in elements {
+        assert_eq!(constant(element), Value::Int(IntValue::new(IntType::U8, 0)));
+    }
+    assert_eq!(constant(members[3]).get_type(&program), Some(bytes));
+}
+
+#[test]
+fn set_and_read_space_values() {
+    let program = parse_program(
+        "global @g: i64 = 5
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + @g
+                => ret %x
+            }
+        }",
+    );
+    let program = program.borrow();
+    let g = program.lookup_global_by_name(&"@g".to_string()).unwrap().0;
+    assert_eq!(
+        *program.space_value(g).unwrap(),
+        FlatLattice::Value(Value::Int(IntValue::i64(5)))
+    );
+    let x = *program
+        .functions
+        .get(&"$f".to_string())
+        .unwrap()
+        .locals
+        .get_name_id(&"%x".to_string())
+        .unwrap();
+    let seven = FlatLattice::Value(Value::Int(IntValue::i64(7)));
+    assert!(program.set_space_value(x, seven.clone()));
+    assert_eq!(*program.space_value(x).unwrap(), seven);
+    let function = program.functions.get(&"$f".to_string()).unwrap();
+    assert_eq!(function.locals.get(&"%x".to_string()).unwrap().value, seven);
+
+    let unknown = x + 1000;
+    assert!(program.space_value(unknown).is_none());
+    assert!(!program.set_space_value(unknown, FlatLattice::Bottom));
+}
+
+#[test]
+fn global_initializers_take_the_declared_type() {
+    let program = parse_program(
+        "global @b: u8 = 255
+        global @h: i16 = -0x10",
+    );
+    let program = program.borrow();
+    let value = |name: &str| {
+        let global = program.lookup_global_by_name(&name.to_string()).unwrap().0;
+        program.space_value(global).unwrap().clone()
+    };
+    assert_eq!(
+        value("@b"),
+        FlatLattice::Value(Value::Int(IntValue::new(IntType::U8, 255)))
+    );
+    assert_eq!(
+        value("@h"),
+        FlatLattice::Value(Value::Int(IntValue::new(IntType::I16, -16)))
+    );
+
+    let Err(error) = Parser::new("global @b: u8 = 256".chars().tokenize()).match_program() else {
+        panic!("256 doesn't fit a u8");
+    };
+    assert!(matches!(
+        error.kind(),
+        ParseErrorKind::OutOfRange { got: 256 }
+    ));
+}
+
+#[test]
+fn identical_string_literals_share_a_constant() {
+    let program = parse_program(
+        r#"fn $f() : str {
+            #entry {
+                %a = "tab\there \"quoted\" \\ \n"
+                %b = "tab\there \"quoted\" \\ \n"
+                %c = "other"
+                => ret %a
+            }
+        }"#,
+    );
+    let program = program.borrow();
+    let expected = Value::Str("tab\there \"quoted\" \\ \n".to_string());
+    let constant = *program.constants.get_name_id(&expected).unwrap();
+    let function = program.functions.get(&"$f".to_string()).unwrap();
+    let block = function.blocks.get(&"#entry".to_string()).unwrap();
+    let sources: Vec<_> = block
+        .irs()
+        .iter()
+        .map(|ir| match ir {
+            IR::Assignment(_, Operation::Unary(UnaryOp::Unit, src), _) => *src,
+            ir => panic!("{ir:?} is not a copy"),
+        })
+        .collect();
+    assert_eq!(sources[..2], [constant, constant]);
+    assert_ne!(sources[2], constant);
+    assert_eq!(function.const_value(constant), Some(expected.clone()));
+    assert_eq!(expected.get_type(&program), Some(DataType::Str));
+    let strings = program
+        .constants
+        .iter()
+        .filter(|(value, _, _)| matches!(value, Value::Str(_)))
+        .count();
+    assert_eq!(strings, 2);
+}
+
+#[test]
+fn integers_display_in_decimal_or_hex() {
+    let value = Value::Int(IntValue::i64(255));
+    assert_eq!(value.to_string(), "255");
+    assert_eq!(value.display_in(Radix::Decimal).to_string(), "255");
+    assert_eq!(value.display_in(Radix::Hex).to_string(), "0xff");
+    let negative = Value::Int(IntValue::i64(i64::MIN));
+    assert_eq!(
+        negative.display_in(Radix::Hex).to_string(),
+        "-0x8000000000000000"
+    );
+    let unsigned = Value::Int(IntValue::new(IntType::U64, -1));
+    assert_eq!(
+        unsigned.display_in(Radix::Hex).to_string(),
+        "0xffffffffffffffff"
+    );
+    assert_eq!(Value::Bool(true).display_in(Radix::Hex).to_string(), "true");
+}
+
+#[test]
+fn self_copies_and_overwritten_results_are_warnings() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a
+                %x = %x
+                %y = @a + 1
+                %y = @a + 2
+                %z = %y
+                => ret %z
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    assert_eq!(verify_function(&function), Ok(()));
+    let irs = function
+        .blocks
+        .get(&"#entry".to_string())
+        .unwrap()
+        .irs()
+        .to_vec();
+    assert_eq!(
+        verify_warnings(&function),
+        vec![
+            VerifyWarning::SelfCopy {
+                ir: irs[1].to_string()
+            },
+            VerifyWarning::OverwrittenBeforeUse {
+                ir: irs[2].to_string(),
+                overwritten_by: irs[3].to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn functions_are_found_by_name() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                => ret @a
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.function_by_name("$f").unwrap();
+    assert_eq!(function.name, "$f");
+    assert_eq!(
+        Some(&function.name_id),
+        p.functions.get_name_id(&"$f".to_string())
+    );
+    drop(function);
+    assert!(p.function_by_name("$missing").is_none());
+    assert!(p.function_by_name("f").is_none());
+}
+
+#[test]
+fn variadic_extern_calls_check_only_fixed_arguments() {
+    let program = parse_program(
+        r#"fn $printf(str @format, ...) : i64 ext
+        fn $abs(i64 @x) : i64 ext
+        fn $f(i64 @a, f64 @b) : i64 {
+            #entry {
+                %format = "%d %f\n"
+                %p = param %format
+                %q = param @a
+                %r = param @b
+                %n = call $printf
+                => ret %n
+            }
+        }
+        fn $g(i64 @a) : i64 {
+            #entry {
+                %p = param @a
+                %n = call $printf
+                => ret %n
+            }
+        }
+        fn $h(i64 @a) : i64 {
+            #entry {
+                %p = param @a
+                %q = param @a
+                %n = call $abs
+                => ret %n
+            }
+        }"#,
+    );
+    let p = program.borrow();
+    let printf = p.function_by_name("$printf").unwrap();
+    assert!(printf.is_extern && printf.is_variadic);
+    assert_eq!(printf.params.len(), 1);
+    drop(printf);
+    assert!(!p.function_by_name("$abs").unwrap().is_variadic);
+    assert_eq!(check_function(&p.function_by_name("$f").unwrap()), Ok(()));
+    let g = p.function_by_name("$g").unwrap();
+    let irs = g.blocks.get(&"#entry".to_string()).unwrap().irs().to_vec();
+    assert_eq!(
+        check_function(&g),
+        Err(TypeError::ArgumentMismatch {
+            ir: irs[0].to_string(),
+            expected: DataType::Str,
+            found: DataType::I64,
+        })
+    );
+    drop(g);
+    let error = check_function(&p.function_by_name("$h").unwrap()).unwrap_err();
+    assert!(matches!(
+        error,
+        TypeError::ArgumentCountMismatch {
+            expected: 1,
+            found: 2,
+            variadic: false,
+            ..
+        }
+    ));
+    assert!(error
+        .to_string()
+        .ends_with("passes 2 arguments, expected 1"));
+    drop(p);
+
+    let Err(error) =
+        Parser::new("fn $f(i64 @a, ...) : i64 stub".chars().tokenize()).match_program()
+    else {
+        panic!("Only extern functions can be variadic");
+    };
+    assert!(matches!(
+        error.kind(),
+        ParseErrorKind::VariadicNotExtern { .. }
+    ));
+}
+
+#[test]
+fn fresh_blocks_with_one_prefix_get_distinct_labels() {
+    let program = Program::new();
+    with_function(&program, "$f", |function| {
+        let (_, taken) = function.lookup_or_insert_block("#then_0".to_string());
+        let (first_name_id, first) = function.declare_fresh_block("#then");
+        let (second_name_id, second) = function.declare_fresh_block("#then");
+        assert_ne!(first_name_id, second_name_id);
+        assert_ne!(first, second);
+        assert_ne!(first, taken);
+        assert_eq!(function.blocks.get_name(&first_name_id).unwrap(), "#then_1");
+        assert_eq!(
+            function.blocks.get_name(&second_name_id).unwrap(),
+            "#then_2"
+        );
+    });
+
+    let src = "fn $f() : i64 {
+        #entry {
+            => #entry
+        }
+        #entry {
+            => ret 0
+        }
+    }";
+    assert!(Parser::new(src.chars().tokenize()).match_program().is_ok());
+    let Err(error) = Parser::new(src.chars().tokenize())
+        .reject_duplicate_blocks()
+        .match_program()
+    else {
+        panic!("`#entry` is given a body twice");
+    };
+    assert!(matches!(
+        error.kind(),
+        ParseErrorKind::BlockAlreadyDeclared { name } if name == "#entry"
+    ));
+}
+
+#[test]
+fn loops_and_calls_cost_more_than_straight_line_code() {
+    let program = parse_program(
+        "fn $g(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %x = %x * 2
+                => ret %x
+            }
+        }
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                => #head
+            }
+            #head {
+                %c = %i < @a
+                => %c ? #body : #done
+            }
+            #body {
+                %p = param %i
+                %r = call $g
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    let p = program.borrow();
+    let straight = p.function_by_name("$g").unwrap();
+    let looping = p.function_by_name("$f").unwrap();
+    let block = |function: &Function, name: &str| {
+        function
+            .blocks
+            .get(&name.to_string())
+            .unwrap()
+            .estimated_cost()
+    };
+    // Two assignments and a return
+    assert_eq!(block(&straight, "#entry"), 3);
+    assert_eq!(straight.estimated_cost(), 3);
+    // A compare and a branch
+    assert_eq!(block(&looping, "#head"), 4);
+    assert!(block(&looping, "#body") > block(&looping, "#head"));
+    assert!(looping.estimated_cost() > straight.estimated_cost());
+}
+
+#[test]
+fn stats_count_instructions_by_kind() {
+    let program = parse_program(
+        "global @g: i64 = 0
+        fn $abs(i64 @a) : i64 ext
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %unused = @a * 3
+                %c = @a > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                %p = param @a
+                %r = call $abs
+                @g <- %r
+                => ret %r
+            }
+            #neg {
+                => ret @a
+            }
+        }",
+    );
+    let stats = program.borrow().stats();
+    assert_eq!(
+        stats,
+        ProgramStats {
+            functions: 1,
+            blocks: 3,
+            assignments: 4,
+            calls: 1,
+            stores: 1,
+            branches: 1,
+        }
+    );
+    assert_eq!(stats.instructions(), 5);
+    assert!((stats.average_block_size() - 5.0 / 3.0).abs() < 1e-9);
+
+    with_function(&program, "$f", |function| {
+        assert_eq!(function.stats(), stats);
+        assert!(eliminate_dead_code(function));
+        assert_eq!(function.stats().assignments, 3);
+    });
+    assert_eq!(program.borrow().stats().assignments, 3);
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::error::{CompileError, Span};
+
+use super::{
+    ops::UnaryOp, CommandOperation, DataType, Function, FunctionNameId, IRInformation,
+    JumpOperation, Operation, Scope, SpaceNameId, IR,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TypeError {
+    #[error("return type mismatch: expected {expected}, found {found}")]
+    ReturnMismatch { expected: DataType, found: DataType },
+    #[error("aggregate return arity mismatch: expected {expected} members, found {found}")]
+    ReturnArityMismatch { expected: usize, found: usize },
+    #[error("operands of `{ir}` differ in type: {left} and {right}, cast one of them")]
+    OperandMismatch {
+        ir: String,
+        left: DataType,
+        right: DataType,
+    },
+    #[error("cannot cast {from} to {to}")]
+    InvalidCast { from: DataType, to: DataType },
+    #[error("condition of `{ir}` is {found}, not bool: compare it instead")]
+    NonBoolCondition { ir: String, found: DataType },
+    #[error(
+        "`{ir}` passes {found} arguments, expected {}{expected}",
+        if *.variadic { "at least " } else { "" }
+    )]
+    ArgumentCountMismatch {
+        ir: String,
+        expected: usize,
+        found: usize,
+        variadic: bool,
+    },
+    #[error("argument `{ir}` is {found}, expected {expected}")]
+    ArgumentMismatch {
+        ir: String,
+        expected: DataType,
+        found: DataType,
+    },
+}
+
+/// Types of the spaces used by `function`: the declared type if there is one,
+/// otherwise the type of the value assigned to it.
+pub fn infer_types(function: &Function) -> HashMap<SpaceNameId, DataType> {
+    let mut types = HashMap::new();
+    let block_ids: Vec<_> = function
+        .block_name_ids()
+        .iter()
+        .filter_map(|name_id| function.blocks.get_id_from_name_id(name_id))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in &block_ids {
+            let block = function.blocks.get_from_id(*id).unwrap();
+            for ir in block.irs().iter().chain(std::iter::once(&block.terminator)) {
+                for space in ir.used_spaces().into_iter().chain(ir.defined_space()) {
+                    if let Some(data_type) = declared_type(function, space) {
+                        changed |= types.insert(space, data_type).is_none();
+                    }
+                }
+                let (dst, data_type) = match ir {
+                    IR::Assignment(dst, op, _) => (*dst, operation_type(function, &types, op)),
+                    IR::Command(CommandOperation::Store(dst, src), _) => {
+                        (*dst, types.get(src).cloned())
+                    }
+                    IR::Jump(..) => continue,
+                };
+                if let (false, Some(data_type)) = (types.contains_key(&dst), data_type) {
+                    types.insert(dst, data_type);
+                    changed = true;
+                }
+            }
+        }
+    }
+    types
+}
+
+/// Checks that operands agree in type, as do both arms of a `select`, that branches
+/// and selects are on a `bool`, that calls pass as many arguments as their callee has
+/// parameters and of their types, and that every `ret` returns a value of the
+/// function's return type. An `end`, or a `next` off the last block, returns `void`.
+///
+/// A variadic callee takes any arguments after its parameters, which aren't checked.
+/// Callees that can't be borrowed, such as one being parsed, aren't checked either.
+///
+/// Values are never coerced: operands of different types need a `cast`, which
+/// `insert_casts` adds where one widens to the other, only scalars can be cast, and an
+/// integer condition must be compared to 0 explicitly. Aggregates are compared member by
+/// member, so returning a struct or array of the wrong size is reported as an arity
+/// mismatch. Spaces whose type cannot be inferred are not checked.
+pub fn check_function(function: &Function) -> Result<(), TypeError> {
+    check(function).map_err(|(error, _)| error)
+}
+
+/// `check_function`, locating the error at the instruction found at fault.
+pub fn check_function_located(function: &Function) -> Result<(), CompileError> {
+    check(function).map_err(|(error, span)| CompileError {
+        span,
+        ..error.into()
+    })
+}
+
+/// Casts the narrower operand of each instruction whose operands differ in type to
+/// their common type, see `DataType::common_type`, into a new space just before it.
+/// Operands with no common type, such as an integer and a float, are left for
+/// `check_function` to report. Returns whether any cast was inserted.
+pub fn insert_casts(function: &mut Function) -> bool {
+    let types = infer_types(function);
+    let scope = Scope::Local {
+        fn_name_id: function.name_id,
+    };
+    let mut inserted = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        let mut cast_irs = Vec::with_capacity(irs.len());
+        for mut ir in irs {
+            let span = ir.info().span;
+            if let IR::Assignment(
+                _,
+                Operation::Binary(_, a, b)
+                | Operation::Compare(_, a, b)
+                | Operation::Select(_, a, b),
+                _,
+            ) = &mut ir
+            {
+                let common = match (types.get(a), types.get(b)) {
+                    (Some(left), Some(right)) if left != right => {
+                        DataType::common_type(left, right)
+                    }
+                    _ => None,
+                };
+                let Some(common) = common else {
+                    cast_irs.push(ir);
+                    continue;
+                };
+                // Only integers coerce to a different type
+                let to = common.scalar_type().unwrap();
+                for operand in [a, b] {
+                    if types[operand] != common {
+                        let (cast, _) = function.declare_space(Some(common.clone()), scope.clone());
+                        let info = IRInformation {
+                            span,
+                            ..Default::default()
+                        };
+                        let op = Operation::Unary(UnaryOp::Cast(to), *operand);
+                        cast_irs.push(IR::Assignment(cast, op, info));
+                        *operand = cast;
+                    }
+                }
+                inserted = true;
+            }
+            cast_irs.push(ir);
+        }
+        *function.blocks.get_mut_from_id(id).unwrap().irs_mut() = cast_irs;
+    }
+    if inserted {
+        function.build_graph();
+    }
+    inserted
+}
+
+fn check(function: &Function) -> Result<(), (TypeError, Option<Span>)> {
+    let types = infer_types(function);
+    let block_name_ids = function.block_name_ids();
+    let last = block_name_ids.last().copied();
+    for name_id in block_name_ids {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        let mut args = vec![];
+        for ir in block.irs().iter() {
+            check_operands(&types, ir).map_err(|error| (error, ir.info().span))?;
+            match ir {
+                IR::Assignment(_, Operation::Unary(UnaryOp::Param, _), _) => args.push(ir),
+                IR::Assignment(_, Operation::Call(callee), _) => {
+                    check_call(function, &types, ir, *callee, &std::mem::take(&mut args))?
+                }
+                _ => {}
+            }
+        }
+        let found = match &block.terminator {
+            IR::Jump(JumpOperation::Branch(cond, _, _), _) => match types.get(cond) {
+                Some(found) if *found != DataType::Bool => {
+                    return Err((
+                        TypeError::NonBoolCondition {
+                            ir: block.terminator.to_string(),
+                            found: found.clone(),
+                        },
+                        block.terminator.info().span,
+                    ))
+                }
+                _ => continue,
+            },
+            IR::Jump(JumpOperation::Ret(v), _) => match types.get(v) {
+                Some(data_type) => data_type.clone(),
+                None => continue,
+            },
+            IR::Jump(JumpOperation::End, _) => DataType::Void,
+            // Falling off the last block ends the function
+            IR::Jump(JumpOperation::Next, _) if Some(name_id) == last => DataType::Void,
+            _ => continue,
+        };
+        check_return(&function.return_type, &found)
+            .map_err(|error| (error, block.terminator.info().span))?;
+    }
+    Ok(())
+}
+
+fn check_operands(types: &HashMap<SpaceNameId, DataType>, ir: &IR) -> Result<(), TypeError> {
+    match ir {
+        IR::Assignment(_, Operation::Binary(_, a, b) | Operation::Compare(_, a, b), _) => {
+            match (types.get(a), types.get(b)) {
+                (Some(left), Some(right)) if left != right => Err(TypeError::OperandMismatch {
+                    ir: ir.to_string(),
+                    left: left.clone(),
+                    right: right.clone(),
+                }),
+                _ => Ok(()),
+            }
+        }
+        IR::Assignment(_, Operation::Select(cond, a, b), _) => {
+            match (types.get(cond), types.get(a), types.get(b)) {
+                (Some(found), _, _) if *found != DataType::Bool => {
+                    Err(TypeError::NonBoolCondition {
+                        ir: ir.to_string(),
+                        found: found.clone(),
+                    })
+                }
+                (_, Some(left), Some(right)) if left != right => Err(TypeError::OperandMismatch {
+                    ir: ir.to_string(),
+                    left: left.clone(),
+                    right: right.clone(),
+                }),
+                _ => Ok(()),
+            }
+        }
+        IR::Assignment(_, Operation::Unary(UnaryOp::Cast(to), a), _) => match types.get(a) {
+            Some(from) if from.scalar_type().is_none() => Err(TypeError::InvalidCast {
+                from: from.clone(),
+                to: (*to).into(),
+            }),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn check_call(
+    function: &Function,
+    types: &HashMap<SpaceNameId, DataType>,
+    call: &IR,
+    callee: FunctionNameId,
+    args: &[&IR],
+) -> Result<(), (TypeError, Option<Span>)> {
+    let check = |callee: &Function| {
+        let params: Vec<_> = callee
+            .params
+            .iter()
+            .map(|param| declared_type(callee, *param))
+            .collect();
+        if args.len() < params.len() || (args.len() > params.len() && !callee.is_variadic) {
+            let error = TypeError::ArgumentCountMismatch {
+                ir: call.to_string(),
+                expected: params.len(),
+                found: args.len(),
+                variadic: callee.is_variadic,
+            };
+            return Err((error, call.info().span));
+        }
+        for (arg, expected) in args.iter().zip(params) {
+            let IR::Assignment(_, Operation::Unary(_, value), _) = arg else {
+                unreachable!("Arguments are `param`s")
+            };
+            match (types.get(value), expected) {
+                (Some(found), Some(expected)) if *found != expected => {
+                    let error = TypeError::ArgumentMismatch {
+                        ir: arg.to_string(),
+                        expected,
+                        found: found.clone(),
+                    };
+                    return Err((error, arg.info().span));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+    if callee == function.name_id {
+        return check(function);
+    }
+    let function_pool = function.program().borrow().function_pool.clone();
+    let Ok(function_pool) = function_pool.try_borrow() else {
+        return Ok(());
+    };
+    let callee = function_pool
+        .get_id(&callee)
+        .and_then(|id| function_pool.get_from_id(*id));
+    callee.map_or(Ok(()), |callee| check(&callee))
+}
+
+fn check_return(expected: &DataType, found: &DataType) -> Result<(), TypeError> {
+    match (expected, found) {
+        (DataType::Struct(expected), DataType::Struct(found)) if expected.len() != found.len() => {
+            Err(TypeError::ReturnArityMismatch {
+                expected: expected.len(),
+                found: found.len(),
+            })
+        }
+        (DataType::Array(_, expected), DataType::Array(_, found)) if expected != found => {
+            Err(TypeError::ReturnArityMismatch {
+                expected: *expected,
+                found: *found,
+            })
+        }
+        _ if expected != found => Err(TypeError::ReturnMismatch {
+            expected: expected.clone(),
+            found: found.clone(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn declared_type(function: &Function, space: SpaceNameId) -> Option<DataType> {
+    let id = function.locals.get_id_from_name_id(&space)?;
+    function.locals.get_from_id(id)?.signature.get_type()
+}
+
+fn operation_type(
+    function: &Function,
+    types: &HashMap<SpaceNameId, DataType>,
+    op: &Operation,
+) -> Option<DataType> {
+    match op {
+        Operation::Binary(_, a, _) => types.get(a).cloned(),
+        Operation::Compare(..) => Some(DataType::Bool),
+        Operation::Unary(UnaryOp::Load, _) => None,
+        Operation::Unary(UnaryOp::Cast(to), _) => Some((*to).into()),
+        Operation::Unary(_, a) => types.get(a).cloned(),
+        Operation::Select(_, a, b) => types.get(a).or_else(|| types.get(b)).cloned(),
+        Operation::Call(name_id) if *name_id == function.name_id => {
+            Some(function.return_type.clone())
+        }
+        Operation::Call(name_id) => {
+            // The callee's pool may be borrowed by whoever holds `function`
+            let function_pool = function.program().borrow().function_pool.clone();
+            let function_pool = function_pool.try_borrow().ok()?;
+            let callee = function_pool.get_from_id(*function_pool.get_id(name_id)?)?;
+            Some(callee.return_type.clone())
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::effects::has_effect;
+use crate::error::{CompileError, Span};
+use crate::escape::escaped_spaces;
+use crate::mem2reg::shared_spaces;
+
+use super::{ops::UnaryOp, Function, Operation, IR};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum VerifyError {
+    #[error("`{ir}` defines a space but has no declaration number")]
+    MissingDeclarationNumber { ir: String },
+    #[error("declaration number {number} of `{ir}` is not below the assignment count {count}")]
+    DeclarationNumberOutOfRange {
+        ir: String,
+        number: usize,
+        count: usize,
+    },
+    #[error("declaration number {number} is shared by `{first}` and `{second}`")]
+    DuplicateDeclarationNumber {
+        first: String,
+        second: String,
+        number: usize,
+    },
+    #[error("{found} definitions are numbered, but the assignment count is {expected}")]
+    AssignmentCountMismatch { expected: usize, found: usize },
+}
+
+/// Something harmless in a function that an earlier pass likely didn't mean to leave.
+#[derive(Debug, Error, PartialEq)]
+pub enum VerifyWarning {
+    #[error("`{ir}` copies a space into itself")]
+    SelfCopy { ir: String },
+    #[error("the result of `{ir}` is overwritten by `{overwritten_by}` before any use")]
+    OverwrittenBeforeUse { ir: String, overwritten_by: String },
+}
+
+/// Checks that every instruction defining a space has a declaration number, that no
+/// two share one and that they number `0..assignment_count` of `function.graph`
+/// densely, as reaching definitions indexes its bit sets by them.
+///
+/// Numbers are given by `Function::build_graph`, so this fails after adding a
+/// definition without rebuilding the graph.
+pub fn verify_function(function: &Function) -> Result<(), VerifyError> {
+    verify(function).map_err(|(error, _)| error)
+}
+
+/// `verify_function`, locating the error at the instruction found at fault.
+pub fn verify_function_located(function: &Function) -> Result<(), CompileError> {
+    verify(function).map_err(|(error, span)| CompileError {
+        span,
+        ..error.into()
+    })
+}
+
+fn verify(function: &Function) -> Result<(), (VerifyError, Option<Span>)> {
+    let count = function.graph.weight.assignment_count;
+    let mut numbered = HashMap::new();
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for ir in block.irs() {
+            if ir.defined_space().is_none() {
+                continue;
+            }
+            let span = ir.info().span;
+            let Some(number) = ir.info().declaration_number else {
+                let ir = ir.to_string();
+                return Err((VerifyError::MissingDeclarationNumber { ir }, span));
+            };
+            if number >= count {
+                let ir = ir.to_string();
+                let error = VerifyError::DeclarationNumberOutOfRange { ir, number, count };
+                return Err((error, span));
+            }
+            if let Some(first) = numbered.insert(number, ir.to_string()) {
+                let error = VerifyError::DuplicateDeclarationNumber {
+                    first,
+                    second: ir.to_string(),
+                    number,
+                };
+                return Err((error, span));
+            }
+        }
+    }
+    if numbered.len() != count {
+        let error = VerifyError::AssignmentCountMismatch {
+            expected: count,
+            found: numbered.len(),
+        };
+        return Err((error, None));
+    }
+    Ok(())
+}
+
+/// Finds copies of a space into itself, and assignments to a local whose result is
+/// assigned again later in the same block before anything reads it. Assignments with
+/// effects, see `has_effect`, and to locals passed to calls or sharing storage with
+/// others aren't reported, as they may be needed anyway.
+///
+/// Unlike `verify_function`, nothing found makes the function wrong.
+pub fn verify_warnings(function: &Function) -> Vec<VerifyWarning> {
+    let mut ignored = shared_spaces(function);
+    ignored.extend(escaped_spaces(function));
+    let mut warnings = vec![];
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        // The assignment to each space not read since
+        let mut unread: HashMap<_, &IR> = HashMap::new();
+        for ir in block.irs().iter().chain([&block.terminator]) {
+            if let IR::Assignment(dst, Operation::Unary(UnaryOp::Unit, src), _) = ir {
+                if dst == src {
+                    unread.remove(dst);
+                    let ir = ir.to_string();
+                    warnings.push(VerifyWarning::SelfCopy { ir });
+                    continue;
+                }
+            }
+            for space in ir.used_spaces() {
+                unread.remove(&space);
+            }
+            let IR::Assignment(dst, ..) = ir else {
+                continue;
+            };
+            if let Some(previous) = unread.remove(dst) {
+                warnings.push(VerifyWarning::OverwrittenBeforeUse {
+                    ir: previous.to_string(),
+                    overwritten_by: ir.to_string(),
+                });
+            }
+            if function.is_local(*dst) && !ignored.contains(dst) && !has_effect(function, ir) {
+                unread.insert(*dst, ir);
+            }
+        }
+    }
+    warnings
+}
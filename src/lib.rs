@@ -1,9 +1,34 @@
 mod block;
 pub mod ir;
+mod live_lattice;
 mod reach_lattice;
-mod semilattice;
-mod constant_propagation;
+pub mod semilattice;
+pub mod cfg_simplification;
+pub mod codegen;
+pub mod common_subexpression_elimination;
+pub mod constant_propagation;
+pub mod control_dependence;
+pub mod dead_code_elimination;
+pub mod dead_store_elimination;
+pub mod def_use;
+pub mod dot;
+pub mod effects;
+pub mod error;
+pub mod escape;
+pub mod gen_kill;
+pub mod if_conversion;
+pub mod loop_unrolling;
+pub mod loops;
+pub mod mem2reg;
+pub mod purity;
+pub mod redundant_load_elimination;
+pub mod register_allocation;
+pub mod relooper;
+pub mod sccp;
+pub mod specialization;
+pub mod tail_calls;
 mod util;
+pub mod value_numbering;
 
 #[cfg(test)]
 mod tests;
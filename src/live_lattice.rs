@@ -0,0 +1,166 @@
+use std::fmt::{Display, Formatter};
+
+use fixedbitset::FixedBitSet;
+
+use crate::block::{DataFlowGraph, Direction};
+use crate::gen_kill::{transfer_all, GenKillAnalysis};
+use crate::ir::block::{CodeBlockAnalysisNode, CodeBlockGraphWeight};
+use crate::ir::IR;
+use crate::semilattice::ProductLattice;
+use crate::block::{BlockLattice, BlockTransfer};
+
+/// Set of live spaces, indexed by space name id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveLattice {
+    pub value: FixedBitSet,
+}
+
+impl LiveLattice {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            value: FixedBitSet::with_capacity(capacity),
+        }
+    }
+    /**
+    Spaces read by the IR are 1
+     */
+    pub fn use_var(ir: &IR, code_block_graph_weight: &CodeBlockGraphWeight) -> Self {
+        let mut set = FixedBitSet::with_capacity(code_block_graph_weight.space_count);
+        ir.used_spaces()
+            .into_iter()
+            .for_each(|space| set.insert(space));
+        Self { value: set }
+    }
+    /**
+    All 1s, but the space written by the IR is 0.
+    Writes to aliased spaces are partial, so they kill nothing.
+     */
+    pub fn def_mask_var(ir: &IR, code_block_graph_weight: &CodeBlockGraphWeight) -> Self {
+        let mut set = FixedBitSet::with_capacity(code_block_graph_weight.space_count);
+        set.toggle_range(..);
+        if let Some(var) = ir.defined_space() {
+            if !code_block_graph_weight.aliased_spaces.contains(&var) {
+                set.set(var, false);
+            }
+        }
+        Self { value: set }
+    }
+}
+
+/// Uses are generated and the space written, unless aliased, is killed.
+impl GenKillAnalysis for LiveLattice {
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn capacity(weight: &CodeBlockGraphWeight) -> usize {
+        weight.space_count
+    }
+
+    fn gen(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        Self::use_var(ir, weight).value
+    }
+
+    fn kill(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        let mut set = Self::def_mask_var(ir, weight).value;
+        set.toggle_range(..);
+        set
+    }
+}
+
+impl Display for LiveLattice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl ProductLattice for LiveLattice {
+    type Element = bool;
+    fn get(&self, index: usize) -> Option<&bool> {
+        if index >= self.value.len() {
+            None
+        } else {
+            Some(&self.value[index])
+        }
+    }
+    fn set(&mut self, index: usize, element: bool) {
+        self.value.set(index, element);
+    }
+    fn dim(&self) -> usize {
+        self.value.len()
+    }
+}
+
+impl BlockLattice<LiveLattice> for CodeBlockAnalysisNode {
+    fn get_in(&self) -> &LiveLattice {
+        &self.live_in
+    }
+
+    fn set_in(&mut self, value: LiveLattice) {
+        self.live_in = value
+    }
+
+    fn get_out(&self) -> &LiveLattice {
+        &self.live_out
+    }
+
+    fn set_out(&mut self, value: LiveLattice) {
+        self.live_out = value
+    }
+}
+
+impl BlockTransfer<LiveLattice, CodeBlockAnalysisNode, CodeBlockGraphWeight>
+    for CodeBlockAnalysisNode
+{
+    fn transfer_forward(
+        &self,
+        _: &LiveLattice,
+        _: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+        _: petgraph::prelude::NodeIndex<u32>,
+    ) -> LiveLattice {
+        unimplemented!("Invalid data flow")
+    }
+
+    fn transfer_backward(
+        &self,
+        out_value: &LiveLattice,
+        graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+        _: petgraph::prelude::NodeIndex<u32>,
+    ) -> LiveLattice {
+        let mut res_in = out_value.value.clone();
+        let Some(block_id) = self.block else {
+            return LiveLattice { value: res_in };
+        };
+        let blocks = graph.weight.blocks.borrow();
+        let block = &blocks[block_id];
+        let irs = block
+            .irs()
+            .iter()
+            .chain(std::iter::once(block.terminator()));
+        transfer_all::<LiveLattice>(irs, &graph.weight, &mut res_in);
+
+        LiveLattice { value: res_in }
+    }
+
+    fn entry_out(_: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>) -> LiveLattice {
+        unimplemented!("Invalid data flow")
+    }
+
+    fn exit_in(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> LiveLattice {
+        Self::top(data_flow_graph)
+    }
+
+    fn top(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> LiveLattice {
+        LiveLattice::new(data_flow_graph.weight.space_count)
+    }
+
+    fn bottom(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> LiveLattice {
+        let mut top: LiveLattice = Self::top(data_flow_graph);
+        top.value.toggle_range(..);
+        top
+    }
+}
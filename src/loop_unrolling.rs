@@ -0,0 +1,250 @@
+use petgraph::Direction::Incoming;
+
+use crate::escape::escaped_spaces;
+use crate::ir::{
+    block::CodeBlockId,
+    ops::{BinaryOp, CompareType, DataType, OverflowMode},
+    AddressMarker, BlockNameId, Function, IRInformation, IntValue, JumpOperation, Operation, Scope,
+    SpaceNameId, Value, IR,
+};
+use crate::loops::retarget;
+use crate::mem2reg::shared_spaces;
+
+/// The most `Function::estimated_cost` the copies of a body may add up to.
+const MAX_UNROLLED_COST: usize = 64;
+
+/// A loop counting `counter` up by `step` while `counter cmp bound` holds.
+struct CountedLoop {
+    body: BlockNameId,
+    counter: SpaceNameId,
+    cmp: CompareType,
+    step: IntValue,
+    bound: IntValue,
+}
+
+/// Unrolls the loop headed by `header` `factor` times, running `factor` iterations of
+/// its body back to back for each test of its condition. Returns whether it did.
+///
+/// Only counted loops are unrolled: the header holds just `%c = %i < N` (or `<=`) on
+/// a local counter `%i` and a constant bound `N`, then branches on `%c` to a single
+/// body block, which jumps back to the header and adds a positive constant step to
+/// the counter once, as `%i = %i + 1`. The body can't read `%c`, and the counter can't
+/// share storage with others or be passed to a call. Loops whose header is the first
+/// block are skipped, as the function is entered there. So are loops whose unrolled
+/// body would cost more than `MAX_UNROLLED_COST`, see `IR::cost`.
+///
+/// A copy of the header tests whether `factor` more iterations stay in bounds, by
+/// comparing the counter with the bound less `factor - 1` steps, and if so runs a
+/// block of `factor` copies of the body. Otherwise it goes on to the original loop,
+/// which runs the iterations left over when the trip count isn't a multiple of
+/// `factor`. Jumps into the loop from outside go to the copied header.
+pub fn unroll_loop(function: &mut Function, header: BlockNameId, factor: usize) -> bool {
+    if factor < 2 || function.block_name_ids().first() == Some(&header) {
+        return false;
+    }
+    let Some(counted) = counted_loop(function, header) else {
+        return false;
+    };
+    let body_cost = {
+        let id = function.blocks.get_id_from_name_id(&counted.body).unwrap();
+        function.blocks.get_from_id(id).unwrap().estimated_cost()
+    };
+    if body_cost * factor > MAX_UNROLLED_COST {
+        return false;
+    }
+    // The counter after `factor - 1` more steps must still be in bounds
+    let steps = IntValue::new(counted.step.int_type, factor as i64 - 1);
+    let Some(last_bound) = counted
+        .step
+        .binary_in(BinaryOp::Mul, &steps, OverflowMode::Checked)
+        .and_then(|delta| {
+            counted
+                .bound
+                .binary_in(BinaryOp::Sub, &delta, OverflowMode::Checked)
+        })
+    else {
+        return false;
+    };
+    unroll(function, header, &counted, factor, last_bound);
+    true
+}
+
+fn counted_loop(function: &Function, header: BlockNameId) -> Option<CountedLoop> {
+    let block = function.blocks.get(function.blocks.get_name(&header)?)?;
+    let [IR::Assignment(cond, Operation::Compare(cmp, counter, bound), _)] = block.irs() else {
+        return None;
+    };
+    let IR::Jump(JumpOperation::Branch(branch_cond, body, exit), _) = block.terminator() else {
+        return None;
+    };
+    let (body, exit) = (body.block_id, exit.block_id);
+    if branch_cond != cond
+        || !matches!(cmp, CompareType::Less | CompareType::LessEqual)
+        || body == header
+        || exit == header
+        || body == exit
+    {
+        return None;
+    }
+    let Some(Value::Int(bound)) = function.const_value(*bound) else {
+        return None;
+    };
+    if !function.is_local(*counter)
+        || function.const_value(*counter).is_some()
+        || shared_spaces(function).contains(counter)
+        || escaped_spaces(function).contains(counter)
+    {
+        return None;
+    }
+
+    let node = *function.graph.weight.block_nodes.get(&body)?;
+    if function
+        .graph
+        .graph
+        .neighbors_directed(node, Incoming)
+        .count()
+        != 1
+    {
+        return None;
+    }
+    let body_block = function.blocks.get(function.blocks.get_name(&body)?)?;
+    let IR::Jump(JumpOperation::Unconditional(back), _) = body_block.terminator() else {
+        return None;
+    };
+    if back.block_id != header
+        || body_block
+            .irs()
+            .iter()
+            .any(|ir| ir.used_spaces().contains(cond))
+    {
+        return None;
+    }
+    let mut steps = body_block
+        .irs()
+        .iter()
+        .filter(|ir| ir.defined_space() == Some(*counter));
+    let Some(IR::Assignment(_, Operation::Binary(BinaryOp::Add, from, step), _)) = steps.next()
+    else {
+        return None;
+    };
+    if steps.next().is_some() || from != counter {
+        return None;
+    }
+    match function.const_value(*step) {
+        Some(Value::Int(step)) if step.value > 0 => Some(CountedLoop {
+            body,
+            counter: *counter,
+            cmp: *cmp,
+            step,
+            bound,
+        }),
+        _ => None,
+    }
+}
+
+fn unroll(
+    function: &mut Function,
+    header: BlockNameId,
+    counted: &CountedLoop,
+    factor: usize,
+    last_bound: IntValue,
+) {
+    let external: Vec<BlockNameId> = function
+        .graph
+        .graph
+        .neighbors_directed(function.graph.weight.block_nodes[&header], Incoming)
+        .filter_map(|predecessor| {
+            let weight = &function.graph.weight;
+            weight
+                .block_nodes
+                .iter()
+                .find(|(_, node)| **node == predecessor)
+                .map(|(name_id, _)| *name_id)
+        })
+        .filter(|predecessor| *predecessor != counted.body)
+        .collect();
+
+    // The copies go last, so a trailing `next` would fall into them
+    if let Some(last) = function.block_name_ids().last() {
+        let id = function.blocks.get_id_from_name_id(last).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        if let IR::Jump(JumpOperation::Next, info) = block.terminator {
+            block.terminator = IR::Jump(JumpOperation::End, info);
+        }
+    }
+    let body_irs = {
+        let id = function.blocks.get_id_from_name_id(&counted.body).unwrap();
+        function.blocks.get_from_id(id).unwrap().irs().to_vec()
+    };
+    let last_bound = function
+        .program()
+        .borrow_mut()
+        .lookup_or_insert_constant(Value::Int(last_bound))
+        .0;
+    let scope = Scope::Local {
+        fn_name_id: function.name_id,
+    };
+    let (cond, _) = function.declare_space(Some(DataType::Bool), scope);
+    let (unrolled_header, header_id) = declare_copy(function, header);
+    let (unrolled_body, body_id) = declare_copy(function, counted.body);
+    {
+        let mut block = function.blocks.get_mut_from_id(header_id).unwrap();
+        *block.irs_mut() = vec![IR::Assignment(
+            cond,
+            Operation::Compare(counted.cmp, counted.counter, last_bound),
+            IRInformation::default(),
+        )];
+        block.terminator = IR::Jump(
+            JumpOperation::Branch(
+                cond,
+                AddressMarker::new(unrolled_body),
+                AddressMarker::new(header),
+            ),
+            IRInformation::default(),
+        );
+    }
+    {
+        let mut block = function.blocks.get_mut_from_id(body_id).unwrap();
+        *block.irs_mut() = (0..factor).flat_map(|_| body_irs.clone()).collect();
+        block.terminator = IR::Jump(
+            JumpOperation::Unconditional(AddressMarker::new(unrolled_header)),
+            IRInformation::default(),
+        );
+    }
+
+    for predecessor in external {
+        let id = function.blocks.get_id_from_name_id(&predecessor).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        match &mut block.terminator {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => {
+                retarget(addr, header, unrolled_header)
+            }
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                retarget(true_addr, header, unrolled_header);
+                retarget(false_addr, header, unrolled_header);
+            }
+            IR::Jump(JumpOperation::Next, info) => {
+                block.terminator = IR::Jump(
+                    JumpOperation::Unconditional(AddressMarker::new(unrolled_header)),
+                    *info,
+                );
+            }
+            _ => {}
+        }
+    }
+    function.build_graph();
+}
+
+/// Declares an empty block called `<name>.unrolled`, `<name>.unrolled1`, ... for the
+/// copy of `block`.
+fn declare_copy(function: &mut Function, block: BlockNameId) -> (BlockNameId, CodeBlockId) {
+    let name = function.blocks.get_name(&block).unwrap().clone();
+    let copy_name = (0..)
+        .map(|i| match i {
+            0 => format!("{name}.unrolled"),
+            _ => format!("{name}.unrolled{i}"),
+        })
+        .find(|name| function.blocks.get_name_id(name).is_none())
+        .unwrap();
+    function.lookup_or_insert_block(copy_name)
+}
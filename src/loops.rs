@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::algo::dominators::simple_fast;
+use petgraph::algo::tarjan_scc;
+use petgraph::stable_graph::NodeIndex;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeFiltered};
+use petgraph::Direction::Incoming;
+
+use crate::ir::{AddressMarker, BlockNameId, Function, IRInformation, JumpOperation, IR};
+
+/// The loop-nesting depth of every block of `function`.
+///
+/// An edge to a block dominating its source is a back edge, whose natural loop
+/// is its target, the header, and every block reaching its source without
+/// passing the header. Loops sharing a header count as one. A block is as deep
+/// as the number of loops containing it, so blocks outside any loop, including
+/// blocks unreachable from the entry, have depth 0.
+pub fn loop_depths(function: &Function) -> HashMap<BlockNameId, u32> {
+    let bodies = natural_loops(function);
+    function
+        .graph
+        .weight
+        .block_nodes
+        .iter()
+        .map(|(name_id, node)| {
+            let depth = bodies.values().filter(|body| body.contains(node)).count();
+            (*name_id, depth as u32)
+        })
+        .collect()
+}
+
+/// The natural loops of `function` by header, each with the nodes of its blocks, the
+/// header included. See `loop_depths`.
+fn natural_loops(function: &Function) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+    let graph = &function.graph.graph;
+    let dominators = simple_fast(graph, function.graph.entry);
+    let mut bodies: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
+    for edge in graph.edge_references() {
+        let (from, header) = (edge.source(), edge.target());
+        let is_back_edge = dominators
+            .dominators(from)
+            .is_some_and(|mut dominators| dominators.any(|node| node == header));
+        if !is_back_edge {
+            continue;
+        }
+        let body = bodies
+            .entry(header)
+            .or_insert_with(|| HashSet::from([header]));
+        let mut worklist = vec![from];
+        while let Some(node) = worklist.pop() {
+            if body.insert(node) {
+                worklist.extend(
+                    graph
+                        .neighbors_directed(node, Incoming)
+                        .filter(|predecessor| dominators.dominators(*predecessor).is_some()),
+                );
+            }
+        }
+    }
+    bodies
+}
+
+/// Whether every cycle of `function` is a natural loop, entered only through its header.
+/// See `irreducible_entries` for the blocks entering the other cycles.
+pub fn is_reducible(function: &Function) -> bool {
+    irreducible_entries(function).is_empty()
+}
+
+/// The blocks through which the multiple-entry cycles of `function` are entered,
+/// ascending. Empty when the control flow is reducible.
+///
+/// A strongly connected component entered only through one block is a natural loop,
+/// whose nested cycles are the components of its body without that header. The
+/// entries of every component, at any depth, entered through more than one block,
+/// the blocks with a predecessor outside it, are reported. Unreachable blocks are
+/// ignored.
+pub fn irreducible_entries(function: &Function) -> Vec<BlockNameId> {
+    let mut entries: Vec<BlockNameId> = irreducible_regions(function)
+        .into_iter()
+        .flat_map(|(_, entries)| entries)
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// Splits blocks of `function` until its control flow is reducible, adding at most
+/// `max_new_blocks` blocks. Returns whether it became reducible; when the limit is hit
+/// first, the blocks already split are kept, as they don't change what it computes.
+///
+/// In each multiple-entry cycle, including those nested in a natural loop, the first
+/// entry is kept as the header and the second is copied: the copy takes over the jumps from outside the cycle, so the original is
+/// only entered from inside it. Copies are called `<name>.split`, `<name>.split1`, ...
+/// Each split can make blocks the copy jumps to into new entries of a smaller cycle,
+/// and nested regions can need exponentially many copies, hence the limit.
+pub fn make_reducible(function: &mut Function, max_new_blocks: usize) -> bool {
+    let mut added = 0;
+    loop {
+        let Some((members, entries)) = irreducible_regions(function).into_iter().next() else {
+            return true;
+        };
+        let [_, split, ..] = entries[..] else {
+            unreachable!("an irreducible region has at least two entries");
+        };
+        if added == max_new_blocks {
+            return false;
+        }
+        added += 1;
+        split_block(function, &members, split);
+    }
+}
+
+/// The multiple-entry regions of `function`, each with its entries, ascending. Every
+/// region has at least two entries.
+///
+/// A strongly connected component entered through a single block, the function's entry
+/// or a block with a predecessor outside it, is the body of a natural loop headed by
+/// that block, and the cycles nested in it are the components of the body without the
+/// header. Components entered through more than one block are the regions; their own
+/// nested cycles are left for after they are split.
+fn irreducible_regions(function: &Function) -> Vec<(HashSet<NodeIndex>, Vec<BlockNameId>)> {
+    let graph = &function.graph.graph;
+    let dominators = simple_fast(graph, function.graph.entry);
+    let reachable: HashSet<NodeIndex> = graph
+        .node_indices()
+        .filter(|node| dominators.dominators(*node).is_some())
+        .collect();
+    let mut regions = vec![];
+    let mut worklist = vec![reachable.clone()];
+    while let Some(nodes) = worklist.pop() {
+        let subgraph = NodeFiltered::from_fn(graph, |node| nodes.contains(&node));
+        for component in tarjan_scc(&subgraph) {
+            let members: HashSet<NodeIndex> = component.into_iter().collect();
+            let mut entries: Vec<NodeIndex> = members
+                .iter()
+                .copied()
+                .filter(|node| {
+                    *node == function.graph.entry
+                        || graph
+                            .neighbors_directed(*node, Incoming)
+                            .any(|predecessor| {
+                                !members.contains(&predecessor) && reachable.contains(&predecessor)
+                            })
+                })
+                .collect();
+            match entries.len() {
+                0 => {}
+                1 => {
+                    let header = entries.pop().unwrap();
+                    let mut body = members;
+                    body.remove(&header);
+                    worklist.push(body);
+                }
+                _ => {
+                    let mut entries: Vec<BlockNameId> = entries
+                        .into_iter()
+                        .filter_map(|node| block_of(function, node))
+                        .collect();
+                    entries.sort();
+                    regions.push((members, entries));
+                }
+            }
+        }
+    }
+    regions.sort_by_key(|(_, entries)| entries[0]);
+    regions
+}
+
+/// Copies the block `split` to a new block taking over every jump to it from outside
+/// `region`, and from the copy itself, then rebuilds the graph.
+fn split_block(function: &mut Function, region: &HashSet<NodeIndex>, split: BlockNameId) {
+    let block_name_ids = function.block_name_ids();
+    let next_of = |name_id: BlockNameId| {
+        let position = block_name_ids.iter().position(|other| *other == name_id);
+        position.and_then(|position| block_name_ids.get(position + 1).copied())
+    };
+    let external: Vec<BlockNameId> = function
+        .graph
+        .graph
+        .neighbors_directed(function.graph.weight.block_nodes[&split], Incoming)
+        .filter(|predecessor| !region.contains(predecessor))
+        .filter_map(|predecessor| {
+            let weight = &function.graph.weight;
+            weight
+                .block_nodes
+                .iter()
+                .find(|(_, node)| **node == predecessor)
+                .map(|(name_id, _)| *name_id)
+        })
+        .collect();
+
+    // The copy goes last, so a trailing `next` would fall into it
+    if let Some(last) = block_name_ids.last() {
+        let id = function.blocks.get_id_from_name_id(last).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        if let IR::Jump(JumpOperation::Next, info) = block.terminator {
+            block.terminator = IR::Jump(JumpOperation::End, info);
+        }
+    }
+    let name = function.blocks.get_name(&split).unwrap().clone();
+    let copy_name = (0..)
+        .map(|i| match i {
+            0 => format!("{name}.split"),
+            _ => format!("{name}.split{i}"),
+        })
+        .find(|name| function.blocks.get_name_id(name).is_none())
+        .unwrap();
+    let (irs, mut terminator) = {
+        let block = function.blocks.get(&name).unwrap();
+        (block.irs().to_vec(), block.terminator.clone())
+    };
+    if let IR::Jump(JumpOperation::Next, info) = terminator {
+        terminator = match next_of(split) {
+            Some(next) => IR::Jump(JumpOperation::Unconditional(AddressMarker::new(next)), info),
+            None => IR::Jump(JumpOperation::End, info),
+        };
+    }
+    let (copy, copy_id) = function.lookup_or_insert_block(copy_name);
+    // A jump of `split` to itself stays in the copy, or it would enter `region` again
+    match &mut terminator {
+        IR::Jump(JumpOperation::Unconditional(addr), _) => retarget(addr, split, copy),
+        IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+            retarget(true_addr, split, copy);
+            retarget(false_addr, split, copy);
+        }
+        _ => {}
+    }
+    {
+        let mut block = function.blocks.get_mut_from_id(copy_id).unwrap();
+        *block.irs_mut() = irs;
+        block.terminator = terminator;
+    }
+
+    for predecessor in external {
+        let id = function.blocks.get_id_from_name_id(&predecessor).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        match &mut block.terminator {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => retarget(addr, split, copy),
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                retarget(true_addr, split, copy);
+                retarget(false_addr, split, copy);
+            }
+            IR::Jump(JumpOperation::Next, info) => {
+                block.terminator = IR::Jump(
+                    JumpOperation::Unconditional(AddressMarker::new(copy)),
+                    *info,
+                );
+            }
+            _ => {}
+        }
+    }
+    function.build_graph();
+}
+
+/// Turns every loop of `function` tested at its top into one tested at its bottom,
+/// guarded by a copy of the test. Returns whether any loop was rotated.
+///
+/// A loop is rotated when its header ends in a branch with one target in the loop,
+/// other than the header itself, and one outside it. A copy of the header, called
+/// `<header>.guard`, takes over the jumps into the loop from outside: it runs the
+/// header's instructions and branches out of the loop or into a new preheader block,
+/// `<target>.preheader`, jumping to the target in the loop. The original header is
+/// then only reached from inside the loop, after each iteration, where it tests
+/// whether to run another. As both copies of the header define the same spaces, code
+/// after the loop sees the same values either way.
+///
+/// Loops headed by the first block aren't rotated, as the function is entered there.
+/// The loops are found before any is rotated, so each is rotated at most once.
+pub fn rotate_loops(function: &mut Function) -> bool {
+    let headers: Vec<BlockNameId> = natural_loops(function)
+        .into_keys()
+        .filter_map(|header| block_of(function, header))
+        .collect();
+    let mut block_name_ids = function.block_name_ids();
+    block_name_ids.retain(|name_id| headers.contains(name_id));
+    let mut changed = false;
+    for header in block_name_ids {
+        changed |= rotate_loop(function, header);
+    }
+    changed
+}
+
+/// The block of `node` in the graph of `function`, if any.
+fn block_of(function: &Function, node: NodeIndex) -> Option<BlockNameId> {
+    let weight = &function.graph.weight;
+    weight
+        .block_nodes
+        .iter()
+        .find(|(_, other)| **other == node)
+        .map(|(name_id, _)| *name_id)
+}
+
+/// Rotates the loop headed by `header`, see `rotate_loops`, then rebuilds the graph.
+fn rotate_loop(function: &mut Function, header: BlockNameId) -> bool {
+    if function.block_name_ids().first() == Some(&header) {
+        return false;
+    }
+    let Some(node) = function.graph.weight.block_nodes.get(&header).copied() else {
+        return false;
+    };
+    let Some(body) = natural_loops(function).remove(&node) else {
+        return false;
+    };
+    let name = function.blocks.get_name(&header).unwrap().clone();
+    let (irs, terminator) = {
+        let block = function.blocks.get(&name).unwrap();
+        (block.irs().to_vec(), block.terminator.clone())
+    };
+    let IR::Jump(JumpOperation::Branch(cond, true_addr, false_addr), info) = terminator else {
+        return false;
+    };
+    let in_loop = |name_id: BlockNameId| {
+        function
+            .graph
+            .weight
+            .block_nodes
+            .get(&name_id)
+            .is_some_and(|node| body.contains(node))
+    };
+    let (true_in, false_in) = (in_loop(true_addr.block_id), in_loop(false_addr.block_id));
+    let target = match (true_in, false_in) {
+        (true, false) => true_addr.block_id,
+        (false, true) => false_addr.block_id,
+        _ => return false,
+    };
+    if target == header {
+        return false;
+    }
+    let external: Vec<BlockNameId> = function
+        .graph
+        .graph
+        .neighbors_directed(node, Incoming)
+        .filter(|predecessor| !body.contains(predecessor))
+        .filter_map(|predecessor| block_of(function, predecessor))
+        .collect();
+
+    // The new blocks go last, so a trailing `next` would fall into them
+    if let Some(last) = function.block_name_ids().last() {
+        let id = function.blocks.get_id_from_name_id(last).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        if let IR::Jump(JumpOperation::Next, info) = block.terminator {
+            block.terminator = IR::Jump(JumpOperation::End, info);
+        }
+    }
+    let fresh_name = |function: &Function, name: &str, suffix: &str| {
+        (0..)
+            .map(|i| match i {
+                0 => format!("{name}.{suffix}"),
+                _ => format!("{name}.{suffix}{i}"),
+            })
+            .find(|name| function.blocks.get_name_id(name).is_none())
+            .unwrap()
+    };
+    let guard_name = fresh_name(function, &name, "guard");
+    let (guard, guard_id) = function.lookup_or_insert_block(guard_name);
+    let target_name = function.blocks.get_name(&target).unwrap().clone();
+    let preheader_name = fresh_name(function, &target_name, "preheader");
+    let (preheader, preheader_id) = function.lookup_or_insert_block(preheader_name);
+    {
+        let mut block = function.blocks.get_mut_from_id(guard_id).unwrap();
+        *block.irs_mut() = irs;
+        let into_loop = |addr: AddressMarker| {
+            if addr.block_id == target {
+                AddressMarker::new(preheader)
+            } else {
+                addr
+            }
+        };
+        block.terminator = IR::Jump(
+            JumpOperation::Branch(cond, into_loop(true_addr), into_loop(false_addr)),
+            info,
+        );
+    }
+    function
+        .blocks
+        .get_mut_from_id(preheader_id)
+        .unwrap()
+        .terminator = IR::Jump(
+        JumpOperation::Unconditional(AddressMarker::new(target)),
+        IRInformation::default(),
+    );
+
+    for predecessor in external {
+        let id = function.blocks.get_id_from_name_id(&predecessor).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        match &mut block.terminator {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => retarget(addr, header, guard),
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                retarget(true_addr, header, guard);
+                retarget(false_addr, header, guard);
+            }
+            IR::Jump(JumpOperation::Next, info) => {
+                block.terminator = IR::Jump(
+                    JumpOperation::Unconditional(AddressMarker::new(guard)),
+                    *info,
+                );
+            }
+            _ => {}
+        }
+    }
+    function.build_graph();
+    true
+}
+
+pub(crate) fn retarget(addr: &mut AddressMarker, from: BlockNameId, to: BlockNameId) {
+    if addr.block_id == from {
+        addr.block_id = to;
+    }
+}
+
+/// Fills in the `frequency` of every block of `function` that has none by static
+/// heuristics, leaving frequencies set from profile data alone.
+///
+/// A block nested in `d` loops runs `10^d` times, as for spill costs in
+/// [`graph_coloring`](crate::register_allocation::graph_coloring). Leaving the function
+/// is assumed unlikely: a branch target ending in `ret` or `end` runs half as often
+/// when the other target does not.
+pub fn estimate_frequencies(function: &mut Function) {
+    let depths = loop_depths(function);
+    let leaves = |name_id: BlockNameId| {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        matches!(
+            block.terminator(),
+            IR::Jump(JumpOperation::Ret(_) | JumpOperation::End, _)
+        )
+    };
+    let mut unlikely = HashSet::new();
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        if let IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) = block.terminator() {
+            let target = match (leaves(true_addr.block_id), leaves(false_addr.block_id)) {
+                (true, false) => true_addr.block_id,
+                (false, true) => false_addr.block_id,
+                _ => continue,
+            };
+            unlikely.insert(target);
+        }
+    }
+    for (name_id, node) in function.graph.weight.block_nodes.clone() {
+        let node = &mut function.graph.graph[node];
+        if node.frequency.is_none() {
+            let frequency = 10f64.powi(depths.get(&name_id).copied().unwrap_or(0) as i32);
+            let scale = if unlikely.contains(&name_id) {
+                0.5
+            } else {
+                1.0
+            };
+            node.frequency = Some(frequency * scale);
+        }
+    }
+}
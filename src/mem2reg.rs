@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::escape::escaped_spaces;
+use crate::ir::{
+    ops::UnaryOp, CommandOperation, Function, Operation, SpaceNameId, SpaceSignature, IR,
+};
+
+/// Turns the stores into locals that live in a register into plain copies, and the
+/// loads of such locals into copies of the definition that reaches them.
+///
+/// A local only ever written by stores is a slot, and a `load` of it reads what was
+/// last stored. When that store is earlier in the same block and its value hasn't
+/// been redefined since, the load copies the stored value directly; otherwise it copies
+/// the local, which holds the reaching store's value once the stores are copies. The
+/// IR is not in SSA form, so a copy needs no phi at a join: every read still sees the
+/// definition that reaches it. The copies are then visible to the passes looking for
+/// `Unit` assignments, such as copy coalescing.
+///
+/// Locals sharing storage with another space are left in memory: those escaping
+/// through a call, `Offset` spaces and the spaces they point into, and aggregates and
+/// their members. Stores to and loads of globals are kept too.
+///
+/// Returns whether any store or load was rewritten.
+pub fn promote_memory_to_register(function: &mut Function) -> bool {
+    let pinned = pinned_spaces(function);
+    let promoted = |space: SpaceNameId| function.is_local(space) && !pinned.contains(&space);
+    let mut assigned = HashSet::new();
+    let mut stored = HashSet::new();
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for ir in block.irs() {
+            match ir {
+                IR::Command(CommandOperation::Store(dst, _), _) => stored.insert(*dst),
+                ir => ir
+                    .defined_space()
+                    .is_some_and(|space| assigned.insert(space)),
+            };
+        }
+    }
+    let slots: HashSet<SpaceNameId> = stored
+        .difference(&assigned)
+        .copied()
+        .filter(|space| promoted(*space) && !function.params.contains(space))
+        .collect();
+
+    let mut rewrites = vec![];
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        // The value last stored to each slot, while it still holds it
+        let mut current: HashMap<SpaceNameId, SpaceNameId> = HashMap::new();
+        for (i, ir) in block.irs().iter().enumerate() {
+            let rewrite = match ir {
+                IR::Command(CommandOperation::Store(dst, src), info) if promoted(*dst) => Some(
+                    IR::Assignment(*dst, Operation::Unary(UnaryOp::Unit, *src), *info),
+                ),
+                IR::Assignment(dst, Operation::Unary(UnaryOp::Load, slot), info)
+                    if slots.contains(slot) =>
+                {
+                    let value = current.get(slot).copied().unwrap_or(*slot);
+                    Some(IR::Assignment(
+                        *dst,
+                        Operation::Unary(UnaryOp::Unit, value),
+                        *info,
+                    ))
+                }
+                _ => None,
+            };
+            if let Some(space) = ir.defined_space() {
+                current.retain(|slot, value| *slot != space && *value != space);
+            }
+            if let IR::Command(CommandOperation::Store(dst, src), _) = ir {
+                if slots.contains(dst) && dst != src {
+                    current.insert(*dst, *src);
+                }
+            }
+            if let Some(rewrite) = rewrite {
+                rewrites.push((id, i, rewrite));
+            }
+        }
+    }
+    if rewrites.is_empty() {
+        return false;
+    }
+    for (id, i, rewrite) in rewrites {
+        function.blocks.get_mut_from_id(id).unwrap().irs_mut()[i] = rewrite;
+    }
+    function.build_graph();
+    true
+}
+
+/// The locals that must stay in memory.
+pub(crate) fn pinned_spaces(function: &Function) -> HashSet<SpaceNameId> {
+    let mut pinned = escaped_spaces(function);
+    pinned.extend(shared_spaces(function));
+    pinned
+}
+
+/// The locals sharing storage with other spaces: `Offset` spaces and what they point
+/// into, and aggregates and their members. A write to one may change the others.
+pub(crate) fn shared_spaces(function: &Function) -> HashSet<SpaceNameId> {
+    let mut shared = HashSet::new();
+    for (_, name_id, space) in function.locals.iter() {
+        let Some(space) = space else {
+            continue;
+        };
+        match &space.signature {
+            SpaceSignature::Offset(base, ..) => {
+                shared.insert(*name_id);
+                shared.insert(*base);
+            }
+            SpaceSignature::Normal(_, members) if !members.is_empty() => {
+                shared.insert(*name_id);
+                shared.extend(members);
+            }
+            SpaceSignature::Normal(..) => {}
+        }
+    }
+    shared
+}
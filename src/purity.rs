@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    ops::UnaryOp, CommandOperation, Function, FunctionNameId, Operation, ProgramRef, IR,
+};
+
+/// Marks pure every defined function that stores nothing, doesn't `load`, reads or
+/// writes no global other than constants, and calls only pure functions.
+///
+/// Functions calling each other are assumed pure until one of them is shown not to
+/// be, so recursive functions can be marked, whether or not they terminate. Marks are
+/// never removed, and extern functions and stubs are only pure if declared so.
+///
+/// Returns the name ids of the functions newly marked, ascending.
+pub fn infer_purity(program: &ProgramRef) -> Vec<FunctionNameId> {
+    let callees: HashMap<FunctionNameId, HashSet<FunctionNameId>> = {
+        let program = program.borrow();
+        program
+            .functions
+            .iter()
+            .filter_map(|(_, name_id, function)| {
+                let function = function?;
+                if !function.is_defined || function.is_pure {
+                    return None;
+                }
+                Some((*name_id, callees(&function)?))
+            })
+            .collect()
+    };
+    let mut pure: HashSet<FunctionNameId> = callees.keys().copied().collect();
+    loop {
+        let program = program.borrow();
+        let impure: Vec<FunctionNameId> = pure
+            .iter()
+            .copied()
+            .filter(|name_id| {
+                callees[name_id]
+                    .iter()
+                    .any(|callee| !pure.contains(callee) && !program.is_pure(*callee))
+            })
+            .collect();
+        if impure.is_empty() {
+            break;
+        }
+        for name_id in impure {
+            pure.remove(&name_id);
+        }
+    }
+    let mut pure: Vec<FunctionNameId> = pure.into_iter().collect();
+    pure.sort();
+    let mut program = program.borrow_mut();
+    for name_id in &pure {
+        program.mark_pure(*name_id);
+    }
+    pure
+}
+
+/// The functions `function` calls, or `None` if it has an effect of its own.
+fn callees(function: &Function) -> Option<HashSet<FunctionNameId>> {
+    let mut callees = HashSet::new();
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for ir in block.irs().iter().chain([block.terminator()]) {
+            match ir {
+                IR::Command(CommandOperation::Store(..), _)
+                | IR::Assignment(_, Operation::Unary(UnaryOp::Load, _), _) => return None,
+                IR::Assignment(_, Operation::Call(callee), _) => {
+                    callees.insert(*callee);
+                }
+                _ => {}
+            }
+            let global = |space| !function.is_local(space) && function.const_value(space).is_none();
+            if ir
+                .used_spaces()
+                .into_iter()
+                .chain(ir.defined_space())
+                .any(global)
+            {
+                return None;
+            }
+        }
+    }
+    Some(callees)
+}
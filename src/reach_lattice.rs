@@ -1,18 +1,16 @@
 use std::fmt::{Display, Formatter};
-use std::ops::Deref;
 
 use fixedbitset::FixedBitSet;
-use id_arena::{Arena, DefaultArenaBehavior};
 
-use crate::block::DataFlowGraph;
+use crate::block::{DataFlowGraph, Direction};
+use crate::gen_kill::{gen_kill_all, GenKillAnalysis};
+use crate::ir::block::{CodeBlockAnalysisNode, CodeBlockGraphWeight};
 use crate::ir::IR;
-use crate::ir::block::{CodeBlockGraphWeight, CodeBlockAnalysisNode};
 use crate::semilattice::ProductLattice;
 use crate::{
     block::{BlockLattice, BlockTransfer},
     semilattice::SemiLattice,
 };
-use crate::ir::block::CodeBlock;
 
 impl SemiLattice for bool {
     fn meet(&self, other: &Self) -> Self {
@@ -29,7 +27,7 @@ impl SemiLattice for bool {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ReachLattice {
     pub value: FixedBitSet,
 }
@@ -45,10 +43,8 @@ impl ReachLattice {
      */
     pub fn gen_var(ir: &IR, code_block_graph_weight: &CodeBlockGraphWeight) -> Self {
         let mut set = FixedBitSet::with_capacity(code_block_graph_weight.assignment_count);
-        if let IR::Assignment(_, _, info) = ir {
-            if let Some(declaration_number) = info.declaration_number {
-                set.set(declaration_number, true);
-            }
+        if let Some(declaration_number) = ir.info().declaration_number {
+            set.set(declaration_number, true);
         }
         Self { value: set }
     }
@@ -61,29 +57,104 @@ impl ReachLattice {
         set
     }
     /**
-    All 0s, but killed declaration numbers are 1
+    All 0s, but killed declaration numbers are 1.
+    Definitions of aliased spaces kill nothing.
      */
     pub fn kill_var(ir: &IR, code_block_graph_weight: &CodeBlockGraphWeight) -> Self {
-        // let mut set = FixedBitSet::with_capacity(code_block_graph_weight.assignment_count);
-        // match ir {
-        //     IR::Assignment(var, _, info) => {
-        //         code_block_graph_weight
-        //             .variable_assignment_map
-        //             .get(var)
-        //             .expect("Variable not found")
-        //             .iter()
-        //             .for_each(|declaration_number| {
-        //                 set.set(*declaration_number, true);
-        //             });
-        //         set.set(
-        //             info.declaration_number.expect("No declaration number"),
-        //             false,
-        //         );
-        //     }
-        //     IR::Jump(_, _) => {}
-        // }
-        // Self { value: set }
-        todo!()
+        let mut set = FixedBitSet::with_capacity(code_block_graph_weight.assignment_count);
+        if let Some(var) = ir.defined_space() {
+            if !code_block_graph_weight.aliased_spaces.contains(&var) {
+                code_block_graph_weight
+                    .variable_assignment_map
+                    .get(&var)
+                    .expect("Variable not found")
+                    .iter()
+                    .for_each(|declaration_number| {
+                        set.set(*declaration_number, true);
+                    });
+                set.set(
+                    ir.info().declaration_number.expect("No declaration number"),
+                    false,
+                );
+            }
+        }
+        Self { value: set }
+    }
+    /**
+    Applies `ir` to the reaching set in place, as intersecting with `kill_mask_var` and
+    uniting with `gen_var` would, without allocating either mask.
+     */
+    pub fn transfer_var(
+        ir: &IR,
+        code_block_graph_weight: &CodeBlockGraphWeight,
+        reaching: &mut FixedBitSet,
+    ) {
+        if let Some(declaration_number) = ir.info().declaration_number {
+            Self::set_killed(ir, code_block_graph_weight, reaching, false);
+            reaching.insert(declaration_number);
+        }
+    }
+    /// Sets every definition of the space `ir` defines to `enabled`. Definitions of
+    /// aliased spaces kill nothing.
+    fn set_killed(
+        ir: &IR,
+        code_block_graph_weight: &CodeBlockGraphWeight,
+        set: &mut FixedBitSet,
+        enabled: bool,
+    ) {
+        if let Some(var) = ir.defined_space() {
+            if !code_block_graph_weight.aliased_spaces.contains(&var) {
+                code_block_graph_weight
+                    .variable_assignment_map
+                    .get(&var)
+                    .expect("Variable not found")
+                    .iter()
+                    .for_each(|killed| set.set(*killed, enabled));
+            }
+        }
+    }
+    /**
+    The gen set and kill mask of a sequence of instructions, so that applying them to a
+    reaching set is intersecting with the mask and uniting with the gen set. The mask
+    also clears the definitions the sequence makes, which the gen set puts back if they
+    survive to its end.
+     */
+    pub fn gen_kill_mask_block(
+        irs: &[IR],
+        code_block_graph_weight: &CodeBlockGraphWeight,
+    ) -> (Self, Self) {
+        let (gen, mut kill_mask) = gen_kill_all::<Self>(irs.iter(), code_block_graph_weight);
+        kill_mask.toggle_range(..);
+        (Self { value: gen }, Self { value: kill_mask })
+    }
+}
+
+/// Kills all definitions of the space defined, including its own, which gen puts back.
+impl GenKillAnalysis for ReachLattice {
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn capacity(weight: &CodeBlockGraphWeight) -> usize {
+        weight.assignment_count
+    }
+
+    fn gen(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        Self::gen_var(ir, weight).value
+    }
+
+    fn kill(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        let mut set = FixedBitSet::with_capacity(weight.assignment_count);
+        Self::add_kill(ir, weight, &mut set);
+        set
+    }
+
+    fn transfer(ir: &IR, weight: &CodeBlockGraphWeight, value: &mut FixedBitSet) {
+        Self::transfer_var(ir, weight, value);
+    }
+
+    fn add_kill(ir: &IR, weight: &CodeBlockGraphWeight, set: &mut FixedBitSet) {
+        if ir.info().declaration_number.is_some() {
+            Self::set_killed(ir, weight, set, true);
+        }
     }
 }
 
@@ -98,23 +169,8 @@ impl PartialOrd for ReachLattice {
     }
 }
 
-impl SemiLattice for ReachLattice {
-    fn meet(&self, other: &Self) -> Self {
-        let mut res_value = self.value.clone();
-        res_value.union_with(&other.value);
-        Self { value: res_value }
-    }
-
-    fn meet_with(&mut self, other: &Self) -> bool {
-        if self.value == other.value {
-            false
-        } else {
-            self.value.union_with(&other.value);
-            true
-        }
-    }
-}
-impl ProductLattice<bool> for ReachLattice {
+impl ProductLattice for ReachLattice {
+    type Element = bool;
     fn get(&self, index: usize) -> Option<&bool> {
         if index >= self.value.len() {
             None
@@ -122,6 +178,12 @@ impl ProductLattice<bool> for ReachLattice {
             Some(&self.value[index])
         }
     }
+    fn set(&mut self, index: usize, element: bool) {
+        self.value.set(index, element);
+    }
+    fn dim(&self) -> usize {
+        self.value.len()
+    }
 }
 
 impl BlockLattice<ReachLattice> for CodeBlockAnalysisNode {
@@ -142,29 +204,23 @@ impl BlockLattice<ReachLattice> for CodeBlockAnalysisNode {
     }
 }
 
-impl BlockTransfer<ReachLattice, CodeBlockAnalysisNode, CodeBlockGraphWeight> for CodeBlockAnalysisNode {
+impl BlockTransfer<ReachLattice, CodeBlockAnalysisNode, CodeBlockGraphWeight>
+    for CodeBlockAnalysisNode
+{
     fn transfer_forward(
         &self,
         in_value: &ReachLattice,
         graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
         _: petgraph::prelude::NodeIndex<u32>,
     ) -> ReachLattice {
-        let mut current_gen = FixedBitSet::with_capacity(graph.weight.assignment_count);
-        let mut current_kill = FixedBitSet::with_capacity(graph.weight.assignment_count);
-        let mut current_kill_mask = FixedBitSet::with_capacity(graph.weight.assignment_count);
-        current_kill_mask.toggle_range(..);
-        // self.block.borrow().irs_range.iter().rev().for_each(|ir| {
-        //     let ir_kill = ReachLattice::kill_var(ir, &graph.weight);
-        //     let mut ir_gen = ReachLattice::gen_var(ir, &graph.weight);
-        //     current_kill.union_with(&ir_kill.value);
-        //     let ir_kill_mask = ReachLattice::kill_mask_var(ir, &graph.weight);
-        //     current_kill_mask.intersect_with(&ir_kill_mask.value);
-        //     ir_gen.value.intersect_with(&current_kill_mask);
-        //     current_gen.union_with(&ir_gen.value);
-        // });
         let mut res_out = in_value.value.clone();
-        res_out.intersect_with(&current_kill_mask);
-        res_out.union_with(&current_gen);
+        let Some(block_id) = self.block else {
+            return ReachLattice { value: res_out };
+        };
+        let blocks = graph.weight.blocks.borrow();
+        let (gen, kill_mask) = blocks[block_id].reach_transfer(&graph.weight);
+        res_out.intersect_with(&kill_mask.value);
+        res_out.union_with(&gen.value);
 
         ReachLattice { value: res_out }
     }
@@ -178,7 +234,9 @@ impl BlockTransfer<ReachLattice, CodeBlockAnalysisNode, CodeBlockGraphWeight> fo
         todo!()
     }
 
-    fn entry_out(data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>) -> ReachLattice {
+    fn entry_out(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> ReachLattice {
         Self::top(data_flow_graph)
     }
 
@@ -186,11 +244,15 @@ impl BlockTransfer<ReachLattice, CodeBlockAnalysisNode, CodeBlockGraphWeight> fo
         unimplemented!("Invalid data flow")
     }
 
-    fn top(data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>) -> ReachLattice {
+    fn top(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> ReachLattice {
         ReachLattice::new(data_flow_graph.weight.assignment_count)
     }
 
-    fn bottom(data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>) -> ReachLattice {
+    fn bottom(
+        data_flow_graph: &DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>,
+    ) -> ReachLattice {
         let mut top: ReachLattice = Self::top(data_flow_graph);
         top.value.toggle_range(..);
         top
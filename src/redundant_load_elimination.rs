@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use crate::effects::has_effect;
+use crate::escape::escaped_spaces;
+use crate::ir::{ops::UnaryOp, CommandOperation, Function, Operation, SpaceNameId, IR};
+use crate::mem2reg::shared_spaces;
+
+/// Replaces each `load` of an address already loaded earlier in the same block with a
+/// copy of the local holding what was loaded, as long as nothing in between may have
+/// written memory: calls to functions not marked pure, writes to globals, and stores
+/// to locals passed to calls or sharing storage with others. Stores to other locals
+/// can't be seen through an address, so loads are kept across them, unless they
+/// overwrite the address or the loaded value.
+///
+/// A store writes its space rather than the memory its value points to, so it never
+/// gives the value of a later `load`.
+///
+/// Returns whether any `load` was replaced.
+pub fn eliminate_redundant_loads(function: &mut Function) -> bool {
+    let mut escaping = shared_spaces(function);
+    escaping.extend(escaped_spaces(function));
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let mut irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        // The local holding what was last loaded from each address
+        let mut loaded: HashMap<SpaceNameId, SpaceNameId> = HashMap::new();
+        for ir in irs.iter_mut() {
+            let writes_memory = match &*ir {
+                IR::Command(CommandOperation::Store(dst, _), _) => {
+                    escaping.contains(dst) || !function.is_local(*dst)
+                }
+                IR::Assignment(_, Operation::Unary(UnaryOp::Load | UnaryOp::Param, _), _) => false,
+                ir => has_effect(function, ir),
+            };
+            if writes_memory {
+                loaded.clear();
+            }
+            let address = match ir {
+                IR::Assignment(dst, op @ Operation::Unary(UnaryOp::Load, _), _) => {
+                    let Operation::Unary(_, address) = *op else {
+                        unreachable!("Matched a load")
+                    };
+                    if let Some(holder) = loaded.get(&address).filter(|holder| *holder != dst) {
+                        *op = Operation::Unary(UnaryOp::Unit, *holder);
+                        changed = true;
+                    }
+                    Some(address)
+                }
+                _ => None,
+            };
+            let Some(dst) = ir.defined_space() else {
+                continue;
+            };
+            loaded.retain(|from, holder| *from != dst && *holder != dst);
+            let is_private = function.is_local(dst) && !escaping.contains(&dst);
+            if let Some(address) = address.filter(|address| *address != dst && is_private) {
+                loaded.insert(address, dst);
+            }
+        }
+        *function.blocks.get_mut_from_id(id).unwrap().irs_mut() = irs;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
@@ -0,0 +1,372 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use crate::block::{BlockUpdate, DataFlowGraph, Direction};
+use crate::ir::block::{CodeBlockAnalysisNode, CodeBlockGraphWeight};
+use crate::ir::{Function, SpaceNameId};
+use crate::live_lattice::LiveLattice;
+use crate::loops::loop_depths;
+
+/// The positions at which a space is live, as one closed interval.
+///
+/// Blocks are laid out in declaration order and the instruction at index `p`
+/// reads its operands at `2p` and writes its result at `2p + 1`, so a space
+/// whose last use is an instruction's operand does not overlap its result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveInterval {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LiveInterval {
+    pub fn overlaps(&self, other: &LiveInterval) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+    fn extend(&mut self, position: usize) {
+        self.start = self.start.min(position);
+        self.end = self.end.max(position);
+    }
+}
+
+/// Live intervals of the locals of a function.
+#[derive(Debug, Default)]
+pub struct Liveness {
+    pub intervals: HashMap<SpaceNameId, LiveInterval>,
+    /// The deepest loop nesting of a block reading or writing each space,
+    /// see [`loop_depths`].
+    pub depths: HashMap<SpaceNameId, u32>,
+}
+
+impl Liveness {
+    /// Runs liveness analysis on `function` and flattens the result into intervals.
+    /// Globals, constants and aliased spaces live in memory and get no interval.
+    pub fn compute(function: &mut Function) -> Self {
+        <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<LiveLattice>>::converge(
+            &mut function.graph,
+            Direction::Backward,
+        );
+        let mut liveness = Liveness::default();
+        for param in &function.params {
+            liveness.extend(*param, 0);
+        }
+        let weight = &function.graph.weight;
+        let block_depths = loop_depths(function);
+        let mut position = 0;
+        for name_id in function.block_name_ids() {
+            let node = &function.graph.graph[weight.block_nodes[&name_id]];
+            let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+            let block = function.blocks.get_from_id(id).unwrap();
+            let irs: Vec<_> = block
+                .irs()
+                .iter()
+                .chain(std::iter::once(&block.terminator))
+                .collect();
+            let depth = block_depths.get(&name_id).copied().unwrap_or(0);
+            for space in irs
+                .iter()
+                .flat_map(|ir| ir.used_spaces().into_iter().chain(ir.defined_space()))
+            {
+                let space_depth = liveness.depths.entry(space).or_default();
+                *space_depth = (*space_depth).max(depth);
+            }
+            let mut live = node.live_out.value.clone();
+            for (offset, ir) in irs.iter().enumerate().rev() {
+                let p = position + offset;
+                let defined = ir.defined_space();
+                for space in live.ones() {
+                    liveness.extend(space, 2 * p + 1);
+                    if defined != Some(space) {
+                        liveness.extend(space, 2 * p);
+                    }
+                }
+                if let Some(space) = defined {
+                    liveness.extend(space, 2 * p + 1);
+                }
+                for space in ir.used_spaces() {
+                    liveness.extend(space, 2 * p);
+                }
+                live.intersect_with(&LiveLattice::def_mask_var(ir, weight).value);
+                live.union_with(&LiveLattice::use_var(ir, weight).value);
+            }
+            position += irs.len();
+        }
+        liveness
+            .intervals
+            .retain(|space, _| function.is_local(*space) && !weight.aliased_spaces.contains(space));
+        liveness
+    }
+    pub fn interferes(&self, a: SpaceNameId, b: SpaceNameId) -> bool {
+        match (self.intervals.get(&a), self.intervals.get(&b)) {
+            (Some(a), Some(b)) => a.overlaps(b),
+            _ => false,
+        }
+    }
+    fn extend(&mut self, space: SpaceNameId, position: usize) {
+        self.intervals
+            .entry(space)
+            .or_insert(LiveInterval {
+                start: position,
+                end: position,
+            })
+            .extend(position);
+    }
+    /// Spaces ordered by the start of their interval.
+    fn by_start(&self) -> Vec<(SpaceNameId, LiveInterval)> {
+        let mut intervals: Vec<_> = self.intervals.iter().map(|(s, i)| (*s, *i)).collect();
+        intervals.sort_by_key(|(space, interval)| (interval.start, interval.end, *space));
+        intervals
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Register(usize),
+    Spill(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Allocation {
+    pub locations: HashMap<SpaceNameId, Location>,
+    /// Number of stack slots used by spilled spaces.
+    pub spill_slots: usize,
+}
+
+/// Assigns each interval in `liveness` one of `registers` registers. Whenever more
+/// are live at once, the interval in the shallowest loop is spilled, and among
+/// those the one that ends last. Every spilled space gets its own slot; see
+/// [`coalesce_spill_slots`] to share them.
+pub fn linear_scan(liveness: &Liveness, registers: usize) -> Allocation {
+    let mut allocation = Allocation::default();
+    let mut free: Vec<usize> = (0..registers).rev().collect();
+    // (end, space, register), kept sorted by end
+    let mut active: Vec<(usize, SpaceNameId, usize)> = vec![];
+    for (space, interval) in liveness.by_start() {
+        active.retain(|&(end, _, register)| {
+            let expired = end < interval.start;
+            if expired {
+                free.push(register);
+            }
+            !expired
+        });
+        if let Some(register) = free.pop() {
+            active.push((interval.end, space, register));
+        } else {
+            let depth = |space| liveness.depths.get(&space).copied().unwrap_or(0);
+            // Candidates spilled first sort first, the current one winning ties
+            let key = |end: usize, space| (depth(space), Reverse(end));
+            let victim = (0..active.len())
+                .filter(|i| key(active[*i].0, active[*i].1) < key(interval.end, space))
+                .min_by_key(|i| key(active[*i].0, active[*i].1));
+            let spilled = match victim {
+                Some(i) => {
+                    let (_, victim, register) = active.remove(i);
+                    active.push((interval.end, space, register));
+                    victim
+                }
+                None => space,
+            };
+            allocation
+                .locations
+                .insert(spilled, Location::Spill(allocation.spill_slots));
+            allocation.spill_slots += 1;
+        }
+        active.sort();
+        for &(_, space, register) in &active {
+            allocation
+                .locations
+                .insert(space, Location::Register(register));
+        }
+    }
+    allocation
+}
+
+/// Renumbers spill slots so spaces whose intervals do not overlap share a slot.
+///
+/// Slots are handed out greedily by interval start, which uses the fewest slots
+/// possible for intervals.
+pub fn coalesce_spill_slots(allocation: &mut Allocation, liveness: &Liveness) {
+    // End of the last interval placed in each slot
+    let mut slot_ends: Vec<usize> = vec![];
+    for (space, interval) in liveness.by_start() {
+        let Some(Location::Spill(_)) = allocation.locations.get(&space) else {
+            continue;
+        };
+        let slot = match slot_ends.iter().position(|end| *end < interval.start) {
+            Some(slot) => {
+                slot_ends[slot] = interval.end;
+                slot
+            }
+            None => {
+                slot_ends.push(interval.end);
+                slot_ends.len() - 1
+            }
+        };
+        allocation.locations.insert(space, Location::Spill(slot));
+    }
+    allocation.spill_slots = slot_ends.len();
+}
+
+/// Removes copies `a = b` whose sides do not interfere by merging `a` into `b`,
+/// so the allocator gives both the same location.
+///
+/// Liveness is recomputed after every merge, since merging lengthens `b`'s interval.
+/// Returns the merged spaces mapped to the space they were merged into.
+pub fn coalesce_copies(function: &mut Function) -> HashMap<SpaceNameId, SpaceNameId> {
+    let mut merged = HashMap::new();
+    loop {
+        let liveness = Liveness::compute(function);
+        let block_ids: Vec<_> = function
+            .block_name_ids()
+            .iter()
+            .map(|name_id| function.blocks.get_id_from_name_id(name_id).unwrap())
+            .collect();
+        let copy = block_ids.iter().find_map(|id| {
+            let block = function.blocks.get_from_id(*id).unwrap();
+            block.irs().iter().enumerate().find_map(|(i, ir)| {
+                let (dst, src) = ir.as_copy()?;
+                let coalescable = dst != src
+                    && liveness.intervals.contains_key(&dst)
+                    && liveness.intervals.contains_key(&src)
+                    && !liveness.interferes(dst, src);
+                coalescable.then_some((*id, i, dst, src))
+            })
+        });
+        let Some((copy_block, copy_index, dst, src)) = copy else {
+            break;
+        };
+        for id in &block_ids {
+            let mut block_ref = function.blocks.get_mut_from_id(*id).unwrap();
+            let block = &mut *block_ref;
+            if *id == copy_block {
+                block.irs_mut().remove(copy_index);
+            }
+            block
+                .irs_mut()
+                .iter_mut()
+                .for_each(|ir| ir.replace_space(dst, src));
+            block.terminator.replace_space(dst, src);
+        }
+        function.build_graph();
+        merged.values_mut().for_each(|into| {
+            if *into == dst {
+                *into = src;
+            }
+        });
+        merged.insert(dst, src);
+    }
+    merged
+}
+
+/// Coalesces copies, then runs [`linear_scan`] with `registers` registers and
+/// packs the spill slots. Merged spaces share the location of the space they
+/// were merged into.
+pub fn allocate_registers(function: &mut Function, registers: usize) -> Allocation {
+    let merged = coalesce_copies(function);
+    let liveness = Liveness::compute(function);
+    let mut allocation = linear_scan(&liveness, registers);
+    coalesce_spill_slots(&mut allocation, &liveness);
+    for (space, into) in merged {
+        if let Some(location) = allocation.locations.get(&into).copied() {
+            allocation.locations.insert(space, location);
+        }
+    }
+    allocation
+}
+
+/// Spaces that are live at the same time and so cannot share a register.
+#[derive(Debug, Default)]
+pub struct InterferenceGraph {
+    pub neighbors: HashMap<SpaceNameId, HashSet<SpaceNameId>>,
+}
+
+impl InterferenceGraph {
+    pub fn new(liveness: &Liveness) -> Self {
+        let intervals = liveness.by_start();
+        let mut graph = InterferenceGraph::default();
+        for (i, (a, a_interval)) in intervals.iter().enumerate() {
+            graph.neighbors.entry(*a).or_default();
+            for (b, b_interval) in &intervals[i + 1..] {
+                if b_interval.start > a_interval.end {
+                    break;
+                }
+                graph.neighbors.get_mut(a).unwrap().insert(*b);
+                graph.neighbors.entry(*b).or_default().insert(*a);
+            }
+        }
+        graph
+    }
+    pub fn interferes(&self, a: SpaceNameId, b: SpaceNameId) -> bool {
+        self.neighbors.get(&a).is_some_and(|n| n.contains(&b))
+    }
+}
+
+/// Chaitin-Briggs allocation of `registers` registers over the interference graph
+/// of `liveness`.
+///
+/// Spaces with fewer than `registers` neighbours are simplified away first. When none
+/// is left, the space with the lowest spill cost, its number of reads and writes in
+/// `function` divided by its degree, is removed optimistically and only spilled if
+/// its neighbours use every register once they are coloured. Reads and writes in a
+/// block nested in `d` loops count `10^d` times, see [`loop_depths`].
+/// Every spilled space gets its own slot; see [`coalesce_spill_slots`] to share them.
+pub fn graph_coloring(function: &Function, liveness: &Liveness, registers: usize) -> Allocation {
+    let graph = InterferenceGraph::new(liveness);
+    let depths = loop_depths(function);
+    let mut occurrences: HashMap<SpaceNameId, usize> = HashMap::new();
+    for (_, name_id, block) in function.blocks.iter() {
+        let Some(block) = block else {
+            continue;
+        };
+        let weight = 10usize.pow(depths.get(name_id).copied().unwrap_or(0));
+        for ir in block.irs().iter().chain(std::iter::once(&block.terminator)) {
+            for space in ir.used_spaces().into_iter().chain(ir.defined_space()) {
+                *occurrences.entry(space).or_default() += weight;
+            }
+        }
+    }
+
+    let mut remaining: Vec<SpaceNameId> = graph.neighbors.keys().copied().collect();
+    remaining.sort();
+    let degree = |space: SpaceNameId, remaining: &[SpaceNameId]| {
+        graph.neighbors[&space]
+            .iter()
+            .filter(|n| remaining.contains(n))
+            .count()
+    };
+    let mut stack = vec![];
+    while !remaining.is_empty() {
+        let position = remaining
+            .iter()
+            .position(|space| degree(*space, &remaining) < registers)
+            .unwrap_or_else(|| {
+                let cost = |space: SpaceNameId| {
+                    occurrences.get(&space).copied().unwrap_or(0) as f64
+                        / degree(space, &remaining) as f64
+                };
+                (0..remaining.len())
+                    .min_by(|a, b| cost(remaining[*a]).total_cmp(&cost(remaining[*b])))
+                    .unwrap()
+            });
+        stack.push(remaining.remove(position));
+    }
+
+    let mut allocation = Allocation::default();
+    while let Some(space) = stack.pop() {
+        let taken: HashSet<usize> = graph.neighbors[&space]
+            .iter()
+            .filter_map(|n| match allocation.locations.get(n) {
+                Some(Location::Register(register)) => Some(*register),
+                _ => None,
+            })
+            .collect();
+        let location = match (0..registers).find(|register| !taken.contains(register)) {
+            Some(register) => Location::Register(register),
+            None => {
+                allocation.spill_slots += 1;
+                Location::Spill(allocation.spill_slots - 1)
+            }
+        };
+        allocation.locations.insert(space, location);
+    }
+    allocation
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use petgraph::algo::dominators::simple_fast;
+use petgraph::graph::{DiGraph, NodeIndex};
+use thiserror::Error;
+
+use crate::ir::{BlockNameId, Function, JumpOperation, SpaceNameId, IR};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RelooperError {
+    #[error("irreducible control flow: {from} jumps back to {to}, which does not dominate it")]
+    Irreducible { from: String, to: String },
+}
+
+/// A construct that [`StructuredNode::Branch`] can leave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    /// Back to the start of the loop headed by the block
+    Loop(BlockNameId),
+    /// Out of the block followed by the block
+    Block(BlockNameId),
+}
+
+/// Structured control flow built from the CFG of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructuredNode {
+    /// The instructions of a block, without its terminator
+    Code(BlockNameId),
+    /// Runs `body`, which `Branch(Label::Loop(header))` restarts
+    Loop {
+        header: BlockNameId,
+        body: Vec<StructuredNode>,
+    },
+    /// Runs `body`, which `Branch(Label::Block(follower))` leaves; the code of
+    /// `follower` is placed right after this node
+    Block {
+        follower: BlockNameId,
+        body: Vec<StructuredNode>,
+    },
+    If {
+        cond: SpaceNameId,
+        then: Vec<StructuredNode>,
+        otherwise: Vec<StructuredNode>,
+    },
+    Branch(Label),
+    /// Branches to `label` if `cond` is truthy, or falsy if `negated`
+    BranchIf {
+        cond: SpaceNameId,
+        negated: bool,
+        label: Label,
+    },
+    /// Returns the value, or nothing when the function falls off its last block
+    Return(Option<SpaceNameId>),
+}
+
+/// Converts the CFG of `function` into nested loops, blocks and ifs.
+///
+/// This follows Ramsey's "Beyond Relooper" on the dominator tree: loop headers
+/// open a `Loop`; blocks reached by more than one forward edge, or leaving the
+/// loop headed by their immediate dominator, are placed after a `Block` that is
+/// left with `Branch`; every other block is emitted inline where it is branched
+/// to. Blocks unreachable from the entry are dropped. Only reducible CFGs can be
+/// structured this way, irreducible ones are rejected.
+pub fn reloop(function: &Function) -> Result<Vec<StructuredNode>, RelooperError> {
+    let cfg = Cfg::new(function)?;
+    if cfg.block_name_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut relooper = Relooper {
+        function,
+        cfg,
+        context: vec![],
+    };
+    Ok(relooper.do_tree(0))
+}
+
+/// The CFG of a function over block indices in declaration order.
+struct Cfg {
+    block_name_ids: Vec<BlockNameId>,
+    successors: Vec<Vec<usize>>,
+    /// Reverse postorder number of each block reachable from the entry
+    rpo: Vec<Option<usize>>,
+    /// Dominator tree children by reverse postorder
+    children: Vec<Vec<usize>>,
+    loop_headers: Vec<bool>,
+    /// Blocks placed after a `Block`, which are branched to with `Branch`
+    followed: Vec<bool>,
+    /// Followed blocks whose immediate dominator heads a loop they are not part of
+    loop_exits: Vec<bool>,
+}
+
+impl Cfg {
+    fn new(function: &Function) -> Result<Self, RelooperError> {
+        let block_name_ids = function.block_name_ids();
+        let index: HashMap<_, _> = block_name_ids
+            .iter()
+            .enumerate()
+            .map(|(i, name_id)| (*name_id, i))
+            .collect();
+        let n = block_name_ids.len();
+        let successors: Vec<Vec<usize>> = block_name_ids
+            .iter()
+            .enumerate()
+            .map(|(i, name_id)| {
+                let id = function.blocks.get_id_from_name_id(name_id).unwrap();
+                let block = function.blocks.get_from_id(id).unwrap();
+                match &block.terminator {
+                    IR::Jump(JumpOperation::Unconditional(target), _) => {
+                        vec![index[&target.block_id]]
+                    }
+                    IR::Jump(JumpOperation::Branch(_, t, f), _) => {
+                        vec![index[&t.block_id], index[&f.block_id]]
+                    }
+                    IR::Jump(JumpOperation::Next, _) if i + 1 < n => vec![i + 1],
+                    _ => vec![],
+                }
+            })
+            .collect();
+
+        let mut rpo = vec![None; n];
+        let mut postorder = vec![];
+        let mut visited = vec![false; n];
+        // (block, next successor to visit)
+        let mut stack = vec![];
+        if n > 0 {
+            visited[0] = true;
+            stack.push((0, 0));
+        }
+        while let Some((block, next)) = stack.pop() {
+            match successors[block].get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+        for (number, block) in postorder.iter().rev().enumerate() {
+            rpo[*block] = Some(number);
+        }
+
+        let mut graph = DiGraph::<(), ()>::new();
+        let nodes: Vec<_> = (0..n).map(|_| graph.add_node(())).collect();
+        for (from, tos) in successors.iter().enumerate() {
+            for to in tos {
+                graph.add_edge(nodes[from], nodes[*to], ());
+            }
+        }
+        let mut idom = vec![None; n];
+        let mut children = vec![vec![]; n];
+        if n > 0 {
+            let dominators = simple_fast(&graph, NodeIndex::new(0));
+            for block in 0..n {
+                idom[block] = dominators
+                    .immediate_dominator(nodes[block])
+                    .map(|node| node.index());
+                if let Some(parent) = idom[block] {
+                    children[parent].push(block);
+                }
+            }
+        }
+        for children in &mut children {
+            children.sort_by_key(|child| rpo[*child]);
+        }
+        let dominates = |a: usize, mut b: usize| loop {
+            if a == b {
+                break true;
+            }
+            match idom[b] {
+                Some(parent) => b = parent,
+                None => break false,
+            }
+        };
+
+        let mut predecessors = vec![vec![]; n];
+        for (from, tos) in successors.iter().enumerate() {
+            if rpo[from].is_some() {
+                tos.iter().for_each(|to| predecessors[*to].push(from));
+            }
+        }
+        let mut loop_headers = vec![false; n];
+        let mut in_loop = vec![vec![false; n]; n];
+        let mut forward_edges = vec![0; n];
+        for (to, froms) in predecessors.iter().enumerate() {
+            for &from in froms {
+                if rpo[to] > rpo[from] {
+                    forward_edges[to] += 1;
+                    continue;
+                }
+                if !dominates(to, from) {
+                    let name = |block: usize| {
+                        let name_id = block_name_ids[block];
+                        function
+                            .blocks
+                            .get_name(&name_id)
+                            .map_or_else(|| name_id.to_string(), Clone::clone)
+                    };
+                    return Err(RelooperError::Irreducible {
+                        from: name(from),
+                        to: name(to),
+                    });
+                }
+                loop_headers[to] = true;
+                // The natural loop of the back edge: blocks reaching it without passing the header
+                let mut worklist = vec![from];
+                in_loop[to][to] = true;
+                while let Some(block) = worklist.pop() {
+                    if !in_loop[to][block] {
+                        in_loop[to][block] = true;
+                        worklist.extend(&predecessors[block]);
+                    }
+                }
+            }
+        }
+        let loop_exits: Vec<bool> = (0..n)
+            .map(|block| idom[block].is_some_and(|h| loop_headers[h] && !in_loop[h][block]))
+            .collect();
+        let followed = (0..n)
+            .map(|block| forward_edges[block] > 1 || loop_exits[block])
+            .collect();
+        Ok(Cfg {
+            block_name_ids,
+            successors,
+            rpo,
+            children,
+            loop_headers,
+            followed,
+            loop_exits,
+        })
+    }
+    fn is_backward(&self, from: usize, to: usize) -> bool {
+        self.rpo[to] <= self.rpo[from]
+    }
+}
+
+struct Relooper<'a> {
+    function: &'a Function,
+    cfg: Cfg,
+    /// Labels of the enclosing constructs, innermost last
+    context: Vec<Label>,
+}
+
+impl<'a> Relooper<'a> {
+    fn do_tree(&mut self, block: usize) -> Vec<StructuredNode> {
+        let is_header = self.cfg.loop_headers[block];
+        // Exits go after the loop, other merges inside it
+        let (exits, merges): (Vec<usize>, Vec<usize>) = self.cfg.children[block]
+            .iter()
+            .rev()
+            .filter(|child| self.cfg.followed[**child])
+            .partition(|child| self.cfg.loop_exits[**child]);
+        self.with_blocks(&exits, |this| {
+            if !is_header {
+                return this.with_blocks(&merges, |this| this.block_code(block));
+            }
+            let header = this.cfg.block_name_ids[block];
+            this.context.push(Label::Loop(header));
+            let body = this.with_blocks(&merges, |this| this.block_code(block));
+            this.context.pop();
+            vec![StructuredNode::Loop { header, body }]
+        })
+    }
+
+    /// Wraps `inner` in a `Block` for each of `followers`, outermost first, and
+    /// places each follower after its block.
+    fn with_blocks(
+        &mut self,
+        followers: &[usize],
+        inner: impl FnOnce(&mut Self) -> Vec<StructuredNode>,
+    ) -> Vec<StructuredNode> {
+        let Some((&follower, inner_followers)) = followers.split_first() else {
+            return inner(self);
+        };
+        let follower_name_id = self.cfg.block_name_ids[follower];
+        self.context.push(Label::Block(follower_name_id));
+        let body = self.with_blocks(inner_followers, inner);
+        self.context.pop();
+        let mut nodes = vec![StructuredNode::Block {
+            follower: follower_name_id,
+            body,
+        }];
+        nodes.extend(self.do_tree(follower));
+        nodes
+    }
+
+    fn block_code(&mut self, index: usize) -> Vec<StructuredNode> {
+        let name_id = self.cfg.block_name_ids[index];
+        let mut nodes = vec![StructuredNode::Code(name_id)];
+        let function = self.function;
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        let successors = self.cfg.successors[index].clone();
+        match block.terminator {
+            IR::Jump(JumpOperation::Branch(cond, ..), _) => {
+                let (t, f) = (successors[0], successors[1]);
+                if let Some(label) = self.label(index, t) {
+                    nodes.push(StructuredNode::BranchIf {
+                        cond,
+                        negated: false,
+                        label,
+                    });
+                    nodes.extend(self.branch(index, f));
+                } else if let Some(label) = self.label(index, f) {
+                    nodes.push(StructuredNode::BranchIf {
+                        cond,
+                        negated: true,
+                        label,
+                    });
+                    nodes.extend(self.branch(index, t));
+                } else {
+                    nodes.push(StructuredNode::If {
+                        cond,
+                        then: self.branch(index, t),
+                        otherwise: self.branch(index, f),
+                    });
+                }
+            }
+            IR::Jump(JumpOperation::Ret(v), _) => nodes.push(StructuredNode::Return(Some(v))),
+            _ => match successors.first() {
+                Some(&target) => nodes.extend(self.branch(index, target)),
+                None => nodes.push(StructuredNode::Return(None)),
+            },
+        }
+        nodes
+    }
+
+    /// The label that a jump from `from` to `to` branches to, if it is not inlined.
+    fn label(&self, from: usize, to: usize) -> Option<Label> {
+        let name_id = self.cfg.block_name_ids[to];
+        if self.cfg.is_backward(from, to) {
+            Some(Label::Loop(name_id))
+        } else if self.cfg.followed[to] {
+            Some(Label::Block(name_id))
+        } else {
+            None
+        }
+    }
+
+    fn branch(&mut self, from: usize, to: usize) -> Vec<StructuredNode> {
+        match self.label(from, to) {
+            Some(label) => {
+                debug_assert!(self.context.contains(&label));
+                vec![StructuredNode::Branch(label)]
+            }
+            None => self.do_tree(to),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::stable_graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::Incoming;
+
+use crate::block::EdgeKind;
+use crate::constant_propagation::{evaluate_with, intern};
+use crate::ir::{
+    ops::UnaryOp, AddressMarker, CommandOperation, Function, JumpOperation, Operation, SpaceNameId,
+    Value, IR,
+};
+use crate::semilattice::{FlatLattice, SemiLattice};
+
+/// Lattice values of the locals at a program point. Absent locals are `Top`.
+type State = HashMap<SpaceNameId, FlatLattice<Value>>;
+
+/// Sparse conditional constant propagation.
+///
+/// Values are tracked per program point, and a block is only visited once an edge
+/// into it is found executable: a branch on a known condition only makes its taken
+/// edge executable, so definitions in blocks it skips never weaken the values at the
+/// join. Starts from the entry with every parameter `Bottom` and every other local
+/// `Top`, and iterates to a fixed point.
+///
+/// Then, in the executable blocks, assignments of a known scalar become copies of the
+/// interned constant, and branches on a known condition become jumps. Blocks found
+/// unreachable are left as they are.
+///
+/// Returns whether any instruction was rewritten.
+pub fn sccp(function: &mut Function) -> bool {
+    let ins = solve(function);
+    let mut rewrites = vec![];
+    let mut jumps = vec![];
+    for name_id in function.block_name_ids() {
+        let node = function.graph.weight.block_nodes[&name_id];
+        let Some(mut state) = ins.get(&node).cloned() else {
+            continue;
+        };
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        for (i, ir) in block.irs().iter().enumerate() {
+            transfer(function, &mut state, ir);
+            let IR::Assignment(dst, op, _) = ir else {
+                continue;
+            };
+            let Some(FlatLattice::Value(value)) = state.get(dst) else {
+                continue;
+            };
+            let Some(constant) = intern(function, value.clone()) else {
+                continue;
+            };
+            if !matches!(op, Operation::Unary(UnaryOp::Unit, src) if *src == constant) {
+                rewrites.push((id, i, constant));
+            }
+        }
+        if let IR::Jump(JumpOperation::Branch(cond, true_addr, false_addr), info) =
+            &block.terminator
+        {
+            let FlatLattice::Value(value) = lookup(function, &state, *cond) else {
+                continue;
+            };
+            let target = match value.is_truthy() {
+                Some(true) => true_addr.block_id,
+                Some(false) => false_addr.block_id,
+                None => continue,
+            };
+            jumps.push((id, target, *info));
+        }
+    }
+    for (id, i, constant) in &rewrites {
+        let mut block = function.blocks.get_mut_from_id(*id).unwrap();
+        if let IR::Assignment(dst, _, info) = block.irs()[*i] {
+            block.irs_mut()[*i] =
+                IR::Assignment(dst, Operation::Unary(UnaryOp::Unit, *constant), info);
+        }
+    }
+    for (id, target, info) in &jumps {
+        function.blocks.get_mut_from_id(*id).unwrap().terminator = IR::Jump(
+            JumpOperation::Unconditional(AddressMarker::new(*target)),
+            *info,
+        );
+    }
+    if rewrites.is_empty() && jumps.is_empty() {
+        return false;
+    }
+    function.build_graph();
+    true
+}
+
+/// The state at the start of every executable block.
+fn solve(function: &Function) -> HashMap<NodeIndex, State> {
+    let graph = &function.graph;
+    let mut ins = HashMap::new();
+    let mut outs = HashMap::new();
+    let params = function
+        .params
+        .iter()
+        .map(|param| (*param, FlatLattice::Bottom));
+    outs.insert(graph.entry, params.collect::<State>());
+    let mut executable: HashSet<(NodeIndex, NodeIndex)> = graph
+        .graph
+        .neighbors(graph.entry)
+        .map(|first| (graph.entry, first))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for name_id in function.block_name_ids() {
+            let node = graph.weight.block_nodes[&name_id];
+            let incoming: Vec<_> = graph
+                .graph
+                .neighbors_directed(node, Incoming)
+                .filter(|predecessor| executable.contains(&(*predecessor, node)))
+                .collect();
+            let Some(mut state) = incoming
+                .iter()
+                .filter_map(|predecessor| outs.get(predecessor))
+                .cloned()
+                .reduce(|a, b| meet(&a, &b))
+            else {
+                continue;
+            };
+            ins.insert(node, state.clone());
+            let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+            let block = function.blocks.get_from_id(id).unwrap();
+            for ir in block.irs() {
+                transfer(function, &mut state, ir);
+            }
+            let taken = match &block.terminator {
+                IR::Jump(JumpOperation::Branch(cond, _, _), _) => {
+                    match lookup(function, &state, *cond) {
+                        FlatLattice::Top => vec![],
+                        FlatLattice::Value(value) => match value.is_truthy() {
+                            Some(true) => vec![EdgeKind::True],
+                            Some(false) => vec![EdgeKind::False],
+                            None => vec![EdgeKind::True, EdgeKind::False],
+                        },
+                        FlatLattice::Bottom => vec![EdgeKind::True, EdgeKind::False],
+                    }
+                }
+                _ => vec![EdgeKind::Unconditional, EdgeKind::Fallthrough],
+            };
+            for edge in graph.graph.edges(node) {
+                if taken.contains(edge.weight()) {
+                    changed |= executable.insert((node, edge.target()));
+                }
+            }
+            if outs.get(&node) != Some(&state) {
+                outs.insert(node, state);
+                changed = true;
+            }
+        }
+    }
+    ins
+}
+
+fn meet(a: &State, b: &State) -> State {
+    let mut state = a.clone();
+    for (space, value) in b {
+        state
+            .entry(*space)
+            .or_insert(FlatLattice::Top)
+            .meet_with(value);
+    }
+    state
+}
+
+fn transfer(function: &Function, state: &mut State, ir: &IR) {
+    let (dst, value) = match ir {
+        IR::Assignment(dst, op, _) => (
+            *dst,
+            evaluate_with(function, op, |space| lookup(function, state, space)),
+        ),
+        IR::Command(CommandOperation::Store(dst, src), _) => (*dst, lookup(function, state, *src)),
+        IR::Jump(..) => return,
+    };
+    if function.is_local(dst) {
+        state.insert(dst, value);
+    }
+}
+
+/// The lattice value of `space` in `state`. Globals other than constants are unknown.
+fn lookup(function: &Function, state: &State, space: SpaceNameId) -> FlatLattice<Value> {
+    if let Some(value) = function.const_value(space) {
+        FlatLattice::Value(value)
+    } else if function.is_local(space) {
+        state.get(&space).cloned().unwrap_or(FlatLattice::Top)
+    } else {
+        FlatLattice::Bottom
+    }
+}
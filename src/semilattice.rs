@@ -1,3 +1,5 @@
+use fixedbitset::FixedBitSet;
+
 pub trait SemiLattice: PartialEq {
     fn meet(&self, other: &Self) -> Self;
     fn meet_with(&mut self, other: &Self) -> bool;
@@ -20,8 +22,38 @@ impl<T: SemiLattice + PartialEq> SemiLatticeOrd for T {
     }
 }
 
-pub trait ProductLattice<SubLattice: SemiLattice, Ix = usize>: SemiLattice {
-    fn get(&self, index: Ix) -> Option<&SubLattice>;
+/// A lattice of `dim` components from the lattice `Element`. Implementing `get`, `set`
+/// and `dim` makes it a `SemiLattice` meeting component by component, for operands of
+/// the same dimension.
+pub trait ProductLattice<Ix = usize> {
+    type Element: SemiLattice;
+    fn get(&self, index: Ix) -> Option<&Self::Element>;
+    fn set(&mut self, index: Ix, element: Self::Element);
+    fn dim(&self) -> usize;
+}
+
+impl<T: ProductLattice + PartialEq + Clone> SemiLattice for T {
+    fn meet(&self, other: &Self) -> Self {
+        let mut res = self.clone();
+        res.meet_with(other);
+        res
+    }
+
+    fn meet_with(&mut self, other: &Self) -> bool {
+        debug_assert_eq!(self.dim(), other.dim(), "meet of different dimensions");
+        let mut changed = false;
+        for index in 0..self.dim() {
+            let (Some(element), Some(other)) = (self.get(index), other.get(index)) else {
+                continue;
+            };
+            let met = element.meet(other);
+            if met != *element {
+                self.set(index, met);
+                changed = true;
+            }
+        }
+        changed
+    }
 }
 
 #[derive(Clone)]
@@ -34,23 +66,17 @@ impl<SubLattice: SemiLattice + Clone> PartialEq for VecProductLattice<SubLattice
         self.storage == other.storage
     }
 }
-impl<SubLattice: SemiLattice + Clone> SemiLattice for VecProductLattice<SubLattice> {
-    fn meet(&self, other: &Self) -> Self {
-        let mut res = self.clone();
-        res.meet_with(other);
-        res
-    }
-
-    fn meet_with(&mut self, other: &Self) -> bool {
-        self.storage.iter_mut().enumerate().fold(false, |cur, (i, e)| {
-            cur || e.meet_with(other.get(i).unwrap())
-        })
-    }
-}
-impl<SubLattice: SemiLattice + Clone> ProductLattice<SubLattice, usize> for VecProductLattice<SubLattice> {
+impl<SubLattice: SemiLattice + Clone> ProductLattice for VecProductLattice<SubLattice> {
+    type Element = SubLattice;
     fn get(&self, index: usize) -> Option<&SubLattice> {
         self.storage.get(index)
     }
+    fn set(&mut self, index: usize, element: SubLattice) {
+        self.storage[index] = element;
+    }
+    fn dim(&self) -> usize {
+        self.storage.len()
+    }
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -78,3 +104,58 @@ impl<T: PartialEq + Clone> SemiLattice for FlatLattice<T> {
         true
     }
 }
+
+/// How a `BitSetLattice` combines the facts reaching a join.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeetKind {
+    /// A fact holds if it holds along some path, as for reaching definitions.
+    Union,
+    /// A fact holds if it holds along every path, as for available expressions.
+    Intersection,
+}
+
+/// A set of facts numbered from 0, for "may" and "must" bit vector analyses alike.
+/// Both operands of a meet must have the same kind and capacity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitSetLattice {
+    pub value: FixedBitSet,
+    pub kind: MeetKind,
+}
+
+impl BitSetLattice {
+    /// The identity of the meet: no facts for `Union`, every fact for `Intersection`.
+    pub fn top(kind: MeetKind, capacity: usize) -> Self {
+        let mut value = FixedBitSet::with_capacity(capacity);
+        if kind == MeetKind::Intersection {
+            value.insert_range(..);
+        }
+        Self { value, kind }
+    }
+    /// The element every meet ends up at: every fact for `Union`, none for `Intersection`.
+    pub fn bottom(kind: MeetKind, capacity: usize) -> Self {
+        let mut bottom = Self::top(kind, capacity);
+        bottom.value.toggle_range(..);
+        bottom
+    }
+}
+
+impl SemiLattice for BitSetLattice {
+    fn meet(&self, other: &Self) -> Self {
+        let mut copy = self.clone();
+        copy.meet_with(other);
+        copy
+    }
+
+    fn meet_with(&mut self, other: &Self) -> bool {
+        debug_assert_eq!(self.kind, other.kind, "meet of different kinds");
+        let changed = match self.kind {
+            MeetKind::Union => !other.value.is_subset(&self.value),
+            MeetKind::Intersection => !self.value.is_subset(&other.value),
+        };
+        match self.kind {
+            MeetKind::Union => self.value.union_with(&other.value),
+            MeetKind::Intersection => self.value.intersect_with(&other.value),
+        }
+        changed
+    }
+}
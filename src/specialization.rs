@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::constant_propagation::propagate_constants;
+use crate::ir::{
+    ops::UnaryOp, AddressMarker, BlockNameId, Function, FunctionNameId, IRInformation,
+    JumpOperation, Operation, ProgramRef, Value, IR,
+};
+use crate::loops::retarget;
+
+/// A `call` and the `param`s passing its arguments, by index in `block` of `caller`.
+struct CallSite {
+    caller: FunctionNameId,
+    block: BlockNameId,
+    call: usize,
+    params: Vec<usize>,
+    /// The argument values known to the caller.
+    arguments: Vec<Option<Value>>,
+}
+
+/// Specializes the functions of `program` for the parameters every call passes the same
+/// constant to, and returns the name ids of the specializations.
+///
+/// A function of at most `max_size` instructions is copied with `Program::clone_function`
+/// as `<name>.specialized`, or `<name>.specialized1`, ... if that is taken. The constant
+/// parameters are dropped from the copy, whose first block starts by assigning them their
+/// constants, and `propagate_constants` folds them in. When the first block is jumped to,
+/// as a loop header is, its code first moves to a new block, `<name>.body`, which it then
+/// jumps to, so the assignments only run on entry. Every call then calls the copy
+/// without passing them; their `param`s become plain copies. The original is left as it
+/// is for callers outside the program.
+///
+/// Only calls passing all their arguments by `param` in their own block are understood:
+/// a function called any other way, never called, or without a body is not specialized.
+pub fn specialize_constant_arguments(program: &ProgramRef, max_size: usize) -> Vec<FunctionNameId> {
+    let mut sites: BTreeMap<FunctionNameId, Vec<CallSite>> = BTreeMap::new();
+    {
+        let program = program.borrow();
+        for (_, _, caller) in program.functions.iter() {
+            let Some(caller) = caller else {
+                continue;
+            };
+            for (callee, site) in call_sites(&caller) {
+                sites.entry(callee).or_default().push(site);
+            }
+        }
+    }
+    let mut specializations = vec![];
+    for (callee, sites) in sites {
+        let Some((name, arity, size)) = with_function(program, callee, |function| {
+            let size = function
+                .blocks
+                .iter()
+                .filter_map(|(_, _, block)| Some(block?.irs().len()))
+                .sum::<usize>();
+            let defined = !function.block_name_ids().is_empty();
+            (
+                function.name.clone(),
+                function.params.len(),
+                defined.then_some(size),
+            )
+        }) else {
+            continue;
+        };
+        let Some(size) = size else {
+            continue;
+        };
+        if size > max_size || sites.iter().any(|site| site.params.len() != arity) {
+            continue;
+        }
+        let constants: Vec<(usize, Value)> = (0..arity)
+            .filter_map(|i| {
+                let value = sites[0].arguments[i].clone()?;
+                let same = sites
+                    .iter()
+                    .all(|site| site.arguments[i].as_ref() == Some(&value));
+                same.then_some((i, value))
+            })
+            .collect();
+        if constants.is_empty() {
+            continue;
+        }
+        let specialization = {
+            let mut program = program.borrow_mut();
+            let name = (0..)
+                .map(|i| match i {
+                    0 => format!("{name}.specialized"),
+                    _ => format!("{name}.specialized{i}"),
+                })
+                .find(|name| program.functions.get_name_id(name).is_none())
+                .unwrap();
+            program.clone_function(callee, name).unwrap()
+        };
+        let constants: Vec<_> = constants
+            .into_iter()
+            .map(|(i, value)| (i, program.borrow_mut().lookup_or_insert_constant(value).0))
+            .collect();
+        with_function(program, specialization, |function| {
+            let first = function.block_name_ids()[0];
+            if is_jumped_to(function, first) {
+                move_into_new_block(function, first);
+            }
+            let first = function.blocks.get_id_from_name_id(&first).unwrap();
+            let mut block = function.blocks.get_mut_from_id(first).unwrap();
+            for (i, constant) in constants.iter().rev() {
+                let param = function.params.remove(*i);
+                block.irs_mut().insert(
+                    0,
+                    IR::Assignment(
+                        param,
+                        Operation::Unary(UnaryOp::Unit, *constant),
+                        IRInformation::default(),
+                    ),
+                );
+            }
+            drop(block);
+            function.build_graph();
+            propagate_constants(function);
+        });
+        let mut callers = HashSet::new();
+        for site in &sites {
+            with_function(program, site.caller, |caller| {
+                let id = caller.blocks.get_id_from_name_id(&site.block).unwrap();
+                let mut block = caller.blocks.get_mut_from_id(id).unwrap();
+                for (i, _) in &constants {
+                    let param = &mut block.irs_mut()[site.params[*i]];
+                    if let IR::Assignment(_, Operation::Unary(op @ UnaryOp::Param, _), _) = param {
+                        *op = UnaryOp::Unit;
+                    }
+                }
+                block.irs_mut()[site.call] = match &block.irs()[site.call] {
+                    IR::Assignment(dst, _, info) => {
+                        IR::Assignment(*dst, Operation::Call(specialization), *info)
+                    }
+                    ir => unreachable!("not a call: {}", ir),
+                };
+            });
+            callers.insert(site.caller);
+        }
+        for caller in callers {
+            with_function(program, caller, Function::build_graph);
+        }
+        specializations.push(specialization);
+    }
+    specializations
+}
+
+/// Whether any block of `function` jumps to `name_id`.
+fn is_jumped_to(function: &Function, name_id: BlockNameId) -> bool {
+    function.block_name_ids().into_iter().any(|other| {
+        let id = function.blocks.get_id_from_name_id(&other).unwrap();
+        match function.blocks.get_from_id(id).unwrap().terminator() {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => addr.block_id == name_id,
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                true_addr.block_id == name_id || false_addr.block_id == name_id
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Moves the code of the block `name_id` to a new block it then jumps to, and retargets
+/// every jump to it to the new block. The graph is left to be rebuilt.
+fn move_into_new_block(function: &mut Function, name_id: BlockNameId) {
+    let block_name_ids = function.block_name_ids();
+    // The new block goes last, so a trailing `next` would fall into it
+    if let Some(last) = block_name_ids.last() {
+        let id = function.blocks.get_id_from_name_id(last).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        if let IR::Jump(JumpOperation::Next, info) = block.terminator {
+            block.terminator = IR::Jump(JumpOperation::End, info);
+        }
+    }
+    let name = function.blocks.get_name(&name_id).unwrap().clone();
+    let body_name = (0..)
+        .map(|i| match i {
+            0 => format!("{name}.body"),
+            _ => format!("{name}.body{i}"),
+        })
+        .find(|name| function.blocks.get_name_id(name).is_none())
+        .unwrap();
+    let (body, body_id) = function.lookup_or_insert_block(body_name);
+    let (irs, mut terminator) = {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        let jump = IR::Jump(
+            JumpOperation::Unconditional(AddressMarker::new(body)),
+            IRInformation::default(),
+        );
+        (
+            std::mem::take(block.irs_mut()),
+            std::mem::replace(&mut block.terminator, jump),
+        )
+    };
+    if let IR::Jump(JumpOperation::Next, info) = terminator {
+        let position = block_name_ids.iter().position(|other| *other == name_id);
+        terminator = match position.and_then(|position| block_name_ids.get(position + 1)) {
+            Some(next) => IR::Jump(
+                JumpOperation::Unconditional(AddressMarker::new(*next)),
+                info,
+            ),
+            None => IR::Jump(JumpOperation::End, info),
+        };
+    }
+    {
+        let mut block = function.blocks.get_mut_from_id(body_id).unwrap();
+        *block.irs_mut() = irs;
+        block.terminator = terminator;
+    }
+    for other in block_name_ids.into_iter().chain([body]) {
+        if other == name_id {
+            continue;
+        }
+        let id = function.blocks.get_id_from_name_id(&other).unwrap();
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        match &mut block.terminator {
+            IR::Jump(JumpOperation::Unconditional(addr), _) => retarget(addr, name_id, body),
+            IR::Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                retarget(true_addr, name_id, body);
+                retarget(false_addr, name_id, body);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Every call of `function`, by callee. The `param`s of a call are those since the
+/// previous call in the same block.
+fn call_sites(function: &Function) -> Vec<(FunctionNameId, CallSite)> {
+    let mut sites = vec![];
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let block = function.blocks.get_from_id(id).unwrap();
+        let mut params = vec![];
+        let mut arguments = vec![];
+        for (i, ir) in block.irs().iter().enumerate() {
+            match ir {
+                IR::Assignment(_, Operation::Unary(UnaryOp::Param, argument), _) => {
+                    params.push(i);
+                    arguments.push(function.const_value(*argument));
+                }
+                IR::Assignment(_, Operation::Call(callee), _) => {
+                    let site = CallSite {
+                        caller: function.name_id,
+                        block: name_id,
+                        call: i,
+                        params: std::mem::take(&mut params),
+                        arguments: std::mem::take(&mut arguments),
+                    };
+                    sites.push((*callee, site));
+                }
+                _ => {}
+            }
+        }
+    }
+    sites
+}
+
+/// Runs `f` on the function `name_id` of `program`, `None` if it does not exist.
+fn with_function<R>(
+    program: &ProgramRef,
+    name_id: FunctionNameId,
+    f: impl FnOnce(&mut Function) -> R,
+) -> Option<R> {
+    let function_pool = program.borrow().function_pool.clone();
+    let mut function_pool = function_pool.borrow_mut();
+    let id = *function_pool.get_id(&name_id)?;
+    let mut function = function_pool.get_mut_from_id(id)?;
+    Some(f(&mut function))
+}
@@ -0,0 +1,114 @@
+use crate::ir::{
+    ops::UnaryOp, AddressMarker, BlockNameId, Function, FunctionNameId, IRInformation,
+    JumpOperation, Operation, Scope, IR,
+};
+
+fn is_call(ir: &IR) -> bool {
+    matches!(ir, IR::Assignment(_, Operation::Call(_), _))
+}
+
+fn is_param(ir: &IR) -> bool {
+    matches!(
+        ir,
+        IR::Assignment(_, Operation::Unary(UnaryOp::Param, _), _)
+    )
+}
+
+/// Where the arguments of the tail call to `callee` ending `irs` start, if `terminator`
+/// returns its result right away and it passes `arity` arguments. The arguments are the
+/// `param`s between that index and the call, which is the last instruction.
+fn tail_call(irs: &[IR], terminator: &IR, callee: FunctionNameId, arity: usize) -> Option<usize> {
+    let (IR::Assignment(dst, Operation::Call(called), _), call) = (irs.last()?, irs.len() - 1)
+    else {
+        return None;
+    };
+    let returns_result = match terminator {
+        IR::Jump(JumpOperation::Ret(value), _) => value == dst,
+        IR::Jump(JumpOperation::End, _) => true,
+        _ => false,
+    };
+    let start = irs[..call].iter().rposition(is_call).map_or(0, |i| i + 1);
+    let arguments = irs[start..call].iter().filter(|ir| is_param(ir)).count();
+    (*called == callee && returns_result && arguments == arity).then_some(start)
+}
+
+/// Turns every self-recursive tail call of `function` into a jump back to its first
+/// block, so the recursion runs as a loop without growing the stack.
+///
+/// A tail call is a `call` of the function itself ending a block that returns its
+/// result, or ends a `void` function, with all its arguments passed by `param` in the
+/// same block. Each of these `param`s becomes a copy of its argument into a fresh
+/// temporary, and the call becomes copies of the temporaries into the parameters, so
+/// arguments computed from the parameters see their old values.
+///
+/// Returns whether any call was rewritten.
+pub fn optimize_tail_calls(function: &mut Function) -> bool {
+    let block_name_ids = function.block_name_ids();
+    let Some(first) = block_name_ids.first().copied() else {
+        return false;
+    };
+    let mut changed = false;
+    for name_id in block_name_ids {
+        changed |= optimize_tail_call(function, name_id, first);
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
+
+fn optimize_tail_call(function: &mut Function, name_id: BlockNameId, first: BlockNameId) -> bool {
+    let (callee, arity) = (function.name_id, function.params.len());
+    let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+    let (mut irs, start) = {
+        let mut block = function.blocks.get_mut_from_id(id).unwrap();
+        let Some(start) = tail_call(block.irs(), &block.terminator, callee, arity) else {
+            return false;
+        };
+        (std::mem::take(block.irs_mut()), start)
+    };
+    irs.pop();
+    let arguments = irs.split_off(start);
+    let scope = Scope::Local {
+        fn_name_id: function.name_id,
+    };
+    let mut temporaries = vec![];
+    for ir in arguments {
+        let IR::Assignment(dst, Operation::Unary(UnaryOp::Param, argument), info) = ir else {
+            irs.push(ir);
+            continue;
+        };
+        let param = function.params[temporaries.len()];
+        let data_type = function
+            .locals
+            .get_id_from_name_id(&param)
+            .and_then(|id| function.locals.get_from_id(id))
+            .and_then(|space| space.signature.get_type());
+        let (temporary, _) = function.declare_space(data_type, scope.clone());
+        irs.push(IR::Assignment(
+            temporary,
+            Operation::Unary(UnaryOp::Unit, argument),
+            IRInformation::default(),
+        ));
+        irs.push(IR::Assignment(
+            dst,
+            Operation::Unary(UnaryOp::Unit, temporary),
+            info,
+        ));
+        temporaries.push(temporary);
+    }
+    for (param, temporary) in function.params.iter().zip(temporaries) {
+        irs.push(IR::Assignment(
+            *param,
+            Operation::Unary(UnaryOp::Unit, temporary),
+            IRInformation::default(),
+        ));
+    }
+    let mut block = function.blocks.get_mut_from_id(id).unwrap();
+    *block.irs_mut() = irs;
+    block.terminator = IR::Jump(
+        JumpOperation::Unconditional(AddressMarker::new(first)),
+        IRInformation::default(),
+    );
+    true
+}
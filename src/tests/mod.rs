@@ -1,14 +1,126 @@
-use crate::block::Direction::Forward;
-use crate::block::{BlockLattice, BlockUpdate, DataFlowGraph};
-use crate::ir::JumpOperation;
+use crate::block::Direction::{Backward, Forward};
+use crate::block::{
+    BlockLattice, BlockUpdate, ConvergenceError, DataFlowGraph, Direction, EdgeKind,
+};
+use crate::cfg_simplification::simplify_cfg;
+use crate::codegen::llvm::emit_llvm_ir;
+use crate::codegen::wasm::emit_wat;
+use crate::codegen::x86_64::{emit_x86_64, REGISTERS};
+use crate::common_subexpression_elimination::eliminate_common_subexpressions;
+use crate::constant_propagation::propagate_constants;
+use crate::control_dependence::{control_dependence_graph, dominates_point, post_dominators};
+use crate::dead_code_elimination::{eliminate_dead_code, eliminate_dead_code_aggressively};
+use crate::dead_store_elimination::eliminate_dead_stores;
+use crate::def_use::{
+    build_def_use_chains, is_live_at, print_analysis, reaching_defs_of_use, solve_liveness,
+    solve_reaching_definitions, solve_reaching_definitions_and_liveness,
+    update_reaching_definitions, UseSite,
+};
+use crate::dot::dominator_tree_to_dot;
+use crate::effects::{effect_tokens, has_effect};
+use crate::escape::escaped_spaces;
+use crate::gen_kill::{gen_kill_all, transfer_all, GenKillAnalysis};
+use crate::if_conversion::if_convert;
 use crate::ir::block::CodeBlockAnalysisNode;
-use crate::ir::ops::BinaryOp::{Add, Sub};
-use crate::ir::ops::UnaryOp::{Unit};
-use crate::ir::IR::{Jump, Assignment};
-use crate::{ir::block::{CodeBlock, CodeBlockGraphWeight}, reach_lattice::ReachLattice};
-use crate::semilattice::{SemiLattice, SemiLatticeOrd};
+use crate::ir::interpreter::interpret;
+use crate::ir::lexer::Tokenizer;
+use crate::ir::ops::BinaryOp::{self, Add, Sub};
+use crate::ir::ops::UnaryOp::{self, Unit};
+use crate::ir::ops::{CompareType, DataType};
+use crate::ir::parser::Parser;
+use crate::ir::IR::{Assignment, Command, Jump};
+use crate::ir::{BlockType, Program};
+use crate::ir::{CommandOperation, JumpOperation};
+use crate::ir::{FloatValue, IntValue, Literal, Operation, Value};
+use crate::ir::{Function, ProgramRef, IR};
+use crate::live_lattice::LiveLattice;
+use crate::loop_unrolling::unroll_loop;
+use crate::loops::{
+    estimate_frequencies, irreducible_entries, is_reducible, loop_depths, make_reducible,
+    rotate_loops,
+};
+use crate::mem2reg::promote_memory_to_register;
+use crate::purity::infer_purity;
+use crate::redundant_load_elimination::eliminate_redundant_loads;
+use crate::register_allocation::{
+    allocate_registers, coalesce_copies, coalesce_spill_slots, graph_coloring, linear_scan,
+    InterferenceGraph, Liveness, Location,
+};
+use crate::relooper::{reloop, Label, RelooperError, StructuredNode};
+use crate::sccp::sccp;
+use crate::semilattice::{BitSetLattice, FlatLattice, MeetKind};
+use crate::semilattice::{ProductLattice, SemiLattice, SemiLatticeOrd};
+use crate::specialization::specialize_constant_arguments;
+use crate::tail_calls::optimize_tail_calls;
+use crate::value_numbering::local_value_numbering;
+use crate::{
+    ir::block::{CodeBlock, CodeBlockGraphWeight},
+    reach_lattice::ReachLattice,
+};
+use fixedbitset::FixedBitSet;
+use petgraph::visit::IntoNeighbors;
+use std::cmp::Ordering::{Equal, Greater, Less};
+use std::collections::HashSet;
 
+mod oscillating_lattice;
 mod u32_lattice;
+
+pub(crate) fn parse_program(src: &str) -> ProgramRef {
+    Parser::new(src.chars().tokenize()).match_program().unwrap()
+}
+
+/// Interprets `function` on each of `inputs` before and after `pass` and asserts
+/// the results are identical. Returns what `pass` returned.
+pub(crate) fn assert_pass_preserves_semantics(
+    function: &mut Function,
+    pass: impl FnOnce(&mut Function) -> bool,
+    inputs: &[Vec<Value>],
+) -> bool {
+    let before: Vec<_> = inputs
+        .iter()
+        .map(|args| interpret(function, args))
+        .collect();
+    let changed = pass(function);
+    for (args, expected) in inputs.iter().zip(before) {
+        assert_eq!(
+            interpret(function, args),
+            expected,
+            "{} changed its result for {:?}",
+            function,
+            args
+        );
+    }
+    changed
+}
+
+fn ir_count(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|(_, _, block)| block.unwrap().irs().len())
+        .sum()
+}
+
+fn int_inputs(values: &[i64]) -> Vec<Vec<Value>> {
+    values
+        .iter()
+        .map(|value| vec![Value::Int(IntValue::i64(*value))])
+        .collect()
+}
+
+pub(crate) fn with_function<R>(
+    program: &ProgramRef,
+    name: &str,
+    f: impl FnOnce(&mut Function) -> R,
+) -> R {
+    let (_, fn_id) = program
+        .borrow_mut()
+        .lookup_or_insert_function(name.to_string());
+    let function_pool = program.borrow().function_pool.clone();
+    let mut function_pool = function_pool.borrow_mut();
+    let mut function = function_pool.get_mut_from_id(fn_id).unwrap();
+    f(&mut function)
+}
 #[test]
 fn it_works() {
     let x = u32_lattice::U32SemiLattice::from(0b101101u32);
@@ -20,26 +132,162 @@ fn it_works() {
     assert!(x.lte(&0))
 }
 #[test]
+fn bitset_meet_kinds() {
+    let set = |kind, bits: &[usize]| {
+        let mut lattice = BitSetLattice::top(kind, 4);
+        lattice.value.clear();
+        lattice.value.extend(bits.iter().copied());
+        lattice
+    };
+    for kind in [MeetKind::Union, MeetKind::Intersection] {
+        let x = set(kind, &[0, 1]);
+        assert_eq!(x.meet(&BitSetLattice::top(kind, 4)), x);
+        assert_eq!(
+            x.meet(&BitSetLattice::bottom(kind, 4)),
+            BitSetLattice::bottom(kind, 4)
+        );
+        assert!(BitSetLattice::bottom(kind, 4).lte(&x));
+    }
+    assert_eq!(
+        BitSetLattice::top(MeetKind::Union, 4).value.count_ones(..),
+        0
+    );
+    assert_eq!(
+        BitSetLattice::top(MeetKind::Intersection, 4)
+            .value
+            .count_ones(..),
+        4
+    );
+
+    let may = set(MeetKind::Union, &[0, 1]);
+    assert_eq!(
+        may.meet(&set(MeetKind::Union, &[1, 2])),
+        set(MeetKind::Union, &[0, 1, 2])
+    );
+    let must = set(MeetKind::Intersection, &[0, 1]);
+    assert_eq!(
+        must.meet(&set(MeetKind::Intersection, &[1, 2])),
+        set(MeetKind::Intersection, &[1])
+    );
+
+    let mut x = set(MeetKind::Union, &[0, 1]);
+    assert!(!x.meet_with(&set(MeetKind::Union, &[1])));
+    assert!(x.meet_with(&set(MeetKind::Union, &[3])));
+    let mut x = set(MeetKind::Intersection, &[0, 1]);
+    assert!(!x.meet_with(&set(MeetKind::Intersection, &[0, 1, 2])));
+    assert!(x.meet_with(&set(MeetKind::Intersection, &[1])));
+}
+#[test]
 fn graph() {
     let mut graph = DataFlowGraph::<u32_lattice::U32Block>::new(());
-    let b1 = graph.graph.add_node(u32_lattice::U32Block::new(
-        1.into(),
-        0b10010,
-        0b01101,
-    ));
-    let b2 = graph.graph.add_node(u32_lattice::U32Block::new(
-        2.into(),
-        0b01110,
-        0b01110,
-    ));
-    graph.graph.add_edge(graph.entry, b1, ());
-    graph.graph.add_edge(b1, b2, ());
+    let b1 = graph
+        .graph
+        .add_node(u32_lattice::U32Block::new(1.into(), 0b10010, 0b01101));
+    let b2 = graph
+        .graph
+        .add_node(u32_lattice::U32Block::new(2.into(), 0b01110, 0b01110));
+    graph.graph.add_edge(graph.entry, b1, EdgeKind::Fallthrough);
+    graph.graph.add_edge(b1, b2, EdgeKind::Fallthrough);
     graph.converge(crate::block::Direction::Forward);
     graph.graph.node_indices().for_each(|i| {
         let node = graph.graph.node_weight(i).unwrap();
         println!("{}, {}", node.get_in(), node.get_out());
     });
 }
+#[test]
+fn capped_convergence_reports_an_oscillating_block() {
+    use oscillating_lattice::OscillatingBlock;
+    let looping = |oscillates| {
+        let mut graph = DataFlowGraph::<OscillatingBlock>::new(());
+        let header = graph.graph.add_node(OscillatingBlock::new(false));
+        let body = graph.graph.add_node(OscillatingBlock::new(oscillates));
+        graph
+            .graph
+            .add_edge(graph.entry, header, EdgeKind::Fallthrough);
+        graph.graph.add_edge(header, body, EdgeKind::True);
+        graph.graph.add_edge(body, header, EdgeKind::Unconditional);
+        graph.graph.add_edge(header, graph.exit, EdgeKind::False);
+        (graph, body)
+    };
+    let (mut graph, body) = looping(false);
+    assert!(graph.converge_capped(Forward, 10).is_ok());
+    assert!(*graph.graph[body].get_out());
+
+    let (mut graph, body) = looping(true);
+    assert_eq!(
+        graph.converge_capped(Forward, 10),
+        Err(ConvergenceError::NotConverged {
+            node: body,
+            sweeps: 10
+        })
+    );
+}
+
+#[test]
+fn removing_a_block_keeps_indices() {
+    let program = parse_program(
+        "fn $f() : i64 {
+            #a {
+                %x = 1
+                => #b
+            }
+            #b {
+                %x = 2
+                => #c
+            }
+            #c {
+                => ret %x
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let node = |name: &str| {
+            let name_id = function.blocks.get_name_id(&name.to_string()).unwrap();
+            function.graph.weight.block_nodes[name_id]
+        };
+        let id = |name: &str| function.blocks.get_id(&name.to_string());
+        let (a, b, c) = (node("#a"), node("#b"), node("#c"));
+        let (a_id, b_id, c_id) = (id("#a"), id("#b"), id("#c"));
+        let (entry, exit) = (function.graph.entry, function.graph.exit);
+
+        assert!(function.graph.remove_node(entry).is_none());
+        let removed = function.graph.remove_node(b).unwrap();
+        assert_eq!(removed.block, b_id);
+        assert!(function.graph.graph.node_weight(b).is_none());
+        assert_eq!(function.graph.graph.node_count(), 4);
+        assert_eq!(function.graph.graph[a].block, a_id);
+        assert_eq!(function.graph.graph[c].block, c_id);
+        assert_eq!(function.graph.graph[c].node_index, c);
+        assert!(function.graph.graph[entry].block.is_none());
+        assert!(function.graph.graph.contains_edge(c, exit));
+        assert!(function.graph.graph.neighbors(a).next().is_none());
+    });
+}
+
+#[test]
+#[should_panic(expected = "which is missing")]
+fn corrupted_graph_trips_consistency_check() {
+    let program = parse_program(
+        "fn $f() : i64 {
+            #a {
+                %x = 1
+                => #b
+            }
+            #b {
+                => ret %x
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        function.graph.debug_assert_consistent();
+        let b = *function.blocks.get_name_id(&"#b".to_string()).unwrap();
+        let b = function.graph.weight.block_nodes[&b];
+        // Bypasses `DataFlowGraph::remove_node`, leaving `block_nodes` dangling
+        function.graph.graph.remove_node(b);
+        function.graph.debug_assert_consistent();
+    });
+}
+
 #[test]
 fn block_partition() {
     let i = 0;
@@ -165,3 +413,2996 @@ fn block_reach() {
     );
     println!("{:}", partitioned)
 }
+
+#[test]
+fn store_kills_reaching_definition() {
+    let program = parse_program(
+        "fn $s(i64 @a) : i64 {
+            #entry {
+                %x = @a
+                %x <- 5
+                => #exit
+            }
+            #exit {
+                => ret %x
+            }
+        }",
+    );
+    let mut program = program.borrow_mut();
+    let mut function = program.functions.get_mut(&"$s".to_string()).unwrap();
+    <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<ReachLattice>>::converge(
+        &mut function.graph,
+        Forward,
+    );
+    let exit = *function.blocks.get_name_id(&"#exit".to_string()).unwrap();
+    let exit_node = &function.graph.graph[function.graph.weight.block_nodes[&exit]];
+    // Only the store reaches the use, the copy of @a is killed
+    assert!(!exit_node.reach_in.value[0]);
+    assert!(exit_node.reach_in.value[1]);
+}
+
+#[test]
+fn reaching_defs_at_merge() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = 1
+                %c = @a > 0
+                => %c ? #then : #join
+            }
+            #then {
+                %x = 2
+                => #join
+            }
+            #join {
+                %y = %x + 1
+                %x = 3
+                %z = %x + %y
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        solve_reaching_definitions(function);
+        let join = *function.blocks.get_name_id(&"#join".to_string()).unwrap();
+        let x = *function.locals.get_name_id(&"%x".to_string()).unwrap();
+        let declaration = |block: &str, index: usize| {
+            let block = function.blocks.get(&block.to_string()).unwrap();
+            block.irs()[index].info().declaration_number.unwrap()
+        };
+        let at = |index| UseSite {
+            block: join,
+            index,
+            space: x,
+        };
+        assert_eq!(
+            reaching_defs_of_use(function, at(0)),
+            vec![declaration("#entry", 0), declaration("#then", 0)]
+        );
+        // The redefinition in #join kills both incoming definitions
+        assert_eq!(
+            reaching_defs_of_use(function, at(2)),
+            vec![declaration("#join", 1)]
+        );
+        assert_eq!(reaching_defs_of_use(function, at(9)), vec![]);
+    });
+}
+
+#[test]
+fn space_is_live_until_its_last_use() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %c = @a > 0
+                => %c ? #then : #join
+            }
+            #then {
+                %y = %x * 2
+                => ret %y
+            }
+            #join {
+                %z = @a - 1
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        solve_liveness(function);
+        let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+        let x = *function.locals.get_name_id(&"%x".to_string()).unwrap();
+        let (entry, then, join) = (block("#entry"), block("#then"), block("#join"));
+        assert!(!is_live_at(function, x, entry, 0));
+        assert!(is_live_at(function, x, entry, 1));
+        assert!(is_live_at(function, x, entry, 2));
+        assert!(is_live_at(function, x, then, 0));
+        // Dead after its last use, and on the path that never reads it
+        assert!(!is_live_at(function, x, then, 1));
+        assert!(!is_live_at(function, x, join, 0));
+        assert!(!is_live_at(function, x, then, 9));
+    });
+}
+
+#[test]
+fn incremental_reaching_definitions_match_full_solve() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = 1
+                %i = 0
+                %c = @a > 0
+                => %c ? #then : #loop
+            }
+            #then {
+                %x = 2
+                => #loop
+            }
+            #loop {
+                %i = %i + %x
+                %d = %i < 10
+                => %d ? #loop : #done
+            }
+            #done {
+                %y = %x + %i
+                => ret %y
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let then = *function.blocks.get_name_id(&"#then".to_string()).unwrap();
+        let solution = |function: &Function| {
+            let graph = &function.graph;
+            let mut nodes: Vec<_> = graph.weight.block_nodes.iter().collect();
+            nodes.sort();
+            nodes
+                .into_iter()
+                .map(|(_, node)| *node)
+                .chain([graph.exit])
+                .map(|node| {
+                    (
+                        graph.graph[node].reach_in.clone(),
+                        graph.graph[node].reach_out.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let assert_matches_full_solve = |function: &mut Function| {
+            type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+            let incremental = solution(function);
+            function.build_graph();
+            let transfers =
+                <Graph as BlockUpdate<ReachLattice>>::converge(&mut function.graph, Forward);
+            assert_eq!(incremental, solution(function));
+            transfers
+        };
+        solve_reaching_definitions(function);
+
+        // Insert a second definition of %x, killing the first on the way to #loop
+        let redefinition = function.blocks.get(&"#entry".to_string()).unwrap().irs()[0].clone();
+        function
+            .blocks
+            .get_mut(&"#then".to_string())
+            .unwrap()
+            .irs_mut()
+            .push(redefinition);
+        let transfers = update_reaching_definitions(function, then);
+        // #entry can't be reached from #then, so it kept its solution
+        assert!(transfers < assert_matches_full_solve(function));
+
+        function
+            .blocks
+            .get_mut(&"#then".to_string())
+            .unwrap()
+            .irs_mut()
+            .clear();
+        update_reaching_definitions(function, then);
+        assert_matches_full_solve(function);
+    });
+}
+
+#[test]
+fn def_use_chains() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = 1
+                %c = @a > 0
+                => %c ? #then : #join
+            }
+            #then {
+                %x = 2
+                => #join
+            }
+            #join {
+                %y = %x + %x
+                => ret %y
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let chains = build_def_use_chains(function);
+        let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+        let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let def = |name: &str, index: usize| {
+            let block_ref = function.blocks.get(&name.to_string()).unwrap();
+            (
+                block(name),
+                block_ref.irs()[index].info().declaration_number.unwrap(),
+            )
+        };
+        let site = |name: &str, index, space_name: &str| UseSite {
+            block: block(name),
+            index,
+            space: space(space_name),
+        };
+        // Both definitions of %x reach the addition, which counts as one use of it
+        let sum = site("#join", 0, "%x");
+        assert_eq!(chains.uses[&def("#entry", 0)], vec![sum]);
+        assert_eq!(chains.uses[&def("#then", 0)], vec![sum]);
+        let mut reaching = chains.defs[&sum].clone();
+        reaching.sort();
+        assert_eq!(reaching, vec![def("#entry", 0), def("#then", 0)]);
+        assert_eq!(
+            chains.uses[&def("#entry", 1)],
+            vec![site("#entry", 2, "%c")]
+        );
+        assert_eq!(chains.uses[&def("#join", 0)], vec![site("#join", 1, "%y")]);
+        // Parameters have no definition
+        assert_eq!(chains.defs[&site("#entry", 1, "@a")], vec![]);
+    });
+}
+
+#[test]
+fn store_uses_source_in_liveness() {
+    let program = parse_program(
+        "fn $s(i64 @a) : i64 {
+            #entry {
+                %x <- @a
+                => ret %x
+            }
+        }",
+    );
+    let mut program = program.borrow_mut();
+    let mut function = program.functions.get_mut(&"$s".to_string()).unwrap();
+    <DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight> as BlockUpdate<LiveLattice>>::converge(
+        &mut function.graph,
+        crate::block::Direction::Backward,
+    );
+    let a = *function.locals.get_name_id(&"@a".to_string()).unwrap();
+    let x = *function.locals.get_name_id(&"%x".to_string()).unwrap();
+    let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+    let entry_node = &function.graph.graph[function.graph.weight.block_nodes[&entry]];
+    assert!(entry_node.live_in.value[a]);
+    assert!(!entry_node.live_in.value[x]);
+    assert!(entry_node.live_out.value.is_clear());
+}
+
+#[test]
+fn constant_propagation_preserves_result() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = 2
+                %b = %a * 3
+                %c = %b + @x
+                => ret %c
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let args = [Value::Int(IntValue::i64(1))];
+        let before = interpret(function, &args).unwrap();
+        assert!(propagate_constants(function));
+        let after = interpret(function, &args).unwrap();
+        assert_eq!(before, Value::Int(IntValue::i64(7)));
+        assert_eq!(before, after);
+        // %b is now a copy of the constant 6, %c still depends on @x
+        let b = *function.locals.get_name_id(&"%b".to_string()).unwrap();
+        let c = *function.locals.get_name_id(&"%c".to_string()).unwrap();
+        let rewritten: Vec<_> = function
+            .blocks
+            .iter()
+            .flat_map(|(_, _, block)| {
+                block
+                    .unwrap()
+                    .irs()
+                    .iter()
+                    .filter_map(|ir| match ir {
+                        Assignment(dst, Operation::Unary(Unit, _), _) => Some(*dst),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert!(rewritten.contains(&b));
+        assert!(!rewritten.contains(&c));
+    });
+}
+
+/// Fixed point with 8 fractional bits, a literal kind unknown to the IR.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Fixed(i64);
+
+impl Literal for Fixed {
+    fn get_type(&self) -> DataType {
+        DataType::I64
+    }
+    fn binary(&mut self, op: BinaryOp, other: Option<Self>) -> Self {
+        let other = other.unwrap().0;
+        Fixed(match op {
+            Add => self.0 + other,
+            Sub => self.0 - other,
+            BinaryOp::Mul => (self.0 * other) >> 8,
+            _ => unimplemented!("{:?} on fixed point", op),
+        })
+    }
+    fn unary(&mut self, op: UnaryOp, _other: Option<Self>) -> Self {
+        match op {
+            UnaryOp::Negative => Fixed(-self.0),
+            _ => self.clone(),
+        }
+    }
+    fn static_cmp(&self, cmp: CompareType, other: Option<Self>) -> bool {
+        let ordering = self.0.cmp(&other.unwrap().0);
+        matches!(
+            (cmp, ordering),
+            (CompareType::Less, Less) | (CompareType::Greater, Greater) | (CompareType::Eq, Equal)
+        )
+    }
+}
+
+#[test]
+fn constant_propagation_seeds_initialized_globals() {
+    let program = parse_program(
+        "global @g: i64 = 5
+        global @h: i64 = 1
+        global @u: i64
+        fn $f() : i64 {
+            #entry {
+                %x = @g + 1
+                %y = @h + 1
+                @h <- %y
+                => ret %x
+            }
+        }
+        fn $g() : i64 {
+            #entry {
+                %z = @u + 1
+                => ret %z
+            }
+        }",
+    );
+    let g = program
+        .borrow()
+        .lookup_global_by_name(&"@g".to_string())
+        .unwrap()
+        .0;
+    // The program is only scanned for stores while its function pool is free
+    let mut function = program.borrow().function_by_name("$f").unwrap().clone();
+    assert_eq!(interpret(&function, &[]), Ok(Value::Int(IntValue::i64(6))));
+    propagate_constants(&mut function);
+    let local = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+    assert_eq!(
+        function.const_value(local("%x")),
+        Some(Value::Int(IntValue::i64(6)))
+    );
+    // @h is both 1 and 2
+    assert_eq!(function.const_value(local("%y")), None);
+    // The initializer stays a property of the global, not a constant
+    assert_eq!(function.const_value(g), None);
+    with_function(&program, "$g", |function| {
+        propagate_constants(function);
+        let z = *function.locals.get_name_id(&"%z".to_string()).unwrap();
+        assert_eq!(function.const_value(z), None);
+    });
+}
+
+#[test]
+fn constant_propagation_ignores_initializers_of_globals_stored_elsewhere() {
+    let program = parse_program(
+        "global @g: i64 = 5
+        fn $f() : i64 {
+            #entry {
+                %x = @g + 1
+                => ret %x
+            }
+        }
+        fn $main() : i64 {
+            #entry {
+                %seven = 7
+                @g <- %seven
+                %r = call $f
+                => ret %r
+            }
+        }",
+    );
+    let main = |program: &ProgramRef| {
+        let main = program.borrow().function_by_name("$main").unwrap().clone();
+        interpret(&main, &[])
+    };
+    assert_eq!(main(&program), Ok(Value::Int(IntValue::i64(8))));
+    let mut f = program.borrow().function_by_name("$f").unwrap().clone();
+    propagate_constants(&mut f);
+    let x = *f.locals.get_name_id(&"%x".to_string()).unwrap();
+    assert_eq!(f.const_value(x), None);
+    // Rewriting $f in place cannot see $main's store either, so it folds nothing
+    with_function(&program, "$f", |function| {
+        propagate_constants(function);
+    });
+    assert_eq!(main(&program), Ok(Value::Int(IntValue::i64(8))));
+}
+
+#[test]
+fn sccp_prunes_unreachable_edges() {
+    let source = "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = 1
+                %c = %x > 0
+                => %c ? #then : #else
+            }
+            #then {
+                %y = 10
+                => #join
+            }
+            #else {
+                %y = @a
+                => #join
+            }
+            #join {
+                %z = %y + 1
+                => ret %z
+            }
+        }";
+    // Without pruning, %y meets 10 and @a at #join
+    with_function(&parse_program(source), "$f", |function| {
+        propagate_constants(function);
+        let z = *function.locals.get_name_id(&"%z".to_string()).unwrap();
+        assert_eq!(function.const_value(z), None);
+    });
+    with_function(&parse_program(source), "$f", |function| {
+        let inputs = [vec![Value::Int(IntValue::i64(-3))]];
+        assert!(assert_pass_preserves_semantics(function, sccp, &inputs));
+        let src = match &function.blocks.get(&"#join".to_string()).unwrap().irs()[0] {
+            Assignment(_, Operation::Unary(Unit, src), _) => *src,
+            ir => panic!("%z was not folded: {}", ir),
+        };
+        assert_eq!(
+            function.const_value(src),
+            Some(Value::Int(IntValue::i64(11)))
+        );
+        let then = *function.blocks.get_name_id(&"#then".to_string()).unwrap();
+        assert!(matches!(
+            &function.blocks.get(&"#entry".to_string()).unwrap().terminator,
+            Jump(JumpOperation::Unconditional(marker), _) if marker.block_id == then
+        ));
+        // Nothing is left to fold
+        assert!(!sccp(function));
+    });
+}
+
+#[test]
+fn constant_propagation_folds_custom_literals() {
+    let fixed = |value: f64| Value::Custom(Box::new(Fixed((value * 256.0) as i64)));
+    assert_eq!(fixed(1.5), fixed(1.5));
+    assert_ne!(fixed(1.5), Value::Int(IntValue::i64(384)));
+
+    let program = Program::new();
+    let one_and_half = program.borrow_mut().lookup_or_insert_constant(fixed(1.5)).0;
+    let two = program.borrow_mut().lookup_or_insert_constant(fixed(2.0)).0;
+    assert_eq!(
+        program.borrow_mut().lookup_or_insert_constant(fixed(1.5)).0,
+        one_and_half
+    );
+    let (_, fn_id) = program
+        .borrow_mut()
+        .lookup_or_insert_function("$f".to_string());
+    let function_pool = program.borrow().function_pool.clone();
+    let mut function_pool = function_pool.borrow_mut();
+    let mut function = function_pool.get_mut_from_id(fn_id).unwrap();
+    function.return_type = DataType::I64;
+    let mut entry = function.build_block("#entry");
+    let product = entry.mul("%p", one_and_half, two);
+    let sum = entry.add("%s", product, one_and_half);
+    entry.ret(sum);
+
+    assert!(propagate_constants(&mut function));
+    assert_eq!(function.const_value(product), Some(fixed(3.0)));
+    assert_eq!(function.const_value(sum), Some(fixed(4.5)));
+    assert!(program
+        .borrow()
+        .constants
+        .get_name_id(&fixed(4.5))
+        .is_some());
+}
+
+#[test]
+fn diamond_arms_depend_on_the_split() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #then : #else
+            }
+            #then {
+                %x = 1
+                => #join
+            }
+            #else {
+                %x = 2
+                => #join
+            }
+            #join {
+                %i = 0
+                => #head
+            }
+            #head {
+                %d = %i < %x
+                => %d ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+    let dependences = control_dependence_graph(&function);
+    let depends_on = |name: &str| dependences[&block(name)].clone();
+    assert_eq!(depends_on("#then"), vec![block("#entry")]);
+    assert_eq!(depends_on("#else"), vec![block("#entry")]);
+    assert!(depends_on("#entry").is_empty());
+    assert!(depends_on("#join").is_empty());
+    // Whether the loop goes round again is decided by its header
+    assert_eq!(depends_on("#body"), vec![block("#head")]);
+    assert_eq!(depends_on("#head"), vec![block("#head")]);
+    assert!(depends_on("#done").is_empty());
+}
+
+#[test]
+fn definitions_dominate_later_points_and_dominated_blocks() {
+    let program = parse_program(
+        "fn $diamond(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %c = %x > 0
+                => %c ? #then : #else
+            }
+            #then {
+                %y = %x * 2
+                => #join
+            }
+            #else {
+                %y = %x - 2
+                => #join
+            }
+            #join {
+                => ret %y
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.function_by_name("$diamond").unwrap();
+    let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+    let (entry, then, join) = (block("#entry"), block("#then"), block("#join"));
+    // Within a block, by order
+    assert!(dominates_point(&function, (entry, 0), (entry, 1)));
+    assert!(dominates_point(&function, (entry, 1), (entry, 1)));
+    assert!(!dominates_point(&function, (entry, 1), (entry, 0)));
+    // Across blocks, by block dominance, whatever the indices
+    assert!(dominates_point(&function, (entry, 1), (then, 0)));
+    assert!(dominates_point(&function, (entry, 2), (join, 0)));
+    assert!(!dominates_point(&function, (then, 0), (join, 0)));
+    assert!(!dominates_point(&function, (join, 0), (entry, 0)));
+}
+
+#[test]
+fn split_is_post_dominated_by_join() {
+    let program = parse_program(
+        "fn $diamond(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #then : #else
+            }
+            #then {
+                %x = 1
+                => #join
+            }
+            #else {
+                %x = 2
+                => #join
+            }
+            #join {
+                => ret %x
+            }
+        }
+        fn $early(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                => ret 1
+            }
+            #neg {
+                => ret 0
+            }
+        }",
+    );
+    {
+        let p = program.borrow();
+        let function = p.functions.get(&"$diamond".to_string()).unwrap();
+        let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+        let post_dominators = post_dominators(&function);
+        assert_eq!(post_dominators[&block("#entry")], block("#join"));
+        assert_eq!(post_dominators[&block("#then")], block("#join"));
+        assert_eq!(post_dominators[&block("#else")], block("#join"));
+        assert!(!post_dominators.contains_key(&block("#join")));
+    }
+    with_function(&program, "$early", |function| {
+        let exit = function.add_virtual_exit();
+        let post_dominators = post_dominators(function);
+        let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+        assert_eq!(post_dominators[&entry], exit);
+    });
+}
+
+#[test]
+#[should_panic(expected = "add a virtual exit first")]
+fn post_dominators_need_a_single_exit() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                => ret 1
+            }
+            #neg {
+                => ret 0
+            }
+        }",
+    );
+    let p = program.borrow();
+    post_dominators(&p.functions.get(&"$f".to_string()).unwrap());
+}
+
+#[test]
+fn passing_to_a_call_escapes() {
+    let program = parse_program(
+        "fn $g(i64 @v) : i64 {
+            #entry {
+                => ret @v
+            }
+        }
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                %y = %x * 2
+                %p = param %x
+                %r = call $g
+                %z = %r + %y
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let local = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let escaped = escaped_spaces(function);
+        assert_eq!(escaped, HashSet::from([local("%x")]));
+        assert!(!escaped.contains(&local("%y")));
+    });
+}
+
+#[test]
+fn stores_and_loads_of_register_locals_become_copies() {
+    let program = parse_program(
+        "fn $g(i64 @v) : i64 {
+            #entry {
+                => ret @v
+            }
+        }
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %x <- @a
+                %l = load %x
+                %c = %l > 0
+                => %c ? #pos : #join
+            }
+            #pos {
+                %x <- 7
+                => #join
+            }
+            #join {
+                %e <- %x
+                %p = param %e
+                %r = call $g
+                %m = load %x
+                %y = %r + %m
+                => ret %y
+            }
+        }",
+    );
+    let results = || {
+        let program = program.borrow();
+        let f = program.functions.get(&"$f".to_string()).unwrap();
+        int_inputs(&[-2, 0, 3])
+            .iter()
+            .map(|args| interpret(&f, args))
+            .collect::<Vec<_>>()
+    };
+    let before = results();
+    assert!(before.iter().all(Result::is_ok));
+    with_function(&program, "$f", |function| {
+        assert!(promote_memory_to_register(function));
+        let local = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let ir = |block: &str, index: usize| {
+            function.blocks.get(&block.to_string()).unwrap().irs()[index].clone()
+        };
+        assert!(matches!(
+            ir("#entry", 0),
+            Assignment(x, Operation::Unary(Unit, _), _) if x == local("%x")
+        ));
+        // The store reaching the first load is in the same block
+        assert!(matches!(
+            ir("#entry", 1),
+            Assignment(l, Operation::Unary(Unit, a), _) if l == local("%l") && a == local("@a")
+        ));
+        assert!(matches!(
+            ir("#pos", 0),
+            Assignment(x, Operation::Unary(Unit, _), _) if x == local("%x")
+        ));
+        // Either store may reach the second one
+        assert!(matches!(
+            ir("#join", 3),
+            Assignment(m, Operation::Unary(Unit, x), _) if m == local("%m") && x == local("%x")
+        ));
+        // %e is passed to $g, so it stays in memory
+        assert!(matches!(
+            ir("#join", 0),
+            Command(CommandOperation::Store(e, x), _)
+                if e == local("%e") && x == local("%x")
+        ));
+        assert!(!promote_memory_to_register(function));
+    });
+    assert_eq!(results(), before);
+}
+
+#[test]
+fn tail_recursion_becomes_a_loop() {
+    let program = parse_program(
+        "fn $fact(i64 @n, i64 @acc) : i64 {
+            #entry {
+                %c = @n > 1
+                => %c ? #rec : #base
+            }
+            #rec {
+                %m = @n - 1
+                %a = @acc * @n
+                %p = param %m
+                %q = param %a
+                %r = call $fact
+                => ret %r
+            }
+            #base {
+                => ret @acc
+            }
+        }",
+    );
+    let fact = |n: i64| {
+        let p = program.borrow();
+        let function = p.functions.get(&"$fact".to_string()).unwrap();
+        interpret(
+            &function,
+            &[Value::Int(IntValue::i64(n)), Value::Int(IntValue::i64(1))],
+        )
+    };
+    let expected: Vec<_> = [1, 5, 20].into_iter().map(fact).collect();
+    assert_eq!(expected[1], Ok(Value::Int(IntValue::i64(120))));
+    with_function(&program, "$fact", |function| {
+        assert!(optimize_tail_calls(function));
+        assert!(!optimize_tail_calls(function));
+        let entry = *function.blocks.get_name_id(&"#entry".to_string()).unwrap();
+        let rec = function.blocks.get(&"#rec".to_string()).unwrap();
+        assert!(!rec
+            .irs()
+            .iter()
+            .any(|ir| matches!(ir, Assignment(_, Operation::Call(_), _))));
+        assert!(matches!(
+            rec.terminator(),
+            Jump(JumpOperation::Unconditional(marker), _) if marker.block_id == entry
+        ));
+        drop(rec);
+        assert_eq!(loop_depths(function)[&entry], 1);
+    });
+    assert_eq!(
+        [1, 5, 20].into_iter().map(fact).collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[test]
+fn constant_arguments_are_specialized() {
+    let source = "fn $f(i64 @x, i64 @k) : i64 {
+            #entry {
+                %t = @k * 2
+                %r = @x + %t
+                => ret %r
+            }
+        }
+        fn $a(i64 @v) : i64 {
+            #entry {
+                %p = param @v
+                %q = param 0
+                %r = call $f
+                => ret %r
+            }
+        }
+        fn $b() : i64 {
+            #entry {
+                %p = param 7
+                %q = param 0
+                %r = call $f
+                => ret %r
+            }
+        }";
+    let program = parse_program(source);
+    assert!(specialize_constant_arguments(&program, 1).is_empty());
+
+    let specializations = specialize_constant_arguments(&program, 10);
+    assert_eq!(specializations.len(), 1);
+    let p = program.borrow();
+    let f = p.functions.get(&"$f".to_string()).unwrap();
+    let specialized = p.functions.get(&"$f.specialized".to_string()).unwrap();
+    assert_eq!(specialized.name_id, specializations[0]);
+    assert_eq!(f.params.len(), 2);
+    assert_eq!(specialized.params.len(), 1);
+    let t = *specialized.locals.get_name_id(&"%t".to_string()).unwrap();
+    assert_eq!(
+        specialized.const_value(t),
+        Some(Value::Int(IntValue::i64(0)))
+    );
+    for caller in ["$a", "$b"] {
+        let caller = p.functions.get(&caller.to_string()).unwrap();
+        let entry = caller.blocks.get(&"#entry".to_string()).unwrap();
+        let callees: Vec<_> = entry
+            .irs()
+            .iter()
+            .filter_map(|ir| match ir {
+                Assignment(_, Operation::Call(callee), _) => Some(*callee),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(callees, vec![specialized.name_id]);
+    }
+    let call = |name: &str, args: &[Value]| {
+        let caller = p.functions.get(&name.to_string()).unwrap();
+        interpret(&caller, args)
+    };
+    assert_eq!(
+        call("$a", &[Value::Int(IntValue::i64(3))]),
+        Ok(Value::Int(IntValue::i64(3)))
+    );
+    assert_eq!(call("$b", &[]), Ok(Value::Int(IntValue::i64(7))));
+    assert_eq!(
+        call(
+            "$f",
+            &[Value::Int(IntValue::i64(3)), Value::Int(IntValue::i64(1))]
+        ),
+        Ok(Value::Int(IntValue::i64(5)))
+    );
+
+    // The constant is assigned once on entry, not on every iteration of the loop
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #top {
+                @n = @n - 1
+                %c = @n > 0
+                => %c ? #top : #out
+            }
+            #out {
+                => ret @n
+            }
+        }
+        fn $a() : i64 {
+            #entry {
+                %p = param 3
+                %r = call $f
+                => ret %r
+            }
+        }",
+    );
+    assert_eq!(specialize_constant_arguments(&program, 10).len(), 1);
+    let p = program.borrow();
+    let specialized = p.functions.get(&"$f.specialized".to_string()).unwrap();
+    assert!(specialized.blocks.get(&"#top.body".to_string()).is_some());
+    let caller = p.functions.get(&"$a".to_string()).unwrap();
+    assert_eq!(interpret(&caller, &[]), Ok(Value::Int(IntValue::i64(0))));
+}
+
+#[test]
+fn folding_and_dce_preserve_straight_line() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = 2
+                %b = %a * 3
+                %d = @x * @x
+                %c = %b + @x
+                => ret %c
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let inputs = int_inputs(&[-3, 0, 1, 100]);
+        assert!(assert_pass_preserves_semantics(
+            function,
+            propagate_constants,
+            &inputs
+        ));
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_code,
+            &inputs
+        ));
+        // %a is unused once %b is folded, %d was never used
+        assert_eq!(ir_count(function), 2);
+        assert!(!assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_code,
+            &inputs
+        ));
+    });
+}
+
+#[test]
+fn dce_preserves_loop() {
+    let program = Program::new();
+    with_function(&program, "$sum", |function| {
+        function.return_type = DataType::I64;
+        let n = function.declare_param("@n".to_string(), DataType::I64);
+
+        let mut entry = function.build_block("#entry");
+        let zero = entry.int(0);
+        let one = entry.int(1);
+        let i = entry.copy("%i", one);
+        let s = entry.copy("%s", zero);
+        entry.next();
+        let mut cond = function.build_block("#cond");
+        let c = cond.compare(CompareType::LessEqual, i, n);
+        cond.branch(c, "#body", "#done");
+        let mut body = function.build_block("#body");
+        let twice = body.add("%twice", i, i);
+        body.mul("%dead", twice, s);
+        body.add("%s", s, i);
+        body.add("%i", i, one);
+        body.jump("#cond");
+        function.build_block("#done").ret(s);
+
+        let inputs = int_inputs(&[0, 1, 10]);
+        assert_eq!(ir_count(function), 7);
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_code,
+            &inputs
+        ));
+        assert_eq!(ir_count(function), 5);
+        assert!(!assert_pass_preserves_semantics(
+            function,
+            propagate_constants,
+            &inputs
+        ));
+    });
+}
+
+#[test]
+fn aggressive_dce_removes_useless_loop() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #head
+            }
+            #head {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s + %i
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                %r = @n * 2
+                => ret %r
+            }
+        }
+        fn $g(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #head
+            }
+            #head {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s + %i
+                %dead = %s * %i
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %s
+            }
+        }",
+    );
+    let inputs = int_inputs(&[-1, 0, 3]);
+    with_function(&program, "$f", |function| {
+        // The loop counter feeds the branch, which is enough for liveness
+        assert!(!eliminate_dead_code(function));
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_code_aggressively,
+            &inputs
+        ));
+        assert_eq!(ir_count(function), 1);
+        let head = function.blocks.get(&"#head".to_string()).unwrap();
+        let done = *function.blocks.get_name_id(&"#done".to_string()).unwrap();
+        assert!(matches!(
+            &head.terminator,
+            Jump(JumpOperation::Unconditional(target), _) if target.block_id == done
+        ));
+        drop(head);
+        assert!(!eliminate_dead_code_aggressively(function));
+    });
+    with_function(&program, "$g", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_code_aggressively,
+            &inputs
+        ));
+        // Only %dead goes, the loop computes the result
+        assert_eq!(ir_count(function), 5);
+        let head = function.blocks.get(&"#head".to_string()).unwrap();
+        assert!(matches!(
+            &head.terminator,
+            Jump(JumpOperation::Branch(..), _)
+        ));
+    });
+}
+
+#[test]
+fn loop_is_strongly_connected() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                => next
+            }
+            #cond {
+                => @n ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #cond
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let node = |name: &str| {
+            let name_id = *function.blocks.get_name_id(&name.to_string()).unwrap();
+            function.graph.weight.block_nodes[&name_id]
+        };
+        let components = function.graph.strongly_connected_components();
+        assert_eq!(components.len(), 5);
+        let mut loop_component = components
+            .iter()
+            .find(|component| component.len() > 1)
+            .unwrap()
+            .clone();
+        loop_component.sort();
+        let mut expected = vec![node("#cond"), node("#body")];
+        expected.sort();
+        assert_eq!(loop_component, expected);
+        assert_eq!(components.first().unwrap(), &vec![function.graph.entry]);
+        assert_eq!(components.last().unwrap(), &vec![function.graph.exit]);
+    });
+}
+
+#[test]
+fn liveness_over_reversed_view() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                => #cond
+            }
+            #cond {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #cond
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let node = |name: &str| {
+            let name_id = *function.blocks.get_name_id(&name.to_string()).unwrap();
+            function.graph.weight.block_nodes[&name_id]
+        };
+        let (entry, cond, body) = (node("#entry"), node("#cond"), node("#body"));
+        let graph = &mut function.graph;
+        // Successors in the reversed view are predecessors in the graph
+        let mut successors: Vec<_> = graph.reversed().neighbors(cond).collect();
+        successors.sort();
+        let mut predecessors = vec![entry, body];
+        predecessors.sort();
+        assert_eq!(successors, predecessors);
+        let components = graph.strongly_connected_components_along(Backward);
+        assert_eq!(components.first().unwrap(), &vec![graph.exit]);
+        assert_eq!(components.last().unwrap(), &vec![graph.entry]);
+
+        <Graph as BlockUpdate<LiveLattice>>::converge(graph, Backward);
+        let live: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| (n.live_in.value.clone(), n.live_out.value.clone()))
+            .collect();
+        // Sweeps every block breadth first, following edges by hand
+        <Graph as BlockUpdate<LiveLattice>>::converge_uniform(graph, Backward);
+        let uniform_live: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| (n.live_in.value.clone(), n.live_out.value.clone()))
+            .collect();
+        assert_eq!(live, uniform_live);
+    });
+}
+
+#[test]
+fn print_reaching_definitions_table() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                => #cond
+            }
+            #cond {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #cond
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        solve_reaching_definitions(function);
+        // %i is 1, @n 0, %c 3, and the constants 0 and 1 are 2 and 4
+        let all = "0 (1 = Unit 2), 1 (3 = 1 Less 0), 2 (1 = 1 Add 4)";
+        let expected = format!(
+            "#entry\n    in: -\n    out: 0 (1 = Unit 2)\n\
+             #cond\n    in: {all}\n    out: {all}\n\
+             #body\n    in: {all}\n    out: 1 (3 = 1 Less 0), 2 (1 = 1 Add 4)\n\
+             #done\n    in: {all}\n    out: {all}\n"
+        );
+        assert_eq!(print_analysis(function), expected);
+    });
+}
+
+#[test]
+fn reach_and_live_converge_jointly() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #cond
+            }
+            #cond {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s + %i
+                %i = %i + 1
+                => #cond
+            }
+            #done {
+                => ret %s
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let values = |graph: &Graph| {
+            graph
+                .graph
+                .node_weights()
+                .map(|n| {
+                    (
+                        n.reach_in.value.clone(),
+                        n.reach_out.value.clone(),
+                        n.live_in.value.clone(),
+                        n.live_out.value.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        <Graph as BlockUpdate<ReachLattice>>::converge(&mut function.graph, Forward);
+        <Graph as BlockUpdate<LiveLattice>>::converge(&mut function.graph, Backward);
+        let standalone = values(&function.graph);
+
+        // A fresh graph, with nothing solved yet
+        function.build_graph();
+        let transfers = solve_reaching_definitions_and_liveness(function);
+        assert_eq!(values(&function.graph), standalone);
+        // Both analyses transfer every block but their boundary at least once
+        assert!(transfers >= 2 * (function.graph.graph.node_count() - 1));
+    });
+}
+
+#[test]
+fn reaching_transfer_of_long_block_matches_masks() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = Program::new();
+    with_function(&program, "$long", |function| {
+        function.return_type = DataType::I64;
+        let mut value = function.declare_param("@x".to_string(), DataType::I64);
+        let mut block = function.build_block("#body");
+        let one = block.int(1);
+        for i in 0..500 {
+            value = block.add(&format!("%v{}", i % 50), value, one);
+        }
+        block.ret(value);
+        let graph = &mut function.graph;
+        <Graph as BlockUpdate<ReachLattice>>::converge(graph, Forward);
+
+        // The solution is the one the gen and kill masks describe; see
+        // tests/allocations.rs for what solving it allocates
+        let blocks = graph.weight.blocks.borrow();
+        for node in graph.graph.node_weights() {
+            let Some(block_id) = node.block else {
+                continue;
+            };
+            let mut expected = node.reach_in.value.clone();
+            for ir in blocks[block_id].irs() {
+                expected.intersect_with(&ReachLattice::kill_mask_var(ir, &graph.weight).value);
+                expected.union_with(&ReachLattice::gen_var(ir, &graph.weight).value);
+            }
+            assert_eq!(node.reach_out.value, expected);
+        }
+    });
+}
+
+#[test]
+fn cached_block_transfer_matches_instructions() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = 1
+                %y = %x + @a
+                %x = 2
+                => #loop
+            }
+            #loop {
+                %y = %y + %x
+                %c = %y < 100
+                => %c ? #loop : #done
+            }
+            #done {
+                => ret %y
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let assert_cache_agrees = |function: &mut Function| {
+            <Graph as BlockUpdate<ReachLattice>>::converge(&mut function.graph, Forward);
+            let graph = &function.graph;
+            let blocks = graph.weight.blocks.borrow();
+            for node in graph.graph.node_weights() {
+                let Some(block_id) = node.block else {
+                    continue;
+                };
+                let mut fresh = node.reach_in.value.clone();
+                for ir in blocks[block_id].irs() {
+                    ReachLattice::transfer_var(ir, &graph.weight, &mut fresh);
+                }
+                assert_eq!(node.reach_out.value, fresh);
+            }
+        };
+        assert_cache_agrees(function);
+
+        // Swapping the definitions of %x keeps the numbering, so the graph stays valid,
+        // but the other one now reaches #loop. Each edit drops the cached transfer.
+        let entry = "#entry".to_string();
+        function
+            .blocks
+            .get_mut(&entry)
+            .unwrap()
+            .irs_mut()
+            .swap(0, 2);
+        assert_cache_agrees(function);
+        {
+            let mut block = function.blocks.get_mut(&entry).unwrap();
+            let ir = block.remove_instruction(0).unwrap();
+            assert!(block.insert_after(1, ir).is_ok());
+        }
+        assert_cache_agrees(function);
+    });
+}
+
+#[test]
+fn unreachable_loop_is_solved_too() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                => ret @a
+            }
+            #b1 {
+                %x = %x + 1
+                => #b1
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        <Graph as BlockUpdate<ReachLattice>>::converge(&mut function.graph, Forward);
+        <Graph as BlockUpdate<LiveLattice>>::converge(&mut function.graph, Backward);
+        build_def_use_chains(function);
+        Liveness::compute(function);
+        allocate_registers(function, 2);
+        eliminate_dead_code(function);
+        eliminate_dead_code_aggressively(function);
+        assert_eq!(
+            interpret(function, &int_inputs(&[3])[0]),
+            Ok(Value::Int(IntValue::i64(3)))
+        );
+    });
+}
+
+#[test]
+fn joint_solve_covers_unreachable_blocks() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                => ret @a
+            }
+            #b1 {
+                %x = %x + 1
+                => #b2
+            }
+            #b2 {
+                %y = %x * 2
+                => #b1
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let values = |function: &Function| {
+            function
+                .graph
+                .graph
+                .node_weights()
+                .map(|n| (n.reach_out.value.clone(), n.live_in.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        solve_reaching_definitions_and_liveness(function);
+        let joint = values(function);
+        solve_reaching_definitions(function);
+        solve_liveness(function);
+        let separate = values(function);
+        assert_eq!(joint, separate);
+    });
+}
+
+#[test]
+fn straight_line_converges_in_one_pass() {
+    type Graph = DataFlowGraph<CodeBlockAnalysisNode, CodeBlockGraphWeight>;
+    let program = Program::new();
+    with_function(&program, "$line", |function| {
+        function.return_type = DataType::I64;
+        let mut value = function.declare_param("@x".to_string(), DataType::I64);
+        for i in 0..20 {
+            let mut block = function.build_block(&format!("#b{i}"));
+            let one = block.int(1);
+            value = block.add(&format!("%v{i}"), value, one);
+            block.next();
+        }
+        function.build_block("#done").ret(value);
+        let graph = &mut function.graph;
+        let passes = graph.graph.node_count() - 1;
+
+        assert_eq!(
+            <Graph as BlockUpdate<ReachLattice>>::converge(graph, Forward),
+            passes
+        );
+        let reach: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| n.reach_out.value.clone())
+            .collect();
+        assert!(<Graph as BlockUpdate<ReachLattice>>::converge_uniform(graph, Forward) > passes);
+        let uniform_reach: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| n.reach_out.value.clone())
+            .collect();
+        assert_eq!(reach, uniform_reach);
+
+        assert_eq!(
+            <Graph as BlockUpdate<LiveLattice>>::converge(graph, Backward),
+            passes
+        );
+        let live: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| n.live_in.value.clone())
+            .collect();
+        assert!(<Graph as BlockUpdate<LiveLattice>>::converge_uniform(graph, Backward) > passes);
+        let uniform_live: Vec<_> = graph
+            .graph
+            .node_weights()
+            .map(|n| n.live_in.value.clone())
+            .collect();
+        assert_eq!(live, uniform_live);
+    });
+}
+
+#[test]
+fn fold_int_float_casts() {
+    let program = parse_program(
+        "fn $to_float() : f64 {
+            #entry {
+                %a = 3
+                %b = cast f64 %a
+                %c = %b * 2.5
+                => ret %c
+            }
+        }
+        fn $to_int() : i64 {
+            #entry {
+                %a = -7.9
+                %b = cast i64 %a
+                => ret %b
+            }
+        }",
+    );
+    let folded = |name: &str, space: &str| {
+        with_function(&program, name, |function| {
+            assert!(assert_pass_preserves_semantics(
+                function,
+                propagate_constants,
+                &[vec![]]
+            ));
+            function
+                .locals
+                .get(&space.to_string())
+                .unwrap()
+                .value
+                .clone()
+        })
+    };
+    assert_eq!(
+        folded("$to_float", "%c"),
+        FlatLattice::Value(Value::Float(FloatValue { value: 7.5 }))
+    );
+    assert_eq!(
+        folded("$to_int", "%b"),
+        FlatLattice::Value(Value::Int(IntValue::i64(-7)))
+    );
+}
+
+#[test]
+fn spill_slots_are_shared() {
+    let program = parse_program(
+        "fn $f() : i64 {
+            #entry {
+                %a = 1
+                %b = 2
+                %c = %a + %b
+                %c = %c + %b
+                => ret %c
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let (a, b, c) = (space("%a"), space("%b"), space("%c"));
+        let liveness = Liveness::compute(function);
+        assert_eq!(liveness.intervals.len(), 3);
+        assert!(!liveness.interferes(a, c));
+        assert!(liveness.interferes(a, b));
+        assert!(liveness.interferes(b, c));
+
+        let mut allocation = linear_scan(&liveness, 0);
+        assert_eq!(allocation.spill_slots, 3);
+        coalesce_spill_slots(&mut allocation, &liveness);
+        assert_eq!(allocation.spill_slots, 2);
+        assert_eq!(allocation.locations[&a], allocation.locations[&c]);
+        assert_ne!(allocation.locations[&a], allocation.locations[&b]);
+
+        let allocation = linear_scan(&liveness, 1);
+        let registers = allocation
+            .locations
+            .values()
+            .filter(|location| matches!(location, Location::Register(_)))
+            .count();
+        assert_eq!(registers, 2);
+        assert_eq!(allocation.spill_slots, 1);
+    });
+}
+
+#[test]
+fn copy_is_coalesced() {
+    let program = parse_program(
+        "fn $dies(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %b = %a
+                %c = %b * 2
+                => ret %c
+            }
+        }
+        fn $lives(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %b = %a
+                %c = %b * %a
+                => ret %c
+            }
+        }",
+    );
+    let inputs = int_inputs(&[0, 5]);
+    with_function(&program, "$dies", |function| {
+        let a = *function.locals.get_name_id(&"%a".to_string()).unwrap();
+        let b = *function.locals.get_name_id(&"%b".to_string()).unwrap();
+        assert!(assert_pass_preserves_semantics(
+            function,
+            |function| {
+                let allocation = allocate_registers(function, 4);
+                assert_eq!(allocation.locations[&a], allocation.locations[&b]);
+                true
+            },
+            &inputs
+        ));
+        assert_eq!(ir_count(function), 2);
+    });
+    with_function(&program, "$lives", |function| {
+        let a = *function.locals.get_name_id(&"%a".to_string()).unwrap();
+        let b = *function.locals.get_name_id(&"%b".to_string()).unwrap();
+        assert!(coalesce_copies(function).is_empty());
+        assert_eq!(ir_count(function), 3);
+        let allocation = allocate_registers(function, 4);
+        assert_ne!(allocation.locations[&a], allocation.locations[&b]);
+    });
+}
+
+#[test]
+fn graph_coloring_spills_at_two_registers() {
+    let program = parse_program(
+        "fn $f(i64 @x) : i64 {
+            #entry {
+                %a = @x + 1
+                %b = @x + 2
+                %c = @x + 3
+                %d = %a + %b
+                %e = %d + %c
+                => ret %e
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let liveness = Liveness::compute(function);
+        let graph = InterferenceGraph::new(&liveness);
+        for registers in [2, 3] {
+            let allocation = graph_coloring(function, &liveness, registers);
+            assert_eq!(allocation.locations.len(), liveness.intervals.len());
+            for (a, location) in &allocation.locations {
+                for (b, other) in &allocation.locations {
+                    if a != b && graph.interferes(*a, *b) {
+                        assert!(
+                            !matches!(location, Location::Register(_)) || location != other,
+                            "{a} and {b} interfere but share {location:?}"
+                        );
+                    }
+                }
+            }
+            let spilled = allocation.spill_slots;
+            assert_eq!(spilled > 0, registers == 2);
+        }
+    });
+}
+
+#[test]
+fn spills_prefer_shallow_loops() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %b = @n + 2
+                %a = @n + 1
+                => #loop
+            }
+            #loop {
+                %b = %b - 1
+                => %b ? #loop : #done
+            }
+            #done {
+                %s = %a + @n
+                %t = %s + %b
+                => ret %t
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+        let (a, b) = (space("%a"), space("%b"));
+        let liveness = Liveness::compute(function);
+        assert_eq!((liveness.depths[&a], liveness.depths[&b]), (0, 1));
+        // Both span the loop, but `%b`, which ends last, is used inside it
+        let (a_interval, b_interval) = (liveness.intervals[&a], liveness.intervals[&b]);
+        assert!(b_interval.start < a_interval.start && a_interval.end < b_interval.end);
+
+        let allocation = linear_scan(&liveness, 2);
+        assert!(matches!(allocation.locations[&a], Location::Spill(_)));
+        assert!(matches!(allocation.locations[&b], Location::Register(_)));
+        let allocation = graph_coloring(function, &liveness, 2);
+        assert!(matches!(allocation.locations[&b], Location::Register(_)));
+    });
+}
+
+#[test]
+fn emit_x86_64_add() {
+    let program = parse_program(
+        "fn $add(i64 @a, i64 @b) : i64 {
+            #entry {
+                %s = @a + @b
+                => ret %s
+            }
+        }",
+    );
+    with_function(&program, "$add", |function| {
+        let allocation = allocate_registers(function, REGISTERS.len());
+        let assembly = emit_x86_64(function, &allocation);
+        println!("{assembly}");
+        let lines: Vec<_> = assembly.lines().map(str::trim).collect();
+        assert!(lines.contains(&"add:"));
+        let add = lines
+            .iter()
+            .position(|l| l.starts_with("add rax, rcx"))
+            .unwrap();
+        let ret = lines.iter().position(|l| *l == "ret").unwrap();
+        assert!(lines[add - 2].starts_with("mov rax, "));
+        assert!(lines[add - 1].starts_with("mov rcx, "));
+        assert!(add < ret);
+        assert_eq!(lines.last(), Some(&"ret"));
+    });
+}
+
+#[test]
+fn emit_llvm_ir_branch() {
+    let program = parse_program(
+        "fn $pick(i64 @a, i64 @b) : i64 {
+            #entry {
+                => @a ? #left : #right
+            }
+            #left {
+                %r = @a - @b
+                => ret %r
+            }
+            #right {
+                => ret @b
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$pick".to_string()).unwrap();
+    let llvm = emit_llvm_ir(&function);
+    println!("{llvm}");
+    assert!(llvm.contains("define i64 @pick(i64 %arg"));
+    assert!(llvm.contains("br i1 "));
+    assert!(llvm.contains("sub i64 "));
+    assert!(llvm.contains("ret i64 "));
+}
+
+#[test]
+fn emitters_read_globals_written_before() {
+    let program = parse_program(
+        "global @g: i64 = 5
+        fn $f() : i64 {
+            #entry {
+                @g = 7
+                %x = @g
+                => ret %x
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    let llvm = emit_llvm_ir(&function);
+    let wat = emit_wat(&function);
+    println!("{llvm}\n{wat}");
+    // The initializer is only the global's first value, not a constant
+    assert!(llvm.contains("load i64, ptr @g") && !llvm.contains(" 5"));
+    assert!(wat.contains("global.get $g") && !wat.contains("i64.const 5"));
+}
+
+#[test]
+fn emit_wat_counting_loop() {
+    let program = Program::new();
+    with_function(&program, "$count", |function| {
+        function.return_type = DataType::I64;
+        let n = function.declare_param("@n".to_string(), DataType::I64);
+
+        let mut entry = function.build_block("#entry");
+        let zero = entry.int(0);
+        let one = entry.int(1);
+        let i = entry.copy("%i", zero);
+        entry.next();
+        let mut cond = function.build_block("#cond");
+        let c = cond.compare(CompareType::Less, i, n);
+        cond.branch(c, "#body", "#done");
+        let mut body = function.build_block("#body");
+        body.add("%i", i, one);
+        body.jump("#cond");
+        function.build_block("#done").ret(i);
+
+        let wat = emit_wat(function);
+        println!("{wat}");
+        let lines: Vec<_> = wat.lines().map(str::trim).collect();
+        assert!(lines[0].starts_with("(func $count (param"));
+        let loop_start = lines.iter().position(|l| *l == "loop").unwrap();
+        let br_if = lines.iter().position(|l| l.starts_with("br_if")).unwrap();
+        let back = lines.iter().rposition(|l| *l == "br 0").unwrap();
+        assert!(loop_start < br_if && br_if < back);
+    });
+}
+
+#[test]
+fn reloop_diamond() {
+    let program = parse_program(
+        "fn $pick(i64 @a, i64 @b) : i64 {
+            #entry {
+                => @a ? #left : #right
+            }
+            #left {
+                %r = @a - @b
+                => #join
+            }
+            #right {
+                %r = @b
+                => #join
+            }
+            #join {
+                => ret %r
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$pick".to_string()).unwrap();
+    let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+    let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+    let join = block("#join");
+    assert_eq!(
+        reloop(&function),
+        Ok(vec![
+            StructuredNode::Block {
+                follower: join,
+                body: vec![
+                    StructuredNode::Code(block("#entry")),
+                    StructuredNode::If {
+                        cond: space("@a"),
+                        then: vec![
+                            StructuredNode::Code(block("#left")),
+                            StructuredNode::Branch(Label::Block(join)),
+                        ],
+                        otherwise: vec![
+                            StructuredNode::Code(block("#right")),
+                            StructuredNode::Branch(Label::Block(join)),
+                        ],
+                    },
+                ],
+            },
+            StructuredNode::Code(join),
+            StructuredNode::Return(Some(space("%r"))),
+        ])
+    );
+}
+
+#[test]
+fn reloop_loop() {
+    let program = parse_program(
+        "fn $count(i64 @n) : i64 {
+            #entry {
+                %i = @n
+                => next
+            }
+            #cond {
+                => %i ? #body : #done
+            }
+            #body {
+                %i = %i - 1
+                => #cond
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$count".to_string()).unwrap();
+    let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+    let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+    let (cond, done) = (block("#cond"), block("#done"));
+    assert_eq!(
+        reloop(&function),
+        Ok(vec![
+            StructuredNode::Code(block("#entry")),
+            StructuredNode::Block {
+                follower: done,
+                body: vec![StructuredNode::Loop {
+                    header: cond,
+                    body: vec![
+                        StructuredNode::Code(cond),
+                        StructuredNode::BranchIf {
+                            cond: space("%i"),
+                            negated: true,
+                            label: Label::Block(done),
+                        },
+                        StructuredNode::Code(block("#body")),
+                        StructuredNode::Branch(Label::Loop(cond)),
+                    ],
+                }],
+            },
+            StructuredNode::Code(done),
+            StructuredNode::Return(Some(space("%i"))),
+        ])
+    );
+}
+
+#[test]
+fn reloop_rejects_irreducible() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                => @a ? #x : #y
+            }
+            #x {
+                => #y
+            }
+            #y {
+                => @a ? #x : #done
+            }
+            #done {
+                => ret @a
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    assert!(matches!(
+        reloop(&function),
+        Err(RelooperError::Irreducible { .. })
+    ));
+}
+
+#[test]
+fn loop_depths_nested() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #outer
+            }
+            #outer {
+                %c = %i < @n
+                => %c ? #init : #done
+            }
+            #init {
+                %j = 0
+                => #inner
+            }
+            #inner {
+                %d = %j < @n
+                => %d ? #body : #latch
+            }
+            #body {
+                %s = %s + %j
+                %j = %j + 1
+                => #inner
+            }
+            #latch {
+                %i = %i + 1
+                => #outer
+            }
+            #done {
+                => ret %s
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    let depths = loop_depths(&function);
+    let depth = |name: &str| depths[function.blocks.get_name_id(&name.to_string()).unwrap()];
+    assert_eq!(depth("#entry"), 0);
+    assert_eq!(depth("#outer"), 1);
+    assert_eq!(depth("#init"), 1);
+    assert_eq!(depth("#inner"), 2);
+    assert_eq!(depth("#body"), 2);
+    assert_eq!(depth("#latch"), 1);
+    assert_eq!(depth("#done"), 0);
+}
+
+#[test]
+fn two_entry_loop_is_irreducible() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #x : #y
+            }
+            #x {
+                => #y
+            }
+            #y {
+                %d = @a > 1
+                => %d ? #x : #done
+            }
+            #done {
+                => ret @a
+            }
+        }
+        fn $g(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                => #head
+            }
+            #head {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    let p = program.borrow();
+    let f = p.functions.get(&"$f".to_string()).unwrap();
+    let block = |name: &str| *f.blocks.get_name_id(&name.to_string()).unwrap();
+    assert!(!is_reducible(&f));
+    assert_eq!(irreducible_entries(&f), vec![block("#x"), block("#y")]);
+    let g = p.functions.get(&"$g".to_string()).unwrap();
+    assert!(is_reducible(&g));
+    assert!(irreducible_entries(&g).is_empty());
+}
+
+#[test]
+fn irreducible_cycle_nested_in_a_loop_reports_its_own_entries() {
+    let program = parse_program(
+        "fn $f(i64 @c, i64 @d, i64 @e) : i64 {
+            #h {
+                => @c ? #a : #b
+            }
+            #a {
+                => @d ? #b : #h
+            }
+            #b {
+                => @e ? #a : #out
+            }
+            #out {
+                => ret @c
+            }
+        }",
+    );
+    let p = program.borrow();
+    let f = p.functions.get(&"$f".to_string()).unwrap();
+    let block = |name: &str| *f.blocks.get_name_id(&name.to_string()).unwrap();
+    assert!(!is_reducible(&f));
+    // #h dominates the whole loop; the cycle of #a and #b is entered from it twice
+    assert_eq!(irreducible_entries(&f), vec![block("#a"), block("#b")]);
+}
+
+#[test]
+fn node_splitting_makes_cfg_reducible() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                %c = @a > 0
+                => %c ? #x : #y
+            }
+            #x {
+                %i = %i + 2
+                => #y
+            }
+            #y {
+                %i = %i - 1
+                %d = %i < @a
+                => %d ? #x : #done
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        assert!(!make_reducible(function, 0));
+        assert!(!is_reducible(function));
+        let reducible = assert_pass_preserves_semantics(
+            function,
+            |function| make_reducible(function, 4),
+            &int_inputs(&[-1, 0, 1, 5]),
+        );
+        assert!(reducible && is_reducible(function));
+        assert_eq!(function.block_name_ids().len(), 5);
+        assert!(function
+            .blocks
+            .get_name_id(&"#y.split".to_string())
+            .is_some());
+        assert!(reloop(function).is_ok());
+        // Already reducible, nothing to split
+        assert!(make_reducible(function, 0));
+    });
+}
+
+#[test]
+fn node_splitting_handles_irreducible_cycle_nested_in_a_loop() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                => #h
+            }
+            #h {
+                %i = %i + 1
+                %c = %i < @a
+                => %c ? #a : #b
+            }
+            #a {
+                %i = %i + 2
+                %d = %i < 10
+                => %d ? #b : #h
+            }
+            #b {
+                %i = %i + 3
+                %e = %i < 20
+                => %e ? #a : #out
+            }
+            #out {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let reducible = assert_pass_preserves_semantics(
+            function,
+            |function| make_reducible(function, 4),
+            &int_inputs(&[-1, 0, 3, 8, 30]),
+        );
+        assert!(reducible && is_reducible(function));
+        assert!(function
+            .blocks
+            .get_name_id(&"#b.split".to_string())
+            .is_some());
+    });
+}
+
+#[test]
+fn node_splitting_keeps_self_loop_in_the_copy() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                %c = @a > 0
+                => %c ? #x : #y
+            }
+            #x {
+                %i = %i + 2
+                %d = %i < @a
+                => %d ? #y : #done
+            }
+            #y {
+                %i = %i + 3
+                %e = %i < 10
+                => %e ? #y : #x
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        assert!(!is_reducible(function));
+        let reducible = assert_pass_preserves_semantics(
+            function,
+            |function| make_reducible(function, 50),
+            &int_inputs(&[-1, 0, 1, 5, 20]),
+        );
+        assert!(reducible && is_reducible(function));
+        assert_eq!(function.block_name_ids().len(), 5);
+    });
+}
+
+#[test]
+fn static_frequencies_favor_loop_bodies() {
+    let program = parse_program(
+        "fn $f(i64 @n) : i64 {
+            #entry {
+                %i = 0
+                => #head
+            }
+            #head {
+                %c = %i < @n
+                => %c ? #body : #done
+            }
+            #body {
+                %e = %i > 100
+                => %e ? #early : #latch
+            }
+            #early {
+                => ret %i
+            }
+            #latch {
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let node = |function: &Function, name: &str| {
+            function.graph.weight.block_nodes
+                [function.blocks.get_name_id(&name.to_string()).unwrap()]
+        };
+        // Profile data is kept
+        let latch = node(function, "#latch");
+        function.graph.graph[latch].frequency = Some(3.0);
+        estimate_frequencies(function);
+        let frequency = |name: &str| function.graph.graph[node(function, name)].frequency;
+        assert_eq!(frequency("#entry"), Some(1.0));
+        assert_eq!(frequency("#head"), Some(10.0));
+        assert_eq!(frequency("#body"), Some(10.0));
+        assert_eq!(frequency("#latch"), Some(3.0));
+        // Leaving the function is unlikely
+        assert_eq!(frequency("#early"), Some(0.5));
+        assert_eq!(frequency("#done"), Some(0.5));
+        assert!(function.graph.graph[function.graph.entry]
+            .frequency
+            .is_none());
+    });
+}
+
+#[test]
+fn redundant_pure_calls_are_eliminated() {
+    let program = parse_program(
+        "global @count: i64 = 0
+        fn $square(i64 @x) : i64 {
+            #entry {
+                %y = @x * @x
+                => ret %y
+            }
+        }
+        fn $tick(i64 @x) : i64 {
+            #entry {
+                @count = @count + @x
+                => ret @count
+            }
+        }
+        fn $sqrt(f64 @x) : f64 ext pure
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %p = param @a
+                %s = call $square
+                %q = param @a
+                %t = call $square
+                %u = param @a
+                %v = call $tick
+                %w = param @a
+                %x = call $tick
+                %y = param @a
+                %unused = call $square
+                %z = param @a
+                %ignored = call $tick
+                %r = %s + %t
+                %r = %r + %v
+                %r = %r + %x
+                => ret %r
+            }
+        }",
+    );
+    let name_id = |name: &str| {
+        *program
+            .borrow()
+            .functions
+            .get_name_id(&name.to_string())
+            .unwrap()
+    };
+    assert!(program.borrow().is_pure(name_id("$sqrt")));
+    assert_eq!(infer_purity(&program), vec![name_id("$square")]);
+    assert!(!program.borrow().is_pure(name_id("$tick")));
+    assert!(!program.borrow().is_pure(name_id("$f")));
+
+    with_function(&program, "$f", |function| {
+        assert!(eliminate_common_subexpressions(function));
+        assert!(eliminate_dead_code(function));
+        let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+        let count = |callee: &str| {
+            let callee = name_id(callee);
+            entry
+                .irs()
+                .iter()
+                .filter(|ir| matches!(ir, Assignment(_, Operation::Call(c), _) if *c == callee))
+                .count()
+        };
+        assert_eq!(count("$square"), 1);
+        assert_eq!(count("$tick"), 3);
+        let params = entry
+            .irs()
+            .iter()
+            .filter(|ir| matches!(ir, Assignment(_, Operation::Unary(UnaryOp::Param, _), _)))
+            .count();
+        assert_eq!(params, 4);
+    });
+    let p = program.borrow();
+    let f = p.functions.get(&"$f".to_string()).unwrap();
+    // 9 + 9 + 3 + 6, with the last tick still counted
+    assert_eq!(
+        interpret(&f, &[Value::Int(IntValue::i64(3))]),
+        Ok(Value::Int(IntValue::i64(27)))
+    );
+}
+
+#[test]
+fn impure_calls_keep_their_order() {
+    let program = parse_program(
+        "global @count: i64 = 0
+        fn $tick(i64 @x) : i64 {
+            #entry {
+                @count = @count + @x
+                => ret @count
+            }
+        }
+        fn $f(i64 @a, i64 @b) : i64 {
+            #entry {
+                %k = @b * 2
+                %g = @count + 1
+                %p = param @a
+                %s = call $tick
+                %q = param @a
+                %t = call $tick
+                %l = @b * 2
+                %h = @count + 1
+                %r = %s - %t
+                %r = %r + %l
+                %r = %r + %h
+                %r = %r - %k
+                %r = %r - %g
+                => ret %r
+            }
+        }",
+    );
+    let run = || {
+        let p = program.borrow();
+        let f = p.functions.get(&"$f".to_string()).unwrap();
+        interpret(&f, &int_inputs(&[3, 5])[..].concat())
+    };
+    // 3 - 6 + 10 + 7 - 10 - 1
+    assert_eq!(run(), Ok(Value::Int(IntValue::i64(3))));
+    let effects = |function: &Function| {
+        let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+        let tokens = effect_tokens(function, entry.irs());
+        let effects: Vec<_> = entry
+            .irs()
+            .iter()
+            .filter(|ir| has_effect(function, ir))
+            .map(|ir| format!("{}", ir))
+            .collect();
+        (effects, *tokens.last().unwrap())
+    };
+    with_function(&program, "$f", |function| {
+        let before = effects(function);
+        assert_eq!(before.1, 2);
+        assert!(eliminate_common_subexpressions(function));
+        assert_eq!(effects(function), before);
+        // Only `%l` reads no global or escaped local, so only it is reused across the calls
+        let l = *function.locals.get_name_id(&"%l".to_string()).unwrap();
+        let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+        let copies: Vec<_> = entry.irs().iter().filter_map(|ir| ir.as_copy()).collect();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].0, l);
+        drop(entry);
+        coalesce_copies(function);
+        assert_eq!(effects(function), before);
+    });
+    assert_eq!(run(), Ok(Value::Int(IntValue::i64(3))));
+}
+
+#[test]
+fn diamond_dominator_tree_to_dot() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #then : #else
+            }
+            #then {
+                %x = 1
+                => #join
+            }
+            #else {
+                %x = 2
+                => #join
+            }
+            #join {
+                => ret %x
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    let block = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+    let dot = dominator_tree_to_dot(&function);
+    assert!(dot.starts_with("digraph \"$f\" {\n"));
+    assert!(dot.ends_with("}\n"));
+    for name in ["#entry", "#then", "#else", "#join"] {
+        assert!(dot.contains(&format!("    b{} [label=\"{}\"];\n", block(name), name)));
+    }
+    let edges: Vec<_> = dot.lines().filter(|line| line.contains("->")).collect();
+    let edge = |from: &str, to: &str| format!("    b{} -> b{};", block(from), block(to));
+    // The join is reached from both arms, so only the split dominates it
+    assert_eq!(
+        edges,
+        ["#then", "#else", "#join"]
+            .map(|to| edge("#entry", to))
+            .to_vec()
+    );
+}
+
+#[test]
+fn recompute_numbers_assignments() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                => #next
+            }
+            #next {
+                %x = %x * 2
+                => ret %x
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let x = *function.locals.get_name_id(&"%x".to_string()).unwrap();
+        let mut weight = CodeBlockGraphWeight::default();
+        weight.recompute(function);
+        assert_eq!(weight.assignment_count, 2);
+        assert_eq!(weight.variable_assignment_map.len(), 1);
+        assert_eq!(weight.variable_assignment_map[&x], vec![0, 1]);
+        assert_eq!(weight.space_count, function.graph.weight.space_count);
+
+        let next = function.blocks.get_id(&"#next".to_string()).unwrap();
+        let mut block = function.blocks.get_mut_from_id(next).unwrap();
+        let copy = Assignment(x, Operation::Unary(Unit, x), Default::default());
+        block.irs_mut().insert(0, copy);
+        drop(block);
+        weight.recompute(function);
+        assert_eq!(weight.assignment_count, 3);
+        assert_eq!(weight.variable_assignment_map[&x], vec![0, 1, 2]);
+        let block = function.blocks.get_from_id(next).unwrap();
+        let numbers: Vec<_> = block
+            .irs()
+            .iter()
+            .map(|ir| ir.info().declaration_number)
+            .collect();
+        assert_eq!(numbers, vec![Some(1), Some(2)]);
+    });
+}
+
+#[test]
+fn add_block_and_connect_map_blocks_to_nodes() {
+    let program = parse_program(
+        "fn $f() : i64 {
+            #entry {
+                => #next
+            }
+            #next {
+                => ret 1
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        let names = function.block_name_ids();
+        let ids: Vec<_> = names
+            .iter()
+            .map(|name_id| function.blocks.get_id_from_name_id(name_id).unwrap())
+            .collect();
+        let mut graph = DataFlowGraph::<CodeBlockAnalysisNode, _>::new(CodeBlockGraphWeight::new(
+            function.blocks.arena().clone(),
+        ));
+        let first = graph.add_block(names[0], ids[0], BlockType::Entry);
+        let second = graph.add_block(names[1], ids[1], BlockType::Exit);
+        assert_eq!(graph.add_block(names[1], ids[1], BlockType::Exit), second);
+        assert!(graph
+            .connect(first, second, EdgeKind::Unconditional)
+            .is_some());
+        graph.debug_assert_consistent();
+
+        assert_eq!(graph.graph.node_count(), 4);
+        assert_eq!(
+            graph.graph.neighbors(first).collect::<Vec<_>>(),
+            vec![second]
+        );
+        assert_eq!(graph.graph.neighbors(second).count(), 0);
+        assert_eq!(graph.weight.block_nodes[&names[0]], first);
+        assert_eq!(graph.weight.block_nodes[&names[1]], second);
+        assert_eq!(graph.graph[first].block, Some(ids[0]));
+        assert_eq!(graph.graph[second].block, Some(ids[1]));
+        let block = function.blocks.get_from_id(ids[1]).unwrap();
+        assert_eq!(block.block_type, BlockType::Exit);
+        drop(block);
+
+        graph.remove_node(second);
+        assert!(graph
+            .connect(first, second, EdgeKind::Unconditional)
+            .is_none());
+        assert_eq!(graph.graph.edge_count(), 0);
+    });
+}
+
+#[test]
+fn blanket_product_meet_matches_reach_lattice() {
+    #[derive(Clone, PartialEq, Debug)]
+    struct Bits(Vec<bool>);
+    impl ProductLattice for Bits {
+        type Element = bool;
+        fn get(&self, index: usize) -> Option<&bool> {
+            self.0.get(index)
+        }
+        fn set(&mut self, index: usize, element: bool) {
+            self.0[index] = element;
+        }
+        fn dim(&self) -> usize {
+            self.0.len()
+        }
+    }
+    let reach = |bits: &[usize]| {
+        let mut lattice = ReachLattice::new(8);
+        bits.iter().for_each(|bit| lattice.value.insert(*bit));
+        lattice
+    };
+    let pairs = [
+        (vec![], vec![]),
+        (vec![0, 3], vec![3, 7]),
+        (vec![1, 2, 5], vec![]),
+        (vec![], vec![4, 6]),
+        (vec![0, 1, 2], vec![1]),
+    ];
+    for (a, b) in pairs {
+        let (a, b) = (reach(&a), reach(&b));
+        // What `ReachLattice::meet` did by hand before it got the blanket implementation
+        let mut union = a.value.clone();
+        union.union_with(&b.value);
+        assert_eq!(a.meet(&b).value, union);
+
+        let bits = |lattice: &ReachLattice| Bits((0..8).map(|i| lattice.value[i]).collect());
+        assert_eq!(bits(&a).meet(&bits(&b)), bits(&a.meet(&b)));
+
+        let mut met = a.clone();
+        assert_eq!(met.meet_with(&b), union != a.value);
+        assert_eq!(met.value, union);
+    }
+}
+
+#[test]
+fn diamond_of_constants_becomes_a_select() {
+    let program = parse_program(
+        "global @g: i64 = 0
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                %x = 1
+                => #join
+            }
+            #neg {
+                %x = 2
+                => #join
+            }
+            #join {
+                => ret %x
+            }
+        }
+        fn $g(i64 @a) : i64 {
+            #entry {
+                %c = @a > 0
+                => %c ? #pos : #neg
+            }
+            #pos {
+                @g <- 1
+                => #join
+            }
+            #neg {
+                %x = 2
+                => #join
+            }
+            #join {
+                => ret @a
+            }
+        }",
+    );
+    let inputs = [
+        vec![Value::Int(IntValue::i64(5))],
+        vec![Value::Int(IntValue::i64(-5))],
+    ];
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function, if_convert, &inputs
+        ));
+        let names: Vec<_> = function
+            .block_name_ids()
+            .iter()
+            .map(|name_id| function.blocks.get_name(name_id).unwrap().clone())
+            .collect();
+        assert_eq!(names, vec!["#entry", "#join"]);
+        let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+        let selects: Vec<_> = entry
+            .irs()
+            .iter()
+            .filter(|ir| matches!(ir, Assignment(_, Operation::Select(..), _)))
+            .collect();
+        assert_eq!(selects.len(), 1);
+        assert!(matches!(
+            entry.terminator(),
+            Jump(JumpOperation::Unconditional(..), _)
+        ));
+    });
+    // A store is an effect, so its arm must not run on the other path
+    with_function(&program, "$g", |function| {
+        assert!(!if_convert(function));
+        assert_eq!(function.block_name_ids().len(), 4);
+    });
+}
+
+#[test]
+fn repeated_sum_in_a_block_is_computed_once() {
+    let program = parse_program(
+        "global @g: i64 = 5
+        fn $f(i64 @a, i64 @b) : i64 {
+            #entry {
+                %x = @a + @b
+                %y = @b + @a
+                %c = %x
+                %z = %c - @b
+                %w = %x - @b
+                %v = @b - %x
+                %l = %z > %v
+                %m = %v < %w
+                %s = %y + %z
+                %h = @g + @a
+                @g = %h
+                %k = @g + @a
+                %s = %s + %h
+                %s = %s + %k
+                => %l ? #next : #other
+            }
+            #next {
+                %q = @a + @b
+                => ret %q
+            }
+            #other {
+                %r = %s + %m
+                => ret %r
+            }
+        }",
+    );
+    let inputs: Vec<_> = [(1, 2), (5, -3), (0, 0)]
+        .iter()
+        .map(|(a, b)| vec![Value::Int(IntValue::i64(*a)), Value::Int(IntValue::i64(*b))])
+        .collect();
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            local_value_numbering,
+            &inputs
+        ));
+        let count = |block: &str, matches: fn(&Operation) -> bool| {
+            let block = function.blocks.get(&block.to_string()).unwrap();
+            block
+                .irs()
+                .iter()
+                .filter(|ir| matches!(ir, Assignment(_, op, _) if matches(op)))
+                .count()
+        };
+        // All but `%y`, with `%k` kept as `@g` was written
+        assert_eq!(
+            count("#entry", |op| matches!(op, Operation::Binary(Add, ..))),
+            6
+        );
+        assert_eq!(
+            count("#entry", |op| matches!(op, Operation::Binary(Sub, ..))),
+            2
+        );
+        assert_eq!(
+            count("#entry", |op| matches!(op, Operation::Compare(..))),
+            1
+        );
+        // Numbers start over in each block
+        assert_eq!(
+            count("#next", |op| matches!(op, Operation::Binary(Add, ..))),
+            1
+        );
+    });
+}
+
+/// The spaces last written by a copy.
+struct Copied;
+
+impl GenKillAnalysis for Copied {
+    const DIRECTION: Direction = Forward;
+
+    fn capacity(weight: &CodeBlockGraphWeight) -> usize {
+        weight.space_count
+    }
+
+    fn gen(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        let mut set = FixedBitSet::with_capacity(weight.space_count);
+        if let Assignment(dst, Operation::Unary(Unit, _), _) = ir {
+            set.insert(*dst);
+        }
+        set
+    }
+
+    fn kill(ir: &IR, weight: &CodeBlockGraphWeight) -> FixedBitSet {
+        let mut set = FixedBitSet::with_capacity(weight.space_count);
+        set.extend(ir.defined_space());
+        set
+    }
+}
+
+#[test]
+fn gen_kill_framework_runs_a_custom_analysis() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a
+                %y = %x + 1
+                %z = %y
+                %w = %z
+                %x = %w + 1
+                => ret %x
+            }
+        }",
+    );
+    let p = program.borrow();
+    let function = p.functions.get(&"$f".to_string()).unwrap();
+    let space = |name: &str| *function.locals.get_name_id(&name.to_string()).unwrap();
+    let block = function.blocks.get(&"#entry".to_string()).unwrap();
+    let weight = &function.graph.weight;
+    let (gen, kill) = gen_kill_all::<Copied>(block.irs().iter(), weight);
+    let mut copied = vec![space("%z"), space("%w")];
+    copied.sort();
+    assert_eq!(gen.ones().collect::<Vec<_>>(), copied);
+    let mut killed = vec![space("%x"), space("%y"), space("%z"), space("%w")];
+    killed.sort();
+    assert_eq!(kill.ones().collect::<Vec<_>>(), killed);
+    // One instruction at a time gives what the summary does
+    let mut value = FixedBitSet::with_capacity(weight.space_count);
+    value.insert(space("%x"));
+    value.insert(space("@a"));
+    let mut expected = value.clone();
+    expected.difference_with(&kill);
+    expected.union_with(&gen);
+    transfer_all::<Copied>(block.irs().iter(), weight, &mut value);
+    assert_eq!(value, expected);
+    assert!(value.contains(space("@a")));
+}
+
+#[test]
+fn counted_loop_unrolled_by_two_computes_the_same() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                %s = @a
+                => #head
+            }
+            #head {
+                %c = %i < 7
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s * 3
+                %s = %s + %i
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %s
+            }
+        }
+        fn $g(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                => #head
+            }
+            #head {
+                %c = %i < @a
+                => %c ? #body : #done
+            }
+            #body {
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %i
+            }
+        }",
+    );
+    let inputs = int_inputs(&[0, 1, -4, 100]);
+    with_function(&program, "$f", |function| {
+        let head = *function.blocks.get_name_id(&"#head".to_string()).unwrap();
+        assert!(assert_pass_preserves_semantics(
+            function,
+            |function| unroll_loop(function, head, 2),
+            &inputs
+        ));
+        let unrolled = function.blocks.get(&"#body.unrolled".to_string()).unwrap();
+        assert_eq!(unrolled.irs().len(), 6);
+        drop(unrolled);
+        // The remainder loop is kept for the seventh iteration
+        assert!(function.blocks.get(&"#body".to_string()).is_some());
+        let entry = function.blocks.get(&"#entry".to_string()).unwrap();
+        let Jump(JumpOperation::Unconditional(target), _) = entry.terminator() else {
+            panic!("`#entry` jumps to the loop");
+        };
+        assert_eq!(
+            function.blocks.get_name(&target.block_id).unwrap(),
+            "#head.unrolled"
+        );
+        drop(entry);
+        // The unrolled body steps the counter twice
+        let unrolled_head = *function
+            .blocks
+            .get_name_id(&"#head.unrolled".to_string())
+            .unwrap();
+        assert!(!unroll_loop(function, unrolled_head, 2));
+        // A hundred copies cost too much
+        assert!(!unroll_loop(function, head, 100));
+    });
+    // The trip count isn't known
+    with_function(&program, "$g", |function| {
+        let head = *function.blocks.get_name_id(&"#head".to_string()).unwrap();
+        assert!(!unroll_loop(function, head, 2));
+    });
+}
+
+#[test]
+fn while_loop_rotates_into_a_guarded_do_while() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %i = 0
+                %s = 0
+                => #head
+            }
+            #head {
+                %c = %i < @a
+                => %c ? #body : #done
+            }
+            #body {
+                %s = %s + %i
+                %i = %i + 1
+                => #head
+            }
+            #done {
+                => ret %s
+            }
+        }",
+    );
+    let inputs = int_inputs(&[0, 1, 5, -3]);
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            rotate_loops,
+            &inputs
+        ));
+        let name_id = |name: &str| *function.blocks.get_name_id(&name.to_string()).unwrap();
+        let successors = |name: &str| {
+            let block = function.blocks.get(&name.to_string()).unwrap();
+            match block.terminator() {
+                Jump(JumpOperation::Unconditional(addr), _) => vec![addr.block_id],
+                Jump(JumpOperation::Branch(_, true_addr, false_addr), _) => {
+                    vec![true_addr.block_id, false_addr.block_id]
+                }
+                _ => vec![],
+            }
+        };
+        assert_eq!(successors("#entry"), vec![name_id("#head.guard")]);
+        assert_eq!(
+            successors("#head.guard"),
+            vec![name_id("#body.preheader"), name_id("#done")]
+        );
+        assert_eq!(successors("#body.preheader"), vec![name_id("#body")]);
+        // The body is unchanged and the test now follows it
+        let body = function.blocks.get(&"#body".to_string()).unwrap();
+        assert_eq!(body.irs().len(), 2);
+        drop(body);
+        assert_eq!(successors("#body"), vec![name_id("#head")]);
+        assert_eq!(
+            successors("#head"),
+            vec![name_id("#body"), name_id("#done")]
+        );
+        let depths = loop_depths(function);
+        assert_eq!(depths[&name_id("#body")], 1);
+        assert_eq!(depths[&name_id("#head")], 1);
+        assert_eq!(depths[&name_id("#head.guard")], 0);
+        assert_eq!(depths[&name_id("#body.preheader")], 0);
+        // `#body` heads the loop now, and its test is at the bottom
+        assert!(!rotate_loops(function));
+    });
+}
+
+#[test]
+fn second_load_of_an_address_reuses_the_first() {
+    let program = parse_program(
+        "global @g: i64 = 0
+        fn $f(i64 @a) : i64 {
+            #entry {
+                %x = load @a
+                %t = 1
+                %t <- %x
+                %y = load @a
+                %s = %x + %y
+                => ret %s
+            }
+        }
+        fn $h(i64 @a) : i64 {
+            #entry {
+                %x = load @a
+                @g <- %x
+                %y = load @a
+                %s = %x + %y
+                => ret %s
+            }
+        }",
+    );
+    let loads = |function: &Function| {
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        block
+            .irs()
+            .iter()
+            .filter(|ir| matches!(ir, Assignment(_, Operation::Unary(UnaryOp::Load, _), _)))
+            .count()
+    };
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_redundant_loads,
+            &int_inputs(&[3, -4])
+        ));
+        // The store to `%t` can't be seen through `@a`
+        assert_eq!(loads(function), 1);
+    });
+    with_function(&program, "$h", |function| {
+        // The store to `@g` may write where `@a` points
+        assert!(!eliminate_redundant_loads(function));
+        assert_eq!(loads(function), 2);
+    });
+}
+
+#[test]
+fn store_overwritten_before_any_read_is_removed() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %p = 0
+                %p <- @a
+                %p <- 5
+                %s = %p + @a
+                => ret %s
+            }
+        }
+        fn $g(i64 @a) : i64 {
+            #entry {
+                %p = 0
+                %p <- @a
+                %x = load %p
+                %p <- 5
+                %s = %x + %p
+                => ret %s
+            }
+        }",
+    );
+    let stores = |function: &Function| {
+        let block = function.blocks.get(&"#entry".to_string()).unwrap();
+        block
+            .irs()
+            .iter()
+            .filter(|ir| matches!(ir, IR::Command(CommandOperation::Store(..), _)))
+            .count()
+    };
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            eliminate_dead_stores,
+            &int_inputs(&[3, -4])
+        ));
+        assert_eq!(stores(function), 1);
+    });
+    with_function(&program, "$g", |function| {
+        // The load reads what the first store wrote
+        assert!(!eliminate_dead_stores(function));
+        assert_eq!(stores(function), 2);
+    });
+}
+
+#[test]
+fn constant_branch_and_chain_collapse_into_one_block() {
+    let program = parse_program(
+        "fn $f(i64 @a) : i64 {
+            #entry {
+                %x = @a + 1
+                => 1 ? #left : #dead
+            }
+            #left {
+                %y = %x * 2
+                => #mid
+            }
+            #dead {
+                %y = 0
+                => #last
+            }
+            #mid {
+                %z = %y - 3
+                => #last
+            }
+            #last {
+                => ret %z
+            }
+        }",
+    );
+    with_function(&program, "$f", |function| {
+        assert!(assert_pass_preserves_semantics(
+            function,
+            simplify_cfg,
+            &int_inputs(&[3, -4])
+        ));
+        let blocks = function.block_name_ids();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(function.blocks.get_name(&blocks[0]).unwrap(), "#entry");
+        assert_eq!(ir_count(function), 3);
+        assert!(!simplify_cfg(function));
+    });
+}
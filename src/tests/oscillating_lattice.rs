@@ -0,0 +1,92 @@
+use petgraph::stable_graph::NodeIndex;
+
+use crate::block::{Block, BlockLattice, BlockTransfer, DataFlowGraph};
+
+/// A block over the two-point lattice of `bool` whose transfer, when `oscillates`, flips
+/// its previous out value instead of following its in value, so it never settles in a
+/// loop.
+pub(crate) struct OscillatingBlock {
+    pub(crate) oscillates: bool,
+    pub(crate) in_value: bool,
+    pub(crate) out_value: bool,
+}
+
+impl OscillatingBlock {
+    pub(crate) fn new(oscillates: bool) -> Self {
+        Self {
+            oscillates,
+            in_value: false,
+            out_value: false,
+        }
+    }
+}
+
+impl BlockLattice<bool> for OscillatingBlock {
+    fn get_in(&self) -> &bool {
+        &self.in_value
+    }
+
+    fn set_in(&mut self, value: bool) {
+        self.in_value = value
+    }
+
+    fn get_out(&self) -> &bool {
+        &self.out_value
+    }
+
+    fn set_out(&mut self, value: bool) {
+        self.out_value = value
+    }
+}
+
+impl Block for OscillatingBlock {
+    fn entry() -> Self {
+        Self::new(false)
+    }
+
+    fn exit() -> Self {
+        Self::new(false)
+    }
+
+    fn set_node_index(&mut self, _: NodeIndex<u32>) {}
+}
+
+impl BlockTransfer<bool, OscillatingBlock, ()> for OscillatingBlock {
+    fn transfer_forward(
+        &self,
+        in_value: &bool,
+        _: &DataFlowGraph<OscillatingBlock>,
+        _: NodeIndex<u32>,
+    ) -> bool {
+        if self.oscillates {
+            !self.out_value
+        } else {
+            *in_value
+        }
+    }
+
+    fn transfer_backward(
+        &self,
+        out_value: &bool,
+        _: &DataFlowGraph<OscillatingBlock>,
+        _: NodeIndex<u32>,
+    ) -> bool {
+        *out_value
+    }
+
+    fn entry_out(_: &DataFlowGraph<OscillatingBlock>) -> bool {
+        true
+    }
+
+    fn exit_in(_: &DataFlowGraph<OscillatingBlock>) -> bool {
+        false
+    }
+
+    fn top(_: &DataFlowGraph<OscillatingBlock>) -> bool {
+        false
+    }
+
+    fn bottom(_: &DataFlowGraph<OscillatingBlock>) -> bool {
+        true
+    }
+}
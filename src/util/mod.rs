@@ -252,6 +252,10 @@ impl<NameIdType: Default + Copy + AddAssign + Eq + Hash, ValueType>
             })
         })
     }
+    /// The arena holding the pooled values.
+    pub fn arena(&self) -> &RcRef<Arena<ValueType>> {
+        &self.arena
+    }
     pub fn create_map<NameType: Eq + Hash>(&self) -> MonotonicNameMap<NameType, NameIdType, ValueType> {
         MonotonicNameMap::new(self.weak_self.upgrade().unwrap())
     }
@@ -294,9 +298,27 @@ impl<NameType: Eq + Hash, NameIdType: Default + Copy + AddAssign + Eq + Hash, Va
             arena: pool.borrow().arena.clone(),
         }
     }
+    /// A map without names over the same pool.
+    pub fn empty_sibling(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+    /// The arena shared with the backing pool.
+    pub fn arena(&self) -> &RcRef<Arena<ValueType>> {
+        &self.arena
+    }
     pub fn get_name_id(&self, name: &NameType) -> Option<&NameIdType> {
         self.name_map.get(name)
     }
+    /// Every bound name with its name id.
+    pub fn names(&self) -> impl Iterator<Item = (&NameType, &NameIdType)> {
+        self.name_map.iter()
+    }
+    /// The name bound to `name_id`, if any.
+    pub fn get_name(&self, name_id: &NameIdType) -> Option<&NameType> {
+        self.name_map
+            .iter()
+            .find_map(|(name, id)| (id == name_id).then_some(name))
+    }
     pub fn get_name_id_and_id(&self, name: &NameType) -> Option<(NameIdType, Id<ValueType>)> {
         self.name_map
             .get(name)
@@ -404,6 +426,10 @@ impl<NameType: Eq + Hash, NameIdType: Default + Copy + AddAssign + Eq + Hash, Va
     pub fn bind(&mut self, name: NameType, id: NameIdType) {
         self.name_map.insert(name, id);
     }
+    /// Drops the binding of `name`, returning its name id. The value stays in the pool.
+    pub fn unbind(&mut self, name: &NameType) -> Option<NameIdType> {
+        self.name_map.remove(name)
+    }
     pub fn entry(&mut self, name: NameType) -> Entry<NameType, NameIdType> {
         self.name_map.entry(name)
     }
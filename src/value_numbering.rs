@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::effects::has_effect;
+use crate::escape::escaped_spaces;
+use crate::ir::{
+    ops::{BinaryOp, CompareType, UnaryOp},
+    Function, Operation, SpaceNameId, IR,
+};
+use crate::mem2reg::shared_spaces;
+
+/// An operation by the value numbers of its operands.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Binary(BinaryOp, usize, usize),
+    Compare(CompareType, usize, usize),
+    Unary(UnaryOp, usize),
+    Select(usize, usize, usize),
+}
+
+/// The value numbers of one block, as far as it has been scanned.
+#[derive(Default)]
+struct Numbering {
+    /// The value number of what each space holds
+    numbers: HashMap<SpaceNameId, usize>,
+    /// The value number of each expression computed
+    expressions: HashMap<Key, usize>,
+    /// A space that held each value number when it was computed
+    holders: HashMap<usize, SpaceNameId>,
+    next: usize,
+}
+
+impl Numbering {
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn number(&mut self, space: SpaceNameId) -> usize {
+        if let Some(number) = self.numbers.get(&space) {
+            return *number;
+        }
+        let number = self.fresh();
+        self.numbers.insert(space, number);
+        number
+    }
+
+    /// Operands are ordered by value number where their order doesn't matter, and
+    /// `Greater` and `GreaterEqual` are written as `Less` and `LessEqual` swapped.
+    fn key(&mut self, op: &Operation) -> Option<Key> {
+        let key = match op {
+            Operation::Binary(op, a, b) => {
+                let (a, b) = (self.number(*a), self.number(*b));
+                if op.is_commutative() {
+                    Key::Binary(*op, a.min(b), a.max(b))
+                } else {
+                    Key::Binary(*op, a, b)
+                }
+            }
+            Operation::Compare(cmp, a, b) => {
+                let (a, b) = (self.number(*a), self.number(*b));
+                match cmp {
+                    CompareType::Eq | CompareType::NotEq => Key::Compare(*cmp, a.min(b), a.max(b)),
+                    CompareType::Greater | CompareType::GreaterEqual => {
+                        Key::Compare(cmp.swapped(), b, a)
+                    }
+                    _ => Key::Compare(*cmp, a, b),
+                }
+            }
+            Operation::Unary(UnaryOp::Load | UnaryOp::Param | UnaryOp::Unit, _) => return None,
+            Operation::Unary(op, a) => Key::Unary(*op, self.number(*a)),
+            Operation::Select(cond, a, b) => {
+                Key::Select(self.number(*cond), self.number(*a), self.number(*b))
+            }
+            Operation::Call(_) => return None,
+        };
+        Some(key)
+    }
+
+    /// A space still holding the value `number`.
+    fn holder(&self, number: usize) -> Option<SpaceNameId> {
+        self.holders
+            .get(&number)
+            .copied()
+            .filter(|holder| self.numbers.get(holder) == Some(&number))
+    }
+}
+
+/// Local value numbering: replaces each assignment recomputing, in the same block, a
+/// value some local still holds with a copy of that local.
+///
+/// Spaces are numbered by the value they hold as each block is scanned from scratch.
+/// Copies share the number of their source, so `%y = %a` followed by `%z = %y + %b`
+/// recomputes `%a + %b`. Operands of commutative operations are ordered, so `%b + %a`
+/// does too. Globals, locals passed to calls and spaces sharing storage with others
+/// are renumbered after each instruction with an effect, see `has_effect`, or writing
+/// one of them, as their values may have changed. `load`s, `param`s and calls are
+/// never reused.
+///
+/// Returns whether any instruction was replaced.
+pub fn local_value_numbering(function: &mut Function) -> bool {
+    let mut volatile = shared_spaces(function);
+    volatile.extend(escaped_spaces(function));
+    let is_volatile = |function: &Function, space: SpaceNameId| {
+        volatile.contains(&space)
+            || (!function.is_local(space) && function.const_value(space).is_none())
+    };
+    let mut changed = false;
+    for name_id in function.block_name_ids() {
+        let id = function.blocks.get_id_from_name_id(&name_id).unwrap();
+        let mut irs = function.blocks.get_from_id(id).unwrap().irs().to_vec();
+        let mut numbering = Numbering::default();
+        for ir in irs.iter_mut() {
+            let Some(dst) = ir.defined_space() else {
+                continue;
+            };
+            let number = match ir {
+                IR::Assignment(_, Operation::Unary(UnaryOp::Unit, src), _) => {
+                    numbering.number(*src)
+                }
+                IR::Assignment(_, op, _) => match numbering.key(op) {
+                    Some(key) => match numbering.expressions.get(&key) {
+                        Some(number) => {
+                            let number = *number;
+                            if let Some(holder) =
+                                numbering.holder(number).filter(|holder| *holder != dst)
+                            {
+                                *op = Operation::Unary(UnaryOp::Unit, holder);
+                                changed = true;
+                            }
+                            number
+                        }
+                        None => {
+                            let number = numbering.fresh();
+                            numbering.expressions.insert(key, number);
+                            number
+                        }
+                    },
+                    None => numbering.fresh(),
+                },
+                _ => numbering.fresh(),
+            };
+            if has_effect(function, ir) || is_volatile(function, dst) {
+                numbering
+                    .numbers
+                    .retain(|space, _| !is_volatile(function, *space));
+            }
+            numbering.numbers.insert(dst, number);
+            if !is_volatile(function, dst) && numbering.holder(number).is_none() {
+                numbering.holders.insert(number, dst);
+            }
+        }
+        *function.blocks.get_mut_from_id(id).unwrap().irs_mut() = irs;
+    }
+    if changed {
+        function.build_graph();
+    }
+    changed
+}
@@ -0,0 +1,62 @@
+//! Allocation counts of the data flow solvers.
+//!
+//! The counting allocator replaces the global one for the whole binary, so it lives in
+//! this test of its own rather than in the library's.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use semilattice::def_use::solve_reaching_definitions;
+use semilattice::ir::lexer::Tokenizer;
+use semilattice::ir::parser::Parser;
+
+/// Counts the allocations of each thread, so tests running in parallel don't see each other's.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The number of allocations `f` makes on the current thread, and its result.
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (usize, R) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (ALLOCATIONS.with(Cell::get) - before, result)
+}
+
+#[test]
+fn reaching_transfer_does_not_allocate_per_instruction() {
+    let irs = 500;
+    let mut src = "fn $long(i64 @x) : i64 {\n#body {\n%v0 = @x + 1\n".to_string();
+    for i in 1..irs {
+        src += &format!("%v{} = %v{} + 1\n", i % 50, (i - 1) % 50);
+    }
+    src += &format!("=> ret %v{}\n}}\n}}", (irs - 1) % 50);
+    let program = Parser::new(src.chars().tokenize()).match_program().unwrap();
+
+    let (_, fn_id) = program
+        .borrow_mut()
+        .lookup_or_insert_function("$long".to_string());
+    let function_pool = program.borrow().function_pool.clone();
+    let mut function_pool = function_pool.borrow_mut();
+    let mut function = function_pool.get_mut_from_id(fn_id).unwrap();
+    let (allocations, _) = count_allocations(|| solve_reaching_definitions(&mut function));
+    assert!(
+        allocations < irs,
+        "{allocations} allocations for {irs} instructions"
+    );
+}